@@ -0,0 +1,317 @@
+// Minimal reading of the bgen header, sample block and variant blocks, used to check files
+// written by this tool
+use crate::{number_of_genotypes, VcfError, PLOIDY_MASK};
+use bgen_reader::bgen::variant_data::DataBlock;
+use color_eyre::Report;
+use flate2::read::ZlibDecoder;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+
+pub struct BgenSummary {
+    pub variant_num: u32,
+    pub sample_num: u32,
+    /// Layout of the variant blocks, from the header flags
+    pub layout_id: u8,
+    /// Compression of the variant blocks: 0 for none, 1 for zlib, 2 for zstd
+    pub compression: u8,
+    pub samples: Option<Vec<String>>,
+    /// Free data area of the header, holding the provenance of files written by this tool
+    pub free_data: Vec<u8>,
+}
+
+fn read_u32(reader: &mut impl Read) -> Result<u32, VcfError> {
+    let mut buffer = [0u8; 4];
+    reader.read_exact(&mut buffer)?;
+    Ok(u32::from_le_bytes(buffer))
+}
+
+fn read_string(reader: &mut impl Read, len: usize) -> Result<String, VcfError> {
+    let mut buffer = vec![0u8; len];
+    reader.read_exact(&mut buffer)?;
+    Ok(String::from_utf8_lossy(&buffer).to_string())
+}
+
+fn read_u16(reader: &mut impl Read) -> Result<u16, VcfError> {
+    let mut buffer = [0u8; 2];
+    reader.read_exact(&mut buffer)?;
+    Ok(u16::from_le_bytes(buffer))
+}
+
+pub fn read_bgen_summary(input: &str) -> Result<BgenSummary, VcfError> {
+    let mut reader = BufReader::new(File::open(input)?);
+    let _start_data_offset = read_u32(&mut reader)?;
+    let header_size = read_u32(&mut reader)?;
+    let variant_num = read_u32(&mut reader)?;
+    let sample_num = read_u32(&mut reader)?;
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != b"bgen" && magic != [0u8; 4] {
+        return Err(VcfError::Validation(Report::msg(format!(
+            "{} is not a bgen file",
+            input
+        ))));
+    }
+    // free data area, flags are the last 4 bytes of the header
+    let mut free_data = vec![0u8; header_size.saturating_sub(20) as usize];
+    reader.read_exact(&mut free_data)?;
+    let flags = read_u32(&mut reader)?;
+    let samples = if flags >> 31 == 1 {
+        let _len_sample_block = read_u32(&mut reader)?;
+        let number_samples = read_u32(&mut reader)?;
+        let samples = (0..number_samples)
+            .map(|_| {
+                let len_id = read_u16(&mut reader)?;
+                let mut id = vec![0u8; len_id as usize];
+                reader.read_exact(&mut id)?;
+                Ok(String::from_utf8_lossy(&id).to_string())
+            })
+            .collect::<Result<Vec<String>, VcfError>>()?;
+        Some(samples)
+    } else {
+        None
+    };
+    Ok(BgenSummary {
+        variant_num,
+        sample_num,
+        layout_id: ((flags >> 2) & 0xf) as u8,
+        compression: (flags & 3) as u8,
+        samples,
+        free_data,
+    })
+}
+
+/// A variant read back from a layout 1 bgen
+pub struct Layout1Variant {
+    pub variants_id: String,
+    pub rsid: String,
+    pub chr: String,
+    pub pos: u32,
+    pub alleles: Vec<String>,
+    /// Probabilities of the three genotypes of each sample, `None` when all are zero (missing)
+    pub probabilities: Vec<Option<[f64; 3]>>,
+}
+
+/// Probabilities of a variant block, as its layout stores them
+pub enum BgenProbabilities {
+    /// Three genotype probabilities of each sample, `None` when all are zero (missing)
+    Layout1(Vec<Option<[f64; 3]>>),
+    Layout2(DataBlock),
+}
+
+/// A variant read back from a bgen of either layout
+pub struct BgenVariant {
+    pub variants_id: String,
+    pub rsid: String,
+    pub chr: String,
+    pub pos: u32,
+    pub alleles: Vec<String>,
+    pub probabilities: BgenProbabilities,
+}
+
+/// Variant blocks of a bgen read in order, zlib, zstd or raw
+pub struct BgenVariantReader {
+    reader: BufReader<File>,
+    summary: BgenSummary,
+    remaining: u32,
+}
+
+impl BgenVariantReader {
+    pub fn open(input: &str) -> Result<Self, VcfError> {
+        let summary = read_bgen_summary(input)?;
+        if !matches!(summary.layout_id, 1 | 2) {
+            return Err(VcfError::Validation(Report::msg(format!(
+                "{} has layout {}, not layout 1 or 2",
+                input, summary.layout_id
+            ))));
+        }
+        let mut reader = BufReader::new(File::open(input)?);
+        let start_data_offset = read_u32(&mut reader)?;
+        reader.seek(SeekFrom::Start(start_data_offset as u64 + 4))?;
+        Ok(BgenVariantReader {
+            reader,
+            remaining: summary.variant_num,
+            summary,
+        })
+    }
+
+    pub fn summary(&self) -> &BgenSummary {
+        &self.summary
+    }
+
+    /// The next variant, `None` after the number of variants of the header
+    pub fn next_variant(&mut self) -> Result<Option<BgenVariant>, VcfError> {
+        self.next_block(true)
+    }
+
+    /// Skip the next variant without decompressing its probabilities, false after the last one
+    pub fn skip_variant(&mut self) -> Result<bool, VcfError> {
+        Ok(self.next_block(false)?.is_some())
+    }
+
+    fn next_block(&mut self, decode: bool) -> Result<Option<BgenVariant>, VcfError> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+        let reader = &mut self.reader;
+        let layout = self.summary.layout_id;
+        let number_individuals = match layout {
+            1 => read_u32(reader)? as usize,
+            _ => 0,
+        };
+        let len = read_u16(reader)? as usize;
+        let variants_id = read_string(reader, len)?;
+        let len = read_u16(reader)? as usize;
+        let rsid = read_string(reader, len)?;
+        let len = read_u16(reader)? as usize;
+        let chr = read_string(reader, len)?;
+        let pos = read_u32(reader)?;
+        let number_alleles = match layout {
+            1 => 2,
+            _ => read_u16(reader)?,
+        };
+        let mut alleles = vec![];
+        for _ in 0..number_alleles {
+            let len = read_u32(reader)? as usize;
+            alleles.push(read_string(reader, len)?);
+        }
+        let compression = self.summary.compression;
+        // stored bytes of the probabilities, and their uncompressed length when compressed
+        let (stored_len, uncompressed_len) = match (layout, compression) {
+            (1, 0) => (number_individuals * 6, None),
+            (1, _) => (read_u32(reader)? as usize, Some(number_individuals * 6)),
+            (_, 0) => (read_u32(reader)? as usize, None),
+            _ => {
+                let block_len = read_u32(reader)? as usize;
+                let uncompressed_len = read_u32(reader)? as usize;
+                (block_len.saturating_sub(4), Some(uncompressed_len))
+            }
+        };
+        if !decode {
+            reader.seek_relative(stored_len as i64)?;
+            return Ok(Some(BgenVariant {
+                variants_id,
+                rsid,
+                chr,
+                pos,
+                alleles,
+                probabilities: BgenProbabilities::Layout2(DataBlock::default()),
+            }));
+        }
+        let mut stored = vec![0u8; stored_len];
+        reader.read_exact(&mut stored)?;
+        let block = match (uncompressed_len, compression) {
+            (None, _) => stored,
+            (Some(len), 1) => {
+                let mut block = vec![0u8; len];
+                ZlibDecoder::new(&stored[..]).read_exact(&mut block)?;
+                block
+            }
+            (Some(len), _) => zstd::bulk::decompress(&stored, len)?,
+        };
+        let probabilities = match layout {
+            1 => BgenProbabilities::Layout1(
+                block
+                    .chunks(6)
+                    .map(|sample| {
+                        let proba = |i: usize| {
+                            u16::from_le_bytes([sample[2 * i], sample[2 * i + 1]]) as f64 / 32768.0
+                        };
+                        let genotypes = [proba(0), proba(1), proba(2)];
+                        Some(genotypes).filter(|g| g.iter().any(|&p| p > 0.0))
+                    })
+                    .collect(),
+            ),
+            _ => BgenProbabilities::Layout2(decode_data_block(&block)?),
+        };
+        Ok(Some(BgenVariant {
+            variants_id,
+            rsid,
+            chr,
+            pos,
+            alleles,
+            probabilities,
+        }))
+    }
+}
+
+// Layout 2 probability data: counts, ploidy bytes, then bit-packed probabilities
+fn decode_data_block(block: &[u8]) -> Result<DataBlock, VcfError> {
+    let truncated = || VcfError::Validation(Report::msg("Truncated layout 2 probability block"));
+    let mut reader = block;
+    let number_individuals = read_u32(&mut reader)?;
+    let number_alleles = read_u16(&mut reader)?;
+    let mut counts = [0u8; 2];
+    reader.read_exact(&mut counts)?;
+    let mut ploidy_missingness = vec![0u8; number_individuals as usize];
+    reader.read_exact(&mut ploidy_missingness)?;
+    let mut flags = [0u8; 2];
+    reader.read_exact(&mut flags)?;
+    let (phased, bits_storage) = (flags[0] == 1, flags[1]);
+    if !(1..=32).contains(&bits_storage) {
+        return Err(truncated());
+    }
+    let num_values: usize = ploidy_missingness
+        .iter()
+        .map(|&ploidy_m| {
+            let ploidy = (ploidy_m & PLOIDY_MASK) as usize;
+            match phased {
+                true => ploidy * (number_alleles as usize - 1),
+                false => number_of_genotypes(ploidy, number_alleles as usize) - 1,
+            }
+        })
+        .sum();
+    let num_bits = bits_storage as usize;
+    if reader.len() < (num_values * num_bits).div_ceil(8) {
+        return Err(truncated());
+    }
+    // each value takes num_bits bits, least significant bit first
+    let probabilities = (0..num_values)
+        .map(|value_i| {
+            (0..num_bits).fold(0u32, |value, i| {
+                let bit = value_i * num_bits + i;
+                value | (((reader[bit / 8] >> (bit % 8)) as u32 & 1) << i)
+            })
+        })
+        .collect();
+    Ok(DataBlock {
+        number_individuals,
+        number_alleles,
+        minimum_ploidy: counts[0],
+        maximum_ploidy: counts[1],
+        ploidy_missingness,
+        phased,
+        bits_storage,
+        probabilities,
+    })
+}
+
+/// Every variant of a layout 1 bgen, with zlib-compressed or raw blocks
+pub fn read_layout1_variants(input: &str) -> Result<Vec<Layout1Variant>, VcfError> {
+    let mut reader = BgenVariantReader::open(input)?;
+    let layout_id = reader.summary().layout_id;
+    let not_layout1 = || {
+        VcfError::Validation(Report::msg(format!(
+            "{} has layout {}, not layout 1",
+            input, layout_id
+        )))
+    };
+    if layout_id != 1 {
+        return Err(not_layout1());
+    }
+    let mut variants = Vec::with_capacity(reader.summary().variant_num as usize);
+    while let Some(variant) = reader.next_variant()? {
+        let BgenProbabilities::Layout1(probabilities) = variant.probabilities else {
+            return Err(not_layout1());
+        };
+        variants.push(Layout1Variant {
+            variants_id: variant.variants_id,
+            rsid: variant.rsid,
+            chr: variant.chr,
+            pos: variant.pos,
+            alleles: variant.alleles,
+            probabilities,
+        });
+    }
+    Ok(variants)
+}
@@ -0,0 +1,114 @@
+// Variant index of a bgen in the sqlite format of bgenix, filled as variants are written
+use crate::VcfError;
+use bgen_reader::bgen::variant_data::VariantData;
+use rusqlite::{params, Connection};
+use std::fs::File;
+use std::io::Read;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Tables of bgenix indexes, read by bgenix and the bgen python and R packages
+const SCHEMA: &str = "
+CREATE TABLE Metadata (
+    filename TEXT NOT NULL,
+    file_size INT NOT NULL,
+    last_write_time INT NOT NULL,
+    first_1000_bytes BLOB NOT NULL,
+    index_creation_time INT NOT NULL
+);
+CREATE TABLE Variant (
+    chromosome TEXT NOT NULL,
+    position INT NOT NULL,
+    rsid TEXT NOT NULL,
+    number_of_alleles INT NOT NULL,
+    allele1 TEXT NOT NULL,
+    allele2 TEXT NULL,
+    file_start_position INT NOT NULL,
+    size_in_bytes INT NOT NULL,
+    PRIMARY KEY (chromosome, position, rsid, allele1, allele2, file_start_position)
+) WITHOUT ROWID;
+";
+
+/// Path of the index of a bgen, as bgenix names it
+pub fn index_path(bgen_path: &str) -> String {
+    format!("{}.bgi", bgen_path)
+}
+
+fn seconds_since_epoch(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs() as i64)
+}
+
+/// The .bgi index of a bgen being written, committed once the bgen is complete
+pub struct BgenIndex {
+    connection: Connection,
+    // timestamps are written as 0, so identical conversions give identical indexes
+    deterministic: bool,
+}
+
+impl BgenIndex {
+    pub fn create(bgen_path: &str, deterministic: bool) -> Result<Self, VcfError> {
+        let path = index_path(bgen_path);
+        // a stale index would keep its tables
+        if std::fs::exists(&path)? {
+            std::fs::remove_file(&path)?;
+        }
+        let connection = Connection::open(&path)?;
+        connection.execute_batch(SCHEMA)?;
+        connection.execute_batch("BEGIN")?;
+        Ok(BgenIndex {
+            connection,
+            deterministic,
+        })
+    }
+
+    /// Record a variant written at `start`, its block included in `size` bytes
+    pub fn add(
+        &mut self,
+        variant_data: &VariantData,
+        start: u64,
+        size: u64,
+    ) -> Result<(), VcfError> {
+        let mut statement = self
+            .connection
+            .prepare_cached("INSERT INTO Variant VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)")?;
+        statement.execute(params![
+            variant_data.chr,
+            variant_data.pos,
+            variant_data.rsid,
+            variant_data.alleles.len(),
+            variant_data.alleles.first(),
+            variant_data.alleles.get(1),
+            start,
+            size,
+        ])?;
+        Ok(())
+    }
+
+    /// Describe the finished bgen, so readers can tell the index matches it, and commit
+    pub fn finish(&mut self, bgen_path: &str) -> Result<(), VcfError> {
+        let metadata = std::fs::metadata(bgen_path)?;
+        let mut first_bytes = Vec::with_capacity(1000);
+        File::open(bgen_path)?
+            .take(1000)
+            .read_to_end(&mut first_bytes)?;
+        let (last_write_time, creation_time) = match self.deterministic {
+            true => (0, 0),
+            false => (
+                seconds_since_epoch(metadata.modified()?),
+                seconds_since_epoch(SystemTime::now()),
+            ),
+        };
+        self.connection.execute(
+            "INSERT INTO Metadata VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                bgen_path,
+                metadata.len(),
+                last_write_time,
+                first_bytes,
+                creation_time,
+            ],
+        )?;
+        self.connection.execute_batch("COMMIT")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,85 @@
+// Progress of a conversion, saved every few variants so an interrupted run can be resumed
+use crate::VcfError;
+use color_eyre::Report;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Path of the checkpoint of the conversion to `output`, in `tmp_dir` when set
+pub fn checkpoint_path(output: &str, tmp_dir: Option<&str>) -> String {
+    match tmp_dir {
+        Some(dir) => {
+            let name = Path::new(output)
+                .file_name()
+                .map_or(output.into(), |name| name.to_string_lossy());
+            Path::new(dir)
+                .join(format!("{}.ckpt", name))
+                .to_string_lossy()
+                .to_string()
+        }
+        None => format!("{}.ckpt", output),
+    }
+}
+
+/// State of a conversion after a whole number of records, every output flushed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub records_read: u32,
+    /// Decompressed bytes of the records read, checked against the input on resume
+    pub input_offset: u64,
+    pub variants_written: u64,
+    /// Size of each output, the blocks written after the checkpoint being dropped on resume
+    pub output_offsets: Vec<u64>,
+    /// Seed of the interrupted run, reused so random choices stay the same
+    pub seed: Option<u64>,
+}
+
+impl Checkpoint {
+    /// Save the checkpoint, replacing the previous one atomically
+    pub fn write(&self, path: &str) -> Result<(), VcfError> {
+        let tmp_path = format!("{}.tmp", path);
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        writeln!(writer, "records_read\t{}", self.records_read)?;
+        writeln!(writer, "input_offset\t{}", self.input_offset)?;
+        writeln!(writer, "variants_written\t{}", self.variants_written)?;
+        let offsets: Vec<String> = self.output_offsets.iter().map(u64::to_string).collect();
+        writeln!(writer, "output_offsets\t{}", offsets.join(","))?;
+        if let Some(seed) = self.seed {
+            writeln!(writer, "seed\t{}", seed)?;
+        }
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        std::fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+
+    pub fn read(path: &str) -> Result<Self, VcfError> {
+        let content = std::fs::read_to_string(path)?;
+        let fields: HashMap<&str, &str> = content
+            .lines()
+            .filter_map(|line| line.split_once('\t'))
+            .collect();
+        let invalid = || {
+            VcfError::Validation(Report::msg(format!(
+                "{} is not a valid checkpoint: restart the conversion without --resume",
+                path
+            )))
+        };
+        let field = |key: &str| fields.get(key).copied().ok_or_else(invalid);
+        let output_offsets = field("output_offsets")?
+            .split(',')
+            .map(|offset| offset.parse().map_err(|_| invalid()))
+            .collect::<Result<Vec<u64>, VcfError>>()?;
+        Ok(Checkpoint {
+            records_read: field("records_read")?.parse().map_err(|_| invalid())?,
+            input_offset: field("input_offset")?.parse().map_err(|_| invalid())?,
+            variants_written: field("variants_written")?.parse().map_err(|_| invalid())?,
+            output_offsets,
+            seed: match fields.get("seed") {
+                Some(seed) => Some(seed.parse().map_err(|_| invalid())?),
+                None => None,
+            },
+        })
+    }
+}
@@ -0,0 +1,143 @@
+// Md5 and sha256 of outputs, computed from the bytes as they are written, and sha256 of inputs,
+// computed from the bytes as they are read
+use crate::VcfError;
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+/// Running md5 and sha256 of the bytes of an output
+pub struct Checksums {
+    md5: Md5,
+    sha256: Sha256,
+}
+
+impl Default for Checksums {
+    fn default() -> Self {
+        Checksums {
+            md5: Md5::new(),
+            sha256: Sha256::new(),
+        }
+    }
+}
+
+impl Checksums {
+    pub fn update(&mut self, bytes: &[u8]) {
+        self.md5.update(bytes);
+        self.sha256.update(bytes);
+    }
+
+    /// Checksums of a complete file, for outputs rewritten after being streamed
+    pub fn of_file(path: &str) -> Result<Self, VcfError> {
+        let mut checksums = Checksums::default();
+        let mut file = File::open(path)?;
+        let mut buffer = vec![0u8; 1 << 16];
+        loop {
+            let read = file.read(&mut buffer)?;
+            if read == 0 {
+                return Ok(checksums);
+            }
+            checksums.update(&buffer[..read]);
+        }
+    }
+
+    /// Write `path.md5` and `path.sha256`, in the format of md5sum and sha256sum
+    pub fn write_files(self, path: &str) -> Result<(), VcfError> {
+        // named relative to the checksum files, so `md5sum -c` works from their directory
+        let name = Path::new(path)
+            .file_name()
+            .map_or(path.into(), |name| name.to_string_lossy());
+        std::fs::write(
+            format!("{}.md5", path),
+            format!("{:x}  {}\n", self.md5.finalize(), name),
+        )?;
+        std::fs::write(
+            format!("{}.sha256", path),
+            format!("{:x}  {}\n", self.sha256.finalize(), name),
+        )?;
+        Ok(())
+    }
+}
+
+/// A writer adding every byte written through it to checksums
+pub struct HashingWriter<'a, W: Write> {
+    inner: &'a mut W,
+    checksums: &'a mut Checksums,
+}
+
+impl<'a, W: Write> HashingWriter<'a, W> {
+    pub fn new(inner: &'a mut W, checksums: &'a mut Checksums) -> Self {
+        HashingWriter { inner, checksums }
+    }
+}
+
+impl<W: Write> Write for HashingWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.checksums.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Running sha256 of an input file, fed by a [`HashingReader`] as the conversion reads the file
+pub struct InputDigest {
+    path: String,
+    sha256: Sha256,
+    bytes_hashed: u64,
+}
+
+impl InputDigest {
+    pub fn new(path: &str) -> Self {
+        InputDigest {
+            path: path.to_string(),
+            sha256: Sha256::new(),
+            bytes_hashed: 0,
+        }
+    }
+
+    /// Hexadecimal sha256 of the whole file, the bytes left unread, as when the conversion stops
+    /// early, being read from the file
+    pub fn finish(&mut self) -> Result<String, VcfError> {
+        let mut file = File::open(&self.path)?;
+        file.seek(SeekFrom::Start(self.bytes_hashed))?;
+        let mut buffer = vec![0u8; 1 << 16];
+        loop {
+            let read = file.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            self.sha256.update(&buffer[..read]);
+            self.bytes_hashed += read as u64;
+        }
+        Ok(format!("{:x}", std::mem::take(&mut self.sha256).finalize()))
+    }
+}
+
+/// A reader adding every byte read through it to a digest of the input
+pub struct HashingReader<R: Read> {
+    inner: R,
+    digest: Rc<RefCell<InputDigest>>,
+}
+
+impl<R: Read> HashingReader<R> {
+    pub fn new(inner: R, digest: Rc<RefCell<InputDigest>>) -> Self {
+        HashingReader { inner, digest }
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        let mut digest = self.digest.borrow_mut();
+        digest.sha256.update(&buf[..read]);
+        digest.bytes_hashed += read as u64;
+        Ok(read)
+    }
+}
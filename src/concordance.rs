@@ -0,0 +1,224 @@
+// Comparison of converted genotypes against a truth vcf, reported by minor allele frequency bin
+use crate::dosage::variant_dosages;
+use crate::pipeline::sink::VariantSink;
+use crate::pipeline::Variant;
+use crate::{
+    open_vcf, parse_genotype_line, read_record_line, read_vcf_header, sample_probabilities,
+    split_multiallelic, ConversionOptions, VcfError, PLOIDY_MASK,
+};
+use bgen_reader::bgen::variant_data::VariantData;
+use color_eyre::Report;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+// Upper bounds of the minor allele frequency bins of the report
+const MAF_BINS: [f64; 7] = [0.001, 0.005, 0.01, 0.05, 0.1, 0.2, 0.5];
+
+/// Most likely number of alt copies of each sample, `None` when the genotype is missing
+pub fn hard_calls(variant_data: &VariantData) -> Vec<Option<u32>> {
+    let data_block = &variant_data.data_block;
+    let max_proba = ((1u64 << data_block.bits_storage) - 1) as f64;
+    sample_probabilities(data_block)
+        .into_iter()
+        .map(|(ploidy_m, probas)| {
+            if ploidy_m >> 7 == 1 {
+                return None;
+            }
+            if data_block.phased {
+                // haplotypes more likely to carry the alt allele
+                let alt_haplotypes = probas.iter().filter(|&&p| (p as f64) < max_proba / 2.0);
+                return Some(alt_haplotypes.count() as u32);
+            }
+            // probabilities of 0 to ploidy - 1 alt copies, all alt is implied
+            let mut copies_proba: Vec<f64> = probas.iter().map(|&p| p as f64 / max_proba).collect();
+            copies_proba.push(1.0 - copies_proba.iter().sum::<f64>());
+            let ploidy = (ploidy_m & PLOIDY_MASK) as usize;
+            (0..=ploidy)
+                .max_by(|&a, &b| copies_proba[a].total_cmp(&copies_proba[b]).then(b.cmp(&a)))
+                .map(|copies| copies as u32)
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Default)]
+struct BinCounts {
+    variants: u64,
+    genotypes: u64,
+    concordant: u64,
+    // sums of imputed dosages (x) and true alt copies (y), for the squared correlation
+    sum_x: f64,
+    sum_y: f64,
+    sum_xx: f64,
+    sum_yy: f64,
+    sum_xy: f64,
+}
+
+impl BinCounts {
+    fn r2(&self) -> Option<f64> {
+        let n = self.genotypes as f64;
+        let covariance = n * self.sum_xy - self.sum_x * self.sum_y;
+        let variance_x = n * self.sum_xx - self.sum_x * self.sum_x;
+        let variance_y = n * self.sum_yy - self.sum_y * self.sum_y;
+        if variance_x <= 0.0 || variance_y <= 0.0 {
+            return None;
+        }
+        Some(covariance * covariance / (variance_x * variance_y))
+    }
+}
+
+/// Concordance of hard calls and dosage r2 against a truth vcf, for the samples and variants in both
+///
+/// Variants are matched on chromosome, position and alleles; the report has one row per MAF bin,
+/// the MAF being computed from the truth genotypes of the compared samples.
+pub struct TruthComparison {
+    truth: String,
+    writer: BufWriter<File>,
+    // index in the converted samples, and true alt copies by variant
+    sample_indices: Vec<usize>,
+    truth_calls: HashMap<(String, u32, String, String), Vec<Option<u32>>>,
+    bins: Vec<BinCounts>,
+}
+
+impl TruthComparison {
+    pub fn create(truth: &str, report: &str) -> Result<Self, VcfError> {
+        Ok(TruthComparison {
+            truth: truth.to_string(),
+            writer: BufWriter::new(File::create(report)?),
+            sample_indices: vec![],
+            truth_calls: HashMap::new(),
+            bins: vec![BinCounts::default(); MAF_BINS.len()],
+        })
+    }
+
+    // Hard calls of the truth vcf, restricted to the samples also converted
+    fn read_truth(&mut self, samples: &[String]) -> Result<(), VcfError> {
+        let mut reader = open_vcf(&self.truth, &ConversionOptions::default())?;
+        let truth_samples = read_vcf_header(&mut reader)?;
+        let mut truth_indices = vec![];
+        for (sample_index, sample) in samples.iter().enumerate() {
+            if let Some(truth_index) = truth_samples.iter().position(|s| s == sample) {
+                self.sample_indices.push(sample_index);
+                truth_indices.push(truth_index);
+            }
+        }
+        if truth_indices.is_empty() {
+            return Err(VcfError::Validation(Report::msg(format!(
+                "No sample of the input is in the truth vcf {}",
+                self.truth
+            ))));
+        }
+        let number_individuals = truth_samples.len() as u32;
+        let mut line = String::new();
+        let mut records_read = 0;
+        while read_record_line(&mut reader, &mut line, records_read)? > 0 {
+            records_read += 1;
+            let variant_data = parse_genotype_line(&line, number_individuals, 8)?;
+            for variant_data in split_multiallelic(variant_data, number_individuals)? {
+                let calls = hard_calls(&variant_data);
+                let key = (
+                    variant_data.chr.clone(),
+                    variant_data.pos,
+                    variant_data.alleles[0].clone(),
+                    variant_data.alleles[1].clone(),
+                );
+                let calls = truth_indices.iter().map(|&i| calls[i]).collect();
+                self.truth_calls.insert(key, calls);
+            }
+            line.clear();
+        }
+        Ok(())
+    }
+}
+
+impl VariantSink for TruthComparison {
+    fn write_header(&mut self, samples: &[String], _variant_num: u32) -> Result<(), VcfError> {
+        self.read_truth(samples)?;
+        writeln!(self.writer, "maf_bin\tvariants\tgenotypes\tconcordance\tr2")?;
+        Ok(())
+    }
+
+    fn write_variant(&mut self, variant: &Variant) -> Result<(), VcfError> {
+        let variant_data = &variant.data;
+        let key = (
+            variant_data.chr.clone(),
+            variant_data.pos,
+            variant_data.alleles[0].clone(),
+            variant_data.alleles[1].clone(),
+        );
+        let Some(truth_calls) = self.truth_calls.get(&key) else {
+            return Ok(());
+        };
+        let dosages = variant_dosages(variant_data);
+        let calls = hard_calls(variant_data);
+        let ploidy: Vec<u8> = sample_probabilities(&variant_data.data_block)
+            .iter()
+            .map(|(ploidy_m, _)| ploidy_m & PLOIDY_MASK)
+            .collect();
+        // genotypes called in both, with the ploidy of the converted sample
+        let pairs: Vec<(f64, u32, u32, u8)> = self
+            .sample_indices
+            .iter()
+            .zip(truth_calls)
+            .filter_map(|(&sample_index, &truth_call)| {
+                Some((
+                    dosages[sample_index]?,
+                    calls[sample_index]?,
+                    truth_call?,
+                    ploidy[sample_index],
+                ))
+            })
+            .collect();
+        let alleles: u32 = pairs.iter().map(|pair| pair.3 as u32).sum();
+        if alleles == 0 {
+            return Ok(());
+        }
+        let frequency = pairs.iter().map(|pair| pair.2).sum::<u32>() as f64 / alleles as f64;
+        let maf = frequency.min(1.0 - frequency);
+        let bin_index = MAF_BINS
+            .iter()
+            .position(|&upper| maf <= upper)
+            .unwrap_or(MAF_BINS.len() - 1);
+        let bin = &mut self.bins[bin_index];
+        bin.variants += 1;
+        for (dosage, call, truth_call, _) in pairs {
+            let truth_call = truth_call as f64;
+            bin.genotypes += 1;
+            bin.concordant += (call as f64 == truth_call) as u64;
+            bin.sum_x += dosage;
+            bin.sum_y += truth_call;
+            bin.sum_xx += dosage * dosage;
+            bin.sum_yy += truth_call * truth_call;
+            bin.sum_xy += dosage * truth_call;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<u64, VcfError> {
+        let mut lower = 0.0;
+        for (upper, bin) in MAF_BINS.iter().zip(&self.bins) {
+            let concordance = if bin.genotypes == 0 {
+                "NA".to_string()
+            } else {
+                let concordance = bin.concordant as f64 / bin.genotypes as f64;
+                ((concordance * 1e6).round() / 1e6).to_string()
+            };
+            let r2 = match bin.r2() {
+                Some(r2) => ((r2 * 1e6).round() / 1e6).to_string(),
+                None => "NA".to_string(),
+            };
+            writeln!(
+                self.writer,
+                "{}-{}\t{}\t{}\t{}\t{}",
+                lower, upper, bin.variants, bin.genotypes, concordance, r2
+            )?;
+            lower = *upper;
+        }
+        self.writer.flush()?;
+        Ok(self.writer.get_ref().metadata()?.len())
+    }
+
+    fn single_pass(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,1057 @@
+// Library entry point: configure a conversion, inspect what it will do, then run it
+use crate::bgi::index_path;
+use crate::checkpoint::{checkpoint_path, Checkpoint};
+use crate::gen_file::gen_sample_path;
+use crate::metrics::ConversionMetrics;
+use crate::output_template::expand_input_stem;
+use crate::pgen::pgen_prefix;
+use crate::pipeline::sink::{shard_manifest_path, shard_path};
+use crate::provenance::{seed_from_time, write_manifest};
+use crate::regions::Regions;
+use crate::sample_names::deduplicated_samples;
+use crate::sample_subset::sample_columns;
+use crate::{
+    build_dry_run_pipeline, convert_to_bgen, convert_to_bgen_single_pass, line_variant_count,
+    read_vcf_header, scan_variants, BlockCompression, ContigStyle, ConversionOptions,
+    DuplicatePolicy, ExtraOutput, FillMissing, GenomeBuild, GenotypeField, GenotypePrior,
+    HalfCallPolicy, MissingPolicy, MonomorphicPolicy, MultiallelicMode, OutputFormat,
+    ProgressFormat, ReadBackend, RefBlockMode, SampleDedupPolicy, ShardMode,
+    SpanningDeletionPolicy, SymbolicAllelePolicy, VariantScan, VcfError, ADAPTIVE_MAX_NUM_BITS,
+    MONOMORPHIC_ALT, OTHER_ALLELES,
+};
+use color_eyre::Report;
+use flate2::read::MultiGzDecoder;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+// Number of records read by `plan` to extrapolate to the whole input
+const PLAN_SAMPLE_RECORDS: u64 = 1000;
+
+/// Description of a conversion, computed from the header and a sample of records
+#[derive(Debug, Clone)]
+pub struct ConversionPlan {
+    pub input: String,
+    pub input_bytes: u64,
+    pub samples: usize,
+    /// Exact when the whole input fits in the sampled records
+    pub estimated_records: u64,
+    pub estimated_variants: u64,
+    pub outputs: Vec<String>,
+    pub filters: Vec<String>,
+    /// Upper bound, assuming genotype blocks do not compress
+    pub estimated_output_bytes: u64,
+    pub estimated_duration: Duration,
+}
+
+/// What a conversion would write, found by reading and checking every record without writing
+#[derive(Debug, Clone)]
+pub struct DryRunReport {
+    /// Samples of the input written, before any renaming
+    pub samples: Vec<String>,
+    pub outputs: Vec<String>,
+    pub filters: Vec<String>,
+    /// Records read and variants that would be written, with the records skipped
+    pub metrics: ConversionMetrics,
+}
+
+pub struct Converter {
+    input: String,
+    output: String,
+    options: ConversionOptions,
+}
+
+impl Converter {
+    /// Convert `input` to `output`, whose `{input_stem}` is replaced by the name of `input`
+    pub fn new(input: &str, output: &str) -> Self {
+        Converter {
+            input: input.to_string(),
+            output: expand_input_stem(output, input),
+            options: ConversionOptions::default(),
+        }
+    }
+
+    pub fn options(mut self, options: ConversionOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn num_bits(mut self, num_bits: u8) -> Self {
+        self.options.num_bits = num_bits;
+        self.options.adaptive_num_bits = false;
+        self
+    }
+
+    /// Pick the fewest bits rounding probabilities by at most `max_error`
+    pub fn max_prob_error(mut self, max_error: f64) -> Self {
+        self.options.max_prob_error = Some(max_error);
+        self
+    }
+
+    /// Store each variant with the fewest bits keeping its probabilities exact, up to 16
+    pub fn adaptive_num_bits(mut self) -> Self {
+        self.options.num_bits = ADAPTIVE_MAX_NUM_BITS;
+        self.options.adaptive_num_bits = true;
+        self
+    }
+
+    /// Write phased haplotypes or unphased genotypes, instead of following the GT separators
+    pub fn phased(mut self, phased: bool) -> Self {
+        self.options.phased = phased;
+        self.options.auto_phase = false;
+        self
+    }
+
+    pub fn reference_panel(mut self) -> Self {
+        let preset = ConversionOptions::reference_panel();
+        self.options.num_bits = preset.num_bits;
+        self.options.phased = preset.phased;
+        self.options.strict_phasing = preset.strict_phasing;
+        self
+    }
+
+    pub fn ref_blocks(mut self, ref_blocks: RefBlockMode) -> Self {
+        self.options.ref_blocks = ref_blocks;
+        self
+    }
+
+    pub fn regroup_chromosomes(mut self, regroup_chromosomes: bool) -> Self {
+        self.options.regroup_chromosomes = regroup_chromosomes;
+        self
+    }
+
+    pub fn backend(mut self, backend: ReadBackend) -> Self {
+        self.options.backend = backend;
+        self
+    }
+
+    pub fn fetch_region(mut self, region: &str) -> Self {
+        self.options.fetch_region = Some(region.to_string());
+        self
+    }
+
+    /// Convert only records in these comma-separated chromosomes or intervals
+    pub fn regions(mut self, regions: &str) -> Self {
+        self.options.regions = Some(regions.to_string());
+        self
+    }
+
+    /// Convert only the variants of this list, by input ID or chr:pos:ref:alt
+    pub fn extract(mut self, path: &str) -> Self {
+        self.options.extract = Some(path.to_string());
+        self
+    }
+
+    /// Leave out the variants of this list, by input ID or chr:pos:ref:alt
+    pub fn exclude(mut self, path: &str) -> Self {
+        self.options.exclude = Some(path.to_string());
+        self
+    }
+
+    /// Convert only records in the intervals of this BED file
+    pub fn regions_file(mut self, path: &str) -> Self {
+        self.options.regions_file = Some(path.to_string());
+        self
+    }
+
+    /// Also write `path` from the same pass, with `num_bits` bits per probability
+    pub fn extra_output(mut self, path: &str, num_bits: u8) -> Self {
+        self.options.extra_outputs.push(ExtraOutput {
+            path: path.to_string(),
+            num_bits,
+        });
+        self
+    }
+
+    /// Also export alt allele dosages as a gzipped variants x samples tsv
+    pub fn dosage_matrix(mut self, path: &str) -> Self {
+        self.options.dosage_matrix = Some(path.to_string());
+        self
+    }
+
+    /// Write chrX of the males of a SAMPLE M/F file as haploid, their heterozygous calls as missing
+    pub fn sex_file(mut self, path: &str) -> Self {
+        self.options.sex_file = Some(path.to_string());
+        self
+    }
+
+    /// Genome build locating the pseudo-autosomal regions kept diploid for males
+    pub fn genome_build(mut self, genome_build: GenomeBuild) -> Self {
+        self.options.genome_build = genome_build;
+        self
+    }
+
+    /// Also write one single-sample bgen per sample to `dir`, keeping at most `max_open_files` open
+    pub fn per_sample_dir(mut self, dir: &str, max_open_files: usize) -> Self {
+        self.options.per_sample_dir = Some(dir.to_string());
+        self.options.max_open_files = max_open_files;
+        self
+    }
+
+    /// Compare the converted genotypes against `truth`, writing concordance and r2 to `report`
+    pub fn truth_comparison(mut self, truth: &str, report: &str) -> Self {
+        self.options.truth_vcf = Some(truth.to_string());
+        self.options.concordance_report = Some(report.to_string());
+        self
+    }
+
+    /// Encode alt allele dosages from this FORMAT field instead of GP and GT
+    pub fn dosage_field(mut self, dosage_field: &str) -> Self {
+        self.options.dosage_field = Some(dosage_field.to_string());
+        self
+    }
+
+    /// Write genotypes from the first of these FORMAT fields each record has
+    pub fn gt_priority(mut self, gt_priority: &[GenotypeField]) -> Self {
+        self.options.gt_priority = gt_priority.to_vec();
+        self
+    }
+
+    /// Write hard calls where the most likely genotype exceeds `threshold`, missing genotypes elsewhere
+    pub fn hard_call_threshold(mut self, threshold: f64) -> Self {
+        self.options.hard_call_threshold = Some(threshold);
+        self
+    }
+
+    /// Write missing genotypes as homozygous reference or with the mean dosage of the variant
+    pub fn fill_missing(mut self, fill_missing: FillMissing) -> Self {
+        self.options.fill_missing = fill_missing;
+        self
+    }
+
+    /// Write bgen layout 1 blocks, read by tools that predate layout 2, instead of layout 2
+    pub fn layout(mut self, layout: u8) -> Self {
+        self.options.layout = layout;
+        self
+    }
+
+    /// Compress variant blocks with zlib or zstd, or leave them uncompressed
+    pub fn compression(mut self, compression: BlockCompression) -> Self {
+        self.options.compression = compression;
+        self
+    }
+
+    /// Zlib level of the variant blocks, from 1 (fastest) to 9 (smallest)
+    pub fn compression_level(mut self, level: u32) -> Self {
+        self.options.compression_level = Some(level);
+        self
+    }
+
+    /// Leave the sample block, and so every sample identifier, out of the bgen outputs
+    pub fn no_sample_ids(mut self) -> Self {
+        self.options.sample_ids = false;
+        self
+    }
+
+    /// Write the new names of a tab-separated OLD NEW file in the bgen sample block
+    pub fn rename_samples(mut self, rename_map: &str) -> Self {
+        self.options.rename_samples = Some(rename_map.to_string());
+        self
+    }
+
+    /// Write sequential pseudonyms in the bgen sample block, and each sample's pseudonym to a tsv
+    pub fn anonymize(mut self, reverse_map: &str) -> Self {
+        self.options.anonymize = Some(reverse_map.to_string());
+        self
+    }
+
+    /// Write only the samples listed in this file, one ID (or FID IID) per line
+    pub fn keep_samples(mut self, path: &str) -> Self {
+        self.options.keep_samples = Some(path.to_string());
+        self
+    }
+
+    /// Leave out the samples listed in this file
+    pub fn remove_samples(mut self, path: &str) -> Self {
+        self.options.remove_samples = Some(path.to_string());
+        self
+    }
+
+    /// Write the samples in the order of this .sample file, e.g. that of another dataset
+    pub fn sample_order(mut self, sample_file: &str) -> Self {
+        self.options.sample_order = Some(sample_file.to_string());
+        self
+    }
+
+    /// Write the main output as bgen or as another format
+    pub fn output_format(mut self, output_format: OutputFormat) -> Self {
+        self.options.output_format = output_format;
+        self
+    }
+
+    /// Read the input once when every output can declare its variant count afterwards
+    pub fn single_pass(mut self) -> Self {
+        self.options.single_pass = true;
+        self
+    }
+
+    /// Record the tool, input sha256 and creation time in the free data area of bgen headers
+    pub fn provenance(mut self) -> Self {
+        self.options.provenance = true;
+        self
+    }
+
+    /// Make outputs byte-identical across runs with the same inputs and options
+    pub fn deterministic(mut self) -> Self {
+        self.options.deterministic = true;
+        self
+    }
+
+    /// Write the md5 and sha256 of each bgen output, computed while it is written
+    pub fn checksums(mut self, checksums: bool) -> Self {
+        self.options.checksums = checksums;
+        self
+    }
+
+    /// Write one bgen per chromosome, the output path being a template such as `out_chr{chr}.bgen`
+    pub fn split_by_chr(mut self, split_by_chr: bool) -> Self {
+        self.options.split_by_chr = split_by_chr;
+        self
+    }
+
+    /// Spread the variants over `shards` bgen files, listing the shard of each variant in a tsv
+    pub fn shards(mut self, shards: u32, shard_mode: ShardMode) -> Self {
+        self.options.shards = Some(shards);
+        self.options.shard_mode = shard_mode;
+        self
+    }
+
+    /// Write a bgenix .bgi index next to each bgen output
+    pub fn write_index(mut self, write_index: bool) -> Self {
+        self.options.write_index = write_index;
+        self
+    }
+
+    /// Derive probabilities from PL or GL likelihoods for records without GP
+    pub fn genotype_likelihoods(mut self, prior: GenotypePrior) -> Self {
+        self.options.genotype_likelihoods = Some(prior);
+        self
+    }
+
+    /// Write contigs in this naming style, converting them when the input differs
+    pub fn target_style(mut self, target_style: ContigStyle) -> Self {
+        self.options.target_style = Some(target_style);
+        self
+    }
+
+    /// Seed of every random choice, recorded in the manifest
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.options.seed = Some(seed);
+        self
+    }
+
+    /// Write a provenance manifest (version, outputs, seed, options) after the conversion
+    pub fn manifest(mut self, path: &str) -> Self {
+        self.options.manifest = Some(path.to_string());
+        self
+    }
+
+    /// Write the input IDs as rsIDs, looking up those missing in a CHROM POS REF ALT RSID reference
+    pub fn annotate_rsid(mut self, path: &str) -> Self {
+        self.options.annotate_rsid = Some(path.to_string());
+        self
+    }
+
+    /// Decode `sample_size` random variants of the finished bgen and fail when one differs from the
+    /// converted variant
+    pub fn verify(mut self, sample_size: u32) -> Self {
+        self.options.verify = Some(sample_size);
+        self
+    }
+
+    /// Refuse to replace outputs that already exist
+    pub fn no_overwrite(mut self) -> Self {
+        self.options.overwrite = false;
+        self
+    }
+
+    /// Write a json summary of the variants written and skipped after the conversion
+    pub fn summary_json(mut self, path: &str) -> Self {
+        self.options.summary_json = Some(path.to_string());
+        self
+    }
+
+    /// Keep multiallelic records as one variant with every allele instead of splitting them
+    pub fn multiallelic(mut self, multiallelic: MultiallelicMode) -> Self {
+        self.options.multiallelic = multiallelic;
+        self
+    }
+
+    /// Write carriers of the spanning deletion allele `*` as missing, or as reference
+    pub fn spanning_deletion(mut self, spanning_deletion: SpanningDeletionPolicy) -> Self {
+        self.options.spanning_deletion = spanning_deletion;
+        self
+    }
+
+    /// Write only SNPs, whose alleles are single bases
+    pub fn snps_only(mut self) -> Self {
+        self.options.snps_only = true;
+        self
+    }
+
+    /// Write only indels, whose alleles are base sequences of different lengths
+    pub fn indels_only(mut self) -> Self {
+        self.options.indels_only = true;
+        self
+    }
+
+    /// Leave out variants with an allele longer than `max_allele_len`
+    pub fn max_allele_len(mut self, max_allele_len: u32) -> Self {
+        self.options.max_allele_len = Some(max_allele_len);
+        self
+    }
+
+    /// Write only variants whose INFO passes `expression`, e.g. `AF>0.001 && INFO/R2>=0.8`
+    pub fn info_filter(mut self, expression: &str) -> Self {
+        self.options.info_filter = Some(expression.to_string());
+        self
+    }
+
+    /// Leave out variants whose every sample is missing
+    pub fn drop_all_missing(mut self) -> Self {
+        self.options.drop_all_missing = true;
+        self
+    }
+
+    /// Keep every `every`th variant of those passing the other filters
+    pub fn thin(mut self, every: u32) -> Self {
+        self.options.thin = Some(every);
+        self
+    }
+
+    /// Keep variants at least `min_distance` bases from the last one kept on their chromosome
+    pub fn thin_bp(mut self, min_distance: u32) -> Self {
+        self.options.thin_bp = Some(min_distance);
+        self
+    }
+
+    /// Draw no progress bar, logging progress instead
+    pub fn quiet(mut self) -> Self {
+        self.options.quiet = true;
+        self
+    }
+
+    /// Show progress as `format` on stderr
+    pub fn progress_format(mut self, format: ProgressFormat) -> Self {
+        self.options.progress_format = format;
+        self
+    }
+
+    /// Parse records and compress variant blocks on `threads` threads instead of every available
+    /// core
+    pub fn threads(mut self, threads: u32) -> Self {
+        self.options.threads = Some(threads);
+        self
+    }
+
+    /// Stop after writing `max_variants` variants, for a quick look at the settings on a large input
+    pub fn max_variants(mut self, max_variants: u32) -> Self {
+        self.options.max_variants = Some(max_variants);
+        self
+    }
+
+    /// Leave out the first `skip_variants` variants, written by another run or job
+    pub fn skip_variants(mut self, skip_variants: u32) -> Self {
+        self.options.skip_variants = Some(skip_variants);
+        self
+    }
+
+    /// Leave out the records before the first one at `chr:pos` in the input
+    pub fn start_position(mut self, start: &str) -> Self {
+        self.options.start_position = Some(start.to_string());
+        self
+    }
+
+    /// Keep each variant with probability `fraction`, reproducible with `seed`
+    pub fn sample_variants(mut self, fraction: f64) -> Self {
+        self.options.sample_variants = Some(fraction);
+        self
+    }
+
+    /// Leave out records whose FILTER is neither PASS nor `.`
+    pub fn pass_only(mut self) -> Self {
+        self.options.pass_only = true;
+        self
+    }
+
+    /// Leave out biallelic variants whose minor allele is carried fewer than `min_mac` times
+    pub fn min_mac(mut self, min_mac: u32) -> Self {
+        self.options.min_mac = Some(min_mac);
+        self
+    }
+
+    /// Write genotypes with a FORMAT/GQ below `min_gq` as missing
+    pub fn min_gq(mut self, min_gq: u32) -> Self {
+        self.options.min_gq = Some(min_gq);
+        self
+    }
+
+    /// Write genotypes with a FORMAT/DP outside `min_dp..=max_dp` as missing
+    pub fn depth_range(mut self, min_dp: Option<u32>, max_dp: Option<u32>) -> Self {
+        self.options.min_dp = min_dp;
+        self.options.max_dp = max_dp;
+        self
+    }
+
+    /// Leave out records whose ALT is `.`, or write them with a placeholder alt
+    pub fn monomorphic(mut self, monomorphic: MonomorphicPolicy) -> Self {
+        self.options.monomorphic = monomorphic;
+        self
+    }
+
+    /// Fail on sample IDs repeated in the vcf header, or number them `_1`, `_2`
+    pub fn dedup_samples(mut self, dedup_samples: SampleDedupPolicy) -> Self {
+        self.options.dedup_samples = dedup_samples;
+        self
+    }
+
+    /// Fail on, leave out or write variants repeating the chr:pos:ref:alt of an earlier one
+    pub fn dup_policy(mut self, dup_policy: DuplicatePolicy) -> Self {
+        self.options.dup_policy = dup_policy;
+        self
+    }
+
+    /// Leave out, write or reject symbolic alleles such as `<DEL>`
+    pub fn symbolic_alleles(mut self, symbolic_alleles: SymbolicAllelePolicy) -> Self {
+        self.options.symbolic_alleles = symbolic_alleles;
+        self
+    }
+
+    /// Write bgen files in `dir` until they are complete, and the checkpoint, instead of next to
+    /// each output
+    pub fn tmp_dir(mut self, dir: &str) -> Self {
+        self.options.tmp_dir = Some(dir.to_string());
+        self
+    }
+
+    /// List the alleles and records left out of the bgen in a tsv
+    pub fn skipped_report(mut self, path: &str) -> Self {
+        self.options.skipped_report = Some(path.to_string());
+        self
+    }
+
+    /// Encode genotypes without any called allele as missing or as reference
+    pub fn missing_policy(mut self, missing_policy: MissingPolicy) -> Self {
+        self.options.missing_policy = missing_policy;
+        self
+    }
+
+    /// Encode half-called genotypes as missing, haploid or with the reference allele
+    pub fn half_call_policy(mut self, half_call_policy: HalfCallPolicy) -> Self {
+        self.options.half_call_policy = half_call_policy;
+        self
+    }
+
+    /// Reject genotypes with more than `max_ploidy` alleles
+    pub fn max_ploidy(mut self, max_ploidy: u8) -> Self {
+        self.options.max_ploidy = max_ploidy;
+        self
+    }
+
+    /// Write samples with the ploidy given per region by a SAMPLE CHROM FROM TO PLOIDY file
+    pub fn ploidy_map(mut self, path: &str) -> Self {
+        self.options.ploidy_map = Some(path.to_string());
+        self
+    }
+
+    /// Also write allele counts recomputed from the converted genotypes to a tsv
+    pub fn allele_counts(mut self, path: &str) -> Self {
+        self.options.allele_counts = Some(path.to_string());
+        self
+    }
+
+    /// List the variants written in a .bim file, or a .pvar when `path` ends in `.pvar`
+    pub fn variant_list(mut self, path: &str) -> Self {
+        self.options.variant_list = Some(path.to_string());
+        self
+    }
+
+    /// Save a checkpoint next to the output every `every` variants
+    pub fn checkpoint_every(mut self, every: u32) -> Self {
+        self.options.checkpoint_every = Some(every);
+        self
+    }
+
+    /// Continue the interrupted conversion saved in the checkpoint next to the output
+    pub fn resume(mut self) -> Self {
+        self.options.resume = true;
+        self
+    }
+
+    /// Describe every variant written in a tsv, or a json array when `path` ends in `.json`
+    pub fn variant_metadata(mut self, path: &str) -> Self {
+        self.options.variant_metadata = Some(path.to_string());
+        self
+    }
+
+    pub fn conversion_options(&self) -> &ConversionOptions {
+        &self.options
+    }
+
+    /// Describe the conversion without writing anything, reading only the first records
+    ///
+    /// Estimates are extrapolated from the compressed size of the input, read with the native backend.
+    pub fn plan(&self) -> Result<ConversionPlan, VcfError> {
+        let input_bytes = File::open(&self.input)?.metadata()?.len();
+        let mut counting_reader = CountingReader {
+            inner: File::open(&self.input)?,
+            bytes_read: 0,
+        };
+        let regions = Regions::load(&self.options)?;
+        let start = Instant::now();
+        let (samples, sampled_records, sampled_variants, compressed_bytes) = {
+            let mut reader = BufReader::new(MultiGzDecoder::new(&mut counting_reader));
+            let mut samples = read_vcf_header(&mut reader)?;
+            samples = deduplicated_samples(&samples, self.options.dedup_samples)?;
+            if let Some(columns) = sample_columns(&samples, &self.options)? {
+                samples = columns.into_iter().map(|i| samples[i].clone()).collect();
+            }
+            let mut line = String::new();
+            let mut sampled_records = 0u64;
+            let mut sampled_variants = 0u64;
+            while sampled_records < PLAN_SAMPLE_RECORDS && reader.read_line(&mut line)? > 0 {
+                if regions
+                    .as_ref()
+                    .is_none_or(|regions| regions.contains_record(&line))
+                {
+                    sampled_variants += line_variant_count(&line, &self.options)? as u64;
+                }
+                sampled_records += 1;
+                line.clear();
+            }
+            let reached_end = reader.fill_buf()?.is_empty();
+            drop(reader);
+            // with the whole input read, the sample is the exact count
+            let compressed_bytes = if reached_end {
+                input_bytes
+            } else {
+                counting_reader.bytes_read
+            };
+            (samples, sampled_records, sampled_variants, compressed_bytes)
+        };
+        let sample_duration = start.elapsed();
+
+        let scale = if compressed_bytes == 0 {
+            1.0
+        } else {
+            input_bytes as f64 / compressed_bytes as f64
+        };
+        // a single pass may still fall back to counting first, depending on the outputs
+        let passes = if self.options.single_pass { 1.0 } else { 2.0 };
+        let estimated_records = (sampled_records as f64 * scale).round() as u64;
+        let estimated_variants = (sampled_variants as f64 * scale).round() as u64;
+        let samples_num = samples.len() as u64;
+        let header_bytes = 20 + 8 + samples.iter().map(|s| 2 + s.len() as u64).sum::<u64>();
+        // variant identifiers plus an uncompressed layout-2 block, for each output
+        let output_bytes = |num_bits: u8| {
+            let probability_bytes = (samples_num * 2 * num_bits as u64).div_ceil(8);
+            header_bytes + estimated_variants * (50 + 10 + samples_num + probability_bytes)
+        };
+        let estimated_output_bytes = output_bytes(self.options.num_bits)
+            + self
+                .options
+                .extra_outputs
+                .iter()
+                .map(|extra_output| output_bytes(extra_output.num_bits))
+                .sum::<u64>();
+        // a single-sample header and variant blocks for each sample
+        let per_sample_bytes = |num_bits: u8| {
+            let probability_bytes = (2 * num_bits as u64).div_ceil(8);
+            (header_bytes - 28)
+                + samples_num * (28 + estimated_variants * (50 + 10 + 1 + probability_bytes))
+        };
+        let estimated_output_bytes = estimated_output_bytes
+            + match self.options.per_sample_dir {
+                Some(_) => per_sample_bytes(self.options.num_bits),
+                None => 0,
+            };
+        let mut outputs = self.output_paths();
+        outputs.extend(self.options.manifest.clone());
+        outputs.extend(self.options.summary_json.clone());
+        Ok(ConversionPlan {
+            input: self.input.clone(),
+            input_bytes,
+            samples: samples.len(),
+            estimated_records,
+            estimated_variants,
+            outputs,
+            filters: self.filter_descriptions(),
+            estimated_output_bytes,
+            // each pass decompresses the whole input
+            estimated_duration: sample_duration.mul_f64(passes * scale),
+        })
+    }
+
+    /// Parse every record and run every check and filter of the conversion, creating no output
+    ///
+    /// Unlike `plan`, the whole input is read: it fails where the conversion would.
+    pub fn dry_run(&self) -> Result<DryRunReport, VcfError> {
+        self.check_overwrite()?;
+        let options = self.run_options()?;
+        let start = Instant::now();
+        let scan = scan_variants(&self.input, &options)?;
+        let options = self.resolve_phasing(options, &scan);
+        let mut pipeline =
+            build_dry_run_pipeline(&self.input, &self.output, scan.number_geno_line, &options)?;
+        info!("Checking variants without writing them");
+        let mut metrics = pipeline.run(scan.variant_num, scan.number_geno_line)?;
+        metrics.add_phase("dry_run", start.elapsed());
+        let mut outputs = self.output_paths();
+        outputs.extend(self.options.manifest.clone());
+        outputs.extend(self.options.summary_json.clone());
+        Ok(DryRunReport {
+            samples: pipeline.samples().to_vec(),
+            outputs,
+            filters: self.filter_descriptions(),
+            metrics,
+        })
+    }
+
+    // Fail before reading the input when an output exists and may not be replaced
+    fn check_overwrite(&self) -> Result<(), VcfError> {
+        // a resumed run continues its own outputs
+        if self.options.overwrite || self.options.resume {
+            return Ok(());
+        }
+        let mut outputs = self.output_paths();
+        outputs.extend(self.options.manifest.clone());
+        outputs.extend(self.options.summary_json.clone());
+        match outputs.iter().find(|path| Path::new(path).exists()) {
+            Some(path) => Err(VcfError::Validation(Report::msg(format!(
+                "{} already exists: use --force to overwrite it",
+                path
+            )))),
+            None => Ok(()),
+        }
+    }
+
+    // Options of a run, the seed continuing a resumed run or drawn when not deterministic
+    fn run_options(&self) -> Result<ConversionOptions, VcfError> {
+        let mut options = self.options.clone();
+        if options.resume {
+            // random choices continue the interrupted run
+            let checkpoint =
+                Checkpoint::read(&checkpoint_path(&self.output, options.tmp_dir.as_deref()))?;
+            options.seed = checkpoint.seed.or(options.seed);
+        }
+        if !options.deterministic {
+            options.seed = Some(options.seed.unwrap_or_else(seed_from_time));
+        }
+        Ok(options)
+    }
+
+    // Write phased haplotypes when the scan found every genotype phased
+    fn resolve_phasing(
+        &self,
+        mut options: ConversionOptions,
+        scan: &VariantScan,
+    ) -> ConversionOptions {
+        // GP, dosages and likelihoods only apply to unphased output
+        if options.auto_phase
+            && scan.all_phased
+            && !scan.has_genotype_probabilities
+            && !options.reads_probabilities()
+        {
+            info!("Every genotype is phased, writing phased haplotypes");
+            options.phased = true;
+        }
+        options
+    }
+
+    /// Count variants, then write the bgen file, or write it in one read with `single_pass`
+    pub fn run(&self) -> Result<ConversionMetrics, VcfError> {
+        self.check_overwrite()?;
+        let options = self.run_options()?;
+        if options.single_pass {
+            match convert_to_bgen_single_pass(&self.input, &self.output, &options)? {
+                Some(metrics) => return self.finish_run(&options, metrics),
+                None => {
+                    info!("These outputs need the variants counted first, reading the input twice")
+                }
+            }
+        }
+        let count_start = Instant::now();
+        let scan = scan_variants(&self.input, &options)?;
+        let count_duration = count_start.elapsed();
+        let mut options = self.resolve_phasing(options, &scan);
+        // headers then have their whole provenance when written
+        options.input_sha256 = scan.input_sha256;
+        let mut metrics = convert_to_bgen(
+            &self.input,
+            &self.output,
+            scan.variant_num,
+            scan.number_geno_line,
+            &options,
+        )?;
+        metrics
+            .phase_durations
+            .insert(0, ("count".to_string(), count_duration));
+        self.finish_run(&options, metrics)
+    }
+
+    // Record the finished conversion in its manifest and summary
+    fn finish_run(
+        &self,
+        options: &ConversionOptions,
+        metrics: ConversionMetrics,
+    ) -> Result<ConversionMetrics, VcfError> {
+        if let Some(manifest) = &options.manifest {
+            write_manifest(
+                manifest,
+                &self.input,
+                &self.output_paths(),
+                options,
+                &metrics,
+            )?;
+        }
+        if let Some(summary_json) = &options.summary_json {
+            metrics.write_summary_json(summary_json, &self.input, &self.output_paths())?;
+        }
+        Ok(metrics)
+    }
+
+    // Every file written by the conversion, the bgen outputs first
+    fn output_paths(&self) -> Vec<String> {
+        let mut outputs = match self.options.output_format {
+            // the bgen of each chromosome is only named once the chromosome is read
+            OutputFormat::Bgen if self.options.split_by_chr => vec![],
+            OutputFormat::Bgen => match self.options.shards {
+                Some(shards) => (0..shards)
+                    .map(|shard| shard_path(&self.output, shard))
+                    .collect(),
+                None => vec![self.output.clone()],
+            },
+            _ => vec![],
+        };
+        outputs.extend(
+            self.options
+                .extra_outputs
+                .iter()
+                .map(|extra_output| extra_output.path.clone()),
+        );
+        // sidecars of the bgen outputs
+        let bgen_outputs = outputs.clone();
+        if self.options.write_index {
+            outputs.extend(bgen_outputs.iter().map(|path| index_path(path)));
+        }
+        if self.options.checksums {
+            for path in &bgen_outputs {
+                outputs.push(format!("{}.md5", path));
+                outputs.push(format!("{}.sha256", path));
+            }
+        }
+        match self.options.output_format {
+            OutputFormat::Bgen => {}
+            OutputFormat::Pgen => outputs.extend(
+                ["pgen", "pvar", "psam"]
+                    .iter()
+                    .map(|extension| format!("{}.{}", pgen_prefix(&self.output), extension)),
+            ),
+            OutputFormat::Gen => {
+                outputs.extend([self.output.clone(), gen_sample_path(&self.output)])
+            }
+        }
+        if self.options.shards.is_some() {
+            outputs.push(shard_manifest_path(&self.output));
+        }
+        outputs.extend(self.options.dosage_matrix.clone());
+        outputs.extend(self.options.allele_counts.clone());
+        outputs.extend(self.options.variant_list.clone());
+        outputs.extend(self.options.variant_metadata.clone());
+        outputs.extend(self.options.per_sample_dir.clone());
+        outputs.extend(self.options.concordance_report.clone());
+        outputs.extend(self.options.skipped_report.clone());
+        outputs.extend(self.options.anonymize.clone());
+        outputs
+    }
+
+    fn filter_descriptions(&self) -> Vec<String> {
+        let mut filters = vec![];
+        match self.options.ref_blocks {
+            RefBlockMode::Skip => filters.push("gVCF reference blocks skipped".to_string()),
+            RefBlockMode::Expand => filters.push("gVCF reference blocks expanded".to_string()),
+        }
+        filters.push("<NON_REF> alleles dropped".to_string());
+        filters.push("breakend records skipped".to_string());
+        filters.push(match self.options.spanning_deletion {
+            SpanningDeletionPolicy::Missing => "'*' alleles dropped, carriers missing".to_string(),
+            SpanningDeletionPolicy::Drop => {
+                "'*' alleles dropped, carriers as reference".to_string()
+            }
+        });
+        filters.push(match self.options.monomorphic {
+            MonomorphicPolicy::Skip => "records without alt allele skipped".to_string(),
+            MonomorphicPolicy::Encode => {
+                format!(
+                    "records without alt allele written with alt {}",
+                    MONOMORPHIC_ALT
+                )
+            }
+        });
+        match self.options.dup_policy {
+            DuplicatePolicy::Error => filters.push("duplicate variants rejected".to_string()),
+            DuplicatePolicy::KeepFirst => filters.push("duplicate variants dropped".to_string()),
+            DuplicatePolicy::KeepAll => {}
+        }
+        if self.options.snps_only {
+            filters.push("SNPs only".to_string());
+        }
+        if self.options.indels_only {
+            filters.push("indels only".to_string());
+        }
+        if let Some(max_allele_len) = self.options.max_allele_len {
+            filters.push(format!("alleles of at most {} bases", max_allele_len));
+        }
+        if let Some(expression) = &self.options.info_filter {
+            filters.push(format!("variants with INFO {}", expression));
+        }
+        if self.options.drop_all_missing {
+            filters.push("variants with a called sample".to_string());
+        }
+        if let Some(min_distance) = self.options.thin_bp {
+            filters.push(format!("variants at least {} bp apart", min_distance));
+        }
+        if let Some(every) = self.options.thin {
+            filters.push(format!("every {}th variant", every));
+        }
+        if let Some(fraction) = self.options.sample_variants {
+            filters.push(format!("a random {} of variants", fraction));
+        }
+        if let Some(start) = &self.options.start_position {
+            filters.push(format!("records from {} on", start));
+        }
+        if let Some(skip_variants) = self.options.skip_variants {
+            filters.push(format!("variants after the first {}", skip_variants));
+        }
+        if let Some(max_variants) = self.options.max_variants {
+            filters.push(format!("the first {} variants", max_variants));
+        }
+        if self.options.pass_only {
+            filters.push("records with FILTER PASS or .".to_string());
+        }
+        if let Some(min_mac) = self.options.min_mac {
+            filters.push(format!(
+                "biallelic variants with a minor allele count < {}",
+                min_mac
+            ));
+        }
+        if let Some(min_gq) = self.options.min_gq {
+            filters.push(format!("genotypes with GQ < {} set missing", min_gq));
+        }
+        if let Some(min_dp) = self.options.min_dp {
+            filters.push(format!("genotypes with DP < {} set missing", min_dp));
+        }
+        if let Some(max_dp) = self.options.max_dp {
+            filters.push(format!("genotypes with DP > {} set missing", max_dp));
+        }
+        if self.options.symbolic_alleles == SymbolicAllelePolicy::Skip {
+            filters.push("symbolic alleles dropped".to_string());
+        }
+        match self.options.multiallelic {
+            MultiallelicMode::Split => {}
+            MultiallelicMode::Keep => filters.push("multiallelic records kept whole".to_string()),
+            MultiallelicMode::Collapse => {
+                filters.push(format!("minor alt alleles merged into {}", OTHER_ALLELES))
+            }
+        }
+        if self.options.strict_phasing {
+            filters.push("unphased or missing genotypes rejected".to_string());
+        }
+        if self.options.regroup_chromosomes {
+            filters.push("records regrouped by chromosome".to_string());
+        }
+        match self.options.genotype_likelihoods {
+            Some(GenotypePrior::Flat) => {
+                filters.push("probabilities derived from PL/GL with a flat prior".to_string())
+            }
+            Some(GenotypePrior::AlleleFrequency) => {
+                filters.push("probabilities derived from PL/GL with an INFO/AF prior".to_string())
+            }
+            None => {}
+        }
+        if self.options.missing_policy == MissingPolicy::Ref {
+            filters.push("uncalled genotypes written as reference".to_string());
+        }
+        match self.options.half_call_policy {
+            HalfCallPolicy::Missing => {}
+            HalfCallPolicy::Haploid => filters.push("half-calls written as haploid".to_string()),
+            HalfCallPolicy::Ref => {
+                filters.push("missing alleles of half-calls written as reference".to_string())
+            }
+        }
+        if let Some(sex_file) = &self.options.sex_file {
+            filters.push(format!(
+                "chrX of males from {} written haploid outside {:?} PARs",
+                sex_file, self.options.genome_build
+            ));
+        }
+        if let Some(ploidy_map) = &self.options.ploidy_map {
+            filters.push(format!("ploidy set per region from {}", ploidy_map));
+        }
+        if let Some(path) = &self.options.annotate_rsid {
+            filters.push(format!("missing IDs filled with rsIDs from {}", path));
+        }
+        if let Some(target_style) = self.options.target_style {
+            filters.push(format!("contigs renamed to {:?} style", target_style));
+        }
+        if let Some(dosage_field) = &self.options.dosage_field {
+            filters.push(format!("dosages read from FORMAT/{}", dosage_field));
+        }
+        if !self.options.gt_priority.is_empty() {
+            let fields: Vec<String> = self
+                .options
+                .gt_priority
+                .iter()
+                .map(|field| format!("{:?}", field).to_uppercase())
+                .collect();
+            filters.push(format!("genotypes read from {}", fields.join(" or ")));
+        }
+        if let Some(threshold) = self.options.hard_call_threshold {
+            filters.push(format!(
+                "hard calls where a genotype probability exceeds {}, missing elsewhere",
+                threshold
+            ));
+        }
+        match self.options.fill_missing {
+            FillMissing::None => {}
+            FillMissing::Ref => filters.push("missing genotypes written as reference".to_string()),
+            FillMissing::MeanDosage => {
+                filters.push("missing genotypes written with the mean dosage".to_string())
+            }
+        }
+        if self.options.layout == 1 {
+            filters.push("bgen layout 1, non-diploid genotypes missing".to_string());
+        }
+        if let Some(region) = &self.options.fetch_region {
+            filters.push(format!("index query restricted to {}", region));
+        }
+        if let Some(regions) = &self.options.regions {
+            filters.push(format!("records in regions {}", regions));
+        }
+        if let Some(path) = &self.options.regions_file {
+            filters.push(format!("records in the intervals of {}", path));
+        }
+        if let Some(path) = &self.options.extract {
+            filters.push(format!("variants listed in {}", path));
+        }
+        if let Some(path) = &self.options.exclude {
+            filters.push(format!("variants not listed in {}", path));
+        }
+        filters
+    }
+}
+
+struct CountingReader<R> {
+    inner: R,
+    bytes_read: u64,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let bytes_read = self.inner.read(buf)?;
+        self.bytes_read += bytes_read as u64;
+        Ok(bytes_read)
+    }
+}
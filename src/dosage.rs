@@ -0,0 +1,164 @@
+// Expected alternate allele counts, and their export as a variants x samples matrix
+use crate::pipeline::sink::VariantSink;
+use crate::pipeline::Variant;
+use crate::{sample_probabilities, VcfError};
+use bgen_reader::bgen::variant_data::VariantData;
+use color_eyre::Report;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+/// Alt allele dosage of each sample, `None` when the genotype is missing
+pub fn variant_dosages(variant_data: &VariantData) -> Vec<Option<f64>> {
+    let data_block = &variant_data.data_block;
+    let max_proba = ((1u64 << data_block.bits_storage) - 1) as f64;
+    sample_probabilities(data_block)
+        .into_iter()
+        .map(|(ploidy_m, probas)| {
+            if ploidy_m >> 7 == 1 {
+                return None;
+            }
+            let dosage = if data_block.phased {
+                // probability of the ref allele on each haplotype
+                probas.iter().map(|&p| 1.0 - p as f64 / max_proba).sum()
+            } else {
+                // probabilities of 0 to ploidy - 1 alt copies, all alt is implied
+                let ploidy = probas.len() as f64;
+                let stored: f64 = probas.iter().map(|&p| p as f64 / max_proba).sum();
+                let partial: f64 = probas
+                    .iter()
+                    .enumerate()
+                    .map(|(copies, &p)| copies as f64 * p as f64 / max_proba)
+                    .sum();
+                partial + ploidy * (1.0 - stored)
+            };
+            Some(dosage)
+        })
+        .collect()
+}
+
+/// Expected alt allele count of the called samples, and the number of alleles they carry
+pub fn allele_count(variant_data: &VariantData) -> (f64, usize) {
+    let called: Vec<f64> = variant_dosages(variant_data)
+        .into_iter()
+        .flatten()
+        .collect();
+    let allele_number = sample_probabilities(&variant_data.data_block)
+        .iter()
+        .filter(|(ploidy_m, _)| ploidy_m >> 7 == 0)
+        .map(|(_, probas)| probas.len())
+        .sum();
+    (called.iter().sum(), allele_number)
+}
+
+/// Expected count of the less frequent allele of a biallelic variant, among called samples
+pub fn minor_allele_count(variant_data: &VariantData) -> f64 {
+    let (alt_count, allele_number) = allele_count(variant_data);
+    alt_count.min(allele_number as f64 - alt_count)
+}
+
+/// Gzipped tsv with one row per variant and one column per sample
+pub struct DosageMatrixWriter {
+    writer: GzEncoder<BufWriter<File>>,
+    max_variants: u32,
+}
+
+impl DosageMatrixWriter {
+    pub fn create(path: &str, max_variants: u32) -> Result<Self, VcfError> {
+        let writer = GzEncoder::new(BufWriter::new(File::create(path)?), Compression::default());
+        Ok(DosageMatrixWriter {
+            writer,
+            max_variants,
+        })
+    }
+}
+
+impl VariantSink for DosageMatrixWriter {
+    /// Refuse to start when the matrix would hold more than `max_variants` rows
+    fn write_header(&mut self, samples: &[String], variant_num: u32) -> Result<(), VcfError> {
+        if variant_num > self.max_variants {
+            return Err(VcfError::Validation(Report::msg(format!(
+                "The dosage matrix would hold {} variants, above the limit of {}: restrict the input to a smaller region or raise the limit",
+                variant_num, self.max_variants
+            ))));
+        }
+        writeln!(self.writer, "variant\t{}", samples.join("\t"))?;
+        Ok(())
+    }
+
+    fn write_variant(&mut self, variant: &Variant) -> Result<(), VcfError> {
+        let variant_data = &variant.data;
+        write!(self.writer, "{}", variant_data.variants_id)?;
+        for dosage in variant_dosages(variant_data) {
+            match dosage {
+                Some(dosage) => write!(self.writer, "\t{}", (dosage * 1e4).round() / 1e4)?,
+                None => write!(self.writer, "\tNA")?,
+            }
+        }
+        writeln!(self.writer)?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<u64, VcfError> {
+        self.writer.try_finish()?;
+        self.writer.get_mut().flush()?;
+        Ok(self.writer.get_ref().get_ref().metadata()?.len())
+    }
+}
+
+/// Tsv of alt allele counts (AC), called allele numbers (AN) and frequencies of every written variant
+///
+/// Counts come from the genotypes written, not from the INFO fields of the input.
+pub struct AlleleCountWriter {
+    writer: BufWriter<File>,
+}
+
+impl AlleleCountWriter {
+    pub fn create(path: &str) -> Result<Self, VcfError> {
+        Ok(AlleleCountWriter {
+            writer: BufWriter::new(File::create(path)?),
+        })
+    }
+}
+
+impl VariantSink for AlleleCountWriter {
+    fn write_header(&mut self, _samples: &[String], _variant_num: u32) -> Result<(), VcfError> {
+        writeln!(self.writer, "variant\tchr\tpos\tref\talt\tAC\tAN\tAF")?;
+        Ok(())
+    }
+
+    fn write_variant(&mut self, variant: &Variant) -> Result<(), VcfError> {
+        let variant_data = &variant.data;
+        let (allele_count, allele_number) = allele_count(variant_data);
+        // expected count, which is the hard call count unless probabilities come from GP
+        let allele_count = (allele_count * 1e4).round() / 1e4;
+        let frequency = if allele_number == 0 {
+            "NA".to_string()
+        } else {
+            ((allele_count / allele_number as f64 * 1e6).round() / 1e6).to_string()
+        };
+        writeln!(
+            self.writer,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            variant_data.variants_id,
+            variant_data.chr,
+            variant_data.pos,
+            variant_data.alleles[0],
+            variant_data.alleles[1],
+            allele_count,
+            allele_number,
+            frequency
+        )?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<u64, VcfError> {
+        self.writer.flush()?;
+        Ok(self.writer.get_ref().metadata()?.len())
+    }
+
+    fn single_pass(&self) -> bool {
+        true
+    }
+}
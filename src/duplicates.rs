@@ -0,0 +1,55 @@
+// Variants repeating the chr:pos:ref:alt of an earlier one, which bgenix cannot tell apart
+use crate::{DuplicatePolicy, VcfError};
+use color_eyre::Report;
+use std::collections::{HashMap, HashSet};
+
+/// Alleles of the variants seen at the last position of each chromosome
+///
+/// Duplicates share a position, so only the alleles at the current one are kept, by chromosome so
+/// that records regrouped by chromosome find the same duplicates as in input order.
+#[derive(Debug, Clone)]
+pub struct DuplicateFilter {
+    policy: DuplicatePolicy,
+    seen: HashMap<String, (u32, HashSet<Vec<String>>)>,
+}
+
+impl DuplicateFilter {
+    /// Filter of the policy, `None` when every variant is written
+    pub fn new(policy: DuplicatePolicy) -> Option<Self> {
+        (policy != DuplicatePolicy::KeepAll).then(|| DuplicateFilter {
+            policy,
+            seen: HashMap::new(),
+        })
+    }
+
+    /// Whether the next variant is kept, failing on a duplicate under the error policy
+    pub fn keeps(
+        &mut self,
+        chr: &str,
+        pos: u32,
+        alleles: &[String],
+        record_num: u32,
+    ) -> Result<bool, VcfError> {
+        let (last_pos, alleles_seen) = self
+            .seen
+            .entry(chr.to_string())
+            .or_insert_with(|| (pos, HashSet::new()));
+        if *last_pos != pos {
+            *last_pos = pos;
+            alleles_seen.clear();
+        }
+        if alleles_seen.insert(alleles.to_vec()) {
+            return Ok(true);
+        }
+        match self.policy {
+            DuplicatePolicy::Error => Err(VcfError::Validation(Report::msg(format!(
+                "Record {} repeats variant {}:{}:{}: use --dup-policy keep-first or keep-all",
+                record_num,
+                chr,
+                pos,
+                alleles.join(":")
+            )))),
+            _ => Ok(false),
+        }
+    }
+}
@@ -0,0 +1,81 @@
+// Oxford .gen text output, with its .sample file, for tools that do not read bgen
+use crate::layout1::diploid_genotype_probabilities;
+use crate::pipeline::sink::VariantSink;
+use crate::pipeline::Variant;
+use crate::VcfError;
+use color_eyre::Report;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+/// Path of the .sample file of `output`: `out.gen` gives `out.sample`
+pub fn gen_sample_path(output: &str) -> String {
+    format!("{}.sample", output.strip_suffix(".gen").unwrap_or(output))
+}
+
+/// Space-delimited rows of chromosome, ids, position, alleles and three probabilities per sample
+///
+/// Missing and non-diploid samples have three zero probabilities, as in layout 1 bgen files.
+pub struct GenOutput {
+    pub path: String,
+    writer: BufWriter<File>,
+}
+
+impl GenOutput {
+    pub fn create(path: &str) -> Result<Self, VcfError> {
+        Ok(GenOutput {
+            path: path.to_string(),
+            writer: BufWriter::new(File::create(path)?),
+        })
+    }
+}
+
+impl VariantSink for GenOutput {
+    fn write_header(&mut self, samples: &[String], _variant_num: u32) -> Result<(), VcfError> {
+        let mut sample_file = BufWriter::new(File::create(gen_sample_path(&self.path))?);
+        writeln!(sample_file, "ID_1 ID_2 missing")?;
+        writeln!(sample_file, "0 0 0")?;
+        for sample in samples {
+            writeln!(sample_file, "{} {} 0", sample, sample)?;
+        }
+        sample_file.flush()?;
+        Ok(())
+    }
+
+    fn write_variant(&mut self, variant: &Variant) -> Result<(), VcfError> {
+        let variant_data = &variant.data;
+        if variant_data.alleles.len() != 2 {
+            return Err(VcfError::Validation(Report::msg(format!(
+                "Variant {} has {} alleles, .gen output only stores biallelic variants",
+                variant_data.variants_id,
+                variant_data.alleles.len()
+            ))));
+        }
+        write!(
+            self.writer,
+            "{} {} {} {} {} {}",
+            variant_data.chr,
+            variant_data.variants_id,
+            variant_data.rsid,
+            variant_data.pos,
+            variant_data.alleles[0],
+            variant_data.alleles[1]
+        )?;
+        for genotypes in diploid_genotype_probabilities(&variant_data.data_block) {
+            for proba in genotypes.unwrap_or_default() {
+                write!(self.writer, " {}", (proba * 1e4).round() / 1e4)?;
+            }
+        }
+        writeln!(self.writer)?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<u64, VcfError> {
+        self.writer.flush()?;
+        let sample_bytes = std::fs::metadata(gen_sample_path(&self.path))?.len();
+        Ok(self.writer.get_ref().metadata()?.len() + sample_bytes)
+    }
+
+    fn single_pass(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,131 @@
+// Vcf/bcf reading through htslib, exposed as vcf text so the rest of the converter is unchanged
+use crate::VcfError;
+use color_eyre::Report;
+use rust_htslib::bcf::{self, Read as BcfRead};
+use rust_htslib::htslib;
+use std::io::{self, Read};
+
+impl From<rust_htslib::errors::Error> for VcfError {
+    fn from(error: rust_htslib::errors::Error) -> Self {
+        VcfError::Io(io::Error::other(error))
+    }
+}
+
+/// Streams the header then one formatted line per record read by htslib
+pub struct HtslibVcfReader<R: BcfRead> {
+    reader: R,
+    record: bcf::Record,
+    line: htslib::kstring_t,
+    buffer: Vec<u8>,
+    buffer_pos: usize,
+    header_written: bool,
+}
+
+impl<R: BcfRead> HtslibVcfReader<R> {
+    pub fn new(reader: R) -> Self {
+        let record = reader.empty_record();
+        HtslibVcfReader {
+            reader,
+            record,
+            line: htslib::kstring_t {
+                l: 0,
+                m: 0,
+                s: std::ptr::null_mut(),
+            },
+            buffer: vec![],
+            buffer_pos: 0,
+            header_written: false,
+        }
+    }
+
+    fn fill_buffer(&mut self) -> io::Result<bool> {
+        self.line.l = 0;
+        let header = self.reader.header().inner;
+        let status = if !self.header_written {
+            self.header_written = true;
+            unsafe { htslib::bcf_hdr_format(header, 0, &mut self.line) }
+        } else {
+            match self.reader.read(&mut self.record) {
+                None => return Ok(false),
+                Some(result) => {
+                    result.map_err(io::Error::other)?;
+                    unsafe { htslib::vcf_format(header, self.record.inner, &mut self.line) }
+                }
+            }
+        };
+        if status < 0 {
+            return Err(io::Error::other("htslib could not format the record"));
+        }
+        self.buffer.clear();
+        if self.line.l > 0 {
+            let formatted =
+                unsafe { std::slice::from_raw_parts(self.line.s as *const u8, self.line.l) };
+            self.buffer.extend_from_slice(formatted);
+        }
+        self.buffer_pos = 0;
+        Ok(true)
+    }
+}
+
+impl<R: BcfRead> Read for HtslibVcfReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.buffer_pos == self.buffer.len() {
+            if !self.fill_buffer()? {
+                return Ok(0);
+            }
+        }
+        let len = buf.len().min(self.buffer.len() - self.buffer_pos);
+        buf[..len].copy_from_slice(&self.buffer[self.buffer_pos..self.buffer_pos + len]);
+        self.buffer_pos += len;
+        Ok(len)
+    }
+}
+
+impl<R: BcfRead> Drop for HtslibVcfReader<R> {
+    fn drop(&mut self) {
+        unsafe { htslib::hts_free(self.line.s as *mut std::os::raw::c_void) };
+    }
+}
+
+/// Open a vcf/bcf file, restricted to `region` (like `22:16000000-17000000`) through its csi/tbi index
+pub fn open_htslib(input: &str, region: Option<&str>) -> Result<Box<dyn Read>, VcfError> {
+    match region {
+        None => Ok(Box::new(HtslibVcfReader::new(bcf::Reader::from_path(
+            input,
+        )?))),
+        Some(region) => {
+            let mut reader = bcf::IndexedReader::from_path(input)?;
+            let (chr, start, end) = parse_region(region)?;
+            let rid = reader.header().name2rid(chr.as_bytes())?;
+            reader.fetch(rid, start, end)?;
+            Ok(Box::new(HtslibVcfReader::new(reader)))
+        }
+    }
+}
+
+// 1-based inclusive region to the 0-based inclusive coordinates used by htslib
+fn parse_region(region: &str) -> Result<(&str, u64, Option<u64>), VcfError> {
+    let invalid_region =
+        || VcfError::Validation(Report::msg(format!("Invalid region '{}'", region)));
+    match region.split_once(':') {
+        None => Ok((region, 0, None)),
+        Some((chr, range)) => {
+            let (start, end) = range.split_once('-').unwrap_or((range, ""));
+            let start: u64 = start
+                .replace(',', "")
+                .parse()
+                .map_err(|_| invalid_region())?;
+            let end = if end.is_empty() {
+                None
+            } else {
+                Some(
+                    end.replace(',', "")
+                        .parse::<u64>()
+                        .map_err(|_| invalid_region())?
+                        .saturating_sub(1),
+                )
+            };
+            Ok((chr, start.saturating_sub(1), end))
+        }
+    }
+}
@@ -0,0 +1,160 @@
+// Expressions on the INFO column, such as `AF>0.001 && INFO/R2>=0.8`, selecting variants
+use crate::{trim_record, VcfError};
+use color_eyre::Report;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operator {
+    Greater,
+    GreaterOrEqual,
+    Less,
+    LessOrEqual,
+    Equal,
+    NotEqual,
+}
+
+// Longest operators first, so `>=` is not read as `>`
+const OPERATORS: [(&str, Operator); 7] = [
+    (">=", Operator::GreaterOrEqual),
+    ("<=", Operator::LessOrEqual),
+    ("==", Operator::Equal),
+    ("!=", Operator::NotEqual),
+    (">", Operator::Greater),
+    ("<", Operator::Less),
+    ("=", Operator::Equal),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Number(f64),
+    Text(String),
+}
+
+// One comparison, or the presence of a flag when it has no operator
+#[derive(Debug, Clone, PartialEq)]
+struct Condition {
+    key: String,
+    comparison: Option<(Operator, Value)>,
+}
+
+impl Condition {
+    fn parse(text: &str, expression: &str) -> Result<Self, VcfError> {
+        let invalid = |reason: &str| {
+            VcfError::Validation(Report::msg(format!(
+                "Invalid INFO filter '{}': {} in '{}'",
+                expression, reason, text
+            )))
+        };
+        let text = text.trim();
+        let operator = OPERATORS
+            .iter()
+            .filter_map(|&(symbol, operator)| text.find(symbol).map(|at| (at, symbol, operator)))
+            .min_by_key(|&(at, symbol, _)| (at, usize::MAX - symbol.len()));
+        let (key, comparison) = match operator {
+            None => (text, None),
+            Some((at, symbol, operator)) => {
+                let literal = text[at + symbol.len()..].trim().trim_matches('"');
+                if literal.is_empty() {
+                    return Err(invalid("missing value"));
+                }
+                let value = match literal.parse() {
+                    Ok(number) => Value::Number(number),
+                    Err(_) if matches!(operator, Operator::Equal | Operator::NotEqual) => {
+                        Value::Text(literal.to_string())
+                    }
+                    Err(_) => return Err(invalid("only numbers can be ordered")),
+                };
+                (text[..at].trim(), Some((operator, value)))
+            }
+        };
+        let key = key.strip_prefix("INFO/").unwrap_or(key);
+        if key.is_empty() || key.contains(char::is_whitespace) {
+            return Err(invalid("expected an INFO key"));
+        }
+        Ok(Condition {
+            key: key.to_string(),
+            comparison,
+        })
+    }
+
+    // Against the value of the alt at `alt_index` of per-alt lists, or any value of the list
+    fn holds(&self, info: &str, alt_index: Option<usize>) -> bool {
+        let Some(entry) = info
+            .split(';')
+            .find(|entry| entry.split('=').next() == Some(self.key.as_str()))
+        else {
+            return false;
+        };
+        let Some((operator, expected)) = &self.comparison else {
+            return true;
+        };
+        let Some((_, values)) = entry.split_once('=') else {
+            return false;
+        };
+        let values: Vec<&str> = values.split(',').collect();
+        let compare = |value: &str| match expected {
+            Value::Number(expected) => value.parse::<f64>().is_ok_and(|value| match operator {
+                Operator::Greater => value > *expected,
+                Operator::GreaterOrEqual => value >= *expected,
+                Operator::Less => value < *expected,
+                Operator::LessOrEqual => value <= *expected,
+                Operator::Equal => value == *expected,
+                Operator::NotEqual => value != *expected,
+            }),
+            Value::Text(expected) => (value == expected) == (*operator == Operator::Equal),
+        };
+        match alt_index {
+            Some(alt_index) if values.len() > 1 => {
+                values.get(alt_index).is_some_and(|v| compare(v))
+            }
+            _ => values.iter().any(|value| compare(value)),
+        }
+    }
+}
+
+/// Conditions joined by `&&` and `||`, `&&` binding tighter, on INFO keys with an optional
+/// `INFO/` prefix
+///
+/// A key without an operator tests a flag. Lists with one value per alt are compared on the
+/// value of each split alt; records kept whole pass when any value does. Missing keys fail.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InfoFilter {
+    // conditions of each `||` alternative
+    alternatives: Vec<Vec<Condition>>,
+}
+
+impl InfoFilter {
+    pub fn parse(expression: &str) -> Result<Self, VcfError> {
+        let alternatives = expression
+            .split("||")
+            .map(|alternative| {
+                alternative
+                    .split("&&")
+                    .map(|condition| Condition::parse(condition, expression))
+                    .collect()
+            })
+            .collect::<Result<Vec<Vec<Condition>>, VcfError>>()?;
+        Ok(InfoFilter { alternatives })
+    }
+
+    /// Whether the variant of the alt at `alt_index`, or a record kept whole, passes
+    pub fn keeps(&self, info: &str, alt_index: Option<usize>) -> bool {
+        self.alternatives.iter().any(|conditions| {
+            conditions
+                .iter()
+                .all(|condition| condition.holds(info, alt_index))
+        })
+    }
+
+    /// Whether every variant of the record on a vcf line passes, `None` when only some do
+    pub fn keeps_record(&self, line: &str) -> Option<bool> {
+        let mut fields = trim_record(line).split('\t');
+        let alt_count = fields.nth(4).unwrap_or_default().split(',').count();
+        let info = fields.nth(2).unwrap_or_default();
+        let kept = (0..alt_count).filter(|&alt_index| self.keeps(info, Some(alt_index)));
+        match kept.count() {
+            0 if !self.keeps(info, None) => Some(false),
+            count if count == alt_count && self.keeps(info, None) => Some(true),
+            _ => None,
+        }
+    }
+}
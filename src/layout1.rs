@@ -0,0 +1,88 @@
+// Variant blocks in bgen layout 1 (v1.1), for tools that predate layout 2 such as SNPTEST v2
+use crate::{sample_probabilities, BlockCompression, VcfError, MISSING_FLAG, PLOIDY_MASK};
+use bgen_reader::bgen::variant_data::{DataBlock, VariantData};
+use color_eyre::Report;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::io::Write;
+
+// Layout 1 probabilities are 16-bit integers, 1 being stored as 32768
+const LAYOUT1_SCALE: f64 = 32768.0;
+
+/// Probabilities of the three diploid genotypes of each sample, `None` when missing or not diploid
+///
+/// Phased haplotypes are combined into unphased genotypes, as layout 1 has no phase.
+pub fn diploid_genotype_probabilities(data_block: &DataBlock) -> Vec<Option<[f64; 3]>> {
+    let max_proba = ((1u64 << data_block.bits_storage) - 1) as f64;
+    sample_probabilities(data_block)
+        .into_iter()
+        .map(|(ploidy_m, probas)| {
+            if ploidy_m & MISSING_FLAG != 0 || ploidy_m & PLOIDY_MASK != 2 || probas.len() != 2 {
+                return None;
+            }
+            let (p0, p1) = (probas[0] as f64 / max_proba, probas[1] as f64 / max_proba);
+            if data_block.phased {
+                // probability of the ref allele on each haplotype
+                Some([
+                    p0 * p1,
+                    p0 * (1.0 - p1) + (1.0 - p0) * p1,
+                    (1.0 - p0) * (1.0 - p1),
+                ])
+            } else {
+                Some([p0, p1, (1.0 - p0 - p1).max(0.0)])
+            }
+        })
+        .collect()
+}
+
+fn write_field_u16(writer: &mut impl Write, field: &str) -> Result<(), VcfError> {
+    writer.write_all(&(field.len() as u16).to_le_bytes())?;
+    writer.write_all(field.as_bytes())?;
+    Ok(())
+}
+
+/// Write a biallelic variant as a layout 1 block, with 16-bit probabilities compressed with zlib
+/// or left raw
+///
+/// Samples that are missing or not diploid have three zero probabilities.
+pub fn write_layout1_variant(
+    writer: &mut impl Write,
+    variant_data: &VariantData,
+    compression: BlockCompression,
+    zlib_level: u32,
+) -> Result<(), VcfError> {
+    if variant_data.alleles.len() != 2 {
+        return Err(VcfError::Validation(Report::msg(format!(
+            "Variant {} has {} alleles, bgen layout 1 only stores biallelic variants",
+            variant_data.variants_id,
+            variant_data.alleles.len()
+        ))));
+    }
+    let data_block = &variant_data.data_block;
+    writer.write_all(&data_block.number_individuals.to_le_bytes())?;
+    write_field_u16(writer, &variant_data.variants_id)?;
+    write_field_u16(writer, &variant_data.rsid)?;
+    write_field_u16(writer, &variant_data.chr)?;
+    writer.write_all(&variant_data.pos.to_le_bytes())?;
+    for allele in &variant_data.alleles {
+        writer.write_all(&(allele.len() as u32).to_le_bytes())?;
+        writer.write_all(allele.as_bytes())?;
+    }
+    let mut probabilities = Vec::with_capacity(data_block.number_individuals as usize * 6);
+    for genotypes in diploid_genotype_probabilities(data_block) {
+        for proba in genotypes.unwrap_or_default() {
+            let proba = (proba * LAYOUT1_SCALE).round().min(u16::MAX as f64) as u16;
+            probabilities.extend_from_slice(&proba.to_le_bytes());
+        }
+    }
+    if compression == BlockCompression::None {
+        writer.write_all(&probabilities)?;
+        return Ok(());
+    }
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::new(zlib_level));
+    encoder.write_all(&probabilities)?;
+    let compressed = encoder.finish()?;
+    writer.write_all(&(compressed.len() as u32).to_le_bytes())?;
+    writer.write_all(&compressed)?;
+    Ok(())
+}
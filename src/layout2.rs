@@ -0,0 +1,82 @@
+// Layout 2 variant blocks, compressed with zlib or zstd or left raw, encoded here so they can be
+// compressed on the worker pool
+use crate::{BlockCompression, VcfError};
+use bgen_reader::bgen::variant_data::{DataBlock, VariantData};
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::io::Write;
+
+// Compression level of zstd blocks, fast while still smaller than zlib
+const ZSTD_LEVEL: i32 = 3;
+
+/// Uncompressed layout 2 probability data: counts, ploidy bytes, then bit-packed probabilities
+pub fn encode_data_block(data_block: &DataBlock) -> Vec<u8> {
+    let num_bits = data_block.bits_storage as usize;
+    let mut encoded = Vec::with_capacity(
+        10 + data_block.ploidy_missingness.len() + data_block.probabilities.len() * num_bits / 8,
+    );
+    encoded.extend_from_slice(&data_block.number_individuals.to_le_bytes());
+    encoded.extend_from_slice(&data_block.number_alleles.to_le_bytes());
+    encoded.push(data_block.minimum_ploidy);
+    encoded.push(data_block.maximum_ploidy);
+    encoded.extend_from_slice(&data_block.ploidy_missingness);
+    encoded.push(data_block.phased as u8);
+    encoded.push(data_block.bits_storage);
+    // each value takes num_bits bits, least significant bit first
+    let mut packed = vec![0u8; (data_block.probabilities.len() * num_bits).div_ceil(8)];
+    let mut bit = 0;
+    for &value in &data_block.probabilities {
+        for i in 0..num_bits {
+            if (value >> i) & 1 == 1 {
+                packed[bit / 8] |= 1 << (bit % 8);
+            }
+            bit += 1;
+        }
+    }
+    encoded.extend(packed);
+    encoded
+}
+
+fn write_field_u16(writer: &mut impl Write, field: &str) -> Result<(), VcfError> {
+    writer.write_all(&(field.len() as u16).to_le_bytes())?;
+    writer.write_all(field.as_bytes())?;
+    Ok(())
+}
+
+/// Write a variant as a layout 2 block, compressed as the header says
+pub fn write_layout2_variant(
+    writer: &mut impl Write,
+    variant_data: &VariantData,
+    compression: BlockCompression,
+    zlib_level: u32,
+) -> Result<(), VcfError> {
+    write_field_u16(writer, &variant_data.variants_id)?;
+    write_field_u16(writer, &variant_data.rsid)?;
+    write_field_u16(writer, &variant_data.chr)?;
+    writer.write_all(&variant_data.pos.to_le_bytes())?;
+    writer.write_all(&(variant_data.alleles.len() as u16).to_le_bytes())?;
+    for allele in &variant_data.alleles {
+        writer.write_all(&(allele.len() as u32).to_le_bytes())?;
+        writer.write_all(allele.as_bytes())?;
+    }
+    let encoded = encode_data_block(&variant_data.data_block);
+    let compressed = match compression {
+        BlockCompression::None => {
+            // raw blocks have no uncompressed length
+            writer.write_all(&(encoded.len() as u32).to_le_bytes())?;
+            writer.write_all(&encoded)?;
+            return Ok(());
+        }
+        BlockCompression::Zlib => {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::new(zlib_level));
+            encoder.write_all(&encoded)?;
+            encoder.finish()?
+        }
+        BlockCompression::Zstd => zstd::bulk::compress(&encoded, ZSTD_LEVEL)?,
+    };
+    // the block length counts the uncompressed length that precedes the data
+    writer.write_all(&(compressed.len() as u32 + 4).to_le_bytes())?;
+    writer.write_all(&(encoded.len() as u32).to_le_bytes())?;
+    writer.write_all(&compressed)?;
+    Ok(())
+}
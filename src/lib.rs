@@ -1,25 +1,219 @@
+pub mod bgen_file;
+pub mod bgi;
+pub mod checkpoint;
+pub mod checksum;
+pub mod concordance;
+pub mod converter;
+pub mod dosage;
+pub mod duplicates;
+pub mod gen_file;
+#[cfg(feature = "htslib")]
+pub mod htslib_reader;
+pub mod info_filter;
+pub mod layout1;
+pub mod layout2;
+pub mod metrics;
+pub mod output_template;
+pub mod pgen;
+pub mod pipeline;
+pub mod ploidy;
+pub mod prelude;
+pub mod progress;
+pub mod provenance;
+pub mod regions;
+pub mod retry;
+pub mod rsid_annotation;
+pub mod sample_file;
+pub mod sample_names;
+pub mod sample_subset;
+pub mod skipped;
+pub mod thinning;
+pub mod variant_class;
+pub mod variant_ids;
+pub mod variant_list;
+pub mod variant_metadata;
+pub mod vcf_header;
+pub mod verify;
+
 use bgen_reader::bgen::bgen_stream::write_samples;
 use bgen_reader::bgen::header::{Header, HeaderFlags};
 use bgen_reader::bgen::variant_data::{DataBlock, VariantData};
+use checkpoint::{checkpoint_path, Checkpoint};
+use checksum::{HashingReader, InputDigest};
 use color_eyre::Report;
+use concordance::TruthComparison;
+use dosage::{AlleleCountWriter, DosageMatrixWriter};
 use flate2::read::MultiGzDecoder;
-use indicatif::ProgressBar;
-use nom::branch::alt;
-use nom::bytes::complete::{is_not, tag, take, take_while1};
-use nom::character::complete::{alpha0, alphanumeric0, char, tab};
-use nom::combinator::success;
-use nom::multi::{count, many0, separated_list0};
-use nom::sequence::{delimited, preceded, terminated};
-use nom::{IResult, InputIter};
-use std::fs::File;
-use std::io::{BufRead, BufReader, BufWriter};
-use std::time::Duration;
+use gen_file::GenOutput;
+use info_filter::InfoFilter;
+use metrics::ConversionMetrics;
+use nom::bytes::complete::{is_not, take_while1};
+use nom::character::complete::{char, tab};
+use nom::combinator::eof;
+use nom::multi::separated_list0;
+use nom::sequence::terminated;
+use nom::IResult;
+use output_template::{check_output_template, expand_input_stem};
+use pgen::PgenOutput;
+use pipeline::sink::{
+    BgenOutput, ChromosomeSplitOutput, PerSampleBgenOutput, RenamedSamples, ShardedOutput,
+    VariantSink,
+};
+use pipeline::source::{VariantSource, VcfSource};
+use pipeline::transform::{
+    ContigStyleTransform, FillMissingTransform, HardCallTransform, RoundingTransform,
+    RsidAnnotationTransform,
+};
+use pipeline::Pipeline;
+use ploidy::{PloidyMap, MAX_PLOIDY};
+use progress::Progress;
+use provenance::{hashes_input, header_provenance};
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use regions::{Regions, StartPosition};
+use retry::{RetryPolicy, RetryingReader};
+use rsid_annotation::RsidReference;
+use sample_names::bgen_sample_names;
+use std::cell::RefCell;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Instant;
+use thinning::Thinning;
+use tracing::{info, warn};
+use variant_class::VariantClassFilter;
+use variant_ids::VariantIdFilter;
+use variant_list::VariantListWriter;
+use variant_metadata::VariantMetadataWriter;
+use vcf_header::{parse_vcf_header, VcfHeader};
+use verify::VerifySink;
+
+// Line endings and separators left at the end of records by some tools (e.g. \r\n from Windows)
+const TRAILING_CHARS: [char; 4] = ['\n', '\r', '\t', ' '];
+
+// Bits of the ploidy/missingness byte of a sample in layout 2 blocks
+const PLOIDY_MASK: u8 = 0x3f;
+const MISSING_FLAG: u8 = 0x80;
+
+// Symbolic alleles used by gVCF files to represent any unobserved alternate allele
+const NON_REF_ALLELES: [&str; 2] = ["<NON_REF>", "<*>"];
+
+// Alt allele of a deletion spanning the position, whose carriers are described by another record
+const SPANNING_DELETION: &str = "*";
+// ALT of records without any alternate allele
+const NO_ALT: &str = ".";
+/// Allele standing for every alt but the most frequent one, with `--multiallelic collapse`
+pub const OTHER_ALLELES: &str = "<OTHER>";
+/// Alt allele written for monomorphic records when they are encoded
+pub const MONOMORPHIC_ALT: &str = "N";
+/// Zlib level of variant blocks when none is given, the flate2 default
+pub const DEFAULT_ZLIB_LEVEL: u32 = 6;
+/// Bits per probability read with `--num-bits auto`, each variant then keeping only what it needs
+pub const ADAPTIVE_MAX_NUM_BITS: u8 = 16;
+/// Most bits per probability bgen stores, used to read probabilities rounded later to a target error
+pub const MAX_NUM_BITS: u8 = 32;
 
+// Alleles written as bgen alleles, the others only appearing in genotypes
+fn is_written_alt(alt: &str, symbolic_alleles: SymbolicAllelePolicy) -> bool {
+    !NON_REF_ALLELES.contains(&alt)
+        && alt != SPANNING_DELETION
+        && (symbolic_alleles != SymbolicAllelePolicy::Skip || !is_symbolic_allele(alt))
+}
+
+/// Breakend alleles in bracket notation, such as `G]17:198982]`, which are not converted
+pub fn is_breakend_allele(alt: &str) -> bool {
+    alt.contains('[') || alt.contains(']')
+}
+
+/// Structural variant alleles such as `<DEL>` or `<DUP:TANDEM>`, gVCF `<NON_REF>` excepted
+pub fn is_symbolic_allele(alt: &str) -> bool {
+    alt.starts_with('<') && alt.ends_with('>') && !NON_REF_ALLELES.contains(&alt)
+}
+
+/// Errors of the conversion; new variants may be added in minor releases
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum VcfError {
     Io(std::io::Error),
     Nom(Report),
     Bgen(Report),
+    Validation(Report),
+    /// Counts that do not fit the fields of a bgen header
+    LimitExceeded(Report),
+    /// Failures writing the .bgi index of a bgen
+    Index(Report),
+    /// Conversion stopped by [`request_cancel`], as on Ctrl-C
+    Cancelled,
+}
+
+/// Exit code of an input or output failure
+pub const EXIT_IO: u8 = 3;
+/// Exit code of a malformed input
+pub const EXIT_PARSE: u8 = 4;
+/// Exit code of an input or option rejected by a check of the conversion
+pub const EXIT_VALIDATION: u8 = 5;
+/// Exit code of a failure encoding the bgen or its index
+pub const EXIT_OUTPUT: u8 = 6;
+/// Exit code of a cancelled conversion, as a shell reports a process stopped by SIGINT
+pub const EXIT_CANCELLED: u8 = 130;
+
+impl VcfError {
+    /// Exit code of the command line for this class of failure, 1 being left to anything else
+    /// and 2 to invalid arguments
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            VcfError::Io(_) => EXIT_IO,
+            VcfError::Nom(_) => EXIT_PARSE,
+            VcfError::Validation(_) | VcfError::LimitExceeded(_) => EXIT_VALIDATION,
+            VcfError::Bgen(_) | VcfError::Index(_) => EXIT_OUTPUT,
+            VcfError::Cancelled => EXIT_CANCELLED,
+        }
+    }
+}
+
+static CANCEL_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Stop the running conversion at its next record, failing with [`VcfError::Cancelled`] once its
+/// partial outputs are removed; safe to call from a signal handler
+pub fn request_cancel() {
+    CANCEL_REQUESTED.store(true, Ordering::Relaxed);
+}
+
+/// Whether [`request_cancel`] was called
+pub fn cancel_requested() -> bool {
+    CANCEL_REQUESTED.load(Ordering::Relaxed)
+}
+
+pub(crate) fn check_cancelled() -> Result<(), VcfError> {
+    match cancel_requested() {
+        true => Err(VcfError::Cancelled),
+        false => Ok(()),
+    }
+}
+
+// Pool of the conversions run with this number of threads
+static WORKER_POOL: Mutex<Option<(Option<u32>, Arc<ThreadPool>)>> = Mutex::new(None);
+
+/// Threads shared by record parsing and block compression: `threads` of them, or every core
+/// available to the process, built once and reused by every conversion asking for as many
+pub fn worker_pool(threads: Option<u32>) -> Result<Arc<ThreadPool>, VcfError> {
+    if threads == Some(0) {
+        return Err(VcfError::Validation(Report::msg("--threads is at least 1")));
+    }
+    let mut worker_pool = WORKER_POOL.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some((pool_threads, pool)) = worker_pool.as_ref() {
+        if *pool_threads == threads {
+            return Ok(pool.clone());
+        }
+    }
+    // no thread count uses every core the process may run on
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(threads.unwrap_or_default() as usize)
+        .build()
+        .map_err(|error| VcfError::Validation(Report::msg(error.to_string())))?;
+    let pool = Arc::new(pool);
+    *worker_pool = Some((threads, pool.clone()));
+    Ok(pool)
 }
 
 impl From<std::io::Error> for VcfError {
@@ -34,80 +228,1264 @@ impl From<Report> for VcfError {
     }
 }
 
+impl From<rusqlite::Error> for VcfError {
+    fn from(error: rusqlite::Error) -> Self {
+        VcfError::Index(Report::msg(format!("Index error: {}", error)))
+    }
+}
+
 impl From<nom::Err<nom::error::Error<&str>>> for VcfError {
     fn from(error: nom::Err<nom::error::Error<&str>>) -> Self {
         VcfError::Nom(Report::msg(format!("Nom Error: {:?}", error)))
     }
 }
 
+/// Settings controlling how genotypes are encoded in the bgen output
+#[derive(Debug, Clone)]
+pub struct ConversionOptions {
+    /// Number of bits used for probability storage
+    pub num_bits: u8,
+    /// Store each variant with the fewest bits, up to `num_bits`, that keep its probabilities exact
+    pub adaptive_num_bits: bool,
+    /// Largest rounding error of GP and DS probabilities, picking `num_bits` instead
+    pub max_prob_error: Option<f64>,
+    /// Write per-haplotype probabilities with the phased flag set
+    pub phased: bool,
+    /// Write records whose GTs are all phased as phased haplotypes, unless `phased` is set
+    pub auto_phase: bool,
+    /// Fail on any genotype that is unphased or missing
+    pub strict_phasing: bool,
+    /// Handling of gVCF reference blocks (records whose only alt is `<NON_REF>`)
+    pub ref_blocks: RefBlockMode,
+    /// Buffer records and write them grouped by chromosome when the input interleaves chromosomes
+    pub regroup_chromosomes: bool,
+    /// Library used to read the input
+    pub backend: ReadBackend,
+    /// Region read through the csi/tbi index of the input (htslib backend only)
+    pub fetch_region: Option<String>,
+    /// Convert only records in these comma-separated chromosomes or intervals (e.g.
+    /// `22:16000000-18000000,21`), read from the whole input with any backend
+    pub regions: Option<String>,
+    /// BED file of the intervals to convert (e.g. exome targets), instead of `regions`
+    pub regions_file: Option<String>,
+    /// Convert only the variants listed in this file, by input ID or chr:pos:ref:alt
+    pub extract: Option<String>,
+    /// Leave out the variants listed in this file, by input ID or chr:pos:ref:alt
+    pub exclude: Option<String>,
+    /// Additional bgen files written from the same pass, each with its own precision
+    pub extra_outputs: Vec<ExtraOutput>,
+    /// Gzipped tsv of alt allele dosages (variants x samples) written alongside the bgen
+    pub dosage_matrix: Option<String>,
+    /// Largest number of variants allowed in the dosage matrix
+    pub dosage_matrix_max_variants: u32,
+    /// Convert vcf files without sample columns, writing variants without genotypes
+    pub allow_sites_only: bool,
+    /// FORMAT field holding alt allele dosages (e.g. DS), used instead of GP and GT
+    pub dosage_field: Option<String>,
+    /// Derive probabilities from PL or GL likelihoods with this prior, for records without GP
+    pub genotype_likelihoods: Option<GenotypePrior>,
+    /// Contig naming style of the output, converted from the input style when they differ
+    pub target_style: Option<ContigStyle>,
+    /// Seed of every random choice, drawn from the clock by `Converter::run` when unset
+    pub seed: Option<u64>,
+    /// Provenance manifest written after the conversion
+    pub manifest: Option<String>,
+    /// Json summary of the counts, skips, missingness and durations written after the conversion
+    pub summary_json: Option<String>,
+    /// Tab-separated CHROM POS REF ALT RSID reference filling the rsIDs of variants without an ID,
+    /// those with one writing it as their rsID
+    pub annotate_rsid: Option<String>,
+    /// Retries of transient read errors on the input
+    pub read_retry: RetryPolicy,
+    /// Whitespace-separated SAMPLE CHROM FROM TO PLOIDY rows giving the ploidy written per region
+    pub ploidy_map: Option<String>,
+    /// Tab-separated SAMPLE M/F rows: chrX of males outside PARs is written haploid, het calls missing
+    pub sex_file: Option<String>,
+    /// Build of the input, whose pseudo-autosomal regions stay diploid for males
+    pub genome_build: GenomeBuild,
+    /// Split multiallelic records into biallelic variants, or keep them whole
+    pub multiallelic: MultiallelicMode,
+    /// Encoding of the spanning deletion allele `*` in genotypes
+    pub spanning_deletion: SpanningDeletionPolicy,
+    /// Handling of symbolic alleles such as `<DEL>`
+    pub symbolic_alleles: SymbolicAllelePolicy,
+    /// Directory of the bgen files being written, moved to their path once complete, and of the
+    /// checkpoint; next to each output when unset. Regrouped chromosomes are held in memory
+    pub tmp_dir: Option<String>,
+    /// Tsv listing every allele or record left out of the bgen, with its input line and the reason
+    pub skipped_report: Option<String>,
+    /// Handling of records without alternate allele (ALT is `.`)
+    pub monomorphic: MonomorphicPolicy,
+    /// Handling of variants repeating the chr:pos:ref:alt of an earlier one
+    pub dup_policy: DuplicatePolicy,
+    /// Handling of sample IDs repeated in the vcf header, which bgen readers mix up
+    pub dedup_samples: SampleDedupPolicy,
+    /// Genotypes with a lower FORMAT/GQ are written as missing
+    pub min_gq: Option<u32>,
+    /// Leave out biallelic variants whose minor allele is carried fewer times than this, after
+    /// splitting multiallelic records
+    pub min_mac: Option<u32>,
+    /// Leave out records whose FILTER is neither `PASS` nor `.`
+    pub pass_only: bool,
+    /// Write only SNPs: variants whose alleles are all single A, C, G or T
+    pub snps_only: bool,
+    /// Write only indels: variants whose alleles are base sequences of different lengths
+    pub indels_only: bool,
+    /// Leave out variants with an allele longer than this
+    pub max_allele_len: Option<u32>,
+    /// Write only variants whose INFO passes this expression, e.g. `AF>0.001 && INFO/R2>=0.8`
+    pub info_filter: Option<String>,
+    /// Leave out variants whose every sample is missing, after splitting multiallelic records
+    pub drop_all_missing: bool,
+    /// Keep every nth variant of those passing the other filters
+    pub thin: Option<u32>,
+    /// Keep variants at least this many bases from the last one kept on their chromosome
+    pub thin_bp: Option<u32>,
+    /// Keep each variant with this probability, drawn from the seed in input order
+    pub sample_variants: Option<f64>,
+    /// Stop after writing this many variants, the headers declaring those written
+    pub max_variants: Option<u32>,
+    /// Leave out the first variants written otherwise, to restart a run or split it into jobs
+    pub skip_variants: Option<u32>,
+    /// Leave out the records before the first one at this `chr:pos`, in input order
+    pub start_position: Option<String>,
+    /// Threads parsing records and compressing variant blocks, all available cores when unset
+    pub threads: Option<u32>,
+    /// Draw no progress bar, logging progress as `tracing` events like every other message
+    pub quiet: bool,
+    /// How progress is shown, JSON lines being written even when quiet
+    pub progress_format: ProgressFormat,
+    /// Genotypes with a lower FORMAT/DP are written as missing
+    pub min_dp: Option<u32>,
+    /// Genotypes with a higher FORMAT/DP are written as missing
+    pub max_dp: Option<u32>,
+    /// FORMAT fields to write genotypes from, the first one a record has being used
+    ///
+    /// When empty, GP takes precedence over GT, and records without either use DS.
+    pub gt_priority: Vec<GenotypeField>,
+    /// Probabilities become hard calls where the most likely genotype exceeds this, missing elsewhere
+    pub hard_call_threshold: Option<f64>,
+    /// Writing of missing genotypes, kept missing by default
+    pub fill_missing: FillMissing,
+    /// Bgen layout of the bgen and extra outputs: 2, or 1 for older tools (biallelic, diploid,
+    /// 16-bit probabilities); per-sample outputs stay in layout 2
+    pub layout: u8,
+    /// Compression of the variant blocks of the bgen and extra outputs
+    pub compression: BlockCompression,
+    /// Zlib level of the variant blocks, 1 (fastest) to 9 (smallest), the writer's default if unset
+    pub compression_level: Option<u32>,
+    /// Write a bgenix .bgi index next to the bgen and extra outputs
+    pub write_index: bool,
+    /// Write the md5 and sha256 of each bgen output next to it, hashed while it is written
+    pub checksums: bool,
+    /// Byte-identical outputs for identical inputs and options: blocks compressed here at a fixed
+    /// level, index timestamps left out and no seed drawn from the clock
+    pub deterministic: bool,
+    /// Read the input once, patching the variant count of headers after the last variant; records
+    /// then follow their own GT phasing, as whole-input phasing needs a counting pass
+    pub single_pass: bool,
+    /// Describe the conversion (tool, command line, input sha256 and time) in the free data area of
+    /// bgen headers
+    pub provenance: bool,
+    /// Command line recorded in the provenance, when run from the binary
+    pub command_line: Option<String>,
+    /// Text of the free data area of bgen headers, filled by `build_pipeline` with the provenance
+    pub free_data: Option<String>,
+    /// Sha256 of the input hashed by the counting pass, written in the provenance of headers so
+    /// they are complete once written
+    pub input_sha256: Option<String>,
+    /// Format of the main output; extra outputs are always bgen
+    pub output_format: OutputFormat,
+    /// Write one bgen per chromosome, the output path being a template containing `{chr}`
+    pub split_by_chr: bool,
+    /// Write sample identifiers in the bgen, or leave the sample block out
+    pub sample_ids: bool,
+    /// Tab-separated OLD NEW sample names, the new names being written in the bgen sample block
+    pub rename_samples: Option<String>,
+    /// Write sequential pseudonyms in the bgen sample block, and each sample's pseudonym to this tsv
+    pub anonymize: Option<String>,
+    /// Write only the samples of this list (one ID, or FID IID, per line)
+    pub keep_samples: Option<String>,
+    /// Leave out the samples of this list
+    pub remove_samples: Option<String>,
+    /// .sample file whose order the samples are written in, to match another dataset
+    pub sample_order: Option<String>,
+    /// Spread the variants over this many bgen files, listed in a tsv next to them
+    pub shards: Option<u32>,
+    /// Assignment of variants to shards
+    pub shard_mode: ShardMode,
+    /// Encoding of genotypes without any called allele
+    pub missing_policy: MissingPolicy,
+    /// Encoding of genotypes with some alleles missing
+    pub half_call_policy: HalfCallPolicy,
+    /// Largest ploidy accepted in GT, up to the 63 stored by bgen
+    pub max_ploidy: u8,
+    /// Tsv of AC, AN and AF recomputed from the written genotypes
+    pub allele_counts: Option<String>,
+    /// Variants written, in .bim columns or .pvar ones when the path ends in `.pvar`
+    pub variant_list: Option<String>,
+    /// Position, ids, alleles, alt frequency, missingness and input line of every variant written,
+    /// as a tsv or a json array when the path ends in `.json`
+    pub variant_metadata: Option<String>,
+    /// Save a checkpoint next to the output every this many variants
+    pub checkpoint_every: Option<u32>,
+    /// Continue the interrupted conversion saved in the checkpoint next to the output
+    pub resume: bool,
+    /// Replace outputs that already exist, which the command line only does with --force
+    pub overwrite: bool,
+    /// Decode this many random variants of the finished bgen and compare them with those converted
+    pub verify: Option<u32>,
+    /// Directory receiving one single-sample bgen per sample, written from the same pass
+    pub per_sample_dir: Option<String>,
+    /// Largest number of per-sample bgen files kept open, the others being appended in batches
+    pub max_open_files: usize,
+    /// Truth vcf that converted genotypes are compared against
+    pub truth_vcf: Option<String>,
+    /// Tsv of concordance and r2 with the truth vcf, by minor allele frequency bin
+    pub concordance_report: Option<String>,
+}
+
+/// A second bgen written alongside the main output, typically at a lower precision
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtraOutput {
+    pub path: String,
+    pub num_bits: u8,
+}
+
+/// Library used to decode the input file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum ReadBackend {
+    /// Built-in parser for gzipped or bgzipped vcf
+    #[default]
+    Native,
+    /// htslib, reading vcf and bcf (requires the `htslib` feature)
+    Htslib,
+}
+
+/// What to do with gVCF reference blocks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum RefBlockMode {
+    /// Do not write reference blocks
+    #[default]
+    Skip,
+    /// Write one monomorphic variant per position covered by the block
+    Expand,
+}
+
+/// Writing of records with several alt alleles
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum MultiallelicMode {
+    /// One biallelic variant per alt allele
+    #[default]
+    Split,
+    /// One variant with every allele and the probabilities of every genotype, as qctool writes
+    Keep,
+    /// One variant with the ref, the most frequent alt and the other alts merged as `<OTHER>`
+    Collapse,
+}
+
+impl MultiallelicMode {
+    /// Whether a record becomes a single variant, with more than two alleles when it has several alts
+    pub fn is_whole(self) -> bool {
+        self != MultiallelicMode::Split
+    }
+}
+
+/// Handling of symbolic structural variant alleles (`<DEL>`, `<DUP>`, `<CNV>`...)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum SymbolicAllelePolicy {
+    /// Leave them out, their carriers being missing, and list them in the skipped-variant report
+    #[default]
+    Skip,
+    /// Write them as alleles named after the symbol
+    Encode,
+    /// Fail on the first one
+    Error,
+}
+
+/// Handling of monomorphic records, whose ALT is `.`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum MonomorphicPolicy {
+    /// Leave them out, listing them in the skipped-variant report
+    #[default]
+    Skip,
+    /// Write them as biallelic variants whose alt is the placeholder `N`
+    Encode,
+}
+
+/// Handling of sample IDs repeated in the vcf header
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum SampleDedupPolicy {
+    /// Fail on the first repeated ID
+    #[default]
+    Error,
+    /// Number each occurrence of a repeated ID, as `ID_1`, `ID_2`
+    Suffix,
+}
+
+/// Handling of variants with the chr:pos:ref:alt of an earlier one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum DuplicatePolicy {
+    /// Fail on the first duplicate
+    Error,
+    /// Write the first variant, leaving out its duplicates
+    KeepFirst,
+    /// Write every variant, duplicates included
+    #[default]
+    KeepAll,
+}
+
+/// Encoding of the spanning deletion allele `*`, which is never written as a variant of its own
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum SpanningDeletionPolicy {
+    /// Carriers of `*` are missing in the variants of the other alleles
+    #[default]
+    Missing,
+    /// `*` is dropped, its carriers having the reference allele in the variants of the other alleles
+    Drop,
+}
+
+/// Encoding of genotypes without any called allele (`./.`, `.`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum MissingPolicy {
+    /// Flag the sample as missing
+    #[default]
+    Missing,
+    /// Call the reference allele on every chromosome
+    Ref,
+}
+
+/// Encoding of genotypes with some alleles called and others not (`./1`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum HalfCallPolicy {
+    /// Flag the sample as missing
+    #[default]
+    Missing,
+    /// Keep the called alleles only, lowering the ploidy (`./1` as haploid `1`)
+    Haploid,
+    /// Call the reference allele in place of the missing alleles (`./1` as `0/1`)
+    Ref,
+}
+
+/// Writing of missing genotypes, for tools that do not read the missingness flag
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum FillMissing {
+    /// Keep them missing
+    #[default]
+    None,
+    /// Write them as homozygous reference
+    Ref,
+    /// Write them with the mean alt dosage of the called samples
+    MeanDosage,
+}
+
+/// Compression of the variant blocks of bgen outputs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum BlockCompression {
+    /// zlib, readable by every bgen tool
+    #[default]
+    Zlib,
+    /// zstd, smaller and faster to write, for layout 2 readers of bgen 1.3
+    Zstd,
+    /// Raw blocks, for readers that memory-map the file
+    None,
+}
+
+/// File format of the main output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum OutputFormat {
+    #[default]
+    Bgen,
+    /// PLINK 2 .pgen/.pvar/.psam hard calls, for biallelic variants
+    Pgen,
+    /// Oxford .gen text with a .sample file, for biallelic diploid variants
+    Gen,
+}
+
+/// How the progress of the conversion is shown on stderr
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum ProgressFormat {
+    /// A bar on a terminal, status lines otherwise
+    #[default]
+    Text,
+    /// One JSON object per line with the variants done, their total and the seconds left, for
+    /// workflow managers and web pages
+    Jsonl,
+}
+
+/// Assignment of variants to the shards of `--shards`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum ShardMode {
+    /// Variant k goes to shard k modulo the number of shards
+    #[default]
+    RoundRobin,
+    /// Consecutive runs of variants, keeping each shard in genomic order
+    Block,
+}
+
+/// FORMAT fields genotypes can be written from, ordered by `--gt-priority`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum GenotypeField {
+    /// Hard calls
+    Gt,
+    /// Genotype probabilities
+    Gp,
+    /// Alt allele dosages
+    Ds,
+}
+
+/// Prior on genotypes when deriving probabilities from PL or GL likelihoods
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum GenotypePrior {
+    /// Every genotype equally likely
+    Flat,
+    /// Hardy-Weinberg frequencies from INFO/AF, flat for records without AF
+    AlleleFrequency,
+}
+
+/// Reference genome of the input, locating the pseudo-autosomal regions of chrX
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum GenomeBuild {
+    /// GRCh37 / hg19
+    Grch37,
+    /// GRCh38 / hg38
+    #[default]
+    Grch38,
+}
+
+impl GenomeBuild {
+    /// PAR1 and PAR2, as inclusive ranges of chrX positions
+    pub fn par_regions(self) -> [(u32, u32); 2] {
+        match self {
+            GenomeBuild::Grch37 => [(60_001, 2_699_520), (154_931_044, 155_260_560)],
+            GenomeBuild::Grch38 => [(10_001, 2_781_479), (155_701_383, 156_030_895)],
+        }
+    }
+}
+
+/// Naming of contigs, `chr1` in UCSC style and `1` in Ensembl style
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ContigStyle {
+    Ucsc,
+    Ensembl,
+}
+
+impl ContigStyle {
+    pub fn of(chr: &str) -> Self {
+        if chr.starts_with("chr") {
+            ContigStyle::Ucsc
+        } else {
+            ContigStyle::Ensembl
+        }
+    }
+
+    /// Style shared by every contig, `None` for no contigs or a mixture
+    pub fn detect<'a>(contigs: impl IntoIterator<Item = &'a str>) -> Option<Self> {
+        let mut styles = contigs.into_iter().map(ContigStyle::of);
+        let first = styles.next()?;
+        styles.all(|style| style == first).then_some(first)
+    }
+
+    /// Name of `chr` in this style, with the mitochondrial `chrM` and `MT` mapped to each other
+    pub fn rename(self, chr: &str) -> String {
+        match (self, chr.strip_prefix("chr")) {
+            (ContigStyle::Ucsc, Some(_)) => chr.to_string(),
+            (ContigStyle::Ucsc, None) if chr == "MT" => "chrM".to_string(),
+            (ContigStyle::Ucsc, None) => format!("chr{}", chr),
+            (ContigStyle::Ensembl, Some("M")) => "MT".to_string(),
+            (ContigStyle::Ensembl, Some(name)) => name.to_string(),
+            (ContigStyle::Ensembl, None) => chr.to_string(),
+        }
+    }
+}
+
+impl Default for ConversionOptions {
+    fn default() -> Self {
+        ConversionOptions {
+            num_bits: 8,
+            adaptive_num_bits: false,
+            max_prob_error: None,
+            phased: false,
+            auto_phase: true,
+            strict_phasing: false,
+            ref_blocks: RefBlockMode::Skip,
+            regroup_chromosomes: false,
+            backend: ReadBackend::Native,
+            fetch_region: None,
+            regions: None,
+            regions_file: None,
+            extract: None,
+            exclude: None,
+            extra_outputs: vec![],
+            dosage_matrix: None,
+            dosage_matrix_max_variants: 10_000,
+            allow_sites_only: false,
+            dosage_field: None,
+            genotype_likelihoods: None,
+            target_style: None,
+            seed: None,
+            manifest: None,
+            summary_json: None,
+            annotate_rsid: None,
+            read_retry: RetryPolicy::default(),
+            ploidy_map: None,
+            sex_file: None,
+            genome_build: GenomeBuild::default(),
+            multiallelic: MultiallelicMode::default(),
+            spanning_deletion: SpanningDeletionPolicy::default(),
+            symbolic_alleles: SymbolicAllelePolicy::default(),
+            tmp_dir: None,
+            skipped_report: None,
+            monomorphic: MonomorphicPolicy::default(),
+            dup_policy: DuplicatePolicy::default(),
+            dedup_samples: SampleDedupPolicy::default(),
+            min_gq: None,
+            min_mac: None,
+            pass_only: false,
+            snps_only: false,
+            indels_only: false,
+            max_allele_len: None,
+            info_filter: None,
+            drop_all_missing: false,
+            thin: None,
+            thin_bp: None,
+            sample_variants: None,
+            max_variants: None,
+            skip_variants: None,
+            start_position: None,
+            threads: None,
+            quiet: false,
+            progress_format: ProgressFormat::default(),
+            min_dp: None,
+            max_dp: None,
+            gt_priority: vec![],
+            hard_call_threshold: None,
+            fill_missing: FillMissing::default(),
+            layout: 2,
+            compression: BlockCompression::default(),
+            compression_level: None,
+            write_index: false,
+            checksums: false,
+            deterministic: false,
+            single_pass: false,
+            provenance: false,
+            command_line: None,
+            free_data: None,
+            input_sha256: None,
+            output_format: OutputFormat::default(),
+            split_by_chr: false,
+            sample_ids: true,
+            rename_samples: None,
+            anonymize: None,
+            keep_samples: None,
+            remove_samples: None,
+            sample_order: None,
+            shards: None,
+            shard_mode: ShardMode::default(),
+            missing_policy: MissingPolicy::default(),
+            half_call_policy: HalfCallPolicy::default(),
+            max_ploidy: MAX_PLOIDY,
+            allele_counts: None,
+            variant_list: None,
+            variant_metadata: None,
+            checkpoint_every: None,
+            resume: false,
+            overwrite: true,
+            verify: None,
+            per_sample_dir: None,
+            max_open_files: 256,
+            truth_vcf: None,
+            concordance_report: None,
+        }
+    }
+}
+
+impl ConversionOptions {
+    /// Preset for phased reference panels: 1-bit haplotypes, every genotype phased and called
+    pub fn reference_panel() -> Self {
+        ConversionOptions {
+            num_bits: 1,
+            phased: true,
+            strict_phasing: true,
+            ..Default::default()
+        }
+    }
+
+    /// Preset for legacy tools such as qctool v1 and SNPTEST v2: layout 1 blocks with zlib
+    /// compression and 16-bit unphased probabilities
+    pub fn legacy() -> Self {
+        ConversionOptions {
+            num_bits: 16,
+            layout: 1,
+            auto_phase: false,
+            ..Default::default()
+        }
+    }
+
+    /// Whether probabilities come from dosages, likelihoods or a field preferred to GT
+    pub fn reads_probabilities(&self) -> bool {
+        self.dosage_field.is_some()
+            || self.genotype_likelihoods.is_some()
+            || self
+                .gt_priority
+                .first()
+                .is_some_and(|&field| field != GenotypeField::Gt)
+    }
+}
+
 // Wrapper type for variant data, with added genotype represented as vcf string
 pub struct VariantDataToParse<'a> {
     variant_data: VariantData,
     geno_string_vcf: Vec<&'a str>,
+    // FORMAT/GP of each sample, when the record has it
+    gp_string_vcf: Option<Vec<&'a str>>,
+    // FORMAT field holding dosages of each sample, when one was selected
+    dosage_string_vcf: Option<Vec<&'a str>>,
+    // FORMAT/GL, or FORMAT/PL when phred-scaled, with the prior of each genotype
+    likelihood_string_vcf: Option<(Vec<&'a str>, bool)>,
+    genotype_prior: Vec<f64>,
+    // expected ploidy of each sample at this position, empty without a ploidy map
+    sample_ploidy: Vec<Option<u8>>,
+    // every GT of the record is phased, and it has no GP
+    phased_genotypes: bool,
+    missing_policy: MissingPolicy,
+    half_call_policy: HalfCallPolicy,
+    spanning_deletion: SpanningDeletionPolicy,
+    symbolic_alleles: SymbolicAllelePolicy,
+    // samples whose genotype fails the quality thresholds, written missing
+    masked_samples: Vec<bool>,
+    format: &'a str,
+    sample_fields: Vec<&'a str>,
+    info: &'a str,
+}
+
+impl<'a> VariantDataToParse<'a> {
+    /// Chromosome, position and reference allele of the record
+    pub fn site(&self) -> (&str, u32, &str) {
+        let variant_data = &self.variant_data;
+        (
+            &variant_data.chr,
+            variant_data.pos,
+            &variant_data.alleles[0],
+        )
+    }
+
+    /// Whether the record has no alternate allele, its ALT being `.`
+    pub fn is_monomorphic(&self) -> bool {
+        self.variant_data.alleles[1] == NO_ALT
+    }
+
+    /// Write the placeholder alt in place of the `.` of a monomorphic record
+    pub fn set_monomorphic_alt(&mut self) {
+        self.variant_data.alleles[1] = MONOMORPHIC_ALT.to_string();
+    }
+
+    /// First breakend allele of the record, which is then skipped
+    pub fn breakend_allele(&self) -> Option<&str> {
+        self.variant_data.alleles[1]
+            .split(',')
+            .find(|alt| is_breakend_allele(alt))
+    }
+
+    /// Whether the record could be written phased, all of its GTs using `|` and it having no GP
+    pub fn phased_genotypes(&self) -> bool {
+        self.phased_genotypes
+    }
+
+    /// Keep the genotypes of the samples in `columns` only, in that order
+    pub fn select_samples(&mut self, columns: &[usize]) {
+        let select = |fields: &[&'a str]| columns.iter().map(|&i| fields[i]).collect();
+        self.geno_string_vcf = select(&self.geno_string_vcf);
+        self.gp_string_vcf = self.gp_string_vcf.as_deref().map(select);
+        self.sample_fields = select(&self.sample_fields);
+        let number_individuals = columns.len() as u32;
+        self.variant_data.number_individuals = Some(number_individuals);
+        self.variant_data.data_block.number_individuals = number_individuals;
+    }
+
+    pub fn set_phased(&mut self, phased: bool) {
+        self.variant_data.data_block.phased = phased;
+    }
+
+    /// Read alt allele dosages from this FORMAT field, records without it keep GP or GT
+    pub fn set_dosage_field(&mut self, dosage_field: &str) {
+        self.dosage_string_vcf = format_subfield(self.format, dosage_field, &self.sample_fields);
+    }
+
+    /// Write genotypes from the first field of `priority` in FORMAT, ignoring the others
+    pub fn set_field_priority(&mut self, priority: &[GenotypeField]) {
+        let key = |field| match field {
+            GenotypeField::Gt => "GT",
+            GenotypeField::Gp => "GP",
+            GenotypeField::Ds => "DS",
+        };
+        let Some(field) = priority
+            .iter()
+            .find(|&&field| self.format.split(':').any(|s| s == key(field)))
+        else {
+            return;
+        };
+        match field {
+            GenotypeField::Gt => {
+                self.gp_string_vcf = None;
+                self.dosage_string_vcf = None;
+                self.likelihood_string_vcf = None;
+            }
+            GenotypeField::Gp => self.dosage_string_vcf = None,
+            GenotypeField::Ds => {
+                self.gp_string_vcf = None;
+                self.dosage_string_vcf = format_subfield(self.format, "DS", &self.sample_fields);
+            }
+        }
+    }
+
+    /// Whether FORMAT has GT, records without it being written from GP or DS
+    pub fn has_hard_calls(&self) -> bool {
+        self.format.split(':').any(|s| s == "GT")
+    }
+
+    /// Encode uncalled and half-called genotypes as the policies say
+    pub fn set_call_policies(
+        &mut self,
+        missing_policy: MissingPolicy,
+        half_call_policy: HalfCallPolicy,
+    ) {
+        self.missing_policy = missing_policy;
+        self.half_call_policy = half_call_policy;
+    }
+
+    /// Encode `*` alleles of genotypes as the policy says
+    pub fn set_spanning_deletion_policy(&mut self, spanning_deletion: SpanningDeletionPolicy) {
+        self.spanning_deletion = spanning_deletion;
+    }
+
+    /// Skip, write or reject symbolic alleles as the policy says
+    pub fn set_symbolic_allele_policy(&mut self, symbolic_alleles: SymbolicAllelePolicy) {
+        self.symbolic_alleles = symbolic_alleles;
+    }
+
+    /// Write genotypes whose GQ is below `min_gq` as missing
+    pub fn set_min_gq(&mut self, min_gq: u32) {
+        self.mask_samples_by("GQ", |gq| gq >= min_gq as f64);
+    }
+
+    /// Write genotypes whose DP is outside `min_dp..=max_dp` as missing
+    pub fn set_depth_range(&mut self, min_dp: Option<u32>, max_dp: Option<u32>) {
+        self.mask_samples_by("DP", |dp| {
+            min_dp.is_none_or(|min| dp >= min as f64) && max_dp.is_none_or(|max| dp <= max as f64)
+        });
+    }
+
+    // Mask the samples whose `key` value fails `keep`, samples without a value being kept
+    fn mask_samples_by(&mut self, key: &str, keep: impl Fn(f64) -> bool) {
+        let Some(values) = format_subfield(self.format, key, &self.sample_fields) else {
+            return;
+        };
+        self.masked_samples.resize(values.len(), false);
+        for (masked, value) in self.masked_samples.iter_mut().zip(values) {
+            if let Ok(value) = value.parse::<f64>() {
+                *masked |= !keep(value);
+            }
+        }
+    }
+
+    /// Write each sample with the ploidy the map gives at this position
+    pub fn set_sample_ploidy(&mut self, ploidy_map: &PloidyMap) {
+        let variant_data = &self.variant_data;
+        self.sample_ploidy = ploidy_map.sample_ploidy(
+            &variant_data.chr,
+            variant_data.pos,
+            self.geno_string_vcf.len(),
+        );
+    }
+
+    /// Derive probabilities from GL, or else PL, for records without GP
+    pub fn set_genotype_likelihoods(&mut self, prior: GenotypePrior) {
+        self.likelihood_string_vcf = format_subfield(self.format, "GL", &self.sample_fields)
+            .map(|gl| (gl, false))
+            .or_else(|| {
+                format_subfield(self.format, "PL", &self.sample_fields).map(|pl| (pl, true))
+            });
+        let num_alleles = self.variant_data.alleles[1].split(',').count() + 1;
+        self.genotype_prior = match prior {
+            GenotypePrior::Flat => vec![],
+            GenotypePrior::AlleleFrequency => allele_frequency_prior(self.info, num_alleles),
+        };
+    }
+}
+
+// Tracks chromosomes in file order, to detect inputs that come back to an earlier chromosome
+#[derive(Default)]
+pub struct ChromosomeOrder {
+    seen: Vec<String>,
+}
+
+impl ChromosomeOrder {
+    pub fn observe(&mut self, chr: &str, record_num: u32) -> Result<(), VcfError> {
+        if self.seen.last().map(|last| last.as_str()) == Some(chr) {
+            return Ok(());
+        }
+        if self.seen.iter().any(|seen_chr| seen_chr == chr) {
+            return Err(VcfError::Validation(Report::msg(format!(
+                "Record {} goes back to chromosome {} after chromosome {}: the input is not grouped by chromosome (use --regroup-chromosomes)",
+                record_num,
+                chr,
+                self.seen.last().unwrap()
+            ))));
+        }
+        self.seen.push(chr.to_string());
+        Ok(())
+    }
+}
+
+/// Open the input as vcf text, with the backend selected in `options`
+pub fn open_vcf(input: &str, options: &ConversionOptions) -> Result<Box<dyn BufRead>, VcfError> {
+    open_hashed_vcf(input, options, None)
+}
+
+/// Open `input` as [`open_vcf`] does, the compressed bytes read being added to `digest`
+///
+/// The htslib backend reads the file itself, so its bytes are left for the digest to read.
+pub fn open_hashed_vcf(
+    input: &str,
+    options: &ConversionOptions,
+    digest: Option<Rc<RefCell<InputDigest>>>,
+) -> Result<Box<dyn BufRead>, VcfError> {
+    match options.backend {
+        ReadBackend::Native => {
+            if options.fetch_region.is_some() {
+                return Err(VcfError::Validation(Report::msg(
+                    "Index region queries require the htslib backend",
+                )));
+            }
+            let reader = RetryingReader::open(input, options.read_retry)?;
+            Ok(match digest {
+                Some(digest) => Box::new(BufReader::new(MultiGzDecoder::new(HashingReader::new(
+                    reader, digest,
+                )))),
+                None => Box::new(BufReader::new(MultiGzDecoder::new(reader))),
+            })
+        }
+        #[cfg(feature = "htslib")]
+        ReadBackend::Htslib => Ok(Box::new(BufReader::new(htslib_reader::open_htslib(
+            input,
+            options.fetch_region.as_deref(),
+        )?))),
+        #[cfg(not(feature = "htslib"))]
+        ReadBackend::Htslib => Err(VcfError::Validation(Report::msg(
+            "The htslib backend is not available, rebuild with `--features htslib`",
+        ))),
+    }
 }
 
 pub fn count_variants(input: &str) -> Result<(u32, u32), VcfError> {
-    let mut reader = BufReader::new(MultiGzDecoder::new(File::open(input)?));
+    count_variants_with_options(input, &ConversionOptions::default())
+}
+
+pub fn count_variants_with_options(
+    input: &str,
+    options: &ConversionOptions,
+) -> Result<(u32, u32), VcfError> {
+    let scan = scan_variants(input, options)?;
+    Ok((scan.variant_num, scan.number_geno_line))
+}
+
+/// What the first pass over the input learns about its records
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VariantScan {
+    /// Variants written, after multiallelic splitting and reference block expansion
+    pub variant_num: u32,
+    pub number_geno_line: u32,
+    /// Every genotype uses `|`, and at least one does
+    pub all_phased: bool,
+    /// Some record has a GP field
+    pub has_genotype_probabilities: bool,
+    /// Sha256 of the input, when its provenance is written in headers
+    pub input_sha256: Option<String>,
+}
+
+/// Count variants and check how genotypes are written, in one pass over the input
+pub fn scan_variants(input: &str, options: &ConversionOptions) -> Result<VariantScan, VcfError> {
+    let regions = Regions::load(options)?;
+    let mut start_position = StartPosition::load(options)?;
+    let variant_ids = VariantIdFilter::load(options)?;
+    let variant_class = VariantClassFilter::load(options)?;
+    // records whose variants are kept for their IDs, alleles or genotypes are converted to count
+    // those kept
+    let info_filter = options
+        .info_filter
+        .as_deref()
+        .map(InfoFilter::parse)
+        .transpose()?;
+    let parse_all = options.min_mac.is_some()
+        || variant_class.is_some()
+        || options.drop_all_missing
+        || options.dup_policy != DuplicatePolicy::KeepAll
+        || Thinning::load(options)?.is_some();
+    let mut parsed_records = match variant_ids.is_some() || info_filter.is_some() || parse_all {
+        true => Some(VcfSource::open_single_pass(
+            input,
+            // only given the records from the start position on
+            &ConversionOptions {
+                regroup_chromosomes: false,
+                skipped_report: None,
+                start_position: None,
+                provenance: false,
+                ..options.clone()
+            },
+        )?),
+        false => None,
+    };
+    let input_digest =
+        hashes_input(input, options).then(|| Rc::new(RefCell::new(InputDigest::new(input))));
+    let mut reader = open_hashed_vcf(input, options, input_digest.clone())?;
     let mut number_geno_line = 0;
-    let mut variant_num = 0;
+    let mut variant_num = 0u64;
+    let mut bytes_read = 0u64;
+    let mut separators = GenotypeSeparators::default();
+    let mut has_genotype_probabilities = false;
     let mut line = String::new();
-    println!("Counting variants...  ");
-    let bar = ProgressBar::new_spinner();
-    bar.enable_steady_tick(Duration::from_millis(100));
+    let mut chromosome_order = ChromosomeOrder::default();
+    // to locate malformed records
+    let mut header_lines = 0u64;
+    let mut samples: Vec<String> = vec![];
+    info!("Counting variants");
+    let mut progress =
+        Progress::new("Counting", None, options.quiet).with_format(options.progress_format, None);
     loop {
-        let num_bytes = reader.read_line(&mut line)?;
+        let num_bytes = read_record_line(&mut reader, &mut line, number_geno_line)?;
         if num_bytes == 0 {
             break;
         }
-        if !line.starts_with('#') {
-            // If variant is multiallelic, we should add more than 1
-            variant_num += alt_allele_count(&line)?;
+        bytes_read += num_bytes as u64;
+        check_cancelled()?;
+        if line.starts_with("#CHROM") {
+            samples = trim_record(&line)
+                .split('\t')
+                .skip(FIXED_COLUMNS.len())
+                .map(str::to_string)
+                .collect();
+        }
+        if line.starts_with('#') {
+            header_lines += 1;
+        } else {
             number_geno_line += 1;
+            // If variant is multiallelic, we should add more than 1
+            let kept = !start_position
+                .as_mut()
+                .is_some_and(|start| start.is_before(&line))
+                && regions
+                    .as_ref()
+                    .is_none_or(|regions| regions.contains_record(&line))
+                && !(options.pass_only && failed_filter(&line).is_some());
+            if kept {
+                let info_kept = info_filter
+                    .as_ref()
+                    .map_or(Some(true), |filter| filter.keeps_record(&line));
+                let parse = parse_all
+                    || info_kept.is_none()
+                    || variant_ids
+                        .as_ref()
+                        .is_some_and(|filter| !filter.keeps_whole_record(&line));
+                variant_num += match (&variant_ids, parsed_records.as_mut()) {
+                    (Some(filter), _) if !filter.may_keep_record(&line) => 0,
+                    _ if info_kept == Some(false) => 0,
+                    (_, Some(source)) if parse => {
+                        source.record_variants(&line, number_geno_line)?.len() as u32
+                    }
+                    _ => line_variant_count(&line, options).map_err(|_| {
+                        let line_num = header_lines + number_geno_line as u64;
+                        malformed_record_error(line_num, &line, &samples)
+                    })?,
+                } as u64;
+            }
+            progress.update(number_geno_line as u64, variant_num, bytes_read);
+            // the first variants skipped are not written, so not counted in the header
+            let written =
+                variant_num.saturating_sub(options.skip_variants.unwrap_or_default() as u64);
+            // fail as soon as the count is too large, not after reading the whole input
+            check_variant_limit(written)?;
+            if !options.regroup_chromosomes {
+                let chr = line.split('\t').next().unwrap_or_default();
+                chromosome_order.observe(chr, number_geno_line)?;
+            }
+            let mut fields = trim_record(&line).split('\t').skip(8);
+            if let Some(format) = fields.next() {
+                has_genotype_probabilities |= format.split(':').any(|key| key == "GP");
+                if !separators.unphased && format.split(':').next() == Some("GT") {
+                    fields.for_each(|sample| separators.observe(sample));
+                }
+            }
+            // the conversion reads the records counted, stopping within the last one
+            if options
+                .max_variants
+                .is_some_and(|max| written >= max as u64)
+            {
+                break;
+            }
         }
         line.clear();
     }
-    bar.finish();
-    println!("Done");
-    Ok((variant_num, number_geno_line))
+    progress.finish();
+    info!("Counted {} records", number_geno_line);
+    let skipped = options.skip_variants.unwrap_or_default() as u64;
+    let written = variant_num.saturating_sub(skipped);
+    let input_sha256 = match input_digest {
+        Some(digest) => Some(digest.borrow_mut().finish()?),
+        None => None,
+    };
+    Ok(VariantScan {
+        variant_num: options
+            .max_variants
+            .map_or(written, |max| written.min(max as u64)) as u32,
+        number_geno_line,
+        all_phased: separators.phased && !separators.unphased,
+        has_genotype_probabilities,
+        input_sha256,
+    })
 }
 
-pub fn read_vcf_header(reader: &mut impl BufRead) -> Result<Vec<String>, VcfError> {
-    let mut line = String::new();
-    // Skip header, parse column/sample line
-    let samples_result = loop {
-        reader.read_line(&mut line)?;
-        if line.starts_with("##") {
-            line.clear();
-            continue;
-        } else if line.starts_with('#') {
-            break parse_samples(&line);
+// Allele separators seen in GT fields
+#[derive(Debug, Default)]
+struct GenotypeSeparators {
+    phased: bool,
+    unphased: bool,
+}
+
+impl GenotypeSeparators {
+    fn observe(&mut self, sample: &str) {
+        let genotype = sample.split(':').next().unwrap_or_default();
+        self.phased |= genotype.contains('|');
+        self.unphased |= genotype.contains('/');
+    }
+}
+
+/// The bgen header stores the number of variants, after multiallelic splitting, as a u32
+pub fn check_variant_limit(variant_num: u64) -> Result<(), VcfError> {
+    if variant_num > u32::MAX as u64 {
+        return Err(VcfError::LimitExceeded(Report::msg(format!(
+            "{} variants after splitting multiallelic records do not fit in a bgen header (at most {}): split the output by chromosome or region",
+            variant_num,
+            u32::MAX
+        ))));
+    }
+    Ok(())
+}
+
+/// The bgen header stores the sample count and the sample block length as u32, each ID length as u16
+pub fn check_sample_limit(samples: &[String]) -> Result<(), VcfError> {
+    if let Some(sample) = samples.iter().find(|s| s.len() > u16::MAX as usize) {
+        return Err(VcfError::LimitExceeded(Report::msg(format!(
+            "Sample ID starting with '{}' is longer than the {} bytes allowed by bgen",
+            sample.chars().take(20).collect::<String>(),
+            u16::MAX
+        ))));
+    }
+    // header, then the sample block length, count and length-prefixed IDs
+    let header_bytes = 20u64 + 8 + samples.iter().map(|s| 2 + s.len() as u64).sum::<u64>();
+    if samples.len() as u64 > u32::MAX as u64 || header_bytes > u32::MAX as u64 {
+        return Err(VcfError::LimitExceeded(Report::msg(format!(
+            "{} samples do not fit in a bgen header: split the samples into several outputs",
+            samples.len()
+        ))));
+    }
+    Ok(())
+}
+
+/// Read one line, reporting a cut compressed stream as a truncated input
+pub fn read_record_line(
+    reader: &mut impl BufRead,
+    line: &mut String,
+    records_read: u32,
+) -> Result<usize, VcfError> {
+    reader.read_line(line).map_err(|error| {
+        if error.kind() == std::io::ErrorKind::UnexpectedEof {
+            VcfError::Nom(Report::msg(format!(
+                "Input is truncated after record {}: {}",
+                records_read, error
+            )))
+        } else {
+            VcfError::Io(error)
+        }
+    })
+}
+
+// Columns of a vcf record before its samples
+const FIXED_COLUMNS: [&str; 9] = [
+    "CHROM", "POS", "ID", "REF", "ALT", "QUAL", "FILTER", "INFO", "FORMAT",
+];
+
+// Start of a field quoted in a message, a whole genotype column of a large cohort being too long
+pub(crate) fn snippet(field: &str) -> String {
+    let start: String = field.chars().take(40).collect();
+    match start.len() < field.len() {
+        true => format!("'{}...'", start),
+        false => format!("'{}'", start),
+    }
+}
+
+// First broken column of a record the parser rejected, with the samples of the header
+fn malformed_record_problem(line: &str, samples: &[String]) -> String {
+    let fields: Vec<&str> = trim_record(line).split('\t').collect();
+    let column_name = |i: usize| match FIXED_COLUMNS.get(i) {
+        Some(name) => name.to_string(),
+        None => match samples.get(i - FIXED_COLUMNS.len()) {
+            Some(sample) => format!("sample {}", sample),
+            None => format!("sample {}", i + 1 - FIXED_COLUMNS.len()),
+        },
+    };
+    if let Some(i) = fields.iter().position(|field| field.is_empty()) {
+        return format!("column {} ({}) is empty", i + 1, column_name(i));
+    }
+    if fields.len() < 8 {
+        return format!(
+            "ends after column {} ({}), without {}",
+            fields.len(),
+            column_name(fields.len() - 1),
+            FIXED_COLUMNS[fields.len()..8].join(", ")
+        );
+    }
+    if fields[1].parse::<u32>().is_err() {
+        return format!("column 2 (POS) is not a position: {}", snippet(fields[1]));
+    }
+    let sample_columns = fields.len().saturating_sub(FIXED_COLUMNS.len());
+    if sample_columns < samples.len() {
+        return format!(
+            "ends after column {} ({}), {} of the {} samples of the header missing",
+            fields.len(),
+            column_name(fields.len() - 1),
+            samples.len() - sample_columns,
+            samples.len()
+        );
+    }
+    if sample_columns > samples.len() {
+        return format!(
+            "column {} {} is past the {} samples of the header",
+            FIXED_COLUMNS.len() + samples.len() + 1,
+            snippet(fields[FIXED_COLUMNS.len() + samples.len()]),
+            samples.len()
+        );
+    }
+    format!("could not be parsed: {}", snippet(trim_record(line)))
+}
+
+/// Error locating what is broken in the record on input line `line_num`, the column and sample
+/// of the header `samples`, and quoting the field
+pub(crate) fn malformed_record_error(line_num: u64, line: &str, samples: &[String]) -> VcfError {
+    let mut fields = line.split('\t');
+    let site = match (fields.next(), fields.next()) {
+        (Some(chr), Some(pos)) if !chr.is_empty() && !pos.trim_end().is_empty() => {
+            format!(" at {}:{}", chr, pos.trim_end())
         }
+        _ => String::new(),
+    };
+    VcfError::Nom(Report::msg(format!(
+        "Line {}{} is malformed: {}",
+        line_num,
+        site,
+        malformed_record_problem(line, samples)
+    )))
+}
+
+pub fn read_vcf_header(reader: &mut impl BufRead) -> Result<Vec<String>, VcfError> {
+    Ok(read_vcf_header_full(reader)?.samples)
+}
+
+pub fn read_vcf_header_full(reader: &mut impl BufRead) -> Result<VcfHeader, VcfError> {
+    parse_vcf_header(reader)
+}
+
+// Length of the sample block: its length and sample count, then each identifier and its length
+fn sample_block_len(samples: &[String], number_individuals: u32) -> u32 {
+    8u32 + number_individuals * 2 + samples.iter().map(|s| s.len() as u32).sum::<u32>()
+}
+
+/// Write the header and sample block byte by byte, through any writer
+///
+/// The bgen_reader header only flags zlib compression; this one also flags zstd.
+#[allow(clippy::too_many_arguments)]
+pub fn write_raw_bgen_header(
+    writer: &mut impl Write,
+    samples: &[String],
+    number_individuals: u32,
+    variant_num: u32,
+    layout_id: u8,
+    compression: BlockCompression,
+    sample_ids: bool,
+    free_data: &[u8],
+) -> Result<(), VcfError> {
+    let len_sample_block = sample_block_len(samples, number_individuals);
+    let header_size = 20 + free_data.len() as u32;
+    let start_data_offset = match sample_ids {
+        true => header_size + len_sample_block,
+        false => header_size,
     };
-    let samples_str = samples_result?.1;
-    Ok(samples_str.into_iter().map(|s| s.to_string()).collect())
+    writer.write_all(&start_data_offset.to_le_bytes())?;
+    writer.write_all(&header_size.to_le_bytes())?;
+    writer.write_all(&variant_num.to_le_bytes())?;
+    writer.write_all(&number_individuals.to_le_bytes())?;
+    writer.write_all(b"bgen")?;
+    writer.write_all(free_data)?;
+    let compression_flag: u32 = match compression {
+        BlockCompression::None => 0,
+        BlockCompression::Zlib => 1,
+        BlockCompression::Zstd => 2,
+    };
+    let flags = compression_flag | (layout_id as u32) << 2 | (sample_ids as u32) << 31;
+    writer.write_all(&flags.to_le_bytes())?;
+    if sample_ids {
+        writer.write_all(&len_sample_block.to_le_bytes())?;
+        writer.write_all(&number_individuals.to_le_bytes())?;
+        for sample in samples {
+            writer.write_all(&(sample.len() as u16).to_le_bytes())?;
+            writer.write_all(sample.as_bytes())?;
+        }
+    }
+    Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn write_bgen_header(
     bgen_writer: &mut BufWriter<std::fs::File>,
     samples: &[String],
     number_individuals: u32,
     variant_num: u32,
+    layout_id: u8,
+    compression: BlockCompression,
+    sample_ids: bool,
+    free_data: &[u8],
 ) -> Result<(), VcfError> {
+    // zstd and the free data area are only written by the raw header
+    if compression == BlockCompression::Zstd || !free_data.is_empty() {
+        return write_raw_bgen_header(
+            bgen_writer,
+            samples,
+            number_individuals,
+            variant_num,
+            layout_id,
+            compression,
+            sample_ids,
+            free_data,
+        );
+    }
+
     // compute length of sample block
-    let len_sample_block =
-        8u32 + number_individuals * 2 + samples.iter().map(|s| s.len() as u32).sum::<u32>();
+    let len_sample_block = sample_block_len(samples, number_individuals);
 
     // compute length of header
     let header_size = 20u32;
 
-    // compute offset to start of data
-    let start_data_offset = header_size + len_sample_block;
+    // compute offset to start of data, right after the header without a sample block
+    let start_data_offset = match sample_ids {
+        true => header_size + len_sample_block,
+        false => header_size,
+    };
 
     // create bgen header
     let header_flags = HeaderFlags {
-        compressed_snp_blocks: true,
-        layout_id: 2,
-        sample_id_present: true,
+        compressed_snp_blocks: compression != BlockCompression::None,
+        layout_id,
+        sample_id_present: sample_ids,
     };
     let header = Header {
         start_data_offset,
@@ -118,38 +1496,417 @@ pub fn write_bgen_header(
         header_flags,
     };
 
-    // write header
-    header.write_header(bgen_writer)?;
+    // write header
+    header.write_header(bgen_writer)?;
+
+    // write samples
+    if sample_ids {
+        write_samples(samples, bgen_writer, len_sample_block)?;
+    }
+    Ok(())
+}
+
+/// Stored probabilities of one sample, with its ploidy and missingness byte
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SampleProbabilities {
+    pub ploidy_missingness: u8,
+    pub probabilities: Vec<u32>,
+}
+
+impl SampleProbabilities {
+    pub fn ploidy(&self) -> u8 {
+        self.ploidy_missingness & PLOIDY_MASK
+    }
+
+    // Unphased probabilities of 0 to ploidy - 1 copies of the alt allele, for a called sample
+    fn unphased(probabilities: Vec<u32>) -> Self {
+        SampleProbabilities {
+            ploidy_missingness: probabilities.len() as u8,
+            probabilities,
+        }
+    }
+}
+
+/// Probabilities of each sample from its GT, written with the ploidy of `sample_ploidy` when set
+///
+/// Uncalled and half-called genotypes are first resolved by the policies, and `ref_alleles`
+/// (dropped `*` alleles) count as the reference.
+/// Calls of another ploidy are rewritten when homozygous (`1/1` as haploid `1`) and missing otherwise.
+#[allow(clippy::too_many_arguments)]
+pub fn parse_geno_line(
+    geno_line: &[&str],
+    sample_ploidy: &[Option<u8>],
+    missing_policy: MissingPolicy,
+    half_call_policy: HalfCallPolicy,
+    ref_alleles: &[usize],
+    alt_allele_num: usize,
+    num_bits: u8,
+    phased: bool,
+) -> Vec<SampleProbabilities> {
+    geno_line
+        .iter()
+        .enumerate()
+        .map(|(geno_i, geno_s)| {
+            // alleles other than the ref and this alt make the sample missing
+            let alleles = sample_alleles(
+                geno_s,
+                sample_ploidy.get(geno_i).copied().flatten(),
+                missing_policy,
+                half_call_policy,
+                |allele| match allele {
+                    0 => Some(0),
+                    allele if allele == alt_allele_num => Some(1),
+                    allele if ref_alleles.contains(&allele) => Some(0),
+                    _ => None,
+                },
+            );
+            let ploidy = alleles.len();
+            let missing = alleles.iter().any(Option::is_none);
+            let genos: Vec<u32> = alleles.iter().map(|allele| allele.unwrap_or(0)).collect();
+            // convert geno to bgen probabilities
+            let probabilities = if phased {
+                haplotypes_to_proba(&genos, num_bits)
+            } else {
+                genos_to_proba(&genos, num_bits)
+            };
+            SampleProbabilities {
+                ploidy_missingness: ploidy as u8 | if missing { MISSING_FLAG } else { 0 },
+                probabilities,
+            }
+        })
+        .collect()
+}
+
+// Make the masked samples missing, with zero probabilities
+fn mask_samples(samples: &mut [SampleProbabilities], masked: &[bool]) {
+    for (sample, _) in samples.iter_mut().zip(masked).filter(|(_, &masked)| masked) {
+        sample.ploidy_missingness |= MISSING_FLAG;
+        sample.probabilities.fill(0);
+    }
+}
+
+// Alleles of a GT, as numbered by `allele_code`, with as many entries as the written ploidy
+//
+// Uncalled and half-called genotypes are resolved by the policies, then calls of another ploidy
+// than `expected_ploidy` are rewritten when homozygous and made missing otherwise.
+fn sample_alleles(
+    geno_s: &str,
+    expected_ploidy: Option<u8>,
+    missing_policy: MissingPolicy,
+    half_call_policy: HalfCallPolicy,
+    allele_code: impl Fn(usize) -> Option<u32>,
+) -> Vec<Option<u32>> {
+    // a lone '.' stands for a missing diploid genotype
+    let mut calls: Vec<&str> = if geno_s == "." {
+        vec!["."; 2]
+    } else {
+        geno_s.split(['/', '|']).collect()
+    };
+    let uncalled = calls.iter().filter(|&&allele| allele == ".").count();
+    if uncalled == calls.len() {
+        if missing_policy == MissingPolicy::Ref {
+            calls.fill("0");
+        }
+    } else if uncalled > 0 {
+        match half_call_policy {
+            HalfCallPolicy::Missing => {}
+            HalfCallPolicy::Haploid => calls.retain(|&allele| allele != "."),
+            HalfCallPolicy::Ref => calls
+                .iter_mut()
+                .filter(|allele| **allele == ".")
+                .for_each(|allele| *allele = "0"),
+        }
+    }
+    let alleles: Vec<Option<u32>> = calls
+        .iter()
+        .map(|allele| allele.parse::<usize>().ok().and_then(&allele_code))
+        .collect();
+    match expected_ploidy.map(usize::from) {
+        Some(expected) if expected != alleles.len() => {
+            let homozygous = alleles.iter().all(|allele| *allele == alleles[0]);
+            let allele = if homozygous { alleles[0] } else { None };
+            vec![allele; expected]
+        }
+        _ => alleles,
+    }
+}
+
+/// Stored probabilities of each sample of a block, with its ploidy and missingness byte
+///
+/// Phased blocks store the probabilities of every allele but the last for each haplotype, and
+/// unphased blocks those of every genotype but the last: as many values as the ploidy when biallelic.
+pub fn sample_probabilities(data_block: &DataBlock) -> Vec<(u8, &[u32])> {
+    let num_alleles = (data_block.number_alleles as usize).max(2);
+    let mut offset = 0;
+    data_block
+        .ploidy_missingness
+        .iter()
+        .map(|&ploidy_m| {
+            let ploidy = (ploidy_m & PLOIDY_MASK) as usize;
+            let num_values = if data_block.phased {
+                ploidy * (num_alleles - 1)
+            } else {
+                number_of_genotypes(ploidy, num_alleles) - 1
+            };
+            let end = (offset + num_values).min(data_block.probabilities.len());
+            let probas = &data_block.probabilities[offset..end];
+            offset = end;
+            (ploidy_m, probas)
+        })
+        .collect()
+}
+
+/// Whether the block has samples and every one of them is missing
+pub fn all_samples_missing(data_block: &DataBlock) -> bool {
+    !data_block.ploidy_missingness.is_empty()
+        && data_block
+            .ploidy_missingness
+            .iter()
+            .all(|ploidy_m| ploidy_m & MISSING_FLAG != 0)
+}
+
+/// Number of samples of the block whose genotype is missing
+pub fn missing_sample_count(data_block: &DataBlock) -> u64 {
+    data_block
+        .ploidy_missingness
+        .iter()
+        .filter(|&ploidy_m| ploidy_m & MISSING_FLAG != 0)
+        .count() as u64
+}
+
+/// Overwrite the probabilities of samples whose GP holds one probability per genotype of their ploidy
+///
+/// Samples with a missing or malformed GP keep the values derived from GT.
+pub fn parse_gp_line(
+    samples: &mut [SampleProbabilities],
+    gp_line: &[&str],
+    alt_allele_num: usize,
+    num_alleles: usize,
+    num_bits: u8,
+) {
+    gp_line.iter().enumerate().for_each(|(geno_i, gp_s)| {
+        let ploidy = samples[geno_i].ploidy() as usize;
+        if ploidy == 0 {
+            return;
+        }
+        let gp: Vec<f64> = gp_s
+            .split(',')
+            .filter_map(|p| p.parse().ok())
+            .filter(|p| (0.0..=1.0).contains(p))
+            .collect();
+        if gp.len() != number_of_genotypes(ploidy, num_alleles) {
+            return;
+        }
+        let copies_proba = genotypes_to_copies(&gp, alt_allele_num, num_alleles, ploidy);
+        if let Some(probabilities) = copies_to_proba(&copies_proba, num_bits) {
+            samples[geno_i] = SampleProbabilities::unphased(probabilities);
+        }
+    });
+}
+
+/// Overwrite the probabilities of diploid samples with a dosage of the alt allele between 0 and 2
+///
+/// The dosage is split over two adjacent genotypes, as done by plink 2.
+/// Samples with a missing or malformed dosage keep the values derived from GP or GT.
+pub fn parse_dosage_line(
+    samples: &mut [SampleProbabilities],
+    dosage_line: &[&str],
+    alt_allele_num: usize,
+    num_bits: u8,
+) {
+    dosage_line
+        .iter()
+        .enumerate()
+        .for_each(|(geno_i, dosage_s)| {
+            if samples[geno_i].ploidy() != 2 {
+                return;
+            }
+            // one dosage per alt allele (Number=A)
+            let dosage = dosage_s
+                .split(',')
+                .nth(alt_allele_num - 1)
+                .and_then(|d| d.parse::<f64>().ok())
+                .filter(|d| (0.0..=2.0).contains(d));
+            let Some(dosage) = dosage else {
+                return;
+            };
+            let copies_proba = if dosage <= 1.0 {
+                vec![1.0 - dosage, dosage, 0.0]
+            } else {
+                vec![0.0, 2.0 - dosage, dosage - 1.0]
+            };
+            if let Some(probabilities) = copies_to_proba(&copies_proba, num_bits) {
+                samples[geno_i] = SampleProbabilities::unphased(probabilities);
+            }
+        });
+}
+
+/// Overwrite the probabilities of called samples with PL or GL likelihoods by genotype posteriors
+///
+/// `prior` holds one weight per diploid genotype, a flat prior when empty.
+/// Samples with missing or malformed likelihoods keep the values derived from GT.
+#[allow(clippy::too_many_arguments)]
+pub fn parse_likelihood_line(
+    samples: &mut [SampleProbabilities],
+    likelihood_line: &[&str],
+    phred_scaled: bool,
+    prior: &[f64],
+    alt_allele_num: usize,
+    num_alleles: usize,
+    num_bits: u8,
+) {
+    likelihood_line
+        .iter()
+        .enumerate()
+        .for_each(|(geno_i, likelihood_s)| {
+            let ploidy = samples[geno_i].ploidy() as usize;
+            let Some(posteriors) =
+                genotype_posteriors(likelihood_s, phred_scaled, prior, ploidy, num_alleles)
+            else {
+                return;
+            };
+            let copies_proba =
+                genotypes_to_copies(&posteriors, alt_allele_num, num_alleles, ploidy);
+            if let Some(probabilities) = copies_to_proba(&copies_proba, num_bits) {
+                samples[geno_i] = SampleProbabilities::unphased(probabilities);
+            }
+        });
+}
+
+// Unnormalised posteriors of each genotype in vcf order, from the PL or GL of a sample
+fn genotype_posteriors(
+    likelihood_s: &str,
+    phred_scaled: bool,
+    prior: &[f64],
+    ploidy: usize,
+    num_alleles: usize,
+) -> Option<Vec<f64>> {
+    if ploidy == 0 {
+        return None;
+    }
+    // the prior is given for diploid genotypes, other ploidies use a flat prior
+    let prior = if ploidy == 2 { prior } else { &[] };
+    let likelihoods: Vec<f64> = likelihood_s
+        .split(',')
+        .filter_map(|l| l.parse().ok())
+        .filter(|l: &f64| l.is_finite())
+        .collect();
+    if likelihoods.len() != number_of_genotypes(ploidy, num_alleles) {
+        return None;
+    }
+    // log10 likelihoods, relative to the most likely genotype to avoid underflow
+    let log_likelihoods: Vec<f64> = if phred_scaled {
+        likelihoods.iter().map(|pl| -pl / 10.0).collect()
+    } else {
+        likelihoods
+    };
+    let best = log_likelihoods
+        .iter()
+        .cloned()
+        .fold(f64::NEG_INFINITY, f64::max);
+    Some(
+        log_likelihoods
+            .iter()
+            .enumerate()
+            .map(|(g, l)| 10f64.powf(l - best) * prior.get(g).unwrap_or(&1.0))
+            .collect(),
+    )
+}
+
+// Hardy-Weinberg genotype frequencies from INFO/AF, empty (flat) when AF is absent or invalid
+fn allele_frequency_prior(info: &str, num_alleles: usize) -> Vec<f64> {
+    let alt_frequencies: Option<Vec<f64>> = info_value(info, "AF").and_then(|af| {
+        af.split(',')
+            .map(|f| f.parse().ok().filter(|f| (0.0..=1.0).contains(f)))
+            .collect()
+    });
+    let Some(alt_frequencies) = alt_frequencies.filter(|f| f.len() + 1 == num_alleles) else {
+        return vec![];
+    };
+    let ref_frequency = 1.0 - alt_frequencies.iter().sum::<f64>();
+    if ref_frequency < 0.0 {
+        return vec![];
+    }
+    let frequencies: Vec<f64> = std::iter::once(ref_frequency)
+        .chain(alt_frequencies)
+        .collect();
+    let mut prior = vec![];
+    for k in 0..num_alleles {
+        for j in 0..=k {
+            let orderings = if j == k { 1.0 } else { 2.0 };
+            prior.push(orderings * frequencies[j] * frequencies[k]);
+        }
+    }
+    prior
+}
 
-    // write samples
-    Ok(write_samples(samples, bgen_writer, len_sample_block)?)
+/// Number of unordered genotypes of `ploidy` alleles among `num_alleles`
+pub fn number_of_genotypes(ploidy: usize, num_alleles: usize) -> usize {
+    // multisets of size ploidy: C(ploidy + num_alleles - 1, ploidy)
+    (1..=ploidy).fold(1, |count, i| count * (num_alleles - 1 + i) / i)
 }
 
-pub fn parse_geno_line(
-    vec_probas: &mut [u32],
-    vec_ploidy_m: &mut [u8],
-    geno_line: &[&str],
+/// Genotypes of `ploidy` alleles, as sorted allele indices, in the order of GP, GL and PL
+///
+/// The last allele varies slowest: 0/0, 0/1, 1/1, 0/2, 1/2, 2/2 for diploids.
+pub fn vcf_genotype_order(ploidy: usize, num_alleles: usize) -> Vec<Vec<usize>> {
+    let mut genotypes: Vec<Vec<usize>> = vec![vec![]];
+    for _ in 0..ploidy {
+        genotypes = genotypes
+            .into_iter()
+            .flat_map(|genotype| {
+                let first = genotype.last().copied().unwrap_or(0);
+                (first..num_alleles).map(move |allele| {
+                    let mut genotype = genotype.clone();
+                    genotype.push(allele);
+                    genotype
+                })
+            })
+            .collect();
+    }
+    genotypes.sort_by(|a, b| a.iter().rev().cmp(b.iter().rev()));
+    genotypes
+}
+
+// Probabilities of 0 to ploidy copies of the alt allele, from one value per genotype
+fn genotypes_to_copies(
+    genotypes: &[f64],
     alt_allele_num: usize,
-    num_bits: u8,
-) {
-    geno_line.iter().enumerate().for_each(|(geno_i, geno_s)| {
-        let mut geno_iter = geno_s
-            .iter_elements()
-            .filter_map(|c| c.to_digit(10))
-            .filter(|&d| d == 0 || d == alt_allele_num as u32)
-            .map(|d| if d == 0 { 0 } else { 1 });
-        let count_valid = geno_iter.clone().count();
-        // if there is less than 2 values, there is missingness
-        let ploidy_m = if count_valid < 2 { (1u8 << 7) + 2 } else { 2u8 };
-        let left_strand = geno_iter.next().unwrap_or(0);
-        let right_strand = geno_iter.next().unwrap_or(0);
-        let genos = [left_strand, right_strand];
-        // convert geno to bgen probabilities
-        let probas = genos_to_proba(&genos, num_bits);
-        vec_probas[geno_i * 2] = probas[0];
-        vec_probas[geno_i * 2 + 1] = probas[1];
-        vec_ploidy_m[geno_i] = ploidy_m;
-    });
+    num_alleles: usize,
+    ploidy: usize,
+) -> Vec<f64> {
+    let mut copies_proba = vec![0.0; ploidy + 1];
+    for (genotype, proba) in vcf_genotype_order(ploidy, num_alleles)
+        .iter()
+        .zip(genotypes)
+    {
+        let copies = genotype
+            .iter()
+            .filter(|&&allele| allele == alt_allele_num)
+            .count();
+        copies_proba[copies] += proba;
+    }
+    copies_proba
+}
+
+// Stored probabilities of 0 to ploidy - 1 alt copies, from probabilities of 0 to ploidy copies
+pub(crate) fn copies_to_proba(copies_proba: &[f64], num_bits: u8) -> Option<Vec<u32>> {
+    let total: f64 = copies_proba.iter().sum();
+    if total <= 0.0 {
+        return None;
+    }
+    let proba_max = ((1u64 << num_bits) - 1) as u32;
+    // stored probabilities of a sample must not sum above the maximum value
+    let mut remaining = proba_max;
+    let probabilities = copies_proba[..copies_proba.len() - 1]
+        .iter()
+        .map(|p| {
+            let p = ((p / total * proba_max as f64).round() as u32).min(remaining);
+            remaining -= p;
+            p
+        })
+        .collect();
+    Some(probabilities)
 }
 
 pub fn parse_vcf_geno(
@@ -159,7 +1916,6 @@ pub fn parse_vcf_geno(
     num_bits: u8,
     number_individuals: u32,
 ) -> VariantData {
-    let number_individuals = number_individuals as usize;
     // use variant data as pattern
     let mut variant_data_clone = variant_data_to_parse.variant_data.clone();
 
@@ -173,25 +1929,74 @@ pub fn parse_vcf_geno(
     variant_data_clone.alleles[1] = alt_allele;
     variant_data_clone.rsid = variant_id_fmt;
 
-    let mut ploidy_missingness = vec![0; number_individuals];
-    let mut probabilities = vec![0; number_individuals * 2];
-
+    let ref_alleles: Vec<usize> = match variant_data_to_parse.spanning_deletion {
+        SpanningDeletionPolicy::Drop => variant_data_to_parse.variant_data.alleles[1]
+            .split(',')
+            .enumerate()
+            .filter(|(_, alt)| *alt == SPANNING_DELETION)
+            .map(|(alt_i, _)| alt_i + 1)
+            .collect(),
+        SpanningDeletionPolicy::Missing => vec![],
+    };
     // convert string to missingness and probas
-    parse_geno_line(
-        &mut probabilities,
-        &mut ploidy_missingness,
+    let mut samples = parse_geno_line(
         &variant_data_to_parse.geno_string_vcf,
+        &variant_data_to_parse.sample_ploidy,
+        variant_data_to_parse.missing_policy,
+        variant_data_to_parse.half_call_policy,
+        &ref_alleles,
         alt_allele_num,
         num_bits,
+        variant_data_clone.data_block.phased,
     );
-    variant_data_clone.data_block.ploidy_missingness = ploidy_missingness;
-    variant_data_clone.data_block.probabilities = probabilities;
+    // genotype probabilities and dosages replace hard calls, except for phased haplotypes and reference blocks
+    let num_alleles = variant_data_to_parse.variant_data.alleles[1]
+        .split(',')
+        .count()
+        + 1;
+    if !variant_data_clone.data_block.phased && alt_allele_num < num_alleles {
+        if let Some((likelihood_line, phred_scaled)) = &variant_data_to_parse.likelihood_string_vcf
+        {
+            if variant_data_to_parse.gp_string_vcf.is_none() {
+                parse_likelihood_line(
+                    &mut samples,
+                    likelihood_line,
+                    *phred_scaled,
+                    &variant_data_to_parse.genotype_prior,
+                    alt_allele_num,
+                    num_alleles,
+                    num_bits,
+                );
+            }
+        }
+        if let Some(gp_line) = &variant_data_to_parse.gp_string_vcf {
+            parse_gp_line(&mut samples, gp_line, alt_allele_num, num_alleles, num_bits);
+        }
+        // a selected dosage field takes precedence over GP
+        if let Some(dosage_line) = &variant_data_to_parse.dosage_string_vcf {
+            parse_dosage_line(&mut samples, dosage_line, alt_allele_num, num_bits);
+        }
+    }
+    mask_samples(&mut samples, &variant_data_to_parse.masked_samples);
+    let data_block = &mut variant_data_clone.data_block;
+    if let (Some(min), Some(max)) = (
+        samples.iter().map(SampleProbabilities::ploidy).min(),
+        samples.iter().map(SampleProbabilities::ploidy).max(),
+    ) {
+        data_block.minimum_ploidy = min;
+        data_block.maximum_ploidy = max;
+    }
+    data_block.ploidy_missingness = samples.iter().map(|s| s.ploidy_missingness).collect();
+    // two stored values per sample for diploids
+    let mut probabilities = Vec::with_capacity(number_individuals as usize * 2);
+    probabilities.extend(samples.into_iter().flat_map(|s| s.probabilities));
+    data_block.probabilities = probabilities;
     variant_data_clone
 }
 
 pub fn split_multiallelic(
     variant_data_to_parse: VariantDataToParse<'_>,
-    number_individuals: u32
+    number_individuals: u32,
 ) -> Result<Vec<VariantData>, VcfError> {
     let variant_data = &variant_data_to_parse.variant_data;
 
@@ -200,38 +2005,807 @@ pub fn split_multiallelic(
         .map(|s| s.to_string())
         .collect();
     let num_bits = variant_data.data_block.bits_storage;
-    // split multiallelic into biallelic
+    // split multiallelic into biallelic, dropping gVCF <NON_REF> alleles
     let vec_variant_data = alt_variants
         .into_iter()
         .enumerate()
-        .map(|(alt_i, alt)| parse_vcf_geno(&variant_data_to_parse, alt, alt_i + 1, num_bits, number_individuals))
+        .filter(|(_, alt)| is_written_alt(alt, variant_data_to_parse.symbolic_alleles))
+        .map(|(alt_i, alt)| {
+            parse_vcf_geno(
+                &variant_data_to_parse,
+                alt,
+                alt_i + 1,
+                num_bits,
+                number_individuals,
+            )
+        })
         .collect::<Vec<VariantData>>();
     Ok(vec_variant_data)
 }
 
-pub fn convert_variant_blocks(
-    reader: &mut impl BufRead,
-    bgen_writer: &mut BufWriter<std::fs::File>,
-    number_geno_line: u32,
+/// Write a multiallelic record as one variant with every allele, instead of one per alt
+///
+/// Probabilities of every genotype come from GP, or else from PL/GL likelihoods, when every allele
+/// is written, and from GT otherwise; dosage fields only apply to biallelic records. Records with a
+/// single alt are split as usual.
+pub fn keep_multiallelic(
+    variant_data_to_parse: VariantDataToParse<'_>,
+    number_individuals: u32,
+) -> Result<Vec<VariantData>, VcfError> {
+    write_multiallelic(variant_data_to_parse, number_individuals, false)
+}
+
+/// Write a multiallelic record as one variant with the ref, its most frequent alt and `<OTHER>`
+///
+/// Carriers of any other alt carry `<OTHER>`; records with two alts or less are kept whole.
+pub fn collapse_multiallelic(
+    variant_data_to_parse: VariantDataToParse<'_>,
+    number_individuals: u32,
+) -> Result<Vec<VariantData>, VcfError> {
+    write_multiallelic(variant_data_to_parse, number_individuals, true)
+}
+
+fn write_multiallelic(
+    variant_data_to_parse: VariantDataToParse<'_>,
+    number_individuals: u32,
+    collapse: bool,
+) -> Result<Vec<VariantData>, VcfError> {
+    let variant_data = &variant_data_to_parse.variant_data;
+    let vcf_alts: Vec<&str> = variant_data.alleles[1].split(',').collect();
+    // position of each vcf allele among the written ones, gVCF <NON_REF> alleles being dropped
+    let mut allele_codes = vec![Some(0)];
+    let mut alleles = vec![variant_data.alleles[0].clone()];
+    for alt in &vcf_alts {
+        if *alt == SPANNING_DELETION {
+            allele_codes.push(match variant_data_to_parse.spanning_deletion {
+                SpanningDeletionPolicy::Drop => Some(0),
+                SpanningDeletionPolicy::Missing => None,
+            });
+        } else if !is_written_alt(alt, variant_data_to_parse.symbolic_alleles) {
+            allele_codes.push(None);
+        } else {
+            allele_codes.push(Some(alleles.len() as u32));
+            alleles.push(alt.to_string());
+        }
+    }
+    if alleles.len() <= 2 {
+        return split_multiallelic(variant_data_to_parse, number_individuals);
+    }
+    if collapse && alleles.len() > 3 {
+        // the alt most called in genotypes, the first one on ties
+        let mut counts = vec![0u32; alleles.len()];
+        for geno_s in &variant_data_to_parse.geno_string_vcf {
+            let codes = geno_s
+                .split(['/', '|'])
+                .filter_map(|allele| allele.parse::<usize>().ok())
+                .filter_map(|allele| allele_codes.get(allele).copied().flatten());
+            for code in codes {
+                counts[code as usize] += 1;
+            }
+        }
+        let major = (1..alleles.len())
+            .max_by(|&a, &b| counts[a].cmp(&counts[b]).then(b.cmp(&a)))
+            .unwrap_or(1) as u32;
+        for code in allele_codes.iter_mut().flatten() {
+            *code = match *code {
+                0 => 0,
+                code if code == major => 1,
+                _ => 2,
+            };
+        }
+        alleles = vec![
+            alleles[0].clone(),
+            alleles[major as usize].clone(),
+            OTHER_ALLELES.to_string(),
+        ];
+    }
+    let num_alleles = alleles.len();
+    let num_bits = variant_data.data_block.bits_storage;
+    let phased = variant_data.data_block.phased;
+    let proba_max = ((1u64 << num_bits) - 1) as u32;
+    // probabilities of every genotype need every allele of the record
+    let every_allele = !phased && num_alleles == vcf_alts.len() + 1;
+    let gp_line = variant_data_to_parse
+        .gp_string_vcf
+        .as_ref()
+        .filter(|_| every_allele);
+    let likelihood_line = variant_data_to_parse
+        .likelihood_string_vcf
+        .as_ref()
+        .filter(|_| every_allele);
+    let mut samples: Vec<SampleProbabilities> = variant_data_to_parse
+        .geno_string_vcf
+        .iter()
+        .enumerate()
+        .map(|(geno_i, geno_s)| {
+            let sample_alleles = sample_alleles(
+                geno_s,
+                variant_data_to_parse
+                    .sample_ploidy
+                    .get(geno_i)
+                    .copied()
+                    .flatten(),
+                variant_data_to_parse.missing_policy,
+                variant_data_to_parse.half_call_policy,
+                |allele| allele_codes.get(allele).copied().flatten(),
+            );
+            let ploidy = sample_alleles.len();
+            let missing = sample_alleles.iter().any(Option::is_none);
+            // missing samples store zero probabilities
+            let probabilities = if phased {
+                // probability of each allele but the last, for each haplotype
+                let mut probabilities = vec![0; ploidy * (num_alleles - 1)];
+                for (haplotype, allele) in sample_alleles.iter().enumerate() {
+                    match allele {
+                        Some(allele) if !missing && (*allele as usize) < num_alleles - 1 => {
+                            probabilities[haplotype * (num_alleles - 1) + *allele as usize] =
+                                proba_max
+                        }
+                        _ => {}
+                    }
+                }
+                probabilities
+            } else {
+                // probability of each genotype but the last, in vcf order
+                let mut probabilities = vec![0; number_of_genotypes(ploidy, num_alleles) - 1];
+                if !missing {
+                    let mut genotype: Vec<usize> = sample_alleles
+                        .iter()
+                        .flatten()
+                        .map(|a| *a as usize)
+                        .collect();
+                    genotype.sort_unstable();
+                    let genotype_i = vcf_genotype_order(ploidy, num_alleles)
+                        .iter()
+                        .position(|g| *g == genotype);
+                    if let Some(proba) = genotype_i.and_then(|i| probabilities.get_mut(i)) {
+                        *proba = proba_max;
+                    }
+                }
+                probabilities
+            };
+            let missing_flag = if missing { MISSING_FLAG } else { 0 };
+            SampleProbabilities {
+                ploidy_missingness: ploidy as u8 | missing_flag,
+                probabilities,
+            }
+        })
+        .collect();
+    if let Some(gp_line) = gp_line {
+        for (sample, gp_s) in samples.iter_mut().zip(gp_line) {
+            let ploidy = sample.ploidy() as usize;
+            let gp: Vec<f64> = gp_s
+                .split(',')
+                .filter_map(|p| p.parse().ok())
+                .filter(|p| (0.0..=1.0).contains(p))
+                .collect();
+            if ploidy == 0 || gp.len() != number_of_genotypes(ploidy, num_alleles) {
+                continue;
+            }
+            if let Some(probabilities) = copies_to_proba(&gp, num_bits) {
+                *sample = SampleProbabilities::unphased(probabilities);
+                sample.ploidy_missingness = ploidy as u8;
+            }
+        }
+    } else if let Some((likelihood_line, phred_scaled)) = likelihood_line {
+        for (sample, likelihood_s) in samples.iter_mut().zip(likelihood_line) {
+            let ploidy = sample.ploidy() as usize;
+            let Some(posteriors) = genotype_posteriors(
+                likelihood_s,
+                *phred_scaled,
+                &variant_data_to_parse.genotype_prior,
+                ploidy,
+                num_alleles,
+            ) else {
+                continue;
+            };
+            if let Some(probabilities) = copies_to_proba(&posteriors, num_bits) {
+                *sample = SampleProbabilities::unphased(probabilities);
+                sample.ploidy_missingness = ploidy as u8;
+            }
+        }
+    }
+    mask_samples(&mut samples, &variant_data_to_parse.masked_samples);
+    let mut multiallelic = variant_data.clone();
+    let variant_id_fmt = format_id_with_alleles(
+        &(multiallelic.chr.to_string() + ":" + &multiallelic.pos.to_string()),
+        &alleles[0],
+        &alleles[1..].join(","),
+    );
+    multiallelic.variants_id = variant_id_fmt.clone();
+    multiallelic.rsid = variant_id_fmt;
+    multiallelic.number_alleles = num_alleles as u16;
+    multiallelic.alleles = alleles;
+    let data_block = &mut multiallelic.data_block;
+    data_block.number_alleles = num_alleles as u16;
+    data_block.minimum_ploidy = samples
+        .iter()
+        .map(SampleProbabilities::ploidy)
+        .min()
+        .unwrap_or(2);
+    data_block.maximum_ploidy = samples
+        .iter()
+        .map(SampleProbabilities::ploidy)
+        .max()
+        .unwrap_or(2);
+    data_block.ploidy_missingness = samples.iter().map(|s| s.ploidy_missingness).collect();
+    data_block.probabilities = samples.into_iter().flat_map(|s| s.probabilities).collect();
+    Ok(vec![multiallelic])
+}
+
+pub fn is_reference_block(variant_data_to_parse: &VariantDataToParse<'_>) -> bool {
+    variant_data_to_parse.variant_data.alleles[1]
+        .split(',')
+        .all(|alt| NON_REF_ALLELES.contains(&alt))
+}
+
+pub fn expand_reference_block(
+    variant_data_to_parse: &VariantDataToParse<'_>,
+    number_individuals: u32,
+) -> Result<Vec<VariantData>, VcfError> {
+    let variant_data = &variant_data_to_parse.variant_data;
+    let end = match info_value(variant_data_to_parse.info, "END") {
+        Some(end) => end.parse().map_err(|_| {
+            VcfError::Validation(Report::msg(format!(
+                "Invalid END '{}' for reference block at {}:{}",
+                end, variant_data.chr, variant_data.pos
+            )))
+        })?,
+        None => variant_data.pos,
+    };
+    let num_bits = variant_data.data_block.bits_storage;
+    // no genotype can reference the allele past the last alt, so only ref calls are kept
+    let no_alt_num = variant_data.alleles[1].split(',').count() + 1;
+    let block_data = parse_vcf_geno(
+        variant_data_to_parse,
+        ".".to_string(),
+        no_alt_num,
+        num_bits,
+        number_individuals,
+    );
+    let vec_variant_data = (variant_data.pos..=end)
+        .map(|pos| {
+            let mut position_data = block_data.clone();
+            // the reference base is only known at the start of the block
+            if pos != variant_data.pos {
+                position_data.alleles[0] = "N".to_string();
+            }
+            position_data.pos = pos;
+            let variant_id_fmt = format_id_with_alleles(
+                &(position_data.chr.to_string() + ":" + &pos.to_string()),
+                &position_data.alleles[0],
+                ".",
+            );
+            position_data.variants_id = variant_id_fmt.clone();
+            position_data.rsid = variant_id_fmt;
+            position_data
+        })
+        .collect();
+    Ok(vec_variant_data)
+}
+
+/// A record cut before its last sample must not become a variant block
+pub fn check_genotype_count(
+    variant_data_to_parse: &VariantDataToParse<'_>,
     number_individuals: u32,
-    num_bits: u8,
 ) -> Result<(), VcfError> {
-    let mut line = String::new();
+    let genotype_count = variant_data_to_parse.geno_string_vcf.len();
+    if genotype_count == number_individuals as usize {
+        return Ok(());
+    }
+    let variant_data = &variant_data_to_parse.variant_data;
+    Err(VcfError::Nom(Report::msg(format!(
+        "Record at {}:{} has {} genotypes but the header lists {} samples",
+        variant_data.chr, variant_data.pos, genotype_count, number_individuals
+    ))))
+}
+
+/// Genotypes may not hold more alleles than `max_ploidy`, at most the 63 a bgen block can store
+pub fn check_ploidy(
+    variant_data_to_parse: &VariantDataToParse<'_>,
+    max_ploidy: u8,
+) -> Result<(), VcfError> {
+    let too_many_alleles = variant_data_to_parse
+        .geno_string_vcf
+        .iter()
+        .find(|geno| geno.split(['/', '|']).count() > max_ploidy as usize);
+    let Some(geno) = too_many_alleles else {
+        return Ok(());
+    };
+    let variant_data = &variant_data_to_parse.variant_data;
+    Err(VcfError::Validation(Report::msg(format!(
+        "Genotype {} at {}:{} has a ploidy above the maximum of {}",
+        geno, variant_data.chr, variant_data.pos, max_ploidy
+    ))))
+}
+
+/// Symbolic alleles of a record left out by the policy, or an error when they are rejected
+pub fn check_symbolic_alleles<'a>(
+    variant_data_to_parse: &'a VariantDataToParse<'_>,
+) -> Result<Vec<&'a str>, VcfError> {
+    let variant_data = &variant_data_to_parse.variant_data;
+    let symbolic: Vec<&str> = variant_data.alleles[1]
+        .split(',')
+        .filter(|alt| is_symbolic_allele(alt))
+        .collect();
+    match variant_data_to_parse.symbolic_alleles {
+        SymbolicAllelePolicy::Skip => Ok(symbolic),
+        SymbolicAllelePolicy::Encode => Ok(vec![]),
+        SymbolicAllelePolicy::Error => match symbolic.first() {
+            Some(alt) => Err(VcfError::Validation(Report::msg(format!(
+                "Symbolic allele {} at {}:{} can not be written to bgen: use --symbolic-alleles skip or encode",
+                alt, variant_data.chr, variant_data.pos
+            )))),
+            None => Ok(vec![]),
+        },
+    }
+}
+
+/// Genotypes of a record are phased when they use `|`, and none uses `/`
+pub fn genotypes_phased(genotypes: &[&str]) -> bool {
+    let mut separators = GenotypeSeparators::default();
+    genotypes
+        .iter()
+        .for_each(|genotype| separators.observe(genotype));
+    separators.phased && !separators.unphased
+}
+
+pub fn check_phased_genotypes(
+    variant_data_to_parse: &VariantDataToParse<'_>,
+) -> Result<(), VcfError> {
+    let variant_data = &variant_data_to_parse.variant_data;
+    let invalid_geno = variant_data_to_parse
+        .geno_string_vcf
+        .iter()
+        .enumerate()
+        .find(|(_, geno_s)| geno_s.contains('/') || geno_s.contains('.'));
+    match invalid_geno {
+        Some((sample_i, geno_s)) => Err(VcfError::Validation(Report::msg(format!(
+            "Genotype '{}' of sample {} at {}:{} is not phased and non-missing",
+            geno_s, sample_i, variant_data.chr, variant_data.pos
+        )))),
+        None => Ok(()),
+    }
+}
 
-    let bar = ProgressBar::new(number_geno_line as u64);
+/// Re-encode the probabilities of a variant with a different number of bits
+pub fn rescale_probabilities(variant_data: &VariantData, num_bits: u8) -> VariantData {
+    let mut rescaled = variant_data.clone();
+    let old_max = (1u64 << variant_data.data_block.bits_storage) - 1;
+    let new_max = (1u64 << num_bits) - 1;
+    let rescale = |proba: u32| ((proba as u64 * new_max + old_max / 2) / old_max) as u32;
+    // stored probabilities of a sample, or of a haplotype when phased, must not sum above the maximum
+    let clamp_sum = |probas: &[u32]| {
+        let mut remaining = new_max as u32;
+        probas
+            .iter()
+            .map(|&p| {
+                let p = rescale(p).min(remaining);
+                remaining -= p;
+                p
+            })
+            .collect::<Vec<u32>>()
+    };
+    let data_block = &variant_data.data_block;
+    rescaled.data_block.probabilities = if data_block.phased {
+        let num_alleles = (data_block.number_alleles as usize).max(2);
+        data_block
+            .probabilities
+            .chunks(num_alleles - 1)
+            .flat_map(clamp_sum)
+            .collect()
+    } else {
+        sample_probabilities(data_block)
+            .into_iter()
+            .flat_map(|(_, probas)| clamp_sum(probas))
+            .collect()
+    };
+    rescaled.data_block.bits_storage = num_bits;
+    rescaled
+}
+
+/// Fewest bits storing the probabilities of a block without changing them, such as 1 for hard calls
+pub fn lossless_num_bits(data_block: &DataBlock) -> u8 {
+    let max = (1u64 << data_block.bits_storage) - 1;
+    (1..data_block.bits_storage)
+        .find(|&num_bits| {
+            let new_max = (1u64 << num_bits) - 1;
+            data_block
+                .probabilities
+                .iter()
+                .all(|&proba| (proba as u64 * new_max).is_multiple_of(max))
+        })
+        .unwrap_or(data_block.bits_storage)
+}
+
+/// Fail on a number of bits per probability that bgen cannot store, outside 1 to 32
+pub fn check_num_bits(num_bits: u8) -> Result<(), VcfError> {
+    match (1..=MAX_NUM_BITS).contains(&num_bits) {
+        true => Ok(()),
+        false => Err(VcfError::Validation(Report::msg(format!(
+            "Probabilities are stored with 1 to {} bits, not {}",
+            MAX_NUM_BITS, num_bits
+        )))),
+    }
+}
+
+/// Fewest bits rounding probabilities by at most `max_error`
+///
+/// Stored probabilities move by half a step of the scale, and the implied last one of a biallelic
+/// genotype by a whole step, so the step itself must not exceed `max_error`.
+pub fn num_bits_for_error(max_error: f64) -> Result<u8, VcfError> {
+    // the last bits are kept to measure the error of the ones written
+    (1..MAX_NUM_BITS)
+        .find(|&num_bits| 1.0 / ((1u64 << num_bits) - 1) as f64 <= max_error)
+        .ok_or_else(|| {
+            VcfError::Validation(Report::msg(format!(
+                "No number of bits rounds probabilities by at most {:e}",
+                max_error
+            )))
+        })
+}
 
+/// Buffer every record, grouped by chromosome in order of first appearance
+pub fn regroup_chromosome_lines(
+    reader: &mut impl BufRead,
+    number_geno_line: u32,
+) -> Result<Vec<String>, VcfError> {
+    let mut chromosome_lines: Vec<(String, Vec<String>)> = vec![];
     for _geno_line in 0..number_geno_line {
+        let mut line = String::new();
         reader.read_line(&mut line)?;
-        let variant_data = parse_genotype_line(&line, number_individuals, num_bits)?;
-        let vec_variant_data = split_multiallelic(variant_data, number_individuals)?;
-        for var_data in vec_variant_data {
-            var_data.write_self(bgen_writer, 2)?;
+        let chr = line.split('\t').next().unwrap_or_default().to_string();
+        match chromosome_lines
+            .iter_mut()
+            .find(|(group_chr, _)| *group_chr == chr)
+        {
+            Some((_, lines)) => lines.push(line),
+            None => chromosome_lines.push((chr, vec![line])),
         }
-        bar.inc(1);
-        line.clear();
     }
-    bar.finish();
-    Ok(())
+    Ok(chromosome_lines
+        .into_iter()
+        .flat_map(|(_, lines)| lines)
+        .collect())
+}
+
+/// Compose the pipeline converting `input` to `output` and the extra outputs of `options`
+pub fn build_pipeline(
+    input: &str,
+    output: &str,
+    number_geno_line: u32,
+    options: &ConversionOptions,
+) -> Result<Pipeline, VcfError> {
+    build_pipeline_records(input, output, Some(number_geno_line), options, false)
+}
+
+/// Compose the pipeline checking every record of `input` as the conversion would, without
+/// creating any output
+pub fn build_dry_run_pipeline(
+    input: &str,
+    output: &str,
+    number_geno_line: u32,
+    options: &ConversionOptions,
+) -> Result<Pipeline, VcfError> {
+    build_pipeline_records(input, output, Some(number_geno_line), options, true)
+}
+
+/// Compose the pipeline converting `input` in a single read, or `None` when an option or output
+/// needs the records counted first
+pub fn build_single_pass_pipeline(
+    input: &str,
+    output: &str,
+    options: &ConversionOptions,
+) -> Result<Option<Pipeline>, VcfError> {
+    if options.regroup_chromosomes {
+        return Ok(None);
+    }
+    let pipeline = build_pipeline_records(input, output, None, options, false)?;
+    Ok(pipeline.single_pass().then_some(pipeline))
+}
+
+fn build_pipeline_records(
+    input: &str,
+    output: &str,
+    number_geno_line: Option<u32>,
+    options: &ConversionOptions,
+    dry_run: bool,
+) -> Result<Pipeline, VcfError> {
+    let output = &expand_input_stem(output, input);
+    // a dry run writes neither the skipped variant report nor the pseudonyms of the samples
+    let dry_run_options;
+    let options = match dry_run {
+        true => {
+            dry_run_options = ConversionOptions {
+                skipped_report: None,
+                anonymize: None,
+                ..options.clone()
+            };
+            &dry_run_options
+        }
+        false => options,
+    };
+    check_num_bits(options.num_bits)?;
+    let open_source = |options: &ConversionOptions| match number_geno_line {
+        Some(number_geno_line) => VcfSource::open(input, options, number_geno_line),
+        None => VcfSource::open_single_pass(input, options),
+    };
+    match options.layout {
+        1 if options.compression == BlockCompression::Zstd => {
+            return Err(VcfError::Validation(Report::msg(
+                "zstd compression needs bgen layout 2",
+            )))
+        }
+        1 if options.adaptive_num_bits || options.max_prob_error.is_some() => {
+            return Err(VcfError::Validation(Report::msg(
+                "Bgen layout 1 always stores 16-bit probabilities, choose the bits with layout 2",
+            )))
+        }
+        1 if options.multiallelic.is_whole() => {
+            return Err(VcfError::Validation(Report::msg(
+                "Bgen layout 1 only stores biallelic variants: split multiallelic records",
+            )))
+        }
+        1 | 2 => {}
+        layout => {
+            return Err(VcfError::Validation(Report::msg(format!(
+                "Bgen layout {} is not supported, use 1 or 2",
+                layout
+            ))))
+        }
+    }
+    if let Some(level) = options.compression_level {
+        if options.compression != BlockCompression::Zlib {
+            return Err(VcfError::Validation(Report::msg(
+                "A compression level only applies to zlib compression",
+            )));
+        }
+        if !(1..=9).contains(&level) {
+            return Err(VcfError::Validation(Report::msg(format!(
+                "Compression level {} is not between 1 and 9",
+                level
+            ))));
+        }
+    }
+    if options.resume && dry_run {
+        return Err(VcfError::Validation(Report::msg(
+            "A dry run checks the whole input: leave out --resume",
+        )));
+    }
+    if options.resume && options.checkpoint_every.is_none() {
+        return Err(VcfError::Validation(Report::msg(
+            "Resuming a conversion needs the interval of its checkpoints",
+        )));
+    }
+    if options.checkpoint_every.is_some() {
+        if options.write_index || options.skipped_report.is_some() {
+            return Err(VcfError::Validation(Report::msg(
+                "A checkpointed conversion cannot write a .bgi index or a skipped variant report",
+            )));
+        }
+        if options.checkpoint_every == Some(0) {
+            return Err(VcfError::Validation(Report::msg(
+                "Checkpoints need at least one variant between them",
+            )));
+        }
+    }
+    // options completed from the input: the bits meeting a rounding error, header provenance
+    let mut resolved = options.clone();
+    if options.provenance {
+        resolved.free_data = Some(header_provenance(input, options));
+    }
+    // probabilities are read with every bit, then rounded to the fewest meeting the error
+    let source = match options.max_prob_error {
+        Some(max_error) => {
+            if options.adaptive_num_bits {
+                return Err(VcfError::Validation(Report::msg(
+                    "The number of bits is either adaptive or chosen for a rounding error",
+                )));
+            }
+            resolved.num_bits = num_bits_for_error(max_error)?;
+            open_source(&ConversionOptions {
+                num_bits: MAX_NUM_BITS,
+                ..options.clone()
+            })?
+        }
+        None => open_source(options)?,
+    };
+    let options = &resolved;
+    let header_lines = source.header().header_lines;
+    let input_style = ContigStyle::detect(
+        source
+            .header()
+            .contigs
+            .iter()
+            .map(|contig| contig.id.as_str()),
+    );
+    if options.split_by_chr && options.shards.is_some() {
+        return Err(VcfError::Validation(Report::msg(
+            "Outputs are either split by chromosome or sharded, not both",
+        )));
+    }
+    // bgen outputs list the samples under their new names, other outputs keep the vcf names
+    let sample_names = bgen_sample_names(source.samples(), options)?;
+    let renamed = |sink: Box<dyn VariantSink>| -> Box<dyn VariantSink> {
+        match &sample_names {
+            Some(names) => Box::new(RenamedSamples::new(sink, names.clone())),
+            None => sink,
+        }
+    };
+    if options.output_format != OutputFormat::Bgen {
+        if options.split_by_chr || options.shards.is_some() {
+            return Err(VcfError::Validation(Report::msg(
+                "Only bgen outputs are split by chromosome or sharded",
+            )));
+        }
+        if options.multiallelic.is_whole() {
+            return Err(VcfError::Validation(Report::msg(format!(
+                "{:?} output needs biallelic variants: split multiallelic records",
+                options.output_format
+            ))));
+        }
+    }
+    check_output_template(output, options)?;
+    if let Some(dir) = &options.tmp_dir {
+        if !std::fs::metadata(dir).is_ok_and(|metadata| metadata.is_dir()) {
+            return Err(VcfError::Validation(Report::msg(format!(
+                "Temporary directory {} does not exist",
+                dir
+            ))));
+        }
+    }
+    if let Some(sample_size) = options.verify {
+        if options.output_format != OutputFormat::Bgen
+            || options.split_by_chr
+            || options.shards.is_some()
+        {
+            return Err(VcfError::Validation(Report::msg(
+                "--verify checks a single bgen output: leave out --split-by-chr and --shards",
+            )));
+        }
+        if options.checkpoint_every.is_some() {
+            return Err(VcfError::Validation(Report::msg(
+                "--verify samples variants from the start of the conversion: leave out --checkpoint-every",
+            )));
+        }
+        if sample_size == 0 {
+            return Err(VcfError::Validation(Report::msg(
+                "--verify decodes at least 1 variant",
+            )));
+        }
+    }
+    if options.shards == Some(0) {
+        return Err(VcfError::Validation(Report::msg(
+            "The number of shards must be at least 1",
+        )));
+    }
+    let dosage_outputs = options.dosage_matrix.is_some()
+        || options.allele_counts.is_some()
+        || options.truth_vcf.is_some();
+    if dosage_outputs && options.multiallelic.is_whole() {
+        return Err(VcfError::Validation(Report::msg(
+            "Dosage matrices, allele counts and truth comparisons need biallelic variants: split multiallelic records",
+        )));
+    }
+    if options.truth_vcf.is_some() != options.concordance_report.is_some() {
+        return Err(VcfError::Validation(Report::msg(
+            "A truth vcf and a concordance report must be given together",
+        )));
+    }
+    let mut pipeline = Pipeline::new(Box::new(source));
+    // looked up with the contigs named as in the input, before any renaming
+    if let Some(path) = &options.annotate_rsid {
+        let reference = RsidReference::read(path)?;
+        pipeline = pipeline.transform(Box::new(RsidAnnotationTransform::new(reference)));
+    }
+    if let Some(target_style) = options.target_style {
+        if input_style.is_none() {
+            warn!(
+                "contigs of {} mix naming styles, converting them to {:?} style",
+                input, target_style
+            );
+        }
+        // already in the target style, unless the header is missing or mixes styles
+        if input_style != Some(target_style) {
+            pipeline = pipeline.transform(Box::new(ContigStyleTransform::new(target_style)));
+        }
+    }
+    if let Some(threshold) = options.hard_call_threshold {
+        if !(0.0..=1.0).contains(&threshold) {
+            return Err(VcfError::Validation(Report::msg(format!(
+                "Hard call threshold {} is not between 0 and 1",
+                threshold
+            ))));
+        }
+        pipeline = pipeline.transform(Box::new(HardCallTransform::new(threshold)));
+    }
+    if options.fill_missing != FillMissing::None {
+        pipeline = pipeline.transform(Box::new(FillMissingTransform::new(options.fill_missing)));
+    }
+    if options.max_prob_error.is_some() {
+        pipeline = pipeline.transform(Box::new(RoundingTransform::new(options.num_bits)));
+    }
+    if !dry_run {
+        pipeline = add_sinks(pipeline, output, options, header_lines, renamed)?;
+        if let Some(every) = options.checkpoint_every {
+            let path = checkpoint_path(output, options.tmp_dir.as_deref());
+            if options.resume {
+                pipeline = pipeline.resume_from(Checkpoint::read(&path)?);
+            }
+            pipeline = pipeline.checkpoint_every(&path, every as u64, options.seed);
+        }
+    }
+    if let Some(max_variants) = options.max_variants {
+        pipeline = pipeline.max_variants(max_variants as u64);
+    }
+    if options.quiet {
+        pipeline = pipeline.quiet();
+    }
+    pipeline = pipeline.progress_format(options.progress_format);
+    if let Some(skip_variants) = options.skip_variants {
+        pipeline = pipeline.skip_variants(skip_variants as u64);
+    }
+    Ok(pipeline)
+}
+
+// Add the outputs of `options` to `pipeline`, the bgen ones first
+fn add_sinks(
+    mut pipeline: Pipeline,
+    output: &str,
+    options: &ConversionOptions,
+    header_lines: u32,
+    renamed: impl Fn(Box<dyn VariantSink>) -> Box<dyn VariantSink>,
+) -> Result<Pipeline, VcfError> {
+    let main_output: Box<dyn VariantSink> = if options.output_format == OutputFormat::Pgen {
+        Box::new(PgenOutput::create(output)?)
+    } else if options.output_format == OutputFormat::Gen {
+        Box::new(GenOutput::create(output)?)
+    } else if options.split_by_chr {
+        Box::new(ChromosomeSplitOutput::new(output, options))
+    } else if let Some(shards) = options.shards {
+        Box::new(ShardedOutput::new(output, shards, options))
+    } else {
+        Box::new(BgenOutput::create(output, options.num_bits, options)?)
+    };
+    pipeline = pipeline.sink(renamed(main_output));
+    // finished after the bgen output, so it reads the complete file
+    if let Some(sample_size) = options.verify {
+        pipeline = pipeline.sink(Box::new(VerifySink::new(output, sample_size, options.seed)));
+    }
+    for extra_output in &options.extra_outputs {
+        pipeline = pipeline.sink(renamed(Box::new(BgenOutput::create(
+            &extra_output.path,
+            extra_output.num_bits,
+            options,
+        )?)));
+    }
+    if let Some(path) = &options.dosage_matrix {
+        pipeline = pipeline.sink(Box::new(DosageMatrixWriter::create(
+            path,
+            options.dosage_matrix_max_variants,
+        )?));
+    }
+    if let Some(path) = &options.allele_counts {
+        pipeline = pipeline.sink(Box::new(AlleleCountWriter::create(path)?));
+    }
+    if let Some(path) = &options.variant_list {
+        pipeline = pipeline.sink(Box::new(VariantListWriter::create(path)?));
+    }
+    if let Some(path) = &options.variant_metadata {
+        pipeline = pipeline.sink(Box::new(VariantMetadataWriter::create(path, header_lines)?));
+    }
+    if let (Some(truth), Some(report)) = (&options.truth_vcf, &options.concordance_report) {
+        pipeline = pipeline.sink(Box::new(TruthComparison::create(truth, report)?));
+    }
+    if let Some(dir) = &options.per_sample_dir {
+        pipeline = pipeline.sink(Box::new(PerSampleBgenOutput::create(
+            dir,
+            options.num_bits,
+            options.max_open_files,
+            options,
+        )?));
+    }
+    Ok(pipeline)
 }
 
 pub fn convert_to_bgen(
@@ -239,62 +2813,168 @@ pub fn convert_to_bgen(
     output: &str,
     variant_num: u32,
     number_geno_line: u32,
-    num_bits: u8,
-) -> Result<(), VcfError> {
-    // reads vcf
-    let mut reader = BufReader::new(MultiGzDecoder::new(File::open(input)?));
-    // writes bgen
-    let mut bgen_writer = BufWriter::new(File::create(output)?);
-
-    // get samples from header
-    let samples = read_vcf_header(&mut reader)?;
-    let number_individuals = samples.len() as u32;
-
-    // write header and samples
-    write_bgen_header(&mut bgen_writer, &samples, number_individuals, variant_num)?;
-
-    // write variant blocks
-    println!("Converting variants to bgen format");
-    convert_variant_blocks(
-        &mut reader,
-        &mut bgen_writer,
-        number_geno_line,
-        number_individuals,
-        num_bits,
-    )
+    options: &ConversionOptions,
+) -> Result<ConversionMetrics, VcfError> {
+    let start = Instant::now();
+    let mut pipeline = build_pipeline(input, output, number_geno_line, options)?;
+    info!("Converting variants to bgen format");
+    let mut metrics = pipeline.run(variant_num, number_geno_line)?;
+    metrics.add_phase("convert", start.elapsed());
+    print_summary(&metrics, options)?;
+    Ok(metrics)
 }
 
-fn genos_to_proba(genos: &[u32], num_bits: u8) -> Vec<u32> {
-    let sum = genos[0] + genos[1];
-    let proba_1 = (1 << num_bits) - 1;
-    let result = if sum == 0 {
-        [proba_1, 0]
-    } else if sum == 1 {
-        [0, proba_1]
-    } else {
-        [0, 0]
+/// Convert reading the input once, or return `None` when the outputs need the variants counted first
+pub fn convert_to_bgen_single_pass(
+    input: &str,
+    output: &str,
+    options: &ConversionOptions,
+) -> Result<Option<ConversionMetrics>, VcfError> {
+    let start = Instant::now();
+    let Some(mut pipeline) = build_single_pass_pipeline(input, output, options)? else {
+        return Ok(None);
     };
-    result.to_vec()
+    info!("Converting variants to bgen format in a single pass");
+    let mut metrics = pipeline.run_single_pass()?;
+    metrics.add_phase("convert", start.elapsed());
+    print_summary(&metrics, options)?;
+    Ok(Some(metrics))
+}
+
+// Events logged after the conversion, for what it skipped or rounded
+fn print_summary(metrics: &ConversionMetrics, options: &ConversionOptions) -> Result<(), VcfError> {
+    if metrics.breakends_skipped > 0 {
+        info!("Skipped {} breakend records", metrics.breakends_skipped);
+    }
+    for (filter, count) in &metrics.filters_skipped {
+        info!("Skipped {} records with FILTER {}", count, filter);
+    }
+    if options.annotate_rsid.is_some() {
+        info!(
+            "Annotated {} variants with rsIDs of the dbSNP reference",
+            metrics.rsids_annotated
+        );
+    }
+    if options
+        .max_variants
+        .is_some_and(|max| metrics.variants_processed >= max as u64)
+    {
+        info!(
+            "Stopped after {} variants, the --max-variants limit",
+            metrics.variants_processed
+        );
+    }
+    if let (Some(max_error), Some(achieved)) = (options.max_prob_error, metrics.max_prob_error) {
+        info!(
+            "Largest probability rounding error: {:.3e} with {} bits",
+            achieved,
+            num_bits_for_error(max_error)?
+        );
+        // the implied probabilities of multiallelic or polyploid genotypes add up more rounding
+        if achieved > max_error {
+            warn!(
+                "some probabilities were rounded by more than {:e}",
+                max_error
+            );
+        }
+    }
+    Ok(())
+}
+
+fn genos_to_proba(genos: &[u32], num_bits: u8) -> Vec<u32> {
+    // one stored probability per number of alt copies, the last one (all alt) is implied
+    let alt_copies = genos.iter().sum::<u32>() as usize;
+    let proba_1 = ((1u64 << num_bits) - 1) as u32;
+    (0..genos.len())
+        .map(|copies| if copies == alt_copies { proba_1 } else { 0 })
+        .collect()
 }
 
-fn parse_samples(input: &str) -> IResult<&str, Vec<&str>> {
-    preceded(
-        preceded(tag("#"), many0(preceded(alpha0, tab))),
-        separated_list0(tab, alphanumeric0),
-    )(input)
+fn haplotypes_to_proba(genos: &[u32], num_bits: u8) -> Vec<u32> {
+    // phased layout stores the probability of the first allele for each haplotype
+    let proba_1 = ((1u64 << num_bits) - 1) as u32;
+    genos
+        .iter()
+        .map(|&allele| if allele == 0 { proba_1 } else { 0 })
+        .collect()
 }
 
 fn parse_one_field(input: &str) -> Result<(&str, &str), VcfError> {
     Ok(terminated(is_not("\t"), char('\t'))(input)?)
 }
 
-fn alt_allele_count(input: &str) -> Result<u32, VcfError> {
+// Like parse_one_field, but the field may also end the line
+fn parse_last_field(input: &str) -> (&str, &str) {
+    parse_one_field(input).unwrap_or(("", input))
+}
+
+/// FILTER of the record on a vcf line, unless it passed (`PASS`) or was not filtered (`.`)
+pub fn failed_filter(line: &str) -> Option<&str> {
+    trim_record(line)
+        .split('\t')
+        .nth(6)
+        .filter(|filter| !["PASS", "."].contains(filter))
+}
+
+pub(crate) fn line_variant_count(
+    input: &str,
+    options: &ConversionOptions,
+) -> Result<u32, VcfError> {
+    let input = trim_record(input);
     let (remaining_input, _) = parse_one_field(input)?;
+    let (remaining_input, pos) = parse_one_field(remaining_input)?;
     let (remaining_input, _) = parse_one_field(remaining_input)?;
     let (remaining_input, _) = parse_one_field(remaining_input)?;
+    let (remaining_input, alt_alleles) = parse_one_field(remaining_input)?;
+    // breakend records are skipped whole
+    if alt_alleles.split(',').any(is_breakend_allele) {
+        return Ok(0);
+    }
+    if alt_alleles == NO_ALT {
+        return Ok((options.monomorphic == MonomorphicPolicy::Encode) as u32);
+    }
+    let alt_count = alt_alleles
+        .split(',')
+        .filter(|alt| is_written_alt(alt, options.symbolic_alleles))
+        .count() as u32;
+    if alt_count > 0 && options.multiallelic.is_whole() {
+        return Ok(1);
+    }
+    let reference_block = alt_alleles
+        .split(',')
+        .all(|alt| NON_REF_ALLELES.contains(&alt));
+    if !reference_block || options.ref_blocks == RefBlockMode::Skip {
+        return Ok(alt_count);
+    }
+    // reference block, expanded to one variant per position
+    let (remaining_input, _) = parse_one_field(remaining_input)?;
     let (remaining_input, _) = parse_one_field(remaining_input)?;
-    let (_remaining_input, alt_alleles) = parse_one_field(remaining_input)?;
-    Ok(alt_alleles.chars().filter(|&c| c == ',').count() as u32 + 1)
+    let (_remaining_input, info) = parse_last_field(remaining_input);
+    let pos: u32 = pos.parse().map_err(|_| invalid_position(pos))?;
+    let end = info_value(info, "END")
+        .and_then(|end| end.parse().ok())
+        .unwrap_or(pos);
+    Ok(end.saturating_sub(pos) + 1)
+}
+
+fn invalid_position(pos: &str) -> VcfError {
+    VcfError::Nom(Report::msg(format!(
+        "POS {} is not a position",
+        snippet(pos)
+    )))
+}
+
+pub fn trim_record(input: &str) -> &str {
+    input.trim_end_matches(TRAILING_CHARS)
+}
+
+fn info_value<'a>(info: &'a str, key: &str) -> Option<&'a str> {
+    info.split(';').find_map(|entry| {
+        entry
+            .split_once('=')
+            .filter(|(entry_key, _)| *entry_key == key)
+            .map(|(_, value)| value)
+    })
 }
 
 pub fn parse_genotype_line(
@@ -302,13 +2982,28 @@ pub fn parse_genotype_line(
     number_individuals: u32,
     num_bits: u8,
 ) -> Result<VariantDataToParse<'_>, VcfError> {
+    let input = trim_record(input);
     let (remaining_input, chr) = parse_one_field(input)?;
     let (remaining_input, pos) = parse_one_field(remaining_input)?;
     let (remaining_input, variant_id) = parse_one_field(remaining_input)?;
     let (remaining_input, a1) = parse_one_field(remaining_input)?;
     let (remaining_input, a2) = parse_one_field(remaining_input)?;
-    let genos_string = parse_genotype_field(remaining_input)?.1;
+    let (remaining_input, _qual) = parse_one_field(remaining_input)?;
+    let (remaining_input, _filter) = parse_one_field(remaining_input)?;
+    let (remaining_input, info) = parse_last_field(remaining_input);
+    // sites-only records end with INFO
+    let (format, samples) = if remaining_input.is_empty() {
+        ("", vec![])
+    } else {
+        parse_genotype_field(remaining_input)?.1
+    };
+    // without GT in FORMAT, every hard call is missing
+    let genos_string =
+        format_subfield(format, "GT", &samples).unwrap_or_else(|| vec!["."; samples.len()]);
+    let gp_string = format_subfield(format, "GP", &samples);
     let variant_id_fmt = format_id_with_alleles(variant_id, a1, a2);
+    // GP describes unphased genotypes, so only records without it can be written phased
+    let phased_genotypes = gp_string.is_none() && genotypes_phased(&genos_string);
     let data_block = DataBlock {
         number_individuals,
         number_alleles: 2,
@@ -325,7 +3020,7 @@ pub fn parse_genotype_line(
         variants_id: variant_id_fmt.to_string(),
         rsid: variant_id_fmt.to_string(),
         chr: chr.to_string(),
-        pos: pos.parse().unwrap(),
+        pos: pos.parse().map_err(|_| invalid_position(pos))?,
         number_alleles: 2,
         alleles: vec![a1.to_string(), a2.to_string()],
         file_start_position: 0,
@@ -335,6 +3030,20 @@ pub fn parse_genotype_line(
     let variant_data_to_parse = VariantDataToParse {
         variant_data,
         geno_string_vcf: genos_string,
+        gp_string_vcf: gp_string,
+        dosage_string_vcf: None,
+        likelihood_string_vcf: None,
+        genotype_prior: vec![],
+        sample_ploidy: vec![],
+        phased_genotypes,
+        missing_policy: MissingPolicy::default(),
+        half_call_policy: HalfCallPolicy::default(),
+        spanning_deletion: SpanningDeletionPolicy::default(),
+        symbolic_alleles: SymbolicAllelePolicy::default(),
+        masked_samples: vec![],
+        format,
+        sample_fields: samples,
+        info,
     };
     Ok(variant_data_to_parse)
 }
@@ -344,28 +3053,26 @@ fn parser_elt_tab(input: &str) -> IResult<&str, &str> {
     terminated(until_tab, tab)(input)
 }
 
-fn parser_elt_colon(input: &str) -> IResult<&str, &str> {
-    terminated(is_not(":"), tag(":"))(input)
+fn parse_genotype_field(input: &str) -> IResult<&str, (&str, Vec<&str>)> {
+    // Genotype starts at column 9, 8 fields are already read
+    // Gives Format field, and remaining line is left to parse
+    let (remaining_string, format) = parser_elt_tab(input)?;
+    // the record is trimmed, so the last sample must end exactly at the end of the input
+    let (remaining_string, samples) =
+        terminated(separated_list0(tab, take_while1(|c| c != '\t')), eof)(remaining_string)?;
+    Ok((remaining_string, (format, samples)))
 }
 
-fn parse_genotype_field(input: &str) -> IResult<&str, Vec<&str>> {
-    let until_tab = take_while1(|c| c != '\t');
-    // Genotype starts at column 9, 5 lines are already read
-    let mut before_genotype_parser = preceded(count(parser_elt_tab, 3), parser_elt_tab);
-    // Gives Format field, and remaining line is left to parse
-    let parse_line_start = before_genotype_parser(input).unwrap();
-    // Format like GT:GP..
-    let remaining_string = parse_line_start.0;
-    let format = parse_line_start.1;
-    let gt_position = format.split(':').position(|s| s == "GT").unwrap();
-
-    // let parse_geno = delimited(count(parser_elt_colon, gt_position), take(3u8), is_not("\t"));
-    let parse_geno = delimited(
-        count(parser_elt_colon, gt_position),
-        take(3u8),
-        alt((until_tab, success("1"))),
-    );
-    separated_list0(tab, parse_geno)(remaining_string)
+// Value of `key` for each sample, when FORMAT (like GT:GP..) has it
+fn format_subfield<'a>(format: &str, key: &str, samples: &[&'a str]) -> Option<Vec<&'a str>> {
+    let position = format.split(':').position(|s| s == key)?;
+    // trailing subfields may be dropped from a sample, the value is then missing
+    Some(
+        samples
+            .iter()
+            .map(|sample| sample.split(':').nth(position).unwrap_or("."))
+            .collect(),
+    )
 }
 
 fn format_id_with_alleles(id: &str, a1: &str, a2: &str) -> String {
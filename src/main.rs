@@ -1,31 +1,801 @@
-use clap::Parser;
-use vcf_to_bgen::{convert_to_bgen, count_variants, VcfError};
+use clap::{Parser, Subcommand};
+use std::fs::File;
+use std::io::IsTerminal;
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::level_filters::LevelFilter;
+use tracing::{warn, Event, Level, Subscriber};
+use tracing_subscriber::fmt;
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::prelude::*;
+use vcf_to_bgen::bgen_file::read_bgen_summary;
+use vcf_to_bgen::converter::Converter;
+use vcf_to_bgen::output_template::expand_input_stem;
+use vcf_to_bgen::retry::RetryPolicy;
+use vcf_to_bgen::sample_file::check_pair;
+use vcf_to_bgen::skipped::skipped_report_path;
+use vcf_to_bgen::{
+    check_num_bits, BlockCompression, ContigStyle, ConversionOptions, DuplicatePolicy, ExtraOutput,
+    FillMissing, GenomeBuild, GenotypeField, GenotypePrior, HalfCallPolicy, MissingPolicy,
+    MonomorphicPolicy, MultiallelicMode, OutputFormat, ProgressFormat, ReadBackend, RefBlockMode,
+    SampleDedupPolicy, ShardMode, SpanningDeletionPolicy, SymbolicAllelePolicy, VcfError,
+    ADAPTIVE_MAX_NUM_BITS, EXIT_CANCELLED,
+};
+
+const EXIT_CODES: &str = "Exit codes:
+  0    success
+  1    other failure
+  2    invalid arguments
+  3    input or output failure
+  4    malformed input
+  5    input or options rejected by a check
+  6    failure encoding the bgen or its index
+  130  cancelled by Ctrl-C";
 
 #[derive(Parser, Debug)]
+#[command(
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true,
+    after_help = EXIT_CODES
+)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Path to the input vcf file
-    #[arg(short, long)]
-    input: String,
+    #[arg(short, long, required = true)]
+    input: Option<String>,
+
+    /// Path to the output bgen file, where {input_stem} is the input name without .vcf.gz, and
+    /// {chr} or {shard} name each file with --split-by-chr or --shards
+    #[arg(short, long, required = true)]
+    output: Option<String>,
+
+    /// Number of bits used for probability storage, or auto for the fewest keeping each variant
+    /// exact, up to 16
+    #[arg(long, value_parser = parse_num_bits)]
+    num_bits: Option<NumBits>,
+
+    /// Largest rounding error of GP and DS probabilities, such as 1e-4, picking the fewest bits
+    /// meeting it instead of --num-bits
+    #[arg(long, conflicts_with_all = ["num_bits", "reference_panel", "legacy"])]
+    max_prob_error: Option<f64>,
+
+    /// Convert a phased reference panel: 1-bit phased haplotypes, failing on unphased or missing genotypes
+    #[arg(long, conflicts_with = "num_bits")]
+    reference_panel: bool,
+
+    /// Write files for legacy qctool v1 and SNPTEST v2: layout 1, zlib and 16-bit probabilities
+    #[arg(long, conflicts_with_all = ["num_bits", "reference_panel", "layout", "phased"])]
+    legacy: bool,
+
+    /// Write phased haplotypes (the default when every GT uses '|' and there is no GP)
+    #[arg(long, conflicts_with = "unphased")]
+    phased: bool,
+
+    /// Write unphased genotype probabilities, even when every GT is phased
+    #[arg(long)]
+    unphased: bool,
+
+    /// Handling of gVCF reference blocks; <NON_REF> alleles are always dropped
+    #[arg(long, value_enum, default_value_t = RefBlockMode::Skip)]
+    gvcf_ref_blocks: RefBlockMode,
+
+    /// Buffer and regroup records by chromosome when the input interleaves chromosomes
+    #[arg(long)]
+    regroup_chromosomes: bool,
+
+    /// Library used to read the input (htslib also reads bcf)
+    #[arg(long, value_enum, default_value_t = ReadBackend::Native)]
+    backend: ReadBackend,
+
+    /// Only convert this region (chr:start-end), using the csi/tbi index (htslib backend)
+    #[arg(long)]
+    fetch_region: Option<String>,
+
+    /// Only convert records in these chromosomes or intervals, e.g. 22:16000000-18000000,21
+    #[arg(long)]
+    regions: Option<String>,
+
+    /// Only convert records in the intervals of this BED file (e.g. exome targets)
+    #[arg(long, conflicts_with = "regions")]
+    regions_file: Option<String>,
+
+    /// Only convert the variants of this list, one input ID or chr:pos:ref:alt per line
+    #[arg(long)]
+    extract: Option<String>,
+
+    /// Leave out the variants of this list, one input ID or chr:pos:ref:alt per line
+    #[arg(long)]
+    exclude: Option<String>,
+
+    /// Also write this bgen from the same pass, as PATH:NUM_BITS (e.g. analysis.bgen:8)
+    #[arg(long, value_parser = parse_extra_output)]
+    extra_output: Vec<ExtraOutput>,
+
+    /// Export alt allele dosages (variants x samples) to this gzipped tsv
+    #[arg(long)]
+    export_dosage_matrix: Option<String>,
+
+    /// Largest number of variants allowed in the dosage matrix
+    #[arg(long, default_value_t = 10_000)]
+    dosage_matrix_max_variants: u32,
+
+    /// FORMAT field with alt allele dosages (e.g. DS from minimac or Beagle) to encode instead of GT
+    #[arg(long)]
+    dosage_field: Option<String>,
+
+    /// FORMAT fields to write genotypes from, in order of preference (e.g. ds,gp,gt)
+    #[arg(long, value_enum, value_delimiter = ',')]
+    gt_priority: Vec<GenotypeField>,
+
+    /// Convert GP or DS probabilities to hard calls where the most likely genotype exceeds this
+    /// probability (e.g. 0.9), writing the sample as missing otherwise
+    #[arg(long)]
+    hard_call_threshold: Option<f64>,
+
+    /// Write missing genotypes as homozygous reference or with the mean dosage of the variant,
+    /// instead of setting their missingness flag
+    #[arg(long, value_enum, default_value_t = FillMissing::None)]
+    fill_missing: FillMissing,
+
+    /// Bgen layout: 2, or 1 for older tools such as SNPTEST classic workflows (biallelic,
+    /// diploid, 16-bit probabilities)
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..=2))]
+    layout: Option<u8>,
+
+    /// Compression of the variant blocks: zlib, zstd (bgen 1.3, smaller and faster to write), or
+    /// none for readers that memory-map the file
+    #[arg(long, value_enum, default_value_t = BlockCompression::Zlib, conflicts_with = "legacy")]
+    compression: BlockCompression,
+
+    /// Zlib level of the variant blocks, from 1 (fastest, slightly larger files) to 9 (smallest)
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..=9))]
+    compression_level: Option<u32>,
+
+    /// Leave sample identifiers out of the bgen, writing no sample block
+    #[arg(long)]
+    no_sample_ids: bool,
+
+    /// Tab-separated OLD NEW sample names, the new names being written in the bgen sample block
+    #[arg(long, conflicts_with = "anonymize")]
+    rename_samples: Option<String>,
+
+    /// Write sequential pseudonyms in the bgen sample block, and each sample's pseudonym to
+    /// this tsv
+    #[arg(long)]
+    anonymize: Option<String>,
+
+    /// Only write the samples of this list, one ID (or FID IID) per line
+    #[arg(long)]
+    keep: Option<String>,
+
+    /// Leave out the samples of this list, one ID (or FID IID) per line
+    #[arg(long)]
+    remove: Option<String>,
+
+    /// Write the samples in the order of this .sample file, e.g. that of another dataset
+    #[arg(long)]
+    sample_order: Option<String>,
+
+    /// Format of the output: bgen, pgen for a PLINK 2 .pgen/.pvar/.psam fileset of hard calls, or
+    /// gen for Oxford .gen text with a .sample file
+    #[arg(long, value_enum, default_value_t = OutputFormat::Bgen)]
+    output_format: OutputFormat,
+
+    /// Write OUTPUT.md5 and OUTPUT.sha256 next to each bgen output, computed while it is written
+    #[arg(long)]
+    checksums: bool,
+
+    /// Write one bgen per chromosome, the output being a template such as out_chr{chr}.bgen
+    #[arg(long)]
+    split_by_chr: bool,
+
+    /// Spread the variants over this many bgen files (out.shard0.bgen, ..., or out_{shard}.bgen
+    /// replaced by out_0.bgen, ...), listed in out.shards.tsv
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..), conflicts_with = "split_by_chr")]
+    shards: Option<u32>,
+
+    /// Assignment of variants to shards: round-robin, or consecutive blocks
+    #[arg(long, value_enum, default_value_t = ShardMode::RoundRobin, requires = "shards")]
+    shard_mode: ShardMode,
+
+    /// Also write a bgenix .bgi index of each bgen output, named after it
+    #[arg(long)]
+    index: bool,
+
+    /// Write byte-identical outputs for identical inputs and options, so they can be compared by
+    /// hash: fixed compression settings and no timestamps or clock-drawn seed
+    #[arg(long)]
+    deterministic: bool,
+
+    /// Read the input once instead of counting variants first, patching the count in the bgen
+    /// header; falls back to two reads for outputs needing the count up front
+    #[arg(long)]
+    single_pass: bool,
+
+    /// Leave the tool, command line, input sha256 and creation time out of the bgen header
+    #[arg(long)]
+    no_provenance: bool,
+
+    /// Derive probabilities from PL or GL likelihoods, with this prior, for records without GP
+    #[arg(long, value_enum)]
+    genotype_likelihoods: Option<GenotypePrior>,
+
+    /// Contig naming style of the output (ucsc: chr1, ensembl: 1), converted when the input differs
+    #[arg(long, value_enum)]
+    target_style: Option<ContigStyle>,
+
+    /// Ploidy per sample and region (whitespace-separated SAMPLE CHROM FROM TO PLOIDY rows)
+    #[arg(long)]
+    ploidy_map: Option<String>,
+
+    /// Sex of each sample (tab-separated SAMPLE M/F), writing male chrX genotypes as haploid
+    #[arg(long)]
+    sex_file: Option<String>,
+
+    /// Genome build of the input, whose pseudo-autosomal regions of chrX stay diploid for males
+    #[arg(long, value_enum, default_value_t = GenomeBuild::Grch38)]
+    genome_build: GenomeBuild,
+
+    /// Split multiallelic records into biallelic variants, keep them as one variant, or keep the
+    /// most frequent alt and merge the others into <OTHER>
+    #[arg(long, value_enum, default_value_t = MultiallelicMode::Split)]
+    multiallelic: MultiallelicMode,
+
+    /// Carriers of the spanning deletion allele '*': missing, or reference once '*' is dropped
+    #[arg(long, value_enum, default_value_t = SpanningDeletionPolicy::Missing)]
+    spanning_deletion: SpanningDeletionPolicy,
+
+    /// Symbolic alleles (<DEL>, <DUP>, <CNV>...): leave out, write as alleles, or fail
+    #[arg(long, value_enum, default_value_t = SymbolicAllelePolicy::Skip)]
+    symbolic_alleles: SymbolicAllelePolicy,
+
+    /// Write genotypes whose FORMAT/GQ is below this value as missing
+    #[arg(long)]
+    min_gq: Option<u32>,
+
+    /// Leave out biallelic variants, after splitting, whose minor allele is carried fewer than N
+    /// times; genotypes are then read when counting variants too
+    #[arg(long = "mac", value_name = "N")]
+    min_mac: Option<u32>,
+
+    /// Leave out records whose FILTER is neither PASS nor `.`, counting them by FILTER value
+    #[arg(long)]
+    pass_only: bool,
+
+    /// Only write SNPs, whose alleles are single A, C, G or T
+    #[arg(long, conflicts_with = "indels_only")]
+    snps_only: bool,
+
+    /// Only write indels, whose alleles are base sequences of different lengths
+    #[arg(long)]
+    indels_only: bool,
+
+    /// Leave out variants with an allele longer than N bases
+    #[arg(long, value_name = "N")]
+    max_allele_len: Option<u32>,
+
+    /// Only write variants whose INFO passes this expression of comparisons joined by && and ||,
+    /// e.g. "AF>0.001 && INFO/R2>=0.8"; per-alt lists are compared on each split alt
+    #[arg(long, value_name = "EXPRESSION")]
+    info_filter: Option<String>,
+
+    /// Leave out variants whose every sample is missing, as split alts can be; genotypes are
+    /// then read when counting variants too
+    #[arg(long)]
+    drop_all_missing: bool,
 
-    /// Path to the output bgen file
+    /// Keep every Nth variant of those passing the other filters, for quick test sets
+    #[arg(long, value_name = "N")]
+    thin: Option<u32>,
+
+    /// Keep variants at least BP bases from the last one kept on their chromosome
+    #[arg(long, value_name = "BP")]
+    thin_bp: Option<u32>,
+
+    /// Keep a random FRACTION of the variants, the same ones for the same --seed
+    #[arg(long, value_name = "FRACTION")]
+    sample_variants: Option<f64>,
+
+    /// Show no progress bar or status messages on stderr, only warnings and errors; progress is
+    /// logged as plain status lines when stderr is not a terminal
     #[arg(short, long)]
-    output: String,
+    quiet: bool,
+
+    /// Show progress as text, or as JSON lines on stderr with the variants done, their total and
+    /// the seconds left, written even with --quiet
+    #[arg(long, value_enum, default_value_t = ProgressFormat::Text)]
+    progress_format: ProgressFormat,
+
+    /// Most detailed messages logged, on stderr and in the log file
+    #[arg(long, value_enum, default_value_t = LogLevel::Info)]
+    log_level: LogLevel,
+
+    /// Also write the log to this file, with timestamps and whatever --quiet hides from stderr
+    #[arg(long)]
+    log_file: Option<String>,
+
+    /// Read and check the whole input as the conversion would, then print the samples, variants
+    /// and warnings instead of writing any output
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Threads parsing records and compressing variant blocks, every core available to the
+    /// process (cgroup limits included) when unset; the input is decompressed as it is read
+    #[arg(long, value_name = "N")]
+    threads: Option<u32>,
+
+    /// Stop after writing N variants, the bgen declaring those written, to check settings quickly
+    #[arg(long, value_name = "N")]
+    max_variants: Option<u32>,
+
+    /// Leave out the first N variants, to restart a run partway or split it across jobs with
+    /// --max-variants
+    #[arg(long, value_name = "N")]
+    skip_variants: Option<u32>,
+
+    /// Leave out the records before the first one at CHR:POS in the input
+    #[arg(long, value_name = "CHR:POS")]
+    start_position: Option<String>,
+
+    /// Write genotypes whose FORMAT/DP is below this value as missing
+    #[arg(long)]
+    min_dp: Option<u32>,
+
+    /// Write genotypes whose FORMAT/DP is above this value as missing
+    #[arg(long)]
+    max_dp: Option<u32>,
+
+    /// Records whose ALT is '.': leave out, or write with the placeholder alt 'N'
+    #[arg(long, value_enum, default_value_t = MonomorphicPolicy::Skip)]
+    monomorphic: MonomorphicPolicy,
+
+    /// Variants repeating the chr:pos:ref:alt of an earlier one, ambiguous in a bgenix index:
+    /// fail, write the first only, or write them all
+    #[arg(long, value_enum, default_value_t = DuplicatePolicy::KeepAll)]
+    dup_policy: DuplicatePolicy,
+
+    /// Sample IDs repeated in the vcf header: fail, or number each occurrence as ID_1, ID_2
+    #[arg(long, value_enum, default_value_t = SampleDedupPolicy::Error)]
+    dedup_samples: SampleDedupPolicy,
+
+    /// Write bgen files and the checkpoint in this directory until they are complete, instead of
+    /// the directory of each output; partial files are removed when the conversion fails or is
+    /// cancelled, and --resume needs the same directory. --regroup-chromosomes holds the records
+    /// in memory, spilling nothing to disk
+    #[arg(long, value_name = "DIR")]
+    tmp_dir: Option<String>,
+
+    /// List the alleles and records left out of the bgen, with their input line and the reason,
+    /// in this tsv rather than OUTPUT.skipped.tsv, written unless checkpointing
+    #[arg(long, value_name = "PATH", num_args = 0..=1)]
+    skipped_report: Option<Option<String>>,
+
+    /// Write no list of the alleles and records left out of the bgen
+    #[arg(long, conflicts_with = "skipped_report")]
+    no_skipped_report: bool,
+
+    /// Encoding of genotypes without any called allele (./. or .)
+    #[arg(long, value_enum, default_value_t = MissingPolicy::Missing)]
+    missing_policy: MissingPolicy,
+
+    /// Encoding of half-called genotypes (./1): missing, haploid 1, or 0/1
+    #[arg(long, value_enum, default_value_t = HalfCallPolicy::Missing)]
+    half_call_policy: HalfCallPolicy,
+
+    /// Largest ploidy accepted in GT (e.g. 4 for tetraploid crops), at most 63
+    #[arg(long, default_value_t = 63, value_parser = clap::value_parser!(u8).range(1..=63))]
+    max_ploidy: u8,
+
+    /// Write AC, AN and AF of every converted variant, counted from its genotypes, to this tsv
+    #[arg(long)]
+    allele_counts: Option<String>,
+
+    /// List every variant written, with the ids of split multiallelics, in this .bim file (or
+    /// .pvar when the name ends in .pvar)
+    #[arg(long)]
+    variant_list: Option<String>,
+
+    /// Write chr, pos, input and written ids, alleles, alt frequency, missing rate and input line
+    /// of every variant to this tsv (or json array when the name ends in .json)
+    #[arg(long)]
+    variant_metadata: Option<String>,
+
+    /// Save a checkpoint to OUTPUT.ckpt (in --tmp-dir when set) every N variants, so an
+    /// interrupted conversion can resume
+    #[arg(long, value_name = "N")]
+    checkpoint_every: Option<u32>,
+
+    /// Continue an interrupted conversion from its checkpoint, dropping blocks written after it
+    #[arg(long, requires = "checkpoint_every")]
+    resume: bool,
+
+    /// Overwrite outputs that already exist, such as the bgen and its .sample and .bgi files
+    #[arg(long)]
+    force: bool,
+
+    /// Decode N random variants of the bgen once written (1000 when N is left out) and fail when
+    /// one differs from the converted vcf record
+    #[arg(long, value_name = "N", num_args = 0..=1, default_missing_value = "1000")]
+    verify: Option<u32>,
+
+    /// Also write one single-sample bgen per sample, named SAMPLE.bgen, to this directory
+    #[arg(long)]
+    per_sample_dir: Option<String>,
+
+    /// Largest number of per-sample bgen files kept open at once
+    #[arg(long, default_value_t = 256)]
+    max_open_files: usize,
+
+    /// Compare hard calls and dosages of overlapping samples and variants against this truth vcf
+    #[arg(long, requires = "concordance_report")]
+    truth_vcf: Option<String>,
+
+    /// Write concordance and r2 with the truth vcf, by minor allele frequency bin, to this tsv
+    #[arg(long, requires = "truth_vcf")]
+    concordance_report: Option<String>,
+
+    /// Convert a vcf without sample columns to a bgen with an empty sample block
+    #[arg(long)]
+    allow_sites_only: bool,
+
+    /// Seed of every random choice (drawn from the clock when unset, and recorded in the manifest)
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Write a provenance manifest (version, outputs, seed, variant order, options) to this file
+    #[arg(long)]
+    manifest: Option<String>,
+
+    /// Fill missing IDs with the rsIDs of a tab-separated CHROM POS REF ALT RSID reference such as
+    /// one derived from dbSNP, gzipped or not; input IDs are written as rsIDs
+    #[arg(long, value_name = "TSV")]
+    annotate_rsid: Option<String>,
+
+    /// Write a json summary (variants written and skipped by reason, samples, missingness,
+    /// seconds per phase) to this file after the conversion
+    #[arg(long)]
+    summary_json: Option<String>,
+
+    /// Retries of a read failing with a transient error (EIO from network filesystems)
+    #[arg(long, default_value_t = 3)]
+    read_retries: u32,
+
+    /// Wait before the first retry of a failed read, doubled after each attempt
+    #[arg(long, default_value_t = 1000)]
+    retry_backoff_ms: u64,
 
-    /// Number of bits used for probability storage
+    /// Write conversion metrics to this file, in Prometheus textfile exporter format
     #[arg(long)]
-    num_bits: Option<u8>,
+    metrics_file: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Check that a .sample file matches the samples of a bgen file (count, order and IDs)
+    CheckPair {
+        /// Path to the .sample file
+        #[arg(short, long)]
+        sample: String,
+
+        /// Path to the bgen file
+        #[arg(short, long)]
+        bgen: String,
+    },
+    /// Print the header of a bgen file and the provenance stored in its free data area
+    Inspect {
+        /// Path to the bgen file
+        #[arg(short, long)]
+        bgen: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<LogLevel> for LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Error => LevelFilter::ERROR,
+            LogLevel::Warn => LevelFilter::WARN,
+            LogLevel::Info => LevelFilter::INFO,
+            LogLevel::Debug => LevelFilter::DEBUG,
+            LogLevel::Trace => LevelFilter::TRACE,
+        }
+    }
+}
+
+// Number of warnings logged, printed by a dry run
+#[derive(Debug, Clone, Default)]
+struct WarningCount(Arc<AtomicU64>);
+
+impl<S: Subscriber> Layer<S> for WarningCount {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        if *event.metadata().level() == Level::WARN {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+// Log the events of the conversion to stderr, warnings only when quiet, and to the log file
+fn init_logging(
+    level: LevelFilter,
+    quiet: bool,
+    log_file: Option<&str>,
+) -> Result<WarningCount, VcfError> {
+    let stderr_level = match quiet {
+        true => level.min(LevelFilter::WARN),
+        false => level,
+    };
+    let stderr_log = fmt::layer()
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::stderr().is_terminal())
+        .with_target(false)
+        .without_time()
+        .with_filter(stderr_level);
+    let file_log = match log_file {
+        Some(path) => Some(
+            fmt::layer()
+                .with_writer(Mutex::new(File::create(path)?))
+                .with_ansi(false)
+                .with_target(false)
+                .with_filter(level),
+        ),
+        None => None,
+    };
+    let warnings = WarningCount::default();
+    tracing_subscriber::registry()
+        .with(stderr_log)
+        .with(file_log)
+        .with(warnings.clone())
+        .init();
+    Ok(warnings)
+}
+
+#[derive(Debug, Clone, Copy)]
+enum NumBits {
+    Fixed(u8),
+    Auto,
+}
+
+fn parse_num_bits(value: &str) -> Result<NumBits, String> {
+    match value {
+        "auto" => Ok(NumBits::Auto),
+        _ => {
+            let num_bits = value
+                .parse()
+                .map_err(|_| format!("expected a number of bits or auto, got '{}'", value))?;
+            if let Err(VcfError::Validation(report)) = check_num_bits(num_bits) {
+                return Err(report.to_string());
+            }
+            Ok(NumBits::Fixed(num_bits))
+        }
+    }
+}
+
+fn parse_extra_output(value: &str) -> Result<ExtraOutput, String> {
+    let (path, num_bits) = value
+        .rsplit_once(':')
+        .ok_or_else(|| format!("expected PATH:NUM_BITS, got '{}'", value))?;
+    let num_bits = num_bits
+        .parse()
+        .map_err(|_| format!("invalid number of bits '{}'", num_bits))?;
+    if let Err(VcfError::Validation(report)) = check_num_bits(num_bits) {
+        return Err(report.to_string());
+    }
+    Ok(ExtraOutput {
+        path: path.to_string(),
+        num_bits,
+    })
+}
+
+// Stop the conversion cleanly on the first Ctrl-C, leaving no partial output, and at once on
+// the second
+fn handle_interrupts() {
+    let result = ctrlc::set_handler(|| {
+        if vcf_to_bgen::cancel_requested() {
+            std::process::exit(EXIT_CANCELLED as i32);
+        }
+        vcf_to_bgen::request_cancel();
+    });
+    if let Err(error) = result {
+        warn!(
+            "Ctrl-C will stop the conversion without cleaning up: {}",
+            error
+        );
+    }
+}
+
+// Exit with the code of the class of failure, so scripts can tell them apart
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("Error: {:?}", error);
+            ExitCode::from(error.exit_code())
+        }
+    }
 }
 
-fn main() -> Result<(), VcfError> {
+fn run() -> Result<(), VcfError> {
     let args = Args::parse();
-    // First pass to get the number of variants
-    let (variant_num, number_geno_line) = count_variants(&args.input)?;
-    // Convert to bgen, line by line
-    convert_to_bgen(
-        &args.input,
-        &args.output,
-        variant_num,
-        number_geno_line,
-        args.num_bits.unwrap_or(8),
-    )
+    if let Some(Command::CheckPair { sample, bgen }) = &args.command {
+        let number_samples = check_pair(sample, bgen)?;
+        println!(
+            "{} and {} are consistent ({} samples)",
+            sample, bgen, number_samples
+        );
+        return Ok(());
+    }
+    if let Some(Command::Inspect { bgen }) = &args.command {
+        let summary = read_bgen_summary(bgen)?;
+        println!("variants\t{}", summary.variant_num);
+        println!("samples\t{}", summary.sample_num);
+        println!("layout\t{}", summary.layout_id);
+        println!("compression\t{}", summary.compression);
+        println!("sample_ids\t{}", summary.samples.is_some());
+        if summary.free_data.is_empty() {
+            println!("provenance\tNA");
+        } else {
+            print!("{}", String::from_utf8_lossy(&summary.free_data));
+        }
+        return Ok(());
+    }
+    let input = args.input.expect("input is required without a subcommand");
+    let output = args
+        .output
+        .expect("output is required without a subcommand");
+    let output = expand_input_stem(&output, &input);
+    let mut options = if args.reference_panel {
+        ConversionOptions::reference_panel()
+    } else if args.legacy {
+        ConversionOptions::legacy()
+    } else {
+        match args.num_bits {
+            Some(NumBits::Auto) => ConversionOptions {
+                num_bits: ADAPTIVE_MAX_NUM_BITS,
+                adaptive_num_bits: true,
+                ..Default::default()
+            },
+            Some(NumBits::Fixed(num_bits)) => ConversionOptions {
+                num_bits,
+                ..Default::default()
+            },
+            None => ConversionOptions::default(),
+        }
+    };
+    options.phased |= args.phased;
+    options.auto_phase &= !args.unphased;
+    options.ref_blocks = args.gvcf_ref_blocks;
+    options.regroup_chromosomes = args.regroup_chromosomes;
+    options.backend = args.backend;
+    options.fetch_region = args.fetch_region;
+    options.regions = args.regions;
+    options.regions_file = args.regions_file;
+    options.extract = args.extract;
+    options.exclude = args.exclude;
+    options.extra_outputs = args.extra_output;
+    options.dosage_matrix = args.export_dosage_matrix;
+    options.dosage_matrix_max_variants = args.dosage_matrix_max_variants;
+    options.allow_sites_only = args.allow_sites_only;
+    options.allele_counts = args.allele_counts;
+    options.variant_list = args.variant_list;
+    options.variant_metadata = args.variant_metadata;
+    options.max_prob_error = args.max_prob_error;
+    options.checkpoint_every = args.checkpoint_every;
+    options.resume = args.resume;
+    options.overwrite = args.force;
+    options.verify = args.verify;
+    options.dosage_field = args.dosage_field;
+    options.gt_priority = args.gt_priority;
+    options.hard_call_threshold = args.hard_call_threshold;
+    options.fill_missing = args.fill_missing;
+    options.compression = args.compression;
+    options.compression_level = args.compression_level;
+    options.write_index = args.index;
+    options.deterministic = args.deterministic;
+    options.single_pass = args.single_pass;
+    options.provenance = !args.no_provenance;
+    options.command_line = Some(std::env::args().collect::<Vec<String>>().join(" "));
+    options.output_format = args.output_format;
+    options.split_by_chr = args.split_by_chr;
+    options.checksums = args.checksums;
+    options.sample_ids = !args.no_sample_ids;
+    options.rename_samples = args.rename_samples;
+    options.anonymize = args.anonymize;
+    options.keep_samples = args.keep;
+    options.remove_samples = args.remove;
+    options.sample_order = args.sample_order;
+    options.shards = args.shards;
+    options.shard_mode = args.shard_mode;
+    if let Some(layout) = args.layout {
+        options.layout = layout;
+    }
+    options.genotype_likelihoods = args.genotype_likelihoods;
+    options.target_style = args.target_style;
+    options.seed = args.seed;
+    options.manifest = args.manifest;
+    options.summary_json = args.summary_json;
+    options.annotate_rsid = args.annotate_rsid;
+    options.ploidy_map = args.ploidy_map;
+    options.sex_file = args.sex_file;
+    options.genome_build = args.genome_build;
+    options.multiallelic = args.multiallelic;
+    options.spanning_deletion = args.spanning_deletion;
+    options.symbolic_alleles = args.symbolic_alleles;
+    options.tmp_dir = args.tmp_dir;
+    // a checkpointed conversion cannot write the report, only asked for explicitly then
+    options.skipped_report = match args.skipped_report {
+        _ if args.no_skipped_report => None,
+        Some(path) => Some(path.unwrap_or_else(|| skipped_report_path(&output))),
+        None if args.checkpoint_every.is_some() => None,
+        None => Some(skipped_report_path(&output)),
+    };
+    options.monomorphic = args.monomorphic;
+    options.dup_policy = args.dup_policy;
+    options.dedup_samples = args.dedup_samples;
+    options.min_gq = args.min_gq;
+    options.min_mac = args.min_mac;
+    options.pass_only = args.pass_only;
+    options.snps_only = args.snps_only;
+    options.indels_only = args.indels_only;
+    options.max_allele_len = args.max_allele_len;
+    options.info_filter = args.info_filter;
+    options.drop_all_missing = args.drop_all_missing;
+    options.thin = args.thin;
+    options.thin_bp = args.thin_bp;
+    options.sample_variants = args.sample_variants;
+    options.max_variants = args.max_variants;
+    options.threads = args.threads;
+    options.quiet = args.quiet;
+    options.progress_format = args.progress_format;
+    options.skip_variants = args.skip_variants;
+    options.start_position = args.start_position;
+    options.min_dp = args.min_dp;
+    options.max_dp = args.max_dp;
+    options.missing_policy = args.missing_policy;
+    options.half_call_policy = args.half_call_policy;
+    options.max_ploidy = args.max_ploidy;
+    options.per_sample_dir = args.per_sample_dir;
+    options.max_open_files = args.max_open_files;
+    options.truth_vcf = args.truth_vcf;
+    options.concordance_report = args.concordance_report;
+    options.read_retry = RetryPolicy {
+        retries: args.read_retries,
+        backoff: Duration::from_millis(args.retry_backoff_ms),
+    };
+    let warnings = init_logging(args.log_level.into(), args.quiet, args.log_file.as_deref())?;
+    handle_interrupts();
+    let converter = Converter::new(&input, &output).options(options);
+    if args.dry_run {
+        let report = converter.dry_run()?;
+        println!("samples\t{}", report.samples.len());
+        println!("records\t{}", report.metrics.records_read);
+        println!("variants\t{}", report.metrics.variants_processed);
+        println!("records_skipped\t{}", report.metrics.records_skipped);
+        println!("breakends_skipped\t{}", report.metrics.breakends_skipped);
+        for (filter, count) in &report.metrics.filters_skipped {
+            println!("filter_skipped\t{}\t{}", filter, count);
+        }
+        println!("warnings\t{}", warnings.0.load(Ordering::Relaxed));
+        for output in &report.outputs {
+            println!("output\t{}", output);
+        }
+        return Ok(());
+    }
+    // Count variants, then convert to bgen line by line
+    let metrics = converter.run()?;
+    if let Some(metrics_file) = args.metrics_file {
+        metrics.write_textfile(&metrics_file)?;
+    }
+    Ok(())
 }
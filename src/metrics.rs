@@ -0,0 +1,212 @@
+// Conversion counters, written in the Prometheus textfile exporter format or as a json summary
+use crate::variant_metadata::json_string;
+use crate::VcfError;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Default)]
+pub struct ConversionMetrics {
+    /// Vcf data lines read
+    pub records_read: u64,
+    /// Bgen variants written, after multiallelic splitting
+    pub variants_processed: u64,
+    /// Vcf data lines that produced no bgen variant
+    pub records_skipped: u64,
+    /// Vcf data lines skipped for their breakend alleles
+    pub breakends_skipped: u64,
+    /// Vcf data lines skipped for failing a filter, by FILTER value
+    pub filters_skipped: Vec<(String, u64)>,
+    /// Alleles and variants left out, by reason as in the skipped variant report
+    pub variants_skipped: Vec<(String, u64)>,
+    /// Multiallelic records split into biallelic variants
+    pub multiallelics_split: u64,
+    /// Variants without an input ID given an rsID of the dbSNP reference
+    pub rsids_annotated: u64,
+    /// Samples of each variant written
+    pub samples: u64,
+    /// Sample genotypes of the variants written, and how many of them are missing
+    pub genotypes_written: u64,
+    pub genotypes_missing: u64,
+    /// Decompressed bytes of vcf read
+    pub bytes_read: u64,
+    /// Bytes written to all outputs
+    pub bytes_written: u64,
+    /// Largest change of a probability rounded to the bits written, when measured
+    pub max_prob_error: Option<f64>,
+    /// Wall-clock time spent in each phase of the conversion
+    pub phase_durations: Vec<(String, Duration)>,
+}
+
+impl ConversionMetrics {
+    pub fn add_phase(&mut self, phase: &str, duration: Duration) {
+        self.phase_durations.push((phase.to_string(), duration));
+    }
+
+    /// Write metrics for the node exporter textfile collector, replacing the file atomically
+    pub fn write_textfile(&self, output: &str) -> Result<(), VcfError> {
+        let tmp_output = format!("{}.tmp", output);
+        let mut writer = BufWriter::new(File::create(&tmp_output)?);
+        let counters = [
+            ("records_read", "Vcf data lines read", self.records_read),
+            (
+                "variants_processed",
+                "Bgen variants written after multiallelic splitting",
+                self.variants_processed,
+            ),
+            (
+                "records_skipped",
+                "Vcf data lines that produced no bgen variant",
+                self.records_skipped,
+            ),
+            (
+                "breakends_skipped",
+                "Vcf data lines skipped for their breakend alleles",
+                self.breakends_skipped,
+            ),
+            ("bytes_read", "Decompressed vcf bytes read", self.bytes_read),
+            (
+                "bytes_written",
+                "Bytes written to all outputs",
+                self.bytes_written,
+            ),
+        ];
+        for (name, help, value) in counters {
+            writeln!(writer, "# HELP vcf_to_bgen_{}_total {}", name, help)?;
+            writeln!(writer, "# TYPE vcf_to_bgen_{}_total counter", name)?;
+            writeln!(writer, "vcf_to_bgen_{}_total {}", name, value)?;
+        }
+        if !self.filters_skipped.is_empty() {
+            writeln!(
+                writer,
+                "# HELP vcf_to_bgen_filter_skipped_total Vcf data lines skipped for failing a filter"
+            )?;
+            writeln!(writer, "# TYPE vcf_to_bgen_filter_skipped_total counter")?;
+            for (filter, count) in &self.filters_skipped {
+                writeln!(
+                    writer,
+                    "vcf_to_bgen_filter_skipped_total{{filter=\"{}\"}} {}",
+                    filter, count
+                )?;
+            }
+        }
+        if let Some(max_prob_error) = self.max_prob_error {
+            writeln!(
+                writer,
+                "# HELP vcf_to_bgen_max_prob_error Largest rounding error of a probability written"
+            )?;
+            writeln!(writer, "# TYPE vcf_to_bgen_max_prob_error gauge")?;
+            writeln!(writer, "vcf_to_bgen_max_prob_error {}", max_prob_error)?;
+        }
+        writeln!(
+            writer,
+            "# HELP vcf_to_bgen_phase_duration_seconds Wall-clock time spent in each conversion phase"
+        )?;
+        writeln!(writer, "# TYPE vcf_to_bgen_phase_duration_seconds gauge")?;
+        for (phase, duration) in &self.phase_durations {
+            writeln!(
+                writer,
+                "vcf_to_bgen_phase_duration_seconds{{phase=\"{}\"}} {}",
+                phase,
+                duration.as_secs_f64()
+            )?;
+        }
+        writer.flush()?;
+        drop(writer);
+        fs::rename(tmp_output, output)?;
+        Ok(())
+    }
+
+    /// Write a json summary of the conversion of `input` to `outputs`, for workflow engines to
+    /// check, replacing the file atomically
+    pub fn write_summary_json(
+        &self,
+        output: &str,
+        input: &str,
+        outputs: &[String],
+    ) -> Result<(), VcfError> {
+        let tmp_output = format!("{}.tmp", output);
+        let mut writer = BufWriter::new(File::create(&tmp_output)?);
+        let outputs: Vec<String> = outputs.iter().map(|path| json_string(path)).collect();
+        // counts by key, a reason or phase being quoted
+        let object = |entries: &[(String, String)]| {
+            let entries: Vec<String> = entries
+                .iter()
+                .map(|(key, value)| format!("{}: {}", json_string(key), value))
+                .collect();
+            format!("{{{}}}", entries.join(", "))
+        };
+        let counts = |counts: &[(String, u64)]| {
+            let entries: Vec<(String, String)> = counts
+                .iter()
+                .map(|(key, count)| (key.clone(), count.to_string()))
+                .collect();
+            object(&entries)
+        };
+        let missing_rate = match self.genotypes_written {
+            0 => "null".to_string(),
+            written => (self.genotypes_missing as f64 / written as f64).to_string(),
+        };
+        let max_prob_error = match self.max_prob_error {
+            Some(max_prob_error) => max_prob_error.to_string(),
+            None => "null".to_string(),
+        };
+        let phase_seconds: Vec<(String, String)> = self
+            .phase_durations
+            .iter()
+            .map(|(phase, duration)| (phase.clone(), duration.as_secs_f64().to_string()))
+            .collect();
+        writeln!(writer, "{{")?;
+        writeln!(writer, "  \"input\": {},", json_string(input))?;
+        writeln!(writer, "  \"outputs\": [{}],", outputs.join(", "))?;
+        writeln!(writer, "  \"samples\": {},", self.samples)?;
+        writeln!(writer, "  \"records_read\": {},", self.records_read)?;
+        writeln!(writer, "  \"records_skipped\": {},", self.records_skipped)?;
+        writeln!(
+            writer,
+            "  \"variants_written\": {},",
+            self.variants_processed
+        )?;
+        writeln!(
+            writer,
+            "  \"multiallelics_split\": {},",
+            self.multiallelics_split
+        )?;
+        writeln!(writer, "  \"rsids_annotated\": {},", self.rsids_annotated)?;
+        writeln!(
+            writer,
+            "  \"breakends_skipped\": {},",
+            self.breakends_skipped
+        )?;
+        writeln!(
+            writer,
+            "  \"filters_skipped\": {},",
+            counts(&self.filters_skipped)
+        )?;
+        writeln!(
+            writer,
+            "  \"variants_skipped\": {},",
+            counts(&self.variants_skipped)
+        )?;
+        writeln!(
+            writer,
+            "  \"genotypes_written\": {},",
+            self.genotypes_written
+        )?;
+        writeln!(
+            writer,
+            "  \"genotypes_missing\": {},",
+            self.genotypes_missing
+        )?;
+        writeln!(writer, "  \"missing_rate\": {},", missing_rate)?;
+        writeln!(writer, "  \"max_prob_error\": {},", max_prob_error)?;
+        writeln!(writer, "  \"bytes_read\": {},", self.bytes_read)?;
+        writeln!(writer, "  \"bytes_written\": {},", self.bytes_written)?;
+        writeln!(writer, "  \"phase_seconds\": {}", object(&phase_seconds))?;
+        writeln!(writer, "}}")?;
+        writer.flush()?;
+        drop(writer);
+        fs::rename(tmp_output, output)?;
+        Ok(())
+    }
+}
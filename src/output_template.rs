@@ -0,0 +1,71 @@
+// Placeholders of the output path, replaced by the input name, or by the chromosome or shard of
+// each bgen
+use crate::{ConversionOptions, VcfError};
+use color_eyre::Report;
+use std::path::Path;
+
+/// Placeholder of the chromosome in the output template of `--split-by-chr`
+pub const CHR_PLACEHOLDER: &str = "{chr}";
+/// Placeholder of the shard number in the output template of `--shards`
+pub const SHARD_PLACEHOLDER: &str = "{shard}";
+/// Placeholder of the input file name, without its directory and vcf extensions
+pub const INPUT_STEM_PLACEHOLDER: &str = "{input_stem}";
+
+// Extensions left out of the input stem, in any order
+const INPUT_EXTENSIONS: [&str; 4] = [".gz", ".bgz", ".vcf", ".bcf"];
+
+/// Name of `input` without its directory and vcf extensions: `data/chr22.vcf.gz` gives `chr22`
+pub fn input_stem(input: &str) -> String {
+    let mut stem = Path::new(input)
+        .file_name()
+        .map_or(input.to_string(), |name| name.to_string_lossy().to_string());
+    while let Some(shorter) = INPUT_EXTENSIONS
+        .iter()
+        .find_map(|extension| stem.strip_suffix(extension))
+    {
+        stem = shorter.to_string();
+    }
+    stem
+}
+
+/// `output` with `{input_stem}` replaced by the stem of `input`, so batch scripts can name the
+/// outputs of many inputs with one template
+pub fn expand_input_stem(output: &str, input: &str) -> String {
+    output.replace(INPUT_STEM_PLACEHOLDER, &input_stem(input))
+}
+
+/// Fail on placeholders of `output` that no output of `options` replaces, and on a split by
+/// chromosome without `{chr}`
+pub fn check_output_template(output: &str, options: &ConversionOptions) -> Result<(), VcfError> {
+    let mut rest = output;
+    while let Some(start) = rest.find('{') {
+        let Some(length) = rest[start..].find('}') else {
+            break;
+        };
+        let placeholder = &rest[start..=start + length];
+        let (replaced, flag) = match placeholder {
+            CHR_PLACEHOLDER => (options.split_by_chr, "--split-by-chr"),
+            SHARD_PLACEHOLDER => (options.shards.is_some(), "--shards"),
+            _ => {
+                return Err(VcfError::Validation(Report::msg(format!(
+                    "Output {} has an unknown placeholder {}: use {}, {} or {}",
+                    output, placeholder, CHR_PLACEHOLDER, SHARD_PLACEHOLDER, INPUT_STEM_PLACEHOLDER
+                ))))
+            }
+        };
+        if !replaced {
+            return Err(VcfError::Validation(Report::msg(format!(
+                "Output {} has {}, which is only replaced with {}",
+                output, placeholder, flag
+            ))));
+        }
+        rest = &rest[start + length + 1..];
+    }
+    if options.split_by_chr && !output.contains(CHR_PLACEHOLDER) {
+        return Err(VcfError::Validation(Report::msg(format!(
+            "Output {} has no {} to replace with each chromosome",
+            output, CHR_PLACEHOLDER
+        ))));
+    }
+    Ok(())
+}
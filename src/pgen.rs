@@ -0,0 +1,118 @@
+// PLINK 2 .pgen/.pvar/.psam fileset, written from the same variants as the bgen
+use crate::concordance::hard_calls;
+use crate::pipeline::sink::VariantSink;
+use crate::pipeline::Variant;
+use crate::{sample_probabilities, VcfError, PLOIDY_MASK};
+use color_eyre::Report;
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+
+// Magic bytes of .pgen files, then the mode of fixed-width 2-bit hard calls
+const PGEN_MAGIC: [u8; 2] = [0x6c, 0x1b];
+const PGEN_FIXED_WIDTH_HARDCALLS: u8 = 0x02;
+// Offset of the variant count, after the magic bytes and the mode
+const PGEN_VARIANT_NUM_OFFSET: u64 = 3;
+// 2-bit codes of the hard calls: alt copies, 3 being missing
+const PGEN_MISSING: u8 = 3;
+
+/// Prefix of the fileset of `output`: `out.pgen` gives `out`
+pub fn pgen_prefix(output: &str) -> &str {
+    output.strip_suffix(".pgen").unwrap_or(output)
+}
+
+/// Hard calls of biallelic variants as a .pgen, with their alleles in a .pvar and samples in a .psam
+///
+/// Probabilities are reduced to the most likely genotype; haploid calls are written homozygous,
+/// as PLINK 2 does for chrY and male chrX.
+pub struct PgenOutput {
+    pub prefix: String,
+    pgen: BufWriter<File>,
+    pvar: BufWriter<File>,
+    psam: BufWriter<File>,
+}
+
+impl PgenOutput {
+    pub fn create(output: &str) -> Result<Self, VcfError> {
+        let prefix = pgen_prefix(output).to_string();
+        Ok(PgenOutput {
+            pgen: BufWriter::new(File::create(format!("{}.pgen", prefix))?),
+            pvar: BufWriter::new(File::create(format!("{}.pvar", prefix))?),
+            psam: BufWriter::new(File::create(format!("{}.psam", prefix))?),
+            prefix,
+        })
+    }
+}
+
+impl VariantSink for PgenOutput {
+    fn write_header(&mut self, samples: &[String], variant_num: u32) -> Result<(), VcfError> {
+        self.pgen.write_all(&PGEN_MAGIC)?;
+        self.pgen.write_all(&[PGEN_FIXED_WIDTH_HARDCALLS])?;
+        self.pgen.write_all(&variant_num.to_le_bytes())?;
+        self.pgen.write_all(&(samples.len() as u32).to_le_bytes())?;
+        writeln!(self.pvar, "#CHROM\tPOS\tID\tREF\tALT")?;
+        writeln!(self.psam, "#IID")?;
+        for sample in samples {
+            writeln!(self.psam, "{}", sample)?;
+        }
+        Ok(())
+    }
+
+    fn write_variant(&mut self, variant: &Variant) -> Result<(), VcfError> {
+        let variant_data = &variant.data;
+        if variant_data.alleles.len() != 2 {
+            return Err(VcfError::Validation(Report::msg(format!(
+                "Variant {} has {} alleles, .pgen output only stores biallelic variants",
+                variant_data.variants_id,
+                variant_data.alleles.len()
+            ))));
+        }
+        let calls = hard_calls(variant_data);
+        let ploidy = sample_probabilities(&variant_data.data_block)
+            .into_iter()
+            .map(|(ploidy_m, _)| ploidy_m & PLOIDY_MASK);
+        // four samples per byte, the first in the lowest bits
+        let mut record = vec![0u8; calls.len().div_ceil(4)];
+        for (sample_i, (call, ploidy)) in calls.into_iter().zip(ploidy).enumerate() {
+            let code = match call {
+                None => PGEN_MISSING,
+                Some(copies) if ploidy == 1 => 2 * copies.min(1) as u8,
+                Some(copies) => copies.min(2) as u8,
+            };
+            record[sample_i / 4] |= code << (2 * (sample_i % 4));
+        }
+        self.pgen.write_all(&record)?;
+        writeln!(
+            self.pvar,
+            "{}\t{}\t{}\t{}\t{}",
+            variant_data.chr,
+            variant_data.pos,
+            variant_data.variants_id,
+            variant_data.alleles[0],
+            variant_data.alleles[1]
+        )?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<u64, VcfError> {
+        let mut bytes_written = 0;
+        for writer in [&mut self.pgen, &mut self.pvar, &mut self.psam] {
+            writer.flush()?;
+            bytes_written += writer.get_ref().metadata()?.len();
+        }
+        Ok(bytes_written)
+    }
+
+    fn single_pass(&self) -> bool {
+        self.pgen
+            .get_ref()
+            .metadata()
+            .is_ok_and(|metadata| metadata.is_file())
+    }
+
+    fn set_variant_num(&mut self, variant_num: u32) -> Result<(), VcfError> {
+        self.pgen.seek(SeekFrom::Start(PGEN_VARIANT_NUM_OFFSET))?;
+        self.pgen.write_all(&variant_num.to_le_bytes())?;
+        self.pgen.seek(SeekFrom::End(0))?;
+        Ok(())
+    }
+}
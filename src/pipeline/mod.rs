@@ -0,0 +1,287 @@
+// Conversion as a pipeline: a source of variants, a chain of transforms, then one or more sinks
+pub mod sink;
+pub mod source;
+pub mod transform;
+
+use crate::checkpoint::Checkpoint;
+use crate::metrics::ConversionMetrics;
+use crate::progress::Progress;
+use crate::{check_cancelled, check_variant_limit, missing_sample_count, ProgressFormat, VcfError};
+use bgen_reader::bgen::variant_data::VariantData;
+use color_eyre::Report;
+use sink::VariantSink;
+use source::VariantSource;
+use transform::VariantTransform;
+
+/// A variant ready to be written, with the number of the input record it comes from
+#[derive(Debug, Clone)]
+pub struct Variant {
+    pub data: VariantData,
+    pub record_num: u32,
+    /// ID column of the input record, replaced by a position-based identifier in `data`
+    pub input_id: String,
+}
+
+pub struct Pipeline {
+    source: Box<dyn VariantSource>,
+    transforms: Vec<Box<dyn VariantTransform>>,
+    sinks: Vec<Box<dyn VariantSink>>,
+    // checkpoint path, variants between checkpoints and seed of the run
+    checkpoints: Option<(String, u64, Option<u64>)>,
+    resume: Option<Checkpoint>,
+    max_variants: Option<u64>,
+    skip_variants: u64,
+    quiet: bool,
+    progress_format: ProgressFormat,
+}
+
+impl Pipeline {
+    pub fn new(source: Box<dyn VariantSource>) -> Self {
+        Pipeline {
+            source,
+            transforms: vec![],
+            sinks: vec![],
+            checkpoints: None,
+            resume: None,
+            max_variants: None,
+            skip_variants: 0,
+            quiet: false,
+            progress_format: ProgressFormat::default(),
+        }
+    }
+
+    /// Apply `transform` after the transforms already added
+    pub fn transform(mut self, transform: Box<dyn VariantTransform>) -> Self {
+        self.transforms.push(transform);
+        self
+    }
+
+    pub fn sink(mut self, sink: Box<dyn VariantSink>) -> Self {
+        self.sinks.push(sink);
+        self
+    }
+
+    /// Save a checkpoint to `path` after the record completing each run of `every` variants
+    pub fn checkpoint_every(mut self, path: &str, every: u64, seed: Option<u64>) -> Self {
+        self.checkpoints = Some((path.to_string(), every, seed));
+        self
+    }
+
+    /// Stop once `max_variants` variants are written, leaving the rest of the source unread
+    pub fn max_variants(mut self, max_variants: u64) -> Self {
+        self.max_variants = Some(max_variants);
+        self
+    }
+
+    /// Show no progress while streaming
+    pub fn quiet(mut self) -> Self {
+        self.quiet = true;
+        self
+    }
+
+    /// Show the progress as `format`
+    pub fn progress_format(mut self, format: ProgressFormat) -> Self {
+        self.progress_format = format;
+        self
+    }
+
+    /// Leave out the first `skip_variants` variants of the source, writing those after them
+    pub fn skip_variants(mut self, skip_variants: u64) -> Self {
+        self.skip_variants = skip_variants;
+        self
+    }
+
+    /// Continue the conversion saved by `checkpoint` instead of starting it over
+    pub fn resume_from(mut self, checkpoint: Checkpoint) -> Self {
+        self.resume = Some(checkpoint);
+        self
+    }
+
+    // Flush every sink and save where the conversion got to
+    fn save_checkpoint(&mut self, metrics: &ConversionMetrics) -> Result<(), VcfError> {
+        let Some((path, _, seed)) = &self.checkpoints else {
+            return Ok(());
+        };
+        let output_offsets = self
+            .sinks
+            .iter_mut()
+            .map(|sink| sink.checkpoint())
+            .collect::<Result<Vec<u64>, VcfError>>()?;
+        Checkpoint {
+            records_read: metrics.records_read as u32,
+            input_offset: self.source.bytes_read(),
+            variants_written: metrics.variants_processed,
+            output_offsets,
+            seed: *seed,
+        }
+        .write(path)
+    }
+
+    /// Stream every variant of the source to the sinks, whose headers declare `variant_num` variants
+    pub fn run(
+        &mut self,
+        variant_num: u32,
+        number_geno_line: u32,
+    ) -> Result<ConversionMetrics, VcfError> {
+        let progress = Progress::new("Converting", Some(number_geno_line as u64), self.quiet)
+            .with_format(self.progress_format, Some(variant_num as u64));
+        self.stream(Some(variant_num), progress)
+    }
+
+    /// Samples of the source, in the order written
+    pub fn samples(&self) -> &[String] {
+        self.source.samples()
+    }
+
+    /// Whether every sink can declare the number of variants after writing them
+    pub fn single_pass(&self) -> bool {
+        self.sinks.iter().all(|sink| sink.single_pass())
+    }
+
+    /// Stream every variant of the source to the sinks without counting them first, their headers
+    /// declaring the number written once the source is exhausted
+    pub fn run_single_pass(&mut self) -> Result<ConversionMetrics, VcfError> {
+        if !self.single_pass() {
+            return Err(VcfError::Validation(Report::msg(
+                "Some output needs the number of variants before they are written",
+            )));
+        }
+        let progress =
+            Progress::new("Converting", None, self.quiet).with_format(self.progress_format, None);
+        self.stream(None, progress)
+    }
+
+    fn stream(
+        &mut self,
+        variant_num: Option<u32>,
+        mut progress: Progress,
+    ) -> Result<ConversionMetrics, VcfError> {
+        let mut metrics = ConversionMetrics::default();
+        let samples = self.source.samples().to_vec();
+        metrics.samples = samples.len() as u64;
+        let mut to_skip = self.skip_variants;
+        match self.resume.take() {
+            Some(checkpoint) => {
+                if checkpoint.output_offsets.len() != self.sinks.len() {
+                    return Err(VcfError::Validation(Report::msg(format!(
+                        "The checkpoint has {} outputs but the conversion writes {}",
+                        checkpoint.output_offsets.len(),
+                        self.sinks.len()
+                    ))));
+                }
+                for (sink, &offset) in self.sinks.iter_mut().zip(&checkpoint.output_offsets) {
+                    sink.resume(offset)?;
+                }
+                let input_offset = self.source.skip_records(checkpoint.records_read)?;
+                if input_offset != checkpoint.input_offset {
+                    return Err(VcfError::Validation(Report::msg(
+                        "The input changed since the checkpoint: convert it again without --resume",
+                    )));
+                }
+                metrics.records_read = checkpoint.records_read as u64;
+                metrics.variants_processed = checkpoint.variants_written;
+                // checkpoints after the start are saved once variants are written, all skipped by then
+                if checkpoint.variants_written > 0 {
+                    to_skip = 0;
+                }
+            }
+            None => {
+                for sink in self.sinks.iter_mut() {
+                    sink.write_header(&samples, variant_num.unwrap_or_default())?;
+                }
+                // fail before converting anything when an output cannot be checkpointed
+                self.save_checkpoint(&metrics)?;
+            }
+        }
+        let mut last_checkpoint = metrics.variants_processed;
+        let max_variants = self.max_variants;
+        let limit_reached = |metrics: &ConversionMetrics| {
+            max_variants.is_some_and(|max| metrics.variants_processed >= max)
+        };
+
+        while let Some(variants) = self.source.next_record()? {
+            check_cancelled()?;
+            metrics.records_read += 1;
+            if variants.is_empty() {
+                metrics.records_skipped += 1;
+            }
+            'variants: for mut variant in variants {
+                if limit_reached(&metrics) {
+                    break;
+                }
+                if to_skip > 0 {
+                    to_skip -= 1;
+                    continue;
+                }
+                for transform in self.transforms.iter_mut() {
+                    match transform.apply(variant)? {
+                        Some(transformed) => variant = transformed,
+                        None => continue 'variants,
+                    }
+                }
+                for sink in self.sinks.iter_mut() {
+                    sink.write_variant(&variant)?;
+                }
+                let data_block = &variant.data.data_block;
+                metrics.genotypes_written += data_block.ploidy_missingness.len() as u64;
+                metrics.genotypes_missing += missing_sample_count(data_block);
+                metrics.variants_processed += 1;
+            }
+            if variant_num.is_none() {
+                check_variant_limit(metrics.variants_processed)?;
+            }
+            if let Some((_, every, _)) = self.checkpoints {
+                if metrics.variants_processed - last_checkpoint >= every {
+                    self.save_checkpoint(&metrics)?;
+                    last_checkpoint = metrics.variants_processed;
+                }
+            }
+            progress.update(
+                metrics.records_read,
+                metrics.variants_processed,
+                self.source.bytes_read(),
+            );
+            if limit_reached(&metrics) {
+                break;
+            }
+        }
+        progress.finish();
+
+        // a header declaring more or fewer blocks than written makes the bgen unreadable
+        match variant_num {
+            Some(variant_num) if metrics.variants_processed != variant_num as u64 => {
+                return Err(VcfError::Bgen(Report::msg(format!(
+                    "{} variants were written but the header declares {}",
+                    metrics.variants_processed, variant_num
+                ))));
+            }
+            Some(_) => {}
+            None => {
+                for sink in self.sinks.iter_mut() {
+                    sink.set_variant_num(metrics.variants_processed as u32)?;
+                }
+            }
+        }
+        if let Some(sha256) = self.source.input_sha256()? {
+            for sink in self.sinks.iter_mut() {
+                sink.set_input_sha256(&sha256)?;
+            }
+        }
+        self.source.finish()?;
+        for transform in self.transforms.iter_mut() {
+            transform.finish(&mut metrics);
+        }
+        for sink in self.sinks.iter_mut() {
+            metrics.bytes_written += sink.finish()?;
+        }
+        metrics.bytes_read = self.source.bytes_read();
+        metrics.breakends_skipped = self.source.breakends_skipped();
+        metrics.filters_skipped = self.source.filters_skipped();
+        metrics.variants_skipped = self.source.variants_skipped();
+        metrics.multiallelics_split = self.source.multiallelics_split();
+        if let Some((path, _, _)) = &self.checkpoints {
+            std::fs::remove_file(path)?;
+        }
+        Ok(metrics)
+    }
+}
@@ -0,0 +1,905 @@
+// Destinations of converted variants
+use crate::bgi::{index_path, BgenIndex};
+use crate::checksum::{Checksums, HashingWriter};
+use crate::layout1::write_layout1_variant;
+use crate::layout2::write_layout2_variant;
+use crate::output_template::{CHR_PLACEHOLDER, SHARD_PLACEHOLDER};
+use crate::pipeline::Variant;
+use crate::provenance::{INPUT_SHA256_KEY, PENDING_SHA256};
+use crate::{
+    check_num_bits, lossless_num_bits, rescale_probabilities, sample_probabilities, worker_pool,
+    write_bgen_header, write_raw_bgen_header, BlockCompression, ConversionOptions, ShardMode,
+    VcfError, DEFAULT_ZLIB_LEVEL,
+};
+use bgen_reader::bgen::variant_data::{DataBlock, VariantData};
+use color_eyre::Report;
+use rayon::prelude::*;
+use rayon::ThreadPool;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+pub trait VariantSink {
+    fn write_header(&mut self, samples: &[String], variant_num: u32) -> Result<(), VcfError>;
+
+    fn write_variant(&mut self, variant: &Variant) -> Result<(), VcfError>;
+
+    /// Flush everything written and return the size of the output
+    fn finish(&mut self) -> Result<u64, VcfError>;
+
+    /// Flush everything written and return the size of the output, where a resumed run continues
+    fn checkpoint(&mut self) -> Result<u64, VcfError> {
+        Err(VcfError::Validation(Report::msg(
+            "Only bgen outputs can be checkpointed: leave out the other outputs",
+        )))
+    }
+
+    /// Continue the output of an interrupted run from `offset`, instead of writing a header
+    fn resume(&mut self, _offset: u64) -> Result<(), VcfError> {
+        Err(VcfError::Validation(Report::msg(
+            "Only bgen outputs can be resumed: leave out the other outputs",
+        )))
+    }
+
+    /// Whether the header can be written before the variants are counted, `set_variant_num`
+    /// declaring their number once the input was read
+    fn single_pass(&self) -> bool {
+        false
+    }
+
+    /// Declare the number of variants written in a single pass, for outputs whose header has it
+    fn set_variant_num(&mut self, _variant_num: u32) -> Result<(), VcfError> {
+        Ok(())
+    }
+
+    /// Write the sha256 of the input, known once it is read, over the placeholder of the header
+    /// provenance
+    fn set_input_sha256(&mut self, _sha256: &str) -> Result<(), VcfError> {
+        Ok(())
+    }
+}
+
+// Offset of the variant count: after the offset of the first variant and the header length
+const VARIANT_NUM_OFFSET: u64 = 8;
+// Offset of the free data area: after the variant and sample counts and the magic number
+const FREE_DATA_OFFSET: u64 = 20;
+
+/// Path the bgen at `path` is written to until it is complete, in `tmp_dir` when given and next
+/// to `path` otherwise
+pub fn temporary_path(path: &str, tmp_dir: Option<&str>) -> String {
+    match tmp_dir {
+        Some(dir) => {
+            let name = Path::new(path)
+                .file_name()
+                .map_or(path.into(), |name| name.to_string_lossy());
+            Path::new(dir)
+                .join(format!("{}.tmp", name))
+                .to_string_lossy()
+                .to_string()
+        }
+        None => format!("{}.tmp", path),
+    }
+}
+
+/// Fail when the bgen at `path` or one of its index and checksum files exists, unless `options`
+/// replace outputs or resume them
+///
+/// Checked as each bgen is created, so the paths of each chromosome or sample are checked too.
+pub fn check_not_overwritten(path: &str, options: &ConversionOptions) -> Result<(), VcfError> {
+    if options.overwrite || options.resume {
+        return Ok(());
+    }
+    let mut paths = vec![path.to_string()];
+    if options.write_index {
+        paths.push(index_path(path));
+    }
+    if options.checksums {
+        paths.push(format!("{}.md5", path));
+        paths.push(format!("{}.sha256", path));
+    }
+    check_paths_not_overwritten(&paths)
+}
+
+// Fail when one of `paths` exists
+fn check_paths_not_overwritten(paths: &[String]) -> Result<(), VcfError> {
+    match paths.iter().find(|path| Path::new(path).exists()) {
+        Some(path) => Err(VcfError::Validation(Report::msg(format!(
+            "{} already exists: use --force to overwrite it",
+            path
+        )))),
+        None => Ok(()),
+    }
+}
+
+// Move the complete file at `from` to `to`, copying it next to `to` first when they are on
+// different file systems so `to` never holds a partial file
+fn move_complete_file(from: &str, to: &str) -> Result<(), VcfError> {
+    match std::fs::rename(from, to) {
+        Err(error) if error.kind() == std::io::ErrorKind::CrossesDevices => {
+            let next_to = temporary_path(to, None);
+            std::fs::copy(from, &next_to)?;
+            std::fs::rename(&next_to, to)?;
+            std::fs::remove_file(from)?;
+            Ok(())
+        }
+        result => Ok(result?),
+    }
+}
+
+/// A bgen file being written, with the number of bits used for its probabilities
+///
+/// The file is written to a temporary path and renamed once complete, and the temporary file is
+/// removed if the output is dropped before, so an interrupted run never leaves a partial bgen.
+pub struct BgenOutput {
+    pub path: String,
+    /// Where the bgen is written until it is complete
+    pub temporary_path: String,
+    pub num_bits: u8,
+    /// Store each variant with fewer bits than `num_bits` when that keeps it exact
+    pub adaptive_num_bits: bool,
+    /// Layout of the variant blocks, 1 always storing 16-bit probabilities
+    pub layout: u8,
+    pub compression: BlockCompression,
+    /// Zlib level, the flate2 default when unset
+    pub compression_level: Option<u32>,
+    /// Write the sample block, left out when identifiers must not be shared
+    pub sample_ids: bool,
+    /// Text of the header free data area
+    pub free_data: String,
+    writer: BufWriter<File>,
+    // variants whose blocks are encoded together on the worker pool
+    pending: Vec<VariantData>,
+    pool: Arc<ThreadPool>,
+    index: Option<BgenIndex>,
+    // running checksums of the bytes written
+    checksums: Option<Checksums>,
+    // the header was patched with a count or input hash known only at the end, as in a single
+    // pass, which leaves the streamed checksums stale
+    rewritten: bool,
+    finished: bool,
+    // the temporary file of a checkpointed run is kept to be resumed
+    keep_partial: bool,
+}
+
+impl BgenOutput {
+    /// Bgen at `path` with `num_bits` probabilities, encoded and indexed as `options` say
+    pub fn create(path: &str, num_bits: u8, options: &ConversionOptions) -> Result<Self, VcfError> {
+        check_num_bits(num_bits)?;
+        check_not_overwritten(path, options)?;
+        let index = match options.write_index {
+            true => Some(BgenIndex::create(path, options.deterministic)?),
+            false => None,
+        };
+        BgenOutput::open(path, num_bits, options, index, options.resume)
+    }
+
+    /// Bgen at `path` set aside with [`BgenOutput::close`], its next variants appended to its
+    /// temporary file
+    ///
+    /// The variants written before are not indexed, so outputs closed and reopened have no index.
+    pub fn reopen(path: &str, num_bits: u8, options: &ConversionOptions) -> Result<Self, VcfError> {
+        let mut output = BgenOutput::open(path, num_bits, options, None, true)?;
+        output.writer.seek(SeekFrom::End(0))?;
+        // the bytes written before were hashed by the closed output
+        output.rewritten = true;
+        Ok(output)
+    }
+
+    /// Flush the bgen and close its temporary file, kept to be reopened with [`BgenOutput::reopen`]
+    pub fn close(mut self) -> Result<(), VcfError> {
+        self.write_pending()?;
+        self.writer.flush()?;
+        self.keep_partial = true;
+        Ok(())
+    }
+
+    // Open the temporary file of the bgen at `path`, truncated unless it is `existing`
+    fn open(
+        path: &str,
+        num_bits: u8,
+        options: &ConversionOptions,
+        index: Option<BgenIndex>,
+        existing: bool,
+    ) -> Result<Self, VcfError> {
+        let temporary_path = temporary_path(path, options.tmp_dir.as_deref());
+        Ok(BgenOutput {
+            path: path.to_string(),
+            temporary_path: temporary_path.clone(),
+            num_bits,
+            adaptive_num_bits: options.adaptive_num_bits,
+            layout: options.layout,
+            compression: options.compression,
+            compression_level: options.compression_level,
+            sample_ids: options.sample_ids,
+            free_data: options.free_data.clone().unwrap_or_default(),
+            writer: BufWriter::new(match existing {
+                true => OpenOptions::new().write(true).open(&temporary_path)?,
+                false => File::create(&temporary_path)?,
+            }),
+            pending: vec![],
+            pool: worker_pool(options.threads)?,
+            index,
+            checksums: options.checksums.then(Checksums::default),
+            rewritten: false,
+            finished: false,
+            keep_partial: options.checkpoint_every.is_some(),
+        })
+    }
+
+    // Offset of the next byte written, including the bytes still buffered
+    fn position(&mut self) -> Result<u64, VcfError> {
+        let buffered = self.writer.buffer().len() as u64;
+        Ok(self.writer.get_mut().stream_position()? + buffered)
+    }
+
+    // Encode the pending variants on the worker pool, then write their blocks in input order
+    fn write_pending(&mut self) -> Result<(), VcfError> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let pending = std::mem::take(&mut self.pending);
+        let encoding = BlockEncoding {
+            layout: self.layout,
+            compression: self.compression,
+            compression_level: self.compression_level,
+            num_bits: self.num_bits,
+            adaptive_num_bits: self.adaptive_num_bits,
+        };
+        let blocks = self.pool.install(|| {
+            pending
+                .par_iter()
+                .map(|variant_data| encoding.encode(variant_data))
+                .collect::<Result<Vec<Vec<u8>>, VcfError>>()
+        })?;
+        for (variant_data, block) in pending.iter().zip(blocks) {
+            let start = match self.index {
+                Some(_) => self.position()?,
+                None => 0,
+            };
+            if let Some(checksums) = &mut self.checksums {
+                checksums.update(&block);
+            }
+            self.writer.write_all(&block)?;
+            if let Some(index) = &mut self.index {
+                index.add(variant_data, start, block.len() as u64)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Variants encoded per thread of the worker pool before their blocks are written
+const BLOCKS_PER_THREAD: usize = 16;
+
+// How a bgen output encodes its variant blocks, shared with the threads encoding them
+#[derive(Debug, Clone, Copy)]
+struct BlockEncoding {
+    layout: u8,
+    compression: BlockCompression,
+    compression_level: Option<u32>,
+    num_bits: u8,
+    adaptive_num_bits: bool,
+}
+
+impl BlockEncoding {
+    // Bytes of the block of `variant_data`
+    fn encode(&self, variant_data: &VariantData) -> Result<Vec<u8>, VcfError> {
+        let mut block = vec![];
+        let level = self.compression_level.unwrap_or(DEFAULT_ZLIB_LEVEL);
+        let num_bits = match self.adaptive_num_bits {
+            true => lossless_num_bits(&variant_data.data_block).min(self.num_bits),
+            false => self.num_bits,
+        };
+        if self.layout == 1 {
+            write_layout1_variant(&mut block, variant_data, self.compression, level)?;
+        } else if variant_data.data_block.bits_storage == num_bits {
+            write_layout2_variant(&mut block, variant_data, self.compression, level)?;
+        } else {
+            let rescaled = rescale_probabilities(variant_data, num_bits);
+            write_layout2_variant(&mut block, &rescaled, self.compression, level)?;
+        }
+        Ok(block)
+    }
+}
+
+impl VariantSink for BgenOutput {
+    fn write_header(&mut self, samples: &[String], variant_num: u32) -> Result<(), VcfError> {
+        if let Some(checksums) = &mut self.checksums {
+            return write_raw_bgen_header(
+                &mut HashingWriter::new(&mut self.writer, checksums),
+                samples,
+                samples.len() as u32,
+                variant_num,
+                self.layout,
+                self.compression,
+                self.sample_ids,
+                self.free_data.as_bytes(),
+            );
+        }
+        write_bgen_header(
+            &mut self.writer,
+            samples,
+            samples.len() as u32,
+            variant_num,
+            self.layout,
+            self.compression,
+            self.sample_ids,
+            self.free_data.as_bytes(),
+        )
+    }
+
+    fn write_variant(&mut self, variant: &Variant) -> Result<(), VcfError> {
+        self.pending.push(variant.data.clone());
+        if self.pending.len() >= self.pool.current_num_threads() * BLOCKS_PER_THREAD {
+            self.write_pending()?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<u64, VcfError> {
+        self.write_pending()?;
+        self.writer.flush()?;
+        let bytes_written = self.writer.get_ref().metadata()?.len();
+        move_complete_file(&self.temporary_path, &self.path)?;
+        self.finished = true;
+        if let Some(index) = &mut self.index {
+            index.finish(&self.path)?;
+        }
+        if let Some(checksums) = self.checksums.take() {
+            // a rewritten header makes the streamed checksums stale
+            let checksums = match self.rewritten {
+                true => Checksums::of_file(&self.path)?,
+                false => checksums,
+            };
+            checksums.write_files(&self.path)?;
+        }
+        Ok(bytes_written)
+    }
+
+    fn checkpoint(&mut self) -> Result<u64, VcfError> {
+        self.write_pending()?;
+        self.writer.flush()?;
+        self.writer.get_ref().sync_data()?;
+        self.position()
+    }
+
+    fn resume(&mut self, offset: u64) -> Result<(), VcfError> {
+        if self.index.is_some() {
+            return Err(VcfError::Validation(Report::msg(
+                "A .bgi index cannot be resumed: convert again without --resume",
+            )));
+        }
+        self.writer.get_ref().set_len(offset)?;
+        self.writer.seek(SeekFrom::End(0))?;
+        // the bytes before the checkpoint were hashed by the interrupted run, so they are hashed
+        // again before streaming the others
+        if let Some(checksums) = &mut self.checksums {
+            *checksums = Checksums::of_file(&self.temporary_path)?;
+        }
+        Ok(())
+    }
+
+    // the header is patched in place, which needs a seekable file
+    fn single_pass(&self) -> bool {
+        self.writer
+            .get_ref()
+            .metadata()
+            .is_ok_and(|metadata| metadata.is_file())
+    }
+
+    fn set_variant_num(&mut self, variant_num: u32) -> Result<(), VcfError> {
+        self.write_pending()?;
+        self.writer.seek(SeekFrom::Start(VARIANT_NUM_OFFSET))?;
+        self.writer.write_all(&variant_num.to_le_bytes())?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.rewritten = true;
+        Ok(())
+    }
+
+    fn set_input_sha256(&mut self, sha256: &str) -> Result<(), VcfError> {
+        let pending = format!("{}{}", INPUT_SHA256_KEY, PENDING_SHA256);
+        let Some(start) = self.free_data.find(&pending) else {
+            return Ok(());
+        };
+        if sha256.len() != PENDING_SHA256.len() {
+            return Err(VcfError::Bgen(Report::msg(format!(
+                "The input sha256 {} does not fit in the header",
+                sha256
+            ))));
+        }
+        let offset = FREE_DATA_OFFSET + (start + INPUT_SHA256_KEY.len()) as u64;
+        self.write_pending()?;
+        self.writer.seek(SeekFrom::Start(offset))?;
+        self.writer.write_all(sha256.as_bytes())?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.free_data = self.free_data.replacen(PENDING_SHA256, sha256, 1);
+        self.rewritten = true;
+        Ok(())
+    }
+}
+
+impl Drop for BgenOutput {
+    fn drop(&mut self) {
+        if !self.finished && !self.keep_partial {
+            // nothing to report from here: the error that stopped the conversion is returned
+            let _ = std::fs::remove_file(&self.temporary_path);
+            if self.index.is_some() {
+                let _ = std::fs::remove_file(index_path(&self.path));
+            }
+        }
+    }
+}
+
+/// A sink whose header lists the samples under other names, in the same order
+pub struct RenamedSamples {
+    sink: Box<dyn VariantSink>,
+    names: Vec<String>,
+}
+
+impl RenamedSamples {
+    pub fn new(sink: Box<dyn VariantSink>, names: Vec<String>) -> Self {
+        RenamedSamples { sink, names }
+    }
+}
+
+impl VariantSink for RenamedSamples {
+    fn write_header(&mut self, samples: &[String], variant_num: u32) -> Result<(), VcfError> {
+        if samples.len() != self.names.len() {
+            return Err(VcfError::Validation(Report::msg(format!(
+                "{} names were given for {} samples",
+                self.names.len(),
+                samples.len()
+            ))));
+        }
+        self.sink.write_header(&self.names, variant_num)
+    }
+
+    fn write_variant(&mut self, variant: &Variant) -> Result<(), VcfError> {
+        self.sink.write_variant(variant)
+    }
+
+    fn finish(&mut self) -> Result<u64, VcfError> {
+        self.sink.finish()
+    }
+
+    fn checkpoint(&mut self) -> Result<u64, VcfError> {
+        self.sink.checkpoint()
+    }
+
+    fn resume(&mut self, offset: u64) -> Result<(), VcfError> {
+        self.sink.resume(offset)
+    }
+
+    fn single_pass(&self) -> bool {
+        self.sink.single_pass()
+    }
+
+    fn set_variant_num(&mut self, variant_num: u32) -> Result<(), VcfError> {
+        self.sink.set_variant_num(variant_num)
+    }
+
+    fn set_input_sha256(&mut self, sha256: &str) -> Result<(), VcfError> {
+        self.sink.set_input_sha256(sha256)
+    }
+}
+
+/// One bgen per chromosome, named by replacing `{chr}` in a template, created as chromosomes appear
+///
+/// Headers are written with no variant and their count is set once every variant is written.
+pub struct ChromosomeSplitOutput {
+    pub template: String,
+    options: ConversionOptions,
+    samples: Vec<String>,
+    // position in `outputs` of each chromosome
+    chromosomes: HashMap<String, usize>,
+    outputs: Vec<(BgenOutput, u32)>,
+}
+
+impl ChromosomeSplitOutput {
+    pub fn new(template: &str, options: &ConversionOptions) -> Self {
+        ChromosomeSplitOutput {
+            template: template.to_string(),
+            options: options.clone(),
+            samples: vec![],
+            chromosomes: HashMap::new(),
+            outputs: vec![],
+        }
+    }
+
+    /// Path of the bgen of `chr`
+    pub fn path(&self, chr: &str) -> String {
+        self.template.replace(CHR_PLACEHOLDER, chr)
+    }
+
+    // Output of `chr`, created with its header on the first variant of the chromosome
+    fn output(&mut self, chr: &str) -> Result<&mut (BgenOutput, u32), VcfError> {
+        let index = match self.chromosomes.get(chr) {
+            Some(&index) => index,
+            None => {
+                let mut output =
+                    BgenOutput::create(&self.path(chr), self.options.num_bits, &self.options)?;
+                output.write_header(&self.samples, 0)?;
+                self.outputs.push((output, 0));
+                self.chromosomes
+                    .insert(chr.to_string(), self.outputs.len() - 1);
+                self.outputs.len() - 1
+            }
+        };
+        Ok(&mut self.outputs[index])
+    }
+}
+
+impl VariantSink for ChromosomeSplitOutput {
+    fn write_header(&mut self, samples: &[String], _variant_num: u32) -> Result<(), VcfError> {
+        self.samples = samples.to_vec();
+        Ok(())
+    }
+
+    fn write_variant(&mut self, variant: &Variant) -> Result<(), VcfError> {
+        let (output, variant_num) = self.output(&variant.data.chr)?;
+        output.write_variant(variant)?;
+        *variant_num += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<u64, VcfError> {
+        let mut bytes_written = 0;
+        for (output, variant_num) in self.outputs.iter_mut() {
+            output.set_variant_num(*variant_num)?;
+            bytes_written += output.finish()?;
+        }
+        Ok(bytes_written)
+    }
+
+    // every header is patched with the count of its chromosome anyway
+    fn single_pass(&self) -> bool {
+        true
+    }
+
+    fn set_input_sha256(&mut self, sha256: &str) -> Result<(), VcfError> {
+        for (output, _) in self.outputs.iter_mut() {
+            output.set_input_sha256(sha256)?;
+        }
+        Ok(())
+    }
+}
+
+/// Path of shard `shard` of `output`: `out.bgen` gives `out.shard0.bgen`, and a template such as
+/// `out_{shard}.bgen` gives `out_0.bgen`
+pub fn shard_path(output: &str, shard: u32) -> String {
+    if output.contains(SHARD_PLACEHOLDER) {
+        return output.replace(SHARD_PLACEHOLDER, &shard.to_string());
+    }
+    let stem = output.strip_suffix(".bgen").unwrap_or(output);
+    format!("{}.shard{}.bgen", stem, shard)
+}
+
+/// Path of the tsv listing the shard of each variant: `out.bgen` and `out_{shard}.bgen` give
+/// `out.shards.tsv`
+pub fn shard_manifest_path(output: &str) -> String {
+    let stem = output.strip_suffix(".bgen").unwrap_or(output);
+    let stem = stem
+        .replace(SHARD_PLACEHOLDER, "")
+        .trim_end_matches(['.', '_', '-'])
+        .to_string();
+    match stem.is_empty() || stem.ends_with('/') {
+        true => format!("{}shards.tsv", stem),
+        false => format!("{}.shards.tsv", stem),
+    }
+}
+
+/// The variants spread over several bgen files, with a tsv giving the file of each variant
+///
+/// The number of variants of each shard follows from the total, so headers are exact. The tsv is
+/// written to a temporary path and renamed once complete, like the bgen files.
+pub struct ShardedOutput {
+    pub output: String,
+    pub shards: u32,
+    options: ConversionOptions,
+    outputs: Vec<BgenOutput>,
+    // variants of each block, with `ShardMode::Block`
+    block_size: u32,
+    variants_written: u32,
+    manifest: Option<BufWriter<File>>,
+    // where the manifest is written until it is complete, removed when dropped before
+    manifest_temporary_path: String,
+}
+
+impl ShardedOutput {
+    pub fn new(output: &str, shards: u32, options: &ConversionOptions) -> Self {
+        ShardedOutput {
+            output: output.to_string(),
+            shards,
+            options: options.clone(),
+            outputs: vec![],
+            block_size: 1,
+            variants_written: 0,
+            manifest: None,
+            manifest_temporary_path: temporary_path(
+                &shard_manifest_path(output),
+                options.tmp_dir.as_deref(),
+            ),
+        }
+    }
+
+    // Shard of the variant written after `variant_index` others
+    fn shard(&self, variant_index: u32) -> u32 {
+        match self.options.shard_mode {
+            ShardMode::RoundRobin => variant_index % self.shards,
+            ShardMode::Block => (variant_index / self.block_size).min(self.shards - 1),
+        }
+    }
+
+    // Number of variants of `shard`, out of `variant_num`
+    fn shard_variant_num(&self, shard: u32, variant_num: u32) -> u32 {
+        match self.options.shard_mode {
+            ShardMode::RoundRobin => {
+                variant_num / self.shards + (shard < variant_num % self.shards) as u32
+            }
+            ShardMode::Block => variant_num
+                .saturating_sub(shard * self.block_size)
+                .min(self.block_size),
+        }
+    }
+}
+
+impl VariantSink for ShardedOutput {
+    fn write_header(&mut self, samples: &[String], variant_num: u32) -> Result<(), VcfError> {
+        self.block_size = variant_num.div_ceil(self.shards).max(1);
+        for shard in 0..self.shards {
+            let mut output = BgenOutput::create(
+                &shard_path(&self.output, shard),
+                self.options.num_bits,
+                &self.options,
+            )?;
+            output.write_header(samples, self.shard_variant_num(shard, variant_num))?;
+            self.outputs.push(output);
+        }
+        let manifest_path = shard_manifest_path(&self.output);
+        if !self.options.overwrite && !self.options.resume {
+            check_paths_not_overwritten(&[manifest_path])?;
+        }
+        let mut manifest = BufWriter::new(File::create(&self.manifest_temporary_path)?);
+        writeln!(manifest, "variant_id	chr	pos	shard	path")?;
+        self.manifest = Some(manifest);
+        Ok(())
+    }
+
+    fn write_variant(&mut self, variant: &Variant) -> Result<(), VcfError> {
+        let shard = self.shard(self.variants_written);
+        let output = &mut self.outputs[shard as usize];
+        output.write_variant(variant)?;
+        if let Some(manifest) = &mut self.manifest {
+            writeln!(
+                manifest,
+                "{}\t{}\t{}\t{}\t{}",
+                variant.data.variants_id, variant.data.chr, variant.data.pos, shard, output.path
+            )?;
+        }
+        self.variants_written += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<u64, VcfError> {
+        let mut bytes_written = 0;
+        for output in self.outputs.iter_mut() {
+            bytes_written += output.finish()?;
+        }
+        if let Some(mut manifest) = self.manifest.take() {
+            manifest.flush()?;
+            bytes_written += manifest.get_ref().metadata()?.len();
+            drop(manifest);
+            move_complete_file(
+                &self.manifest_temporary_path,
+                &shard_manifest_path(&self.output),
+            )?;
+        }
+        Ok(bytes_written)
+    }
+
+    fn set_input_sha256(&mut self, sha256: &str) -> Result<(), VcfError> {
+        for output in self.outputs.iter_mut() {
+            output.set_input_sha256(sha256)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ShardedOutput {
+    fn drop(&mut self) {
+        if self.manifest.take().is_some() {
+            let _ = std::fs::remove_file(&self.manifest_temporary_path);
+        }
+    }
+}
+
+// Variants held per sample before the files that are not kept open are appended to
+const PER_SAMPLE_BUFFERED_VARIANTS: usize = 1024;
+
+/// One single-sample bgen per sample, named after the sample, in a directory
+///
+/// Each file is a [`BgenOutput`], encoded as `options` say but without an index, and renamed
+/// once complete. The first `max_open_files` files stay open; variants of the other samples are
+/// buffered and appended to their closed file, one file at a time, so the number of open files
+/// stays bounded.
+pub struct PerSampleBgenOutput {
+    pub dir: String,
+    pub num_bits: u8,
+    pub max_open_files: usize,
+    options: ConversionOptions,
+    paths: Vec<String>,
+    outputs: Vec<BgenOutput>,
+    buffered: Vec<Vec<Variant>>,
+    // written into the closed files as they are finished
+    input_sha256: Option<String>,
+    finished: bool,
+}
+
+impl PerSampleBgenOutput {
+    pub fn create(
+        dir: &str,
+        num_bits: u8,
+        max_open_files: usize,
+        options: &ConversionOptions,
+    ) -> Result<Self, VcfError> {
+        std::fs::create_dir_all(dir)?;
+        Ok(PerSampleBgenOutput {
+            dir: dir.to_string(),
+            num_bits,
+            max_open_files,
+            options: ConversionOptions {
+                write_index: false,
+                checkpoint_every: None,
+                resume: false,
+                ..options.clone()
+            },
+            paths: vec![],
+            outputs: vec![],
+            buffered: vec![],
+            input_sha256: None,
+            finished: false,
+        })
+    }
+
+    // Append the buffered variants of every sample without an open file
+    fn flush_buffered(&mut self) -> Result<(), VcfError> {
+        let open_files = self.outputs.len();
+        for (path, variants) in self.paths[open_files..]
+            .iter()
+            .zip(self.buffered.iter_mut())
+        {
+            if variants.is_empty() {
+                continue;
+            }
+            let mut output = BgenOutput::reopen(path, self.num_bits, &self.options)?;
+            for variant in variants.drain(..) {
+                output.write_variant(&variant)?;
+            }
+            output.close()?;
+        }
+        Ok(())
+    }
+}
+
+/// The variant restricted to each of its samples, in sample order
+pub fn sample_variants(variant_data: &VariantData) -> Vec<VariantData> {
+    let data_block = &variant_data.data_block;
+    sample_probabilities(data_block)
+        .into_iter()
+        .map(|(ploidy_m, probas)| {
+            let ploidy = ploidy_m & crate::PLOIDY_MASK;
+            VariantData {
+                number_individuals: Some(1),
+                variants_id: variant_data.variants_id.clone(),
+                rsid: variant_data.rsid.clone(),
+                chr: variant_data.chr.clone(),
+                pos: variant_data.pos,
+                number_alleles: variant_data.number_alleles,
+                alleles: variant_data.alleles.clone(),
+                file_start_position: variant_data.file_start_position,
+                size_in_bytes: variant_data.size_in_bytes,
+                data_block: DataBlock {
+                    number_individuals: 1,
+                    number_alleles: data_block.number_alleles,
+                    minimum_ploidy: ploidy,
+                    maximum_ploidy: ploidy,
+                    ploidy_missingness: vec![ploidy_m],
+                    phased: data_block.phased,
+                    bits_storage: data_block.bits_storage,
+                    probabilities: probas.to_vec(),
+                },
+            }
+        })
+        .collect()
+}
+
+impl VariantSink for PerSampleBgenOutput {
+    fn write_header(&mut self, samples: &[String], variant_num: u32) -> Result<(), VcfError> {
+        for sample in samples {
+            // sample IDs become file names
+            if sample.is_empty() || sample.contains(['/', '\\']) || sample == "." || sample == ".."
+            {
+                return Err(VcfError::Validation(Report::msg(format!(
+                    "Sample '{}' can not be used as a file name for per-sample outputs",
+                    sample
+                ))));
+            }
+            let path = Path::new(&self.dir).join(format!("{}.bgen", sample));
+            let path = path.to_string_lossy().to_string();
+            let mut output = BgenOutput::create(&path, self.num_bits, &self.options)?;
+            output.write_header(std::slice::from_ref(sample), variant_num)?;
+            if self.outputs.len() < self.max_open_files {
+                self.outputs.push(output);
+            } else {
+                output.close()?;
+                self.buffered.push(vec![]);
+            }
+            self.paths.push(path);
+        }
+        Ok(())
+    }
+
+    fn write_variant(&mut self, variant: &Variant) -> Result<(), VcfError> {
+        // each output rescales the probabilities to its number of bits
+        let mut sample_variants = sample_variants(&variant.data)
+            .into_iter()
+            .map(|data| Variant {
+                data,
+                record_num: variant.record_num,
+                input_id: variant.input_id.clone(),
+            });
+        for (output, sample_variant) in self.outputs.iter_mut().zip(&mut sample_variants) {
+            output.write_variant(&sample_variant)?;
+        }
+        for (variants, sample_variant) in self.buffered.iter_mut().zip(sample_variants) {
+            variants.push(sample_variant);
+        }
+        if self
+            .buffered
+            .first()
+            .is_some_and(|variants| variants.len() >= PER_SAMPLE_BUFFERED_VARIANTS)
+        {
+            self.flush_buffered()?;
+        }
+        Ok(())
+    }
+
+    /// Sum of the sizes of every per-sample file
+    fn finish(&mut self) -> Result<u64, VcfError> {
+        self.flush_buffered()?;
+        let mut bytes_written = 0;
+        for output in &mut self.outputs {
+            bytes_written += output.finish()?;
+        }
+        for path in &self.paths[self.outputs.len()..] {
+            let mut output = BgenOutput::reopen(path, self.num_bits, &self.options)?;
+            if let Some(sha256) = &self.input_sha256 {
+                output.set_input_sha256(sha256)?;
+            }
+            bytes_written += output.finish()?;
+        }
+        self.finished = true;
+        Ok(bytes_written)
+    }
+
+    fn set_input_sha256(&mut self, sha256: &str) -> Result<(), VcfError> {
+        for output in self.outputs.iter_mut() {
+            output.set_input_sha256(sha256)?;
+        }
+        self.input_sha256 = Some(sha256.to_string());
+        Ok(())
+    }
+}
+
+impl Drop for PerSampleBgenOutput {
+    // the open outputs remove their own temporary files
+    fn drop(&mut self) {
+        if !self.finished {
+            for path in &self.paths[self.outputs.len()..] {
+                let _ = std::fs::remove_file(temporary_path(path, self.options.tmp_dir.as_deref()));
+            }
+        }
+    }
+}
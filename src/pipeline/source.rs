@@ -0,0 +1,794 @@
+// Inputs producing variants, one record at a time
+use crate::checksum::InputDigest;
+use crate::dosage::minor_allele_count;
+use crate::duplicates::DuplicateFilter;
+use crate::info_filter::InfoFilter;
+use crate::pipeline::Variant;
+use crate::ploidy::PloidyMap;
+use crate::provenance::hashes_input;
+use crate::regions::{Regions, StartPosition};
+use crate::sample_names::deduplicated_samples;
+use crate::sample_subset::sample_columns;
+use crate::skipped::SkippedVariantReport;
+use crate::thinning::Thinning;
+use crate::variant_class::VariantClassFilter;
+use crate::variant_ids::VariantIdFilter;
+use crate::vcf_header::VcfHeader;
+use crate::{
+    all_samples_missing, check_genotype_count, check_phased_genotypes, check_ploidy,
+    check_sample_limit, check_symbolic_alleles, collapse_multiallelic, expand_reference_block,
+    failed_filter, is_reference_block, keep_multiallelic, malformed_record_error, open_hashed_vcf,
+    parse_genotype_line, read_record_line, read_vcf_header_full, regroup_chromosome_lines,
+    split_multiallelic, trim_record, worker_pool, ChromosomeOrder, ConversionOptions,
+    GenotypeField, MonomorphicPolicy, MultiallelicMode, RefBlockMode, VcfError,
+};
+use bgen_reader::bgen::variant_data::VariantData;
+use color_eyre::Report;
+use rayon::prelude::*;
+use rayon::ThreadPool;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, VecDeque};
+use std::io::BufRead;
+use std::rc::Rc;
+use std::sync::Arc;
+use tracing::warn;
+
+// Records read ahead for each thread parsing them, and the most bytes of records read ahead
+const RECORDS_PER_THREAD: usize = 16;
+const MAX_BATCH_BYTES: usize = 128 << 20;
+
+pub trait VariantSource {
+    fn samples(&self) -> &[String];
+
+    /// Variants of the next record, empty when the record is skipped, `None` at the end of the input
+    fn next_record(&mut self) -> Result<Option<Vec<Variant>>, VcfError>;
+
+    /// Decompressed bytes read so far
+    fn bytes_read(&self) -> u64;
+
+    /// Skip the first `records`, already converted by an interrupted run, returning `bytes_read`
+    fn skip_records(&mut self, _records: u32) -> Result<u64, VcfError> {
+        Err(VcfError::Validation(Report::msg(
+            "This input cannot be resumed",
+        )))
+    }
+
+    /// Records left out for their breakend alleles
+    fn breakends_skipped(&self) -> u64 {
+        0
+    }
+
+    /// Records left out for failing a FILTER, by FILTER value
+    fn filters_skipped(&self) -> Vec<(String, u64)> {
+        vec![]
+    }
+
+    /// Alleles and variants left out, by reason as in the skipped variant report
+    fn variants_skipped(&self) -> Vec<(String, u64)> {
+        vec![]
+    }
+
+    /// Multiallelic records split into biallelic variants
+    fn multiallelics_split(&self) -> u64 {
+        0
+    }
+
+    /// Flush what the source writes besides variants, once every record is read
+    fn finish(&mut self) -> Result<(), VcfError> {
+        Ok(())
+    }
+
+    /// Sha256 of the whole input, hashed as it was read, for the header provenance
+    fn input_sha256(&mut self) -> Result<Option<String>, VcfError> {
+        Ok(None)
+    }
+}
+
+// Allele of a record left out while parsing it, listed in the skipped-variant report
+struct SkippedAllele {
+    chr: String,
+    pos: u32,
+    reference: String,
+    alt: String,
+}
+
+impl SkippedAllele {
+    fn new(site: (&str, u32, &str), alt: &str) -> Self {
+        let (chr, pos, reference) = site;
+        SkippedAllele {
+            chr: chr.to_string(),
+            pos,
+            reference: reference.to_string(),
+            alt: alt.to_string(),
+        }
+    }
+}
+
+// Record parsed on any thread, what it leaves out being reported in input order afterwards
+enum ParsedRecord {
+    Breakend(SkippedAllele),
+    Monomorphic(SkippedAllele),
+    Variants {
+        data: Vec<VariantData>,
+        symbolic: Vec<SkippedAllele>,
+        // split into several biallelic variants
+        split: bool,
+    },
+}
+
+// How a record is converted, decided in input order before it is parsed
+enum RecordPlan {
+    // left out whole, for this reason
+    Skip(&'static str),
+    FailedFilter(String),
+    // whether its variants are checked one by one against the INFO filter
+    Parse { check_info: bool },
+}
+
+// Record read ahead, parsed when its plan asks for it
+struct PendingRecord {
+    line: String,
+    record_num: u32,
+    plan: RecordPlan,
+    parsed: Option<Result<ParsedRecord, VcfError>>,
+}
+
+// Parsing of a record into variants, which depends on no other record
+struct RecordParser {
+    options: ConversionOptions,
+    ploidy_map: Option<PloidyMap>,
+    // sample columns of the input written, how many the input has and how many are written
+    sample_columns: Option<Vec<usize>>,
+    input_sample_num: u32,
+    number_individuals: u32,
+    // sample names and header lines of the input, to locate malformed records
+    input_samples: Vec<String>,
+    header_lines: u32,
+}
+
+impl RecordParser {
+    fn parse(&self, line: &str, record_num: u32) -> Result<ParsedRecord, VcfError> {
+        let options = &self.options;
+        let malformed = || {
+            let line_num = self.header_lines as u64 + record_num as u64;
+            malformed_record_error(line_num, line, &self.input_samples)
+        };
+        let mut variant_data = parse_genotype_line(line, self.input_sample_num, options.num_bits)
+            .map_err(|_| malformed())?;
+        if let Some(alt) = variant_data.breakend_allele() {
+            return Ok(ParsedRecord::Breakend(SkippedAllele::new(
+                variant_data.site(),
+                alt,
+            )));
+        }
+        if variant_data.is_monomorphic() {
+            match options.monomorphic {
+                MonomorphicPolicy::Encode => variant_data.set_monomorphic_alt(),
+                MonomorphicPolicy::Skip => {
+                    return Ok(ParsedRecord::Monomorphic(SkippedAllele::new(
+                        variant_data.site(),
+                        ".",
+                    )))
+                }
+            }
+        }
+        check_genotype_count(&variant_data, self.input_sample_num).map_err(|_| malformed())?;
+        if let Some(columns) = &self.sample_columns {
+            variant_data.select_samples(columns);
+        }
+        check_ploidy(&variant_data, options.max_ploidy)?;
+        if options.strict_phasing {
+            check_phased_genotypes(&variant_data)?;
+        }
+        // follow the phasing of each record, unless forced or probabilities come from other fields
+        let phased = if options.phased || !options.auto_phase || options.reads_probabilities() {
+            options.phased
+        } else {
+            variant_data.phased_genotypes()
+        };
+        variant_data.set_phased(phased);
+        variant_data.set_call_policies(options.missing_policy, options.half_call_policy);
+        variant_data.set_spanning_deletion_policy(options.spanning_deletion);
+        variant_data.set_symbolic_allele_policy(options.symbolic_alleles);
+        let symbolic = check_symbolic_alleles(&variant_data)?
+            .into_iter()
+            .map(|alt| SkippedAllele::new(variant_data.site(), alt))
+            .collect();
+        if let Some(min_gq) = options.min_gq {
+            variant_data.set_min_gq(min_gq);
+        }
+        if options.min_dp.is_some() || options.max_dp.is_some() {
+            variant_data.set_depth_range(options.min_dp, options.max_dp);
+        }
+        if let Some(ploidy_map) = &self.ploidy_map {
+            variant_data.set_sample_ploidy(ploidy_map);
+        }
+        if let Some(prior) = options.genotype_likelihoods {
+            variant_data.set_genotype_likelihoods(prior);
+        }
+        if let Some(dosage_field) = &options.dosage_field {
+            variant_data.set_dosage_field(dosage_field);
+        }
+        if !options.gt_priority.is_empty() {
+            variant_data.set_field_priority(&options.gt_priority);
+        } else if !variant_data.has_hard_calls() && options.dosage_field.is_none() {
+            // imputation outputs may only have DS or GP
+            variant_data.set_field_priority(&[GenotypeField::Gp, GenotypeField::Ds]);
+        }
+        let number_individuals = self.number_individuals;
+        let mut split = false;
+        let data =
+            if options.ref_blocks == RefBlockMode::Expand && is_reference_block(&variant_data) {
+                expand_reference_block(&variant_data, number_individuals)?
+            } else {
+                match options.multiallelic {
+                    MultiallelicMode::Split => {
+                        let data = split_multiallelic(variant_data, number_individuals)?;
+                        split = data.len() > 1;
+                        data
+                    }
+                    MultiallelicMode::Keep => keep_multiallelic(variant_data, number_individuals)?,
+                    MultiallelicMode::Collapse => {
+                        collapse_multiallelic(variant_data, number_individuals)?
+                    }
+                }
+            };
+        Ok(ParsedRecord::Variants {
+            data,
+            symbolic,
+            split,
+        })
+    }
+}
+
+/// Records of a vcf, split into biallelic variants
+///
+/// Records are read ahead in batches and parsed on the threads of the conversion, then filtered
+/// and reported one at a time in input order.
+pub struct VcfSource {
+    reader: Box<dyn BufRead>,
+    // sha256 of the input, fed by the reader
+    input_digest: Option<Rc<RefCell<InputDigest>>>,
+    header: VcfHeader,
+    options: ConversionOptions,
+    parser: RecordParser,
+    // taken on the first batch, so the count pass parsing records one at a time starts no thread
+    pool: Option<Arc<ThreadPool>>,
+    pending: VecDeque<Result<PendingRecord, VcfError>>,
+    regions: Option<Regions>,
+    start_position: Option<StartPosition>,
+    variant_ids: Option<VariantIdFilter>,
+    variant_class: Option<VariantClassFilter>,
+    info_filter: Option<InfoFilter>,
+    duplicates: Option<DuplicateFilter>,
+    thinning: Option<Thinning>,
+    skipped_report: Option<SkippedVariantReport>,
+    breakends_skipped: u64,
+    filters_skipped: BTreeMap<String, u64>,
+    variants_skipped: BTreeMap<String, u64>,
+    multiallelics_split: u64,
+    // records expected, read until the end of the input in a single pass
+    number_geno_line: Option<u32>,
+    // chromosomes seen, checked while reading in a single pass rather than when counting
+    chromosome_order: Option<ChromosomeOrder>,
+    // all records, when they are regrouped by chromosome before conversion
+    regrouped_lines: Option<std::vec::IntoIter<String>>,
+    // records read from the input, ahead of those returned
+    lines_read: u32,
+    records_read: u32,
+    bytes_read: u64,
+    line: String,
+}
+
+impl VcfSource {
+    /// Open `input` and read its header, expecting `number_geno_line` records after it
+    pub fn open(
+        input: &str,
+        options: &ConversionOptions,
+        number_geno_line: u32,
+    ) -> Result<Self, VcfError> {
+        Self::open_records(input, options, Some(number_geno_line))
+    }
+
+    /// Open `input` and read its header, its records being read until the end of the input
+    pub fn open_single_pass(input: &str, options: &ConversionOptions) -> Result<Self, VcfError> {
+        if options.regroup_chromosomes {
+            return Err(VcfError::Validation(Report::msg(
+                "Records are regrouped by chromosome after counting them, not in a single pass",
+            )));
+        }
+        Self::open_records(input, options, None)
+    }
+
+    fn open_records(
+        input: &str,
+        options: &ConversionOptions,
+        number_geno_line: Option<u32>,
+    ) -> Result<Self, VcfError> {
+        if let (Some(min_dp), Some(max_dp)) = (options.min_dp, options.max_dp) {
+            if min_dp > max_dp {
+                return Err(VcfError::Validation(Report::msg(format!(
+                    "--min-dp {} is above --max-dp {}",
+                    min_dp, max_dp
+                ))));
+            }
+        }
+        let input_digest =
+            hashes_input(input, options).then(|| Rc::new(RefCell::new(InputDigest::new(input))));
+        let mut reader = open_hashed_vcf(input, options, input_digest.clone())?;
+        let mut header = read_vcf_header_full(&mut reader)?;
+        if header.samples.is_empty() && !options.allow_sites_only {
+            return Err(VcfError::Validation(Report::msg(format!(
+                "{} has no sample columns (sites-only vcf); use --allow-sites-only to write a bgen without samples",
+                input
+            ))));
+        }
+        let input_sample_num = header.samples.len() as u32;
+        let input_samples = header.samples.clone();
+        let header_lines = header.header_lines;
+        // numbered before subsetting, so keep and remove lists can name the numbered samples
+        header.samples = deduplicated_samples(&header.samples, options.dedup_samples)?;
+        let sample_columns = sample_columns(&header.samples, options)?;
+        let ploidy_map = PloidyMap::load(options, &header.samples, sample_columns.as_deref())?;
+        if let Some(columns) = &sample_columns {
+            header.samples = columns.iter().map(|&i| header.samples[i].clone()).collect();
+        }
+        check_sample_limit(&header.samples)?;
+        let regions = Regions::load(options)?;
+        let start_position = StartPosition::load(options)?;
+        let variant_ids = VariantIdFilter::load(options)?;
+        let variant_class = VariantClassFilter::load(options)?;
+        let info_filter = options
+            .info_filter
+            .as_deref()
+            .map(InfoFilter::parse)
+            .transpose()?;
+        let thinning = Thinning::load(options)?;
+        let skipped_report = match &options.skipped_report {
+            Some(path) => Some(SkippedVariantReport::create(path)?),
+            None => None,
+        };
+        let regrouped_lines = if options.regroup_chromosomes {
+            Some(
+                regroup_chromosome_lines(&mut reader, number_geno_line.unwrap_or_default())?
+                    .into_iter(),
+            )
+        } else {
+            None
+        };
+        let number_individuals = header.samples.len() as u32;
+        if options.threads == Some(0) {
+            return Err(VcfError::Validation(Report::msg("--threads is at least 1")));
+        }
+        Ok(VcfSource {
+            reader,
+            input_digest,
+            header,
+            options: options.clone(),
+            parser: RecordParser {
+                options: options.clone(),
+                ploidy_map,
+                number_individuals,
+                sample_columns,
+                input_sample_num,
+                input_samples,
+                header_lines,
+            },
+            pool: None,
+            pending: VecDeque::new(),
+            regions,
+            start_position,
+            variant_ids,
+            variant_class,
+            info_filter,
+            duplicates: DuplicateFilter::new(options.dup_policy),
+            thinning,
+            skipped_report,
+            breakends_skipped: 0,
+            filters_skipped: BTreeMap::new(),
+            variants_skipped: BTreeMap::new(),
+            multiallelics_split: 0,
+            number_geno_line,
+            chromosome_order: number_geno_line.is_none().then(ChromosomeOrder::default),
+            regrouped_lines,
+            lines_read: 0,
+            records_read: 0,
+            bytes_read: 0,
+            line: String::new(),
+        })
+    }
+
+    pub fn header(&self) -> &VcfHeader {
+        &self.header
+    }
+
+    // Read the next records, enough to keep every thread busy, and parse them together
+    //
+    // An error reading a record is returned after the records before it, as when reading one at
+    // a time.
+    fn read_batch(&mut self) -> Result<(), VcfError> {
+        let pool = match self.pool.take() {
+            Some(pool) => pool,
+            None => worker_pool(self.options.threads)?,
+        };
+        let mut batch = vec![];
+        let mut batch_bytes = 0;
+        let mut read_error = None;
+        while batch.len() < pool.current_num_threads() * RECORDS_PER_THREAD
+            && batch_bytes < MAX_BATCH_BYTES
+        {
+            match self.read_next_line() {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(error) => {
+                    read_error = Some(error);
+                    break;
+                }
+            }
+            self.lines_read += 1;
+            let line = std::mem::take(&mut self.line);
+            batch_bytes += line.len();
+            let plan = self.plan_record(&line);
+            batch.push(PendingRecord {
+                line,
+                record_num: self.lines_read,
+                plan,
+                parsed: None,
+            });
+        }
+        let parser = &self.parser;
+        pool.install(|| {
+            batch.par_iter_mut().for_each(|record| {
+                if let RecordPlan::Parse { .. } = record.plan {
+                    record.parsed = Some(parser.parse(&record.line, record.record_num));
+                }
+            })
+        });
+        self.pool = Some(pool);
+        self.pending.extend(batch.into_iter().map(Ok));
+        self.pending.extend(read_error.map(Err));
+        Ok(())
+    }
+
+    // Fill `self.line` with the next record, returning false after the last one
+    fn read_next_line(&mut self) -> Result<bool, VcfError> {
+        self.line.clear();
+        if let Some(lines) = self.regrouped_lines.as_mut() {
+            return Ok(match lines.next() {
+                Some(line) => {
+                    self.line = line;
+                    true
+                }
+                None => false,
+            });
+        }
+        if Some(self.lines_read) == self.number_geno_line {
+            return Ok(false);
+        }
+        let num_bytes = read_record_line(&mut self.reader, &mut self.line, self.lines_read)?;
+        match self.number_geno_line {
+            Some(number_geno_line) if num_bytes == 0 => {
+                return Err(VcfError::Nom(Report::msg(format!(
+                    "Input ended after {} records, {} were expected",
+                    self.lines_read, number_geno_line
+                ))))
+            }
+            _ if num_bytes == 0 => return Ok(false),
+            _ => {}
+        }
+        if let Some(chromosome_order) = &mut self.chromosome_order {
+            let chr = self.line.split('\t').next().unwrap_or_default();
+            chromosome_order.observe(chr, self.lines_read + 1)?;
+        }
+        Ok(true)
+    }
+
+    /// Variants of the record on `line`, empty when it is skipped or all of them filtered out
+    pub fn record_variants(
+        &mut self,
+        line: &str,
+        record_num: u32,
+    ) -> Result<Vec<Variant>, VcfError> {
+        let plan = self.plan_record(line);
+        let parsed = match plan {
+            RecordPlan::Parse { .. } => Some(self.parser.parse(line, record_num)),
+            _ => None,
+        };
+        self.finish_record(line, record_num, plan, parsed)
+    }
+
+    // Prefilters of a record, from its fixed columns
+    fn plan_record(&mut self, line: &str) -> RecordPlan {
+        // left out of the count too
+        if let Some(start) = self.start_position.as_mut() {
+            if start.is_before(line) {
+                return RecordPlan::Skip("before start");
+            }
+        }
+        if let Some(regions) = &self.regions {
+            if !regions.contains_record(line) {
+                return RecordPlan::Skip("region");
+            }
+        }
+        if self.options.pass_only {
+            if let Some(filter) = failed_filter(line) {
+                return RecordPlan::FailedFilter(filter.to_string());
+            }
+        }
+        if let Some(filter) = &self.variant_ids {
+            if !filter.may_keep_record(line) {
+                return RecordPlan::Skip("id");
+            }
+        }
+        if let Some(filter) = &self.variant_class {
+            let reference = line.split('\t').nth(3).unwrap_or_default();
+            if !filter.may_keep_reference(reference) {
+                return RecordPlan::Skip("class");
+            }
+        }
+        // variants of records where only some alts pass are checked one by one
+        match self
+            .info_filter
+            .as_ref()
+            .map(|filter| filter.keeps_record(line))
+        {
+            Some(Some(false)) => RecordPlan::Skip("info"),
+            Some(None) => RecordPlan::Parse { check_info: true },
+            _ => RecordPlan::Parse { check_info: false },
+        }
+    }
+
+    fn report(
+        &mut self,
+        record_num: u32,
+        allele: &SkippedAllele,
+        reason: &str,
+    ) -> Result<(), VcfError> {
+        *self.variants_skipped.entry(reason.to_string()).or_default() += 1;
+        match self.skipped_report.as_mut() {
+            Some(report) => report.record(
+                self.header.header_lines + record_num,
+                &allele.chr,
+                allele.pos,
+                &allele.reference,
+                &allele.alt,
+                reason,
+            ),
+            None => Ok(()),
+        }
+    }
+
+    // Count and report a variant left out by a filter
+    fn report_variant(&mut self, variant: &Variant, reason: &str) -> Result<(), VcfError> {
+        *self.variants_skipped.entry(reason.to_string()).or_default() += 1;
+        match self.skipped_report.as_mut() {
+            Some(report) => {
+                let data = &variant.data;
+                let alts = data.alleles[1..].join(",");
+                let line = self.header.header_lines + variant.record_num;
+                report.record(line, &data.chr, data.pos, &data.alleles[0], &alts, reason)
+            }
+            None => Ok(()),
+        }
+    }
+
+    // Count and report a record left out whole, from its fixed columns
+    fn report_record(&mut self, line: &str, record_num: u32, reason: &str) -> Result<(), VcfError> {
+        let fields: Vec<&str> = line.split('\t').take(5).collect();
+        match fields[..] {
+            [chr, pos, _, reference, alt] => {
+                let pos = pos.parse().unwrap_or_default();
+                let allele = SkippedAllele::new((chr, pos, reference), alt);
+                self.report(record_num, &allele, reason)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Report what the record leaves out, then filter its variants, in input order
+    fn finish_record(
+        &mut self,
+        line: &str,
+        record_num: u32,
+        plan: RecordPlan,
+        parsed: Option<Result<ParsedRecord, VcfError>>,
+    ) -> Result<Vec<Variant>, VcfError> {
+        let check_info = match plan {
+            RecordPlan::Skip(reason) => {
+                self.report_record(line, record_num, reason)?;
+                return Ok(vec![]);
+            }
+            RecordPlan::FailedFilter(filter) => {
+                *self.filters_skipped.entry(filter.clone()).or_default() += 1;
+                self.report_record(line, record_num, &format!("FILTER {}", filter))?;
+                return Ok(vec![]);
+            }
+            RecordPlan::Parse { check_info } => check_info,
+        };
+        let vec_variant_data =
+            match parsed.unwrap_or_else(|| self.parser.parse(line, record_num))? {
+                ParsedRecord::Breakend(allele) => {
+                    if self.breakends_skipped == 0 {
+                        warn!(
+                            "skipping breakend records, the first at {}:{} ({})",
+                            allele.chr, allele.pos, allele.alt
+                        );
+                    }
+                    self.breakends_skipped += 1;
+                    self.report(record_num, &allele, "breakend")?;
+                    return Ok(vec![]);
+                }
+                ParsedRecord::Monomorphic(allele) => {
+                    self.report(record_num, &allele, "monomorphic")?;
+                    return Ok(vec![]);
+                }
+                ParsedRecord::Variants {
+                    data,
+                    symbolic,
+                    split,
+                } => {
+                    for allele in &symbolic {
+                        self.report(record_num, allele, "symbolic allele")?;
+                    }
+                    self.multiallelics_split += split as u64;
+                    data
+                }
+            };
+        let fields: Vec<&str> = trim_record(line).split('\t').take(8).collect();
+        let input_id = fields.get(2).copied().unwrap_or_default();
+        let vcf_alts: Vec<&str> = fields
+            .get(4)
+            .copied()
+            .unwrap_or_default()
+            .split(',')
+            .collect();
+        let info = fields.get(7).copied().unwrap_or_default();
+        let mut variants = Vec::with_capacity(vec_variant_data.len());
+        for data in vec_variant_data {
+            let variant = Variant {
+                data,
+                record_num,
+                input_id: input_id.to_string(),
+            };
+            if let Some(filter) = &self.variant_ids {
+                if !filter.keeps(&variant) {
+                    self.report_variant(&variant, "id")?;
+                    continue;
+                }
+            }
+            if let Some(filter) = &self.variant_class {
+                if !filter.keeps(&variant.data.alleles) {
+                    self.report_variant(&variant, "class")?;
+                    continue;
+                }
+            }
+            if let Some(filter) = self.info_filter.as_ref().filter(|_| check_info) {
+                let alleles = &variant.data.alleles;
+                let alt_index = match alleles.len() {
+                    2 => vcf_alts.iter().position(|&alt| alt == alleles[1]),
+                    _ => None,
+                };
+                if !filter.keeps(info, alt_index) {
+                    self.report_variant(&variant, "info")?;
+                    continue;
+                }
+            }
+            // biallelic variants only, those of records kept whole having no single minor allele
+            if let Some(min_mac) = self.options.min_mac {
+                let data = &variant.data;
+                if data.alleles.len() == 2 && minor_allele_count(data) < min_mac as f64 {
+                    self.report_variant(&variant, "mac")?;
+                    continue;
+                }
+            }
+            if self.options.drop_all_missing && all_samples_missing(&variant.data.data_block) {
+                self.report_variant(&variant, "all missing")?;
+                continue;
+            }
+            if let Some(duplicates) = self.duplicates.as_mut() {
+                let data = &variant.data;
+                if !duplicates.keeps(&data.chr, data.pos, &data.alleles, record_num)? {
+                    self.report_variant(&variant, "duplicate")?;
+                    continue;
+                }
+            }
+            if let Some(thinning) = self.thinning.as_mut() {
+                let data = &variant.data;
+                if !thinning.keeps(&data.chr, data.pos) {
+                    self.report_variant(&variant, "thin")?;
+                    continue;
+                }
+            }
+            variants.push(variant);
+        }
+        Ok(variants)
+    }
+}
+
+impl VariantSource for VcfSource {
+    fn samples(&self) -> &[String] {
+        &self.header.samples
+    }
+
+    fn next_record(&mut self) -> Result<Option<Vec<Variant>>, VcfError> {
+        if self.pending.is_empty() {
+            self.read_batch()?;
+        }
+        let Some(record) = self.pending.pop_front() else {
+            return Ok(None);
+        };
+        let record = record?;
+        self.records_read += 1;
+        self.bytes_read += record.line.len() as u64;
+        self.finish_record(&record.line, record.record_num, record.plan, record.parsed)
+            .map(Some)
+    }
+
+    fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    fn skip_records(&mut self, records: u32) -> Result<u64, VcfError> {
+        for _ in 0..records {
+            if !self.read_next_line()? {
+                return Err(VcfError::Validation(Report::msg(format!(
+                    "The input has fewer than the {} records of the checkpoint",
+                    records
+                ))));
+            }
+            self.lines_read += 1;
+            self.records_read += 1;
+            self.bytes_read += self.line.len() as u64;
+            if let Some(start) = self.start_position.as_mut() {
+                start.is_before(&self.line);
+            }
+            // the variants thinned out or deduplicated depend on those before, so the skipped
+            // records are replayed
+            if self.thinning.is_some() || self.duplicates.is_some() {
+                let line = std::mem::take(&mut self.line);
+                let report = self.skipped_report.take();
+                let replayed = self.record_variants(&line, self.records_read);
+                self.skipped_report = report;
+                self.line = line;
+                replayed?;
+            }
+        }
+        Ok(self.bytes_read)
+    }
+
+    fn breakends_skipped(&self) -> u64 {
+        self.breakends_skipped
+    }
+
+    fn filters_skipped(&self) -> Vec<(String, u64)> {
+        self.filters_skipped
+            .iter()
+            .map(|(filter, count)| (filter.clone(), *count))
+            .collect()
+    }
+
+    fn variants_skipped(&self) -> Vec<(String, u64)> {
+        self.variants_skipped
+            .iter()
+            .map(|(reason, count)| (reason.clone(), *count))
+            .collect()
+    }
+
+    fn multiallelics_split(&self) -> u64 {
+        self.multiallelics_split
+    }
+
+    fn finish(&mut self) -> Result<(), VcfError> {
+        match self.skipped_report.as_mut() {
+            Some(report) => report.finish(),
+            None => Ok(()),
+        }
+    }
+
+    fn input_sha256(&mut self) -> Result<Option<String>, VcfError> {
+        match self.input_digest.take() {
+            Some(digest) => Ok(Some(digest.borrow_mut().finish()?)),
+            None => Ok(None),
+        }
+    }
+}
@@ -0,0 +1,333 @@
+// Steps applied to each variant between the source and the sinks
+use crate::dosage::variant_dosages;
+use crate::metrics::ConversionMetrics;
+use crate::pipeline::Variant;
+use crate::rsid_annotation::RsidReference;
+use crate::{
+    copies_to_proba, rescale_probabilities, sample_probabilities, ContigStyle, FillMissing,
+    VcfError, MISSING_FLAG, PLOIDY_MASK,
+};
+use bgen_reader::bgen::variant_data::DataBlock;
+use tracing::warn;
+
+pub trait VariantTransform {
+    /// Name used when reporting what the transform did
+    fn name(&self) -> &str;
+
+    /// Return the variant to pass on, or `None` to drop it
+    fn apply(&mut self, variant: Variant) -> Result<Option<Variant>, VcfError>;
+
+    /// Add what the transform measured to the metrics of the run
+    fn finish(&mut self, _metrics: &mut ConversionMetrics) {}
+}
+
+/// Rename contigs to a naming style, warning once when records mix styles
+pub struct ContigStyleTransform {
+    target_style: ContigStyle,
+    seen_style: Option<ContigStyle>,
+    warned: bool,
+}
+
+impl ContigStyleTransform {
+    pub fn new(target_style: ContigStyle) -> Self {
+        ContigStyleTransform {
+            target_style,
+            seen_style: None,
+            warned: false,
+        }
+    }
+}
+
+impl VariantTransform for ContigStyleTransform {
+    fn name(&self) -> &str {
+        "contig_style"
+    }
+
+    fn apply(&mut self, mut variant: Variant) -> Result<Option<Variant>, VcfError> {
+        let style = ContigStyle::of(&variant.data.chr);
+        if !self.warned && self.seen_style.is_some_and(|seen| seen != style) {
+            warn!(
+                "records mix contig naming styles from {}:{}",
+                variant.data.chr, variant.data.pos
+            );
+            self.warned = true;
+        }
+        self.seen_style = Some(style);
+        if style == self.target_style {
+            return Ok(Some(variant));
+        }
+        let chr = self.target_style.rename(&variant.data.chr);
+        // identifiers built from the position start with the contig
+        let old_prefix = format!("{}:", variant.data.chr);
+        for id in [&mut variant.data.variants_id, &mut variant.data.rsid] {
+            if let Some(rest) = id.strip_prefix(&old_prefix) {
+                *id = format!("{}:{}", chr, rest);
+            }
+        }
+        variant.data.chr = chr;
+        Ok(Some(variant))
+    }
+}
+
+/// Keep the most likely genotype of each sample as a hard call when its probability exceeds the
+/// threshold, the sample being missing otherwise, as plink 2 does
+pub struct HardCallTransform {
+    threshold: f64,
+}
+
+impl HardCallTransform {
+    pub fn new(threshold: f64) -> Self {
+        HardCallTransform { threshold }
+    }
+}
+
+impl VariantTransform for HardCallTransform {
+    fn name(&self) -> &str {
+        "hard_call"
+    }
+
+    fn apply(&mut self, mut variant: Variant) -> Result<Option<Variant>, VcfError> {
+        let data_block = &variant.data.data_block;
+        // phased haplotypes are written from GT only
+        if data_block.phased {
+            return Ok(Some(variant));
+        }
+        let max_proba = (1u64 << data_block.bits_storage) - 1;
+        let mut ploidy_missingness = Vec::with_capacity(data_block.ploidy_missingness.len());
+        let mut probabilities = Vec::with_capacity(data_block.probabilities.len());
+        for (ploidy_m, probas) in sample_probabilities(data_block) {
+            let implied = max_proba.saturating_sub(probas.iter().map(|&p| p as u64).sum());
+            let (best, best_proba) = probas
+                .iter()
+                .map(|&p| p as u64)
+                .chain(std::iter::once(implied))
+                .enumerate()
+                .fold(
+                    (0, 0),
+                    |best, (i, p)| if p > best.1 { (i, p) } else { best },
+                );
+            let called = ploidy_m & MISSING_FLAG == 0
+                && best_proba as f64 / max_proba as f64 > self.threshold;
+            ploidy_missingness.push(if called {
+                ploidy_m
+            } else {
+                ploidy_m | MISSING_FLAG
+            });
+            // the last genotype is implied by zero stored values
+            probabilities.extend((0..probas.len()).map(|i| {
+                if called && i == best {
+                    max_proba as u32
+                } else {
+                    0
+                }
+            }));
+        }
+        variant.data.data_block.ploidy_missingness = ploidy_missingness;
+        variant.data.data_block.probabilities = probabilities;
+        Ok(Some(variant))
+    }
+}
+
+/// Write missing genotypes as homozygous reference or with the mean dosage of the variant,
+/// clearing their missingness flag
+///
+/// The mean dosage only applies to biallelic variants, others being filled with the reference.
+pub struct FillMissingTransform {
+    fill: FillMissing,
+}
+
+impl FillMissingTransform {
+    pub fn new(fill: FillMissing) -> Self {
+        FillMissingTransform { fill }
+    }
+}
+
+impl VariantTransform for FillMissingTransform {
+    fn name(&self) -> &str {
+        "fill_missing"
+    }
+
+    fn apply(&mut self, mut variant: Variant) -> Result<Option<Variant>, VcfError> {
+        let data_block = &variant.data.data_block;
+        if self.fill == FillMissing::None
+            || data_block
+                .ploidy_missingness
+                .iter()
+                .all(|ploidy_m| ploidy_m & MISSING_FLAG == 0)
+        {
+            return Ok(Some(variant));
+        }
+        // alt allele frequency of the called haplotypes, zero filling with the reference
+        let mut alt_frequency = 0.0;
+        if self.fill == FillMissing::MeanDosage && data_block.number_alleles <= 2 {
+            let dosages = variant_dosages(&variant.data);
+            let (dosage_sum, haplotypes) = dosages
+                .iter()
+                .zip(&data_block.ploidy_missingness)
+                .filter_map(|(dosage, ploidy_m)| Some((dosage.as_ref()?, ploidy_m & PLOIDY_MASK)))
+                .fold((0.0, 0u32), |(sum, haplotypes), (dosage, ploidy)| {
+                    (sum + dosage, haplotypes + ploidy as u32)
+                });
+            if haplotypes > 0 {
+                alt_frequency = dosage_sum / haplotypes as f64;
+            }
+        }
+        let num_bits = data_block.bits_storage;
+        let max_proba = ((1u64 << num_bits) - 1) as f64;
+        let mut ploidy_missingness = Vec::with_capacity(data_block.ploidy_missingness.len());
+        let mut probabilities = Vec::with_capacity(data_block.probabilities.len());
+        for (ploidy_m, probas) in sample_probabilities(data_block) {
+            if ploidy_m & MISSING_FLAG == 0 {
+                ploidy_missingness.push(ploidy_m);
+                probabilities.extend_from_slice(probas);
+                continue;
+            }
+            ploidy_missingness.push(ploidy_m & !MISSING_FLAG);
+            let ploidy = (ploidy_m & PLOIDY_MASK) as usize;
+            if data_block.phased {
+                // probability of the first allele on each haplotype, the others being zero
+                let num_alleles = (data_block.number_alleles as usize).max(2);
+                for value in 0..probas.len() {
+                    let first_allele = value % (num_alleles - 1) == 0;
+                    let ref_proba = if first_allele {
+                        1.0 - alt_frequency
+                    } else {
+                        0.0
+                    };
+                    probabilities.push((ref_proba * max_proba).round() as u32);
+                }
+            } else if data_block.number_alleles <= 2 {
+                // the dosage is split over the two nearest numbers of alt copies
+                let dosage = alt_frequency * ploidy as f64;
+                let mut copies_proba = vec![0.0; ploidy + 1];
+                let below = (dosage.floor() as usize).min(ploidy);
+                copies_proba[below] = 1.0 - (dosage - below as f64);
+                if below < ploidy {
+                    copies_proba[below + 1] = dosage - below as f64;
+                }
+                let filled = copies_to_proba(&copies_proba, num_bits)
+                    .unwrap_or_else(|| vec![0; probas.len()]);
+                probabilities.extend(filled);
+            } else {
+                // homozygous reference is the first genotype
+                probabilities.extend((0..probas.len()).map(|i| {
+                    if i == 0 {
+                        max_proba as u32
+                    } else {
+                        0
+                    }
+                }));
+            }
+        }
+        variant.data.data_block.ploidy_missingness = ploidy_missingness;
+        variant.data.data_block.probabilities = probabilities;
+        Ok(Some(variant))
+    }
+}
+
+/// Round probabilities to fewer bits, measuring the largest change of any probability, the
+/// implied last one of each sample or haplotype included
+pub struct RoundingTransform {
+    num_bits: u8,
+    max_error: f64,
+}
+
+impl RoundingTransform {
+    pub fn new(num_bits: u8) -> Self {
+        RoundingTransform {
+            num_bits,
+            max_error: 0.0,
+        }
+    }
+}
+
+// Stored probabilities of each haplotype when phased, of each sample otherwise
+fn probability_groups(data_block: &DataBlock) -> Vec<&[u32]> {
+    if data_block.phased {
+        let num_alleles = (data_block.number_alleles as usize).max(2);
+        data_block.probabilities.chunks(num_alleles - 1).collect()
+    } else {
+        sample_probabilities(data_block)
+            .into_iter()
+            .map(|(_, probas)| probas)
+            .collect()
+    }
+}
+
+impl VariantTransform for RoundingTransform {
+    fn name(&self) -> &str {
+        "rounding"
+    }
+
+    fn apply(&mut self, mut variant: Variant) -> Result<Option<Variant>, VcfError> {
+        if variant.data.data_block.bits_storage == self.num_bits {
+            return Ok(Some(variant));
+        }
+        let rounded = rescale_probabilities(&variant.data, self.num_bits);
+        let read = &variant.data.data_block;
+        let read_max = ((1u64 << read.bits_storage) - 1) as f64;
+        let rounded_max = ((1u64 << self.num_bits) - 1) as f64;
+        for (read_probas, rounded_probas) in probability_groups(read)
+            .into_iter()
+            .zip(probability_groups(&rounded.data_block))
+        {
+            let (mut read_sum, mut rounded_sum) = (0.0, 0.0);
+            for (&read_p, &rounded_p) in read_probas.iter().zip(rounded_probas) {
+                let (read_p, rounded_p) =
+                    (read_p as f64 / read_max, rounded_p as f64 / rounded_max);
+                self.max_error = self.max_error.max((read_p - rounded_p).abs());
+                read_sum += read_p;
+                rounded_sum += rounded_p;
+            }
+            self.max_error = self.max_error.max((read_sum - rounded_sum).abs());
+        }
+        variant.data = rounded;
+        Ok(Some(variant))
+    }
+
+    fn finish(&mut self, metrics: &mut ConversionMetrics) {
+        metrics.max_prob_error = Some(self.max_error);
+    }
+}
+
+/// Write the input ID of each variant as its rsID, looking up those missing or `.` in a dbSNP
+/// reference; variants it does not list keep their chr:pos:ref:alt identifier
+pub struct RsidAnnotationTransform {
+    reference: RsidReference,
+    annotated: u64,
+}
+
+impl RsidAnnotationTransform {
+    pub fn new(reference: RsidReference) -> Self {
+        RsidAnnotationTransform {
+            reference,
+            annotated: 0,
+        }
+    }
+}
+
+impl VariantTransform for RsidAnnotationTransform {
+    fn name(&self) -> &str {
+        "rsid_annotation"
+    }
+
+    fn apply(&mut self, mut variant: Variant) -> Result<Option<Variant>, VcfError> {
+        if !variant.input_id.is_empty() && variant.input_id != "." {
+            variant.data.rsid = variant.input_id.clone();
+            return Ok(Some(variant));
+        }
+        let data = &variant.data;
+        // records kept whole have no single alt to look up
+        if let [reference, alt] = &data.alleles[..] {
+            if let Some(rsid) = self.reference.lookup(&data.chr, data.pos, reference, alt) {
+                variant.data.rsid = rsid.to_string();
+                self.annotated += 1;
+            }
+        }
+        Ok(Some(variant))
+    }
+
+    fn finish(&mut self, metrics: &mut ConversionMetrics) {
+        metrics.rsids_annotated = self.annotated;
+    }
+}
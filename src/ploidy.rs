@@ -0,0 +1,191 @@
+// Expected ploidy of samples by region, e.g. haploid chrX for males
+use crate::{ConversionOptions, GenomeBuild, VcfError};
+use color_eyre::Report;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+// Largest ploidy stored in the 6 low bits of the ploidy/missingness byte
+pub const MAX_PLOIDY: u8 = 63;
+
+/// Ploidy of one sample over an inclusive range of positions of a contig
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PloidyRule {
+    pub sample_index: usize,
+    pub chr: String,
+    pub from: u32,
+    pub to: u32,
+    pub ploidy: u8,
+}
+
+/// Ploidy rules, the last rule covering a position taking precedence
+#[derive(Debug, Clone, Default)]
+pub struct PloidyMap {
+    pub rules: Vec<PloidyRule>,
+}
+
+impl PloidyMap {
+    /// Rules of the sex file then of the ploidy map of `options`, `None` when neither is set
+    ///
+    /// Rows name samples of the vcf, all of them in `samples`; when only `sample_columns` are kept,
+    /// the rules of the samples left out are dropped and the others point to the kept samples.
+    pub fn load(
+        options: &ConversionOptions,
+        samples: &[String],
+        sample_columns: Option<&[usize]>,
+    ) -> Result<Option<Self>, VcfError> {
+        if options.sex_file.is_none() && options.ploidy_map.is_none() {
+            return Ok(None);
+        }
+        let mut ploidy_map = PloidyMap::default();
+        if let Some(path) = &options.sex_file {
+            ploidy_map
+                .rules
+                .extend(read_sex_file(path, samples, options.genome_build)?);
+        }
+        // explicit regions take precedence over the rules derived from sex
+        if let Some(path) = &options.ploidy_map {
+            ploidy_map
+                .rules
+                .extend(PloidyMap::read(path, samples)?.rules);
+        }
+        if let Some(columns) = sample_columns {
+            let mut kept_index = vec![None; samples.len()];
+            for (index, &column) in columns.iter().enumerate() {
+                kept_index[column] = Some(index);
+            }
+            ploidy_map
+                .rules
+                .retain_mut(|rule| match kept_index[rule.sample_index] {
+                    Some(index) => {
+                        rule.sample_index = index;
+                        true
+                    }
+                    None => false,
+                });
+        }
+        Ok(Some(ploidy_map))
+    }
+
+    /// Read whitespace-separated `SAMPLE CHROM FROM TO PLOIDY` rows, skipping `#` comments
+    pub fn read(path: &str, samples: &[String]) -> Result<Self, VcfError> {
+        let reader = BufReader::new(File::open(path)?);
+        let mut rules = vec![];
+        for (line_i, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid_row = || {
+                VcfError::Validation(Report::msg(format!(
+                    "{} line {}: expected SAMPLE CHROM FROM TO PLOIDY, found '{}'",
+                    path,
+                    line_i + 1,
+                    line
+                )))
+            };
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [sample, chr, from, to, ploidy] = fields[..] else {
+                return Err(invalid_row());
+            };
+            let sample_index = samples.iter().position(|s| s == sample).ok_or_else(|| {
+                VcfError::Validation(Report::msg(format!(
+                    "{} line {}: sample '{}' is not in the vcf",
+                    path,
+                    line_i + 1,
+                    sample
+                )))
+            })?;
+            let ploidy: u8 = ploidy.parse().map_err(|_| invalid_row())?;
+            if ploidy > MAX_PLOIDY {
+                return Err(invalid_row());
+            }
+            rules.push(PloidyRule {
+                sample_index,
+                chr: chr.to_string(),
+                from: from.parse().map_err(|_| invalid_row())?,
+                to: to.parse().map_err(|_| invalid_row())?,
+                ploidy,
+            });
+        }
+        Ok(PloidyMap { rules })
+    }
+
+    /// Ploidy of each sample at a position, empty when no rule covers it
+    pub fn sample_ploidy(&self, chr: &str, pos: u32, number_individuals: usize) -> Vec<Option<u8>> {
+        let mut sample_ploidy = vec![];
+        for rule in &self.rules {
+            if rule.chr == chr && (rule.from..=rule.to).contains(&pos) {
+                if sample_ploidy.is_empty() {
+                    sample_ploidy = vec![None; number_individuals];
+                }
+                sample_ploidy[rule.sample_index] = Some(rule.ploidy);
+            }
+        }
+        sample_ploidy
+    }
+}
+
+/// Haploid chrX rules for the males of a `SAMPLE M/F` file, diploid in the PARs of `genome_build`
+///
+/// Samples absent from the vcf are ignored, as sex files usually cover a whole cohort.
+pub fn read_sex_file(
+    path: &str,
+    samples: &[String],
+    genome_build: GenomeBuild,
+) -> Result<Vec<PloidyRule>, VcfError> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut rules = vec![];
+    for (line_i, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [sample, sex] = fields[..] else {
+            return Err(VcfError::Validation(Report::msg(format!(
+                "{} line {}: expected SAMPLE SEX, found '{}'",
+                path,
+                line_i + 1,
+                line
+            ))));
+        };
+        let male = match sex {
+            "M" | "m" => true,
+            "F" | "f" => false,
+            _ => {
+                return Err(VcfError::Validation(Report::msg(format!(
+                    "{} line {}: sex of {} should be M or F, found '{}'",
+                    path,
+                    line_i + 1,
+                    sample,
+                    sex
+                ))))
+            }
+        };
+        let Some(sample_index) = samples.iter().position(|s| s == sample) else {
+            continue;
+        };
+        if male {
+            for chr in ["X", "chrX"] {
+                rules.push(PloidyRule {
+                    sample_index,
+                    chr: chr.to_string(),
+                    from: 0,
+                    to: u32::MAX,
+                    ploidy: 1,
+                });
+                // later rules win, keeping the pseudo-autosomal regions diploid
+                rules.extend(genome_build.par_regions().map(|(from, to)| PloidyRule {
+                    sample_index,
+                    chr: chr.to_string(),
+                    from,
+                    to,
+                    ploidy: 2,
+                }));
+            }
+        }
+    }
+    Ok(rules)
+}
@@ -0,0 +1,31 @@
+// The supported public API, re-exported in one place
+//
+// Downstream crates should import from here: items are only removed or changed in a breaking
+// way with a new major version, while the module layout behind them may move between releases.
+pub use crate::concordance::TruthComparison;
+pub use crate::converter::{ConversionPlan, Converter};
+pub use crate::dosage::{AlleleCountWriter, DosageMatrixWriter};
+pub use crate::gen_file::GenOutput;
+pub use crate::metrics::ConversionMetrics;
+pub use crate::pgen::PgenOutput;
+pub use crate::pipeline::sink::{
+    BgenOutput, ChromosomeSplitOutput, PerSampleBgenOutput, RenamedSamples, ShardedOutput,
+    VariantSink,
+};
+pub use crate::pipeline::source::{VariantSource, VcfSource};
+pub use crate::pipeline::transform::{
+    ContigStyleTransform, FillMissingTransform, HardCallTransform, VariantTransform,
+};
+pub use crate::pipeline::{Pipeline, Variant};
+pub use crate::retry::RetryPolicy;
+pub use crate::variant_list::VariantListWriter;
+pub use crate::variant_metadata::VariantMetadataWriter;
+pub use crate::{
+    build_pipeline, BlockCompression, ContigStyle, ConversionOptions, ExtraOutput, FillMissing,
+    GenomeBuild, GenotypeField, GenotypePrior, HalfCallPolicy, MissingPolicy, MonomorphicPolicy,
+    MultiallelicMode, OutputFormat, ReadBackend, RefBlockMode, ShardMode, SpanningDeletionPolicy,
+    SymbolicAllelePolicy, VcfError,
+};
+
+/// Version of the library, as released on crates.io
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
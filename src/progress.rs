@@ -0,0 +1,248 @@
+// Progress of the counting and converting passes: a bar on a terminal, status lines in logs, both
+// on stderr so stdout stays free for piped outputs
+use crate::ProgressFormat;
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::io::IsTerminal;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+// Time between status lines when stderr is not a terminal, as in batch job logs
+const STATUS_INTERVAL: Duration = Duration::from_secs(30);
+// Time between JSON progress events, often enough for a workflow manager or web page to poll
+const EVENT_INTERVAL: Duration = Duration::from_secs(5);
+// Time between updates of the rates shown next to the bar
+const RATE_INTERVAL: Duration = Duration::from_millis(500);
+
+const BAR_TEMPLATE: &str =
+    "{prefix} [{elapsed_precise}] {wide_bar} {pos}/{len} records, ETA {eta} {msg}";
+const SPINNER_TEMPLATE: &str = "{spinner} {prefix} [{elapsed_precise}] {pos} records {msg}";
+
+// Records, variants and decompressed input bytes done
+#[derive(Debug, Clone, Copy, Default)]
+struct Done {
+    records: u64,
+    variants: u64,
+    bytes: u64,
+}
+
+// Throughput since the first update, which a resumed conversion starts past zero
+struct Rates {
+    start: Instant,
+    first: Option<Done>,
+}
+
+impl Rates {
+    fn new() -> Self {
+        Rates {
+            start: Instant::now(),
+            first: None,
+        }
+    }
+
+    // Records, variants and MB per second
+    fn per_second(&mut self, done: Done) -> (f64, f64, f64) {
+        let first = *self.first.get_or_insert(done);
+        let seconds = self.start.elapsed().as_secs_f64().max(1e-3);
+        (
+            done.records.saturating_sub(first.records) as f64 / seconds,
+            done.variants.saturating_sub(first.variants) as f64 / seconds,
+            done.bytes.saturating_sub(first.bytes) as f64 / 1e6 / seconds,
+        )
+    }
+
+    fn describe(&mut self, done: Done) -> String {
+        let (_, variants, megabytes) = self.per_second(done);
+        format!("{:.0} variants/s, {:.1} MB/s", variants, megabytes)
+    }
+}
+
+// Events logging the records done so far
+struct StatusLines {
+    label: String,
+    total: Option<u64>,
+    done: Done,
+    last_line: Instant,
+}
+
+impl StatusLines {
+    fn write(&mut self, rates: &mut Rates, finished: bool) {
+        let throughput = rates.describe(self.done);
+        let done = self.done.records;
+        match self.total {
+            Some(total) if total > 0 => {
+                let (records_per_second, _, _) = rates.per_second(self.done);
+                let eta = match finished || records_per_second <= 0.0 {
+                    true => String::new(),
+                    false => format!(
+                        ", ETA {}s",
+                        (total.saturating_sub(done) as f64 / records_per_second).round()
+                    ),
+                };
+                info!(
+                    "{}: {} of {} records ({:.0}%), {}{}",
+                    self.label,
+                    done,
+                    total,
+                    100.0 * done as f64 / total as f64,
+                    throughput,
+                    eta
+                )
+            }
+            _ => info!("{}: {} records, {}", self.label, done, throughput),
+        }
+        self.last_line = Instant::now();
+    }
+}
+
+// JSON objects written one per line to stderr, for wrappers tracking the conversion
+struct JsonEvents {
+    phase: String,
+    variants_total: Option<u64>,
+    done: Done,
+    last_event: Instant,
+}
+
+impl JsonEvents {
+    fn write(&mut self, rates: &mut Rates, finished: bool) {
+        let (_, variants_per_second, _) = rates.per_second(self.done);
+        let or_null = |value: Option<String>| value.unwrap_or_else(|| "null".to_string());
+        let eta = match (self.variants_total, finished) {
+            (_, true) => Some("0".to_string()),
+            (Some(total), false) if variants_per_second > 0.0 => Some(format!(
+                "{:.0}",
+                total.saturating_sub(self.done.variants) as f64 / variants_per_second
+            )),
+            _ => None,
+        };
+        eprintln!(
+            "{{\"phase\":\"{}\",\"records_done\":{},\"variants_done\":{},\"total\":{},\"eta_s\":{},\"finished\":{}}}",
+            self.phase,
+            self.done.records,
+            self.done.variants,
+            or_null(self.variants_total.map(|total| total.to_string())),
+            or_null(eta),
+            finished
+        );
+        self.last_event = Instant::now();
+    }
+}
+
+/// Records processed by a pass, with the variants per second, MB of input per second and, when
+/// the records are counted, the time left
+///
+/// A bar, or a spinner when the records are not counted, is drawn on stderr when it is a terminal;
+/// otherwise, as in quiet passes, a status event is logged every 30 seconds, free of terminal
+/// escapes. With [`ProgressFormat::Jsonl`], a JSON object is written every 5 seconds instead.
+pub struct Progress {
+    label: String,
+    bar: Option<ProgressBar>,
+    status: Option<StatusLines>,
+    events: Option<JsonEvents>,
+    rates: Rates,
+    last_rates: Instant,
+}
+
+impl Progress {
+    /// Progress of the pass named `label`, out of `total` records when they are known
+    pub fn new(label: &str, total: Option<u64>, quiet: bool) -> Self {
+        if quiet || !std::io::stderr().is_terminal() {
+            return Progress {
+                label: label.to_string(),
+                bar: None,
+                status: Some(StatusLines {
+                    label: label.to_string(),
+                    total,
+                    done: Done::default(),
+                    last_line: Instant::now(),
+                }),
+                events: None,
+                rates: Rates::new(),
+                last_rates: Instant::now(),
+            };
+        }
+        let (bar, template) = match total {
+            Some(total) => (
+                ProgressBar::with_draw_target(Some(total), ProgressDrawTarget::stderr()),
+                BAR_TEMPLATE,
+            ),
+            None => {
+                let spinner = ProgressBar::with_draw_target(None, ProgressDrawTarget::stderr());
+                spinner.enable_steady_tick(Duration::from_millis(100));
+                (spinner, SPINNER_TEMPLATE)
+            }
+        };
+        if let Ok(style) = ProgressStyle::with_template(template) {
+            bar.set_style(style);
+        }
+        bar.set_prefix(label.to_string());
+        Progress {
+            label: label.to_string(),
+            bar: Some(bar),
+            status: None,
+            events: None,
+            rates: Rates::new(),
+            last_rates: Instant::now(),
+        }
+    }
+
+    /// Write the progress as `format`, out of `variants_total` variants when they are known
+    ///
+    /// JSON lines replace the bar and status lines, and are written even by quiet passes.
+    pub fn with_format(mut self, format: ProgressFormat, variants_total: Option<u64>) -> Self {
+        if format == ProgressFormat::Jsonl {
+            if let Some(bar) = self.bar.take() {
+                bar.finish_and_clear();
+            }
+            self.status = None;
+            self.events = Some(JsonEvents {
+                phase: self.label.to_lowercase(),
+                variants_total,
+                done: Done::default(),
+                last_event: Instant::now(),
+            });
+        }
+        self
+    }
+
+    /// Record the `records`, `variants` and decompressed input `bytes` done so far
+    pub fn update(&mut self, records: u64, variants: u64, bytes: u64) {
+        let done = Done {
+            records,
+            variants,
+            bytes,
+        };
+        if let Some(bar) = &self.bar {
+            bar.set_position(records);
+            if self.rates.first.is_none() || self.last_rates.elapsed() >= RATE_INTERVAL {
+                bar.set_message(self.rates.describe(done));
+                self.last_rates = Instant::now();
+            }
+        }
+        if let Some(status) = self.status.as_mut() {
+            self.rates.first.get_or_insert(done);
+            status.done = done;
+            if status.last_line.elapsed() >= STATUS_INTERVAL {
+                status.write(&mut self.rates, false);
+            }
+        }
+        if let Some(events) = self.events.as_mut() {
+            self.rates.first.get_or_insert(done);
+            events.done = done;
+            if events.last_event.elapsed() >= EVENT_INTERVAL {
+                events.write(&mut self.rates, false);
+            }
+        }
+    }
+
+    pub fn finish(&mut self) {
+        if let Some(bar) = &self.bar {
+            bar.finish();
+        }
+        if let Some(status) = self.status.as_mut() {
+            status.write(&mut self.rates, true);
+        }
+        if let Some(events) = self.events.as_mut() {
+            events.write(&mut self.rates, true);
+        }
+    }
+}
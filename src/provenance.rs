@@ -0,0 +1,116 @@
+// Seeded randomness and the manifest recording how outputs were produced
+use crate::metrics::ConversionMetrics;
+use crate::prelude::VERSION;
+use crate::{ConversionOptions, VcfError};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// SplitMix64 generator: integer arithmetic only, so a seed gives the same draws on every platform
+///
+/// Features using randomness (thinning, subsampling, spot checks) draw from it, one draw per variant
+/// in output order, so identical inputs, options and seeds give identical outputs.
+#[derive(Debug, Clone)]
+pub struct SeededRng {
+    state: u64,
+}
+
+impl SeededRng {
+    pub fn new(seed: u64) -> Self {
+        SeededRng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in [0, 1), from the 53 high bits of the next draw
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Seed used when none is given, recorded in the manifest so the run can be repeated
+pub fn seed_from_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos() as u64)
+        .unwrap_or_default()
+}
+
+/// Order of variants in the outputs
+pub fn variant_order(options: &ConversionOptions) -> &'static str {
+    if options.regroup_chromosomes {
+        "input order within chromosomes, chromosomes in order of first appearance"
+    } else {
+        "input order"
+    }
+}
+
+/// Tab-separated key and value lines describing a finished conversion
+pub fn write_manifest(
+    path: &str,
+    input: &str,
+    outputs: &[String],
+    options: &ConversionOptions,
+    metrics: &ConversionMetrics,
+) -> Result<(), VcfError> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(writer, "tool\tvcf_to_bgen {}", VERSION)?;
+    writeln!(writer, "input\t{}", input)?;
+    for output in outputs {
+        writeln!(writer, "output\t{}", output)?;
+    }
+    match options.seed {
+        Some(seed) => writeln!(writer, "seed\t{}", seed)?,
+        None => writeln!(writer, "seed\tNA")?,
+    }
+    writeln!(writer, "variant_order\t{}", variant_order(options))?;
+    writeln!(writer, "records_read\t{}", metrics.records_read)?;
+    writeln!(writer, "variants_written\t{}", metrics.variants_processed)?;
+    writeln!(writer, "options\t{:?}", options)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Key of the input sha256 in the header provenance
+pub const INPUT_SHA256_KEY: &str = "input_sha256\t";
+/// Input sha256 of headers written before the input is read, as long as the digest replacing it
+pub const PENDING_SHA256: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Whether reading `input` hashes it for the header provenance, which the counting pass may
+/// already have done
+pub fn hashes_input(input: &str, options: &ConversionOptions) -> bool {
+    options.provenance && options.input_sha256.is_none() && Path::new(input).is_file()
+}
+
+/// Tab-separated key and value lines stored in the free data area of bgen headers
+///
+/// The creation time is left out of deterministic runs, and the hash out of inputs that are not
+/// regular files. Unless the counting pass hashed the input, the hash is [`PENDING_SHA256`] until
+/// the input is read by the conversion, which then writes it over.
+pub fn header_provenance(input: &str, options: &ConversionOptions) -> String {
+    let mut provenance = format!("tool\tvcf_to_bgen {}\n", VERSION);
+    let command = options.command_line.as_deref().unwrap_or("NA");
+    provenance.push_str(&format!("command\t{}\n", command));
+    provenance.push_str(&format!("input\t{}\n", input));
+    let input_sha256 = match (&options.input_sha256, hashes_input(input, options)) {
+        (Some(sha256), _) => sha256,
+        (None, true) => PENDING_SHA256,
+        (None, false) => "NA",
+    };
+    provenance.push_str(&format!("{}{}\n", INPUT_SHA256_KEY, input_sha256));
+    if !options.deterministic {
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or_default();
+        provenance.push_str(&format!("created\t{}\n", created));
+    }
+    provenance
+}
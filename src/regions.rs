@@ -0,0 +1,204 @@
+// Chromosomes and intervals restricting the records converted
+use crate::{ConversionOptions, VcfError};
+use color_eyre::Report;
+use flate2::read::MultiGzDecoder;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+/// Inclusive 1-based intervals by chromosome; a record is kept when its position is in one
+///
+/// Intervals of a chromosome are sorted and overlapping ones merged, so finding the interval of a
+/// position is a binary search, however many targets a BED file has.
+#[derive(Debug, Clone, Default)]
+pub struct Regions {
+    intervals: HashMap<String, Vec<(u32, u32)>>,
+}
+
+impl Regions {
+    /// Regions of `options`, `None` when every record is converted
+    pub fn load(options: &ConversionOptions) -> Result<Option<Self>, VcfError> {
+        match (&options.regions, &options.regions_file) {
+            (Some(_), Some(_)) => Err(VcfError::Validation(Report::msg(
+                "Regions are either listed or read from a file, not both",
+            ))),
+            (Some(regions), None) => Ok(Some(Regions::parse(regions)?)),
+            (None, Some(path)) => Ok(Some(Regions::read_bed(path)?)),
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// Parse comma-separated `chr`, `chr:pos`, `chr:start-` or `chr:start-end` regions
+    pub fn parse(regions: &str) -> Result<Self, VcfError> {
+        let mut parsed = Regions::default();
+        for region in regions.split(',') {
+            let invalid = || {
+                VcfError::Validation(Report::msg(format!(
+                    "Invalid region '{}': expected chr, chr:pos or chr:start-end",
+                    region
+                )))
+            };
+            let (chr, start, end) = match region.split_once(':') {
+                None => (region, 1, u32::MAX),
+                Some((chr, range)) => {
+                    let position = |value: &str| value.parse::<u32>();
+                    match range.split_once('-') {
+                        None => {
+                            let pos = position(range).map_err(|_| invalid())?;
+                            (chr, pos, pos)
+                        }
+                        Some((start, "")) => {
+                            (chr, position(start).map_err(|_| invalid())?, u32::MAX)
+                        }
+                        Some((start, end)) => (
+                            chr,
+                            position(start).map_err(|_| invalid())?,
+                            position(end).map_err(|_| invalid())?,
+                        ),
+                    }
+                }
+            };
+            if chr.is_empty() || start > end {
+                return Err(invalid());
+            }
+            parsed.insert(chr, start, end);
+        }
+        parsed.merge();
+        Ok(parsed)
+    }
+
+    /// Read the 0-based, end-exclusive `chrom start end` columns of a BED file, gzipped or not
+    ///
+    /// Empty, `#`, `track` and `browser` lines are skipped, as are columns after the third.
+    pub fn read_bed(path: &str) -> Result<Self, VcfError> {
+        let file = File::open(path)?;
+        let reader: Box<dyn BufRead> = match path.ends_with(".gz") {
+            true => Box::new(BufReader::new(MultiGzDecoder::new(file))),
+            false => Box::new(BufReader::new(file)),
+        };
+        let mut parsed = Regions::default();
+        for (line_i, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim_end();
+            if line.is_empty()
+                || ["#", "track", "browser"]
+                    .iter()
+                    .any(|p| line.starts_with(p))
+            {
+                continue;
+            }
+            let invalid_row = || {
+                VcfError::Validation(Report::msg(format!(
+                    "{} line {}: expected CHROM START END, found '{}'",
+                    path,
+                    line_i + 1,
+                    line
+                )))
+            };
+            let mut fields = line.split_whitespace();
+            let (Some(chr), Some(start), Some(end)) = (fields.next(), fields.next(), fields.next())
+            else {
+                return Err(invalid_row());
+            };
+            let start: u32 = start.parse().map_err(|_| invalid_row())?;
+            let end: u32 = end.parse().map_err(|_| invalid_row())?;
+            if start >= end {
+                return Err(invalid_row());
+            }
+            parsed.insert(chr, start + 1, end);
+        }
+        parsed.merge();
+        Ok(parsed)
+    }
+
+    fn insert(&mut self, chr: &str, start: u32, end: u32) {
+        self.intervals
+            .entry(chr.to_string())
+            .or_default()
+            .push((start, end));
+    }
+
+    // Sort the intervals of each chromosome, joining those overlapping or adjacent
+    fn merge(&mut self) {
+        for intervals in self.intervals.values_mut() {
+            intervals.sort_unstable();
+            let mut merged: Vec<(u32, u32)> = Vec::with_capacity(intervals.len());
+            for &(start, end) in intervals.iter() {
+                match merged.last_mut() {
+                    Some(last) if start <= last.1.saturating_add(1) => last.1 = last.1.max(end),
+                    _ => merged.push((start, end)),
+                }
+            }
+            *intervals = merged;
+        }
+    }
+
+    pub fn contains(&self, chr: &str, pos: u32) -> bool {
+        self.intervals.get(chr).is_some_and(|intervals| {
+            // the last interval starting at or before the position
+            let after = intervals.partition_point(|(start, _)| *start <= pos);
+            after > 0 && intervals[after - 1].1 >= pos
+        })
+    }
+
+    /// Whether the record on a vcf line is inside the regions, from its CHROM and POS columns
+    pub fn contains_record(&self, line: &str) -> bool {
+        let mut fields = line.split('\t');
+        let chr = fields.next().unwrap_or_default();
+        let pos = fields.next().and_then(|pos| pos.parse().ok());
+        pos.is_some_and(|pos| self.contains(chr, pos))
+    }
+}
+
+/// Position of the first record converted, those before it in the input being left out
+///
+/// Records are left out until one is on the chromosome at or after the position; every record
+/// after that one is converted, whatever its chromosome.
+#[derive(Debug, Clone)]
+pub struct StartPosition {
+    chr: String,
+    pos: u32,
+    reached: bool,
+}
+
+impl StartPosition {
+    /// Start of `options`, `None` when records are converted from the first one
+    pub fn load(options: &ConversionOptions) -> Result<Option<Self>, VcfError> {
+        let Some(start) = &options.start_position else {
+            return Ok(None);
+        };
+        // records are scanned in input order, so starting in regrouped order would give another count
+        if options.regroup_chromosomes {
+            return Err(VcfError::Validation(Report::msg(
+                "Records are converted from a start position in input order, not regrouped by chromosome",
+            )));
+        }
+        let invalid = || {
+            VcfError::Validation(Report::msg(format!(
+                "Invalid start position '{}': expected chr:pos",
+                start
+            )))
+        };
+        let (chr, pos) = start.split_once(':').ok_or_else(invalid)?;
+        let pos = pos.parse().map_err(|_| invalid())?;
+        if chr.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Some(StartPosition {
+            chr: chr.to_string(),
+            pos,
+            reached: false,
+        }))
+    }
+
+    /// Whether the record on a vcf line comes before the start, the records being read in order
+    pub fn is_before(&mut self, line: &str) -> bool {
+        if !self.reached {
+            let mut fields = line.split('\t');
+            let chr = fields.next().unwrap_or_default();
+            let pos = fields.next().and_then(|pos| pos.parse::<u32>().ok());
+            self.reached = chr == self.chr && pos.is_some_and(|pos| pos >= self.pos);
+        }
+        !self.reached
+    }
+}
@@ -0,0 +1,112 @@
+// Retry of transient read errors, as returned by network filesystems (NFS, Lustre)
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::thread::sleep;
+use std::time::Duration;
+use tracing::warn;
+
+// errno of an I/O error reported by the filesystem
+const EIO: i32 = 5;
+
+/// How many times a failed read is retried, waiting `backoff` and doubling it after each attempt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub retries: u32,
+    pub backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            retries: 3,
+            backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+type Reopen<R> = Box<dyn FnMut(u64) -> io::Result<R>>;
+
+/// Reader retrying transient errors, reopened at the last offset read when it is seekable
+pub struct RetryingReader<R> {
+    inner: R,
+    reopen: Option<Reopen<R>>,
+    offset: u64,
+    policy: RetryPolicy,
+}
+
+impl RetryingReader<File> {
+    pub fn open(path: &str, policy: RetryPolicy) -> io::Result<Self> {
+        let reopen_path = path.to_string();
+        let reopen = move |offset| {
+            let mut file = File::open(&reopen_path)?;
+            file.seek(SeekFrom::Start(offset))?;
+            Ok(file)
+        };
+        Ok(RetryingReader::with_reopen(
+            File::open(path)?,
+            policy,
+            Box::new(reopen),
+        ))
+    }
+}
+
+impl<R: Read> RetryingReader<R> {
+    /// Retry reads on the same reader, for inputs that cannot be reopened
+    pub fn new(inner: R, policy: RetryPolicy) -> Self {
+        RetryingReader {
+            inner,
+            reopen: None,
+            offset: 0,
+            policy,
+        }
+    }
+
+    /// Replace the reader with `reopen(offset)` before each retry
+    pub fn with_reopen(inner: R, policy: RetryPolicy, reopen: Reopen<R>) -> Self {
+        RetryingReader {
+            inner,
+            reopen: Some(reopen),
+            offset: 0,
+            policy,
+        }
+    }
+}
+
+pub fn is_transient(error: &io::Error) -> bool {
+    error.raw_os_error() == Some(EIO)
+        || matches!(
+            error.kind(),
+            io::ErrorKind::Interrupted | io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+        )
+}
+
+impl<R: Read> Read for RetryingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut attempt = 0;
+        let mut backoff = self.policy.backoff;
+        loop {
+            match self.inner.read(buf) {
+                Ok(bytes_read) => {
+                    self.offset += bytes_read as u64;
+                    return Ok(bytes_read);
+                }
+                Err(error) if attempt < self.policy.retries && is_transient(&error) => {
+                    attempt += 1;
+                    warn!(
+                        "read failed at byte {} ({}), retry {} of {}",
+                        self.offset, error, attempt, self.policy.retries
+                    );
+                    sleep(backoff);
+                    backoff *= 2;
+                    // a failed reopen is retried with the next attempt
+                    if let Some(reopen) = self.reopen.as_mut() {
+                        if let Ok(inner) = reopen(self.offset) {
+                            self.inner = inner;
+                        }
+                    }
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    }
+}
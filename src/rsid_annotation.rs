@@ -0,0 +1,68 @@
+// rsIDs of a dbSNP reference, filling the IDs missing from the input
+use crate::VcfError;
+use color_eyre::Report;
+use flate2::read::MultiGzDecoder;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+/// rsIDs by chromosome, then position and alleles
+///
+/// The whole reference is held in memory: subset a full dbSNP release to the converted regions.
+#[derive(Debug, Clone, Default)]
+pub struct RsidReference {
+    rsids: HashMap<String, HashMap<(u32, String, String), String>>,
+}
+
+impl RsidReference {
+    /// Read the tab-separated CHROM POS REF ALT RSID columns of `path`, gzipped or not
+    ///
+    /// `#` lines are skipped, alts listed with commas share the rsID, and the first rsID of a
+    /// variant listed twice is kept.
+    pub fn read(path: &str) -> Result<Self, VcfError> {
+        let file = File::open(path)?;
+        let reader: Box<dyn BufRead> = match path.ends_with(".gz") {
+            true => Box::new(BufReader::new(MultiGzDecoder::new(file))),
+            false => Box::new(BufReader::new(file)),
+        };
+        let mut reference = RsidReference::default();
+        for (line_i, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid_row = || {
+                VcfError::Validation(Report::msg(format!(
+                    "{} line {}: expected CHROM POS REF ALT RSID, found '{}'",
+                    path,
+                    line_i + 1,
+                    line
+                )))
+            };
+            let fields: Vec<&str> = line.split('\t').collect();
+            let [chr, pos, reference_allele, alts, rsid] = fields[..] else {
+                return Err(invalid_row());
+            };
+            let pos: u32 = pos.parse().map_err(|_| invalid_row())?;
+            if rsid == "." {
+                continue;
+            }
+            let positions = reference.rsids.entry(chr.to_string()).or_default();
+            for alt in alts.split(',') {
+                positions
+                    .entry((pos, reference_allele.to_string(), alt.to_string()))
+                    .or_insert_with(|| rsid.to_string());
+            }
+        }
+        Ok(reference)
+    }
+
+    /// rsID of the biallelic variant `chr:pos:reference:alt`, with the contig named as in the input
+    pub fn lookup(&self, chr: &str, pos: u32, reference: &str, alt: &str) -> Option<&str> {
+        self.rsids
+            .get(chr)?
+            .get(&(pos, reference.to_string(), alt.to_string()))
+            .map(String::as_str)
+    }
+}
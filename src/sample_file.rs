@@ -0,0 +1,91 @@
+// Oxford .sample files: two header rows (column names, column types) then one row per sample
+use crate::bgen_file::read_bgen_summary;
+use crate::VcfError;
+use color_eyre::Report;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+pub fn read_sample_file(input: &str) -> Result<Vec<String>, VcfError> {
+    let reader = BufReader::new(File::open(input)?);
+    let lines = reader.lines().collect::<Result<Vec<String>, _>>()?;
+    let lines: Vec<&String> = lines.iter().filter(|l| !l.trim().is_empty()).collect();
+    if lines.len() < 2 {
+        return Err(sample_file_error(format!(
+            "{} is missing the two header rows of a .sample file",
+            input
+        )));
+    }
+    let columns: Vec<&str> = lines[0].split_whitespace().collect();
+    if columns.first() != Some(&"ID_1") {
+        return Err(sample_file_error(format!(
+            "{}: first row should be the column names starting with ID_1, found '{}'",
+            input, lines[0]
+        )));
+    }
+    let types: Vec<&str> = lines[1].split_whitespace().collect();
+    if types.first() != Some(&"0") || types.len() != columns.len() {
+        return Err(sample_file_error(format!(
+            "{}: second row should be the column types starting with 0, found '{}'",
+            input, lines[1]
+        )));
+    }
+    // ID_2 is the identifier written in the bgen sample block
+    let id_column = columns.iter().position(|&c| c == "ID_2").unwrap_or(0);
+    lines[2..]
+        .iter()
+        .enumerate()
+        .map(|(row_i, line)| {
+            line.split_whitespace()
+                .nth(id_column)
+                .map(|id| id.to_string())
+                .ok_or_else(|| {
+                    sample_file_error(format!("{}: sample row {} is empty", input, row_i + 1))
+                })
+        })
+        .collect()
+}
+
+/// Check that a .sample file lists the same samples, in the same order, as a bgen file
+pub fn check_pair(sample_path: &str, bgen_path: &str) -> Result<usize, VcfError> {
+    let sample_ids = read_sample_file(sample_path)?;
+    let bgen_summary = read_bgen_summary(bgen_path)?;
+    if sample_ids.len() != bgen_summary.sample_num as usize {
+        let hint = match sample_ids.len() as i64 - bgen_summary.sample_num as i64 {
+            1 | 2 => " (are header rows duplicated in the .sample file?)",
+            -1 | -2 => " (are the header rows missing from the .sample file?)",
+            _ => "",
+        };
+        return Err(sample_file_error(format!(
+            "{} lists {} samples but {} contains {}{}",
+            sample_path,
+            sample_ids.len(),
+            bgen_path,
+            bgen_summary.sample_num,
+            hint
+        )));
+    }
+    if let Some(bgen_samples) = bgen_summary.samples {
+        if let Some(sample_i) = (0..sample_ids.len()).find(|&i| sample_ids[i] != bgen_samples[i]) {
+            let shifted = sample_i + 1 < sample_ids.len()
+                && sample_ids[sample_i + 1] == bgen_samples[sample_i];
+            return Err(sample_file_error(format!(
+                "Sample {} is '{}' in {} but '{}' in {}{}",
+                sample_i + 1,
+                sample_ids[sample_i],
+                sample_path,
+                bgen_samples[sample_i],
+                bgen_path,
+                if shifted {
+                    " (sample rows are shifted by one)"
+                } else {
+                    ""
+                }
+            )));
+        }
+    }
+    Ok(sample_ids.len())
+}
+
+fn sample_file_error(message: String) -> VcfError {
+    VcfError::Validation(Report::msg(message))
+}
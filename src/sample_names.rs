@@ -0,0 +1,123 @@
+// Names written in the bgen sample block in place of the vcf sample names
+use crate::{ConversionOptions, SampleDedupPolicy, VcfError};
+use color_eyre::Report;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+
+/// Tab-separated OLD NEW rows, one per renamed sample
+pub fn read_rename_map(input: &str) -> Result<HashMap<String, String>, VcfError> {
+    let reader = BufReader::new(File::open(input)?);
+    let mut renames = HashMap::new();
+    for (line_i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.trim_end_matches('\r').split('\t').collect();
+        let [old, new] = fields[..] else {
+            return Err(VcfError::Validation(Report::msg(format!(
+                "{}: line {} should be the old and new sample names separated by a tab",
+                input,
+                line_i + 1
+            ))));
+        };
+        if renames.insert(old.to_string(), new.to_string()).is_some() {
+            return Err(VcfError::Validation(Report::msg(format!(
+                "{}: sample {} is renamed twice",
+                input, old
+            ))));
+        }
+    }
+    Ok(renames)
+}
+
+/// Samples renamed by `renames`, the samples it does not list keeping their name
+pub fn renamed_samples(
+    samples: &[String],
+    renames: &HashMap<String, String>,
+) -> Result<Vec<String>, VcfError> {
+    let renamed: Vec<String> = samples
+        .iter()
+        .map(|sample| renames.get(sample).unwrap_or(sample).clone())
+        .collect();
+    let mut seen = HashSet::new();
+    if let Some(duplicate) = renamed.iter().find(|&name| !seen.insert(name)) {
+        return Err(VcfError::Validation(Report::msg(format!(
+            "Renaming samples gives two samples the name {}",
+            duplicate
+        ))));
+    }
+    Ok(renamed)
+}
+
+/// Sequential pseudonyms of the samples, with the pseudonym of each sample written to `reverse_map`
+pub fn anonymized_samples(samples: &[String], reverse_map: &str) -> Result<Vec<String>, VcfError> {
+    let pseudonyms: Vec<String> = (1..=samples.len())
+        .map(|sample_i| format!("sample_{}", sample_i))
+        .collect();
+    let mut writer = BufWriter::new(File::create(reverse_map)?);
+    for (pseudonym, sample) in pseudonyms.iter().zip(samples) {
+        writeln!(writer, "{}\t{}", pseudonym, sample)?;
+    }
+    writer.flush()?;
+    Ok(pseudonyms)
+}
+
+/// Sample IDs of the vcf header, repeated ones failing or numbered `_1`, `_2` in header order
+pub fn deduplicated_samples(
+    samples: &[String],
+    policy: SampleDedupPolicy,
+) -> Result<Vec<String>, VcfError> {
+    let mut occurrences: HashMap<&String, usize> = HashMap::new();
+    for sample in samples {
+        *occurrences.entry(sample).or_default() += 1;
+    }
+    let Some(repeated) = samples.iter().find(|sample| occurrences[sample] > 1) else {
+        return Ok(samples.to_vec());
+    };
+    if policy == SampleDedupPolicy::Error {
+        return Err(VcfError::Validation(Report::msg(format!(
+            "Sample {} appears {} times in the vcf header: use --dedup-samples suffix to number them",
+            repeated, occurrences[repeated]
+        ))));
+    }
+    let mut numbered: HashMap<&String, usize> = HashMap::new();
+    let deduplicated: Vec<String> = samples
+        .iter()
+        .map(|sample| match occurrences[sample] {
+            1 => sample.clone(),
+            _ => {
+                let number = numbered.entry(sample).or_default();
+                *number += 1;
+                format!("{}_{}", sample, number)
+            }
+        })
+        .collect();
+    let mut seen = HashSet::new();
+    if let Some(duplicate) = deduplicated.iter().find(|&name| !seen.insert(name)) {
+        return Err(VcfError::Validation(Report::msg(format!(
+            "Numbering repeated samples gives two samples the name {}",
+            duplicate
+        ))));
+    }
+    Ok(deduplicated)
+}
+
+/// Names of the bgen sample block, `None` when the vcf names are written unchanged
+pub fn bgen_sample_names(
+    samples: &[String],
+    options: &ConversionOptions,
+) -> Result<Option<Vec<String>>, VcfError> {
+    match (&options.rename_samples, &options.anonymize) {
+        (Some(_), Some(_)) => Err(VcfError::Validation(Report::msg(
+            "Samples are either renamed from a file or anonymized, not both",
+        ))),
+        (Some(rename_map), None) => Ok(Some(renamed_samples(
+            samples,
+            &read_rename_map(rename_map)?,
+        )?)),
+        (None, Some(reverse_map)) => Ok(Some(anonymized_samples(samples, reverse_map)?)),
+        (None, None) => Ok(None),
+    }
+}
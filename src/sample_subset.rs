@@ -0,0 +1,110 @@
+// Samples of the input written to the outputs, from keep and remove lists and a sample order
+use crate::sample_file::read_sample_file;
+use crate::{ConversionOptions, VcfError};
+use color_eyre::Report;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+/// Sample IDs of a list, one per line, or `FID IID` rows whose IID is taken as in PLINK
+pub fn read_sample_list(path: &str) -> Result<Vec<String>, VcfError> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut samples = vec![];
+    for (line_i, line) in reader.lines().enumerate() {
+        let line = line?;
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields[..] {
+            [] => continue,
+            [sample] | [_, sample] => samples.push(sample.to_string()),
+            _ => {
+                return Err(VcfError::Validation(Report::msg(format!(
+                    "{} line {}: expected a sample ID or FID IID, found '{}'",
+                    path,
+                    line_i + 1,
+                    line
+                ))))
+            }
+        }
+    }
+    Ok(samples)
+}
+
+/// Columns of the samples written, in output order, `None` when every sample is written in order
+///
+/// Samples of the keep list must all be in the input; those of the remove list need not be. With
+/// a sample order, the samples left must be exactly those of its .sample file, written in its order.
+pub fn sample_columns(
+    samples: &[String],
+    options: &ConversionOptions,
+) -> Result<Option<Vec<usize>>, VcfError> {
+    if options.keep_samples.is_none()
+        && options.remove_samples.is_none()
+        && options.sample_order.is_none()
+    {
+        return Ok(None);
+    }
+    let mut kept: Vec<bool> = vec![true; samples.len()];
+    if let Some(path) = &options.keep_samples {
+        let keep: HashSet<String> = read_sample_list(path)?.into_iter().collect();
+        let input: HashSet<&String> = samples.iter().collect();
+        if let Some(missing) = keep.iter().find(|sample| !input.contains(sample)) {
+            return Err(VcfError::Validation(Report::msg(format!(
+                "{}: sample {} is not in the input",
+                path, missing
+            ))));
+        }
+        for (kept, sample) in kept.iter_mut().zip(samples) {
+            *kept = keep.contains(sample);
+        }
+    }
+    if let Some(path) = &options.remove_samples {
+        let remove: HashSet<String> = read_sample_list(path)?.into_iter().collect();
+        for (kept, sample) in kept.iter_mut().zip(samples) {
+            *kept &= !remove.contains(sample);
+        }
+    }
+    let mut columns: Vec<usize> = (0..samples.len()).filter(|&i| kept[i]).collect();
+    if let Some(path) = &options.sample_order {
+        columns = ordered_columns(samples, &columns, path)?;
+    }
+    if columns.is_empty() {
+        return Err(VcfError::Validation(Report::msg(
+            "No sample is left after applying the keep and remove lists",
+        )));
+    }
+    Ok(Some(columns))
+}
+
+// The kept columns permuted to the order of the samples of a .sample file
+fn ordered_columns(
+    samples: &[String],
+    columns: &[usize],
+    path: &str,
+) -> Result<Vec<usize>, VcfError> {
+    let order = read_sample_file(path)?;
+    let column_of: HashMap<&String, usize> = columns.iter().map(|&i| (&samples[i], i)).collect();
+    let mut seen = HashSet::new();
+    let mut ordered = Vec::with_capacity(order.len());
+    for sample in &order {
+        if !seen.insert(sample) {
+            return Err(VcfError::Validation(Report::msg(format!(
+                "{}: sample {} is listed twice",
+                path, sample
+            ))));
+        }
+        let column = column_of.get(sample).ok_or_else(|| {
+            VcfError::Validation(Report::msg(format!(
+                "{}: sample {} is not among the samples converted",
+                path, sample
+            )))
+        })?;
+        ordered.push(*column);
+    }
+    if let Some(&missing) = columns.iter().find(|&&i| !seen.contains(&samples[i])) {
+        return Err(VcfError::Validation(Report::msg(format!(
+            "Sample {} is not in {}: leave it out with --keep or --remove",
+            samples[missing], path
+        ))));
+    }
+    Ok(ordered)
+}
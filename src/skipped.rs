@@ -0,0 +1,44 @@
+// Report of the alleles and records left out of the bgen, with the reason for each
+use crate::VcfError;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+/// Name of the report next to `output` when none is given
+pub fn skipped_report_path(output: &str) -> String {
+    format!("{}.skipped.tsv", output)
+}
+
+/// Tsv with one row per allele or record that was not converted, from its line of the input
+pub struct SkippedVariantReport {
+    writer: BufWriter<File>,
+}
+
+impl SkippedVariantReport {
+    pub fn create(path: &str) -> Result<Self, VcfError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "line\tchr\tpos\tref\talt\treason")?;
+        Ok(SkippedVariantReport { writer })
+    }
+
+    pub fn record(
+        &mut self,
+        line: u32,
+        chr: &str,
+        pos: u32,
+        reference: &str,
+        alt: &str,
+        reason: &str,
+    ) -> Result<(), VcfError> {
+        writeln!(
+            self.writer,
+            "{}\t{}\t{}\t{}\t{}\t{}",
+            line, chr, pos, reference, alt, reason
+        )?;
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> Result<(), VcfError> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
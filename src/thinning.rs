@@ -0,0 +1,87 @@
+// Thinning of the variants written, to every nth one, a minimum spacing or a random fraction, for
+// small test sets
+use crate::provenance::SeededRng;
+use crate::{ConversionOptions, VcfError};
+use color_eyre::Report;
+use std::collections::HashMap;
+
+/// Variants kept from those passing the other filters, in input order
+///
+/// With a minimum spacing, a variant is kept when it is at least that many bases from the last
+/// one kept on its chromosome; every nth variant is then taken from those, starting with the first,
+/// and each of these is drawn with the sampled fraction.
+#[derive(Debug, Clone)]
+pub struct Thinning {
+    every: Option<u32>,
+    min_distance: Option<u32>,
+    fraction: Option<(f64, SeededRng)>,
+    // candidates seen by the every nth selection, and the last position kept by chromosome
+    seen: u64,
+    last_kept: HashMap<String, u32>,
+}
+
+impl Thinning {
+    /// Thinning of `options`, `None` when every variant is kept
+    pub fn load(options: &ConversionOptions) -> Result<Option<Self>, VcfError> {
+        if options.thin == Some(0) {
+            return Err(VcfError::Validation(Report::msg(
+                "--thin keeps every nth variant, n being at least 1",
+            )));
+        }
+        let fraction = match options.sample_variants {
+            Some(fraction) if !(fraction > 0.0 && fraction <= 1.0) => {
+                return Err(VcfError::Validation(Report::msg(format!(
+                    "--sample-variants {} is not a fraction in (0, 1]",
+                    fraction
+                ))))
+            }
+            // variants are counted in input order, so drawing them regrouped would give another count
+            Some(_) if options.regroup_chromosomes => {
+                return Err(VcfError::Validation(Report::msg(
+                    "Variants are sampled in input order, not regrouped by chromosome",
+                )))
+            }
+            // without a seed, as in deterministic runs, the draws are those of seed 0
+            Some(fraction) => Some((fraction, SeededRng::new(options.seed.unwrap_or_default()))),
+            None => None,
+        };
+        if options.thin.is_none() && options.thin_bp.is_none() && fraction.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(Thinning {
+            every: options.thin,
+            min_distance: options.thin_bp,
+            fraction,
+            seen: 0,
+            last_kept: HashMap::new(),
+        }))
+    }
+
+    /// Whether the next variant, at `chr:pos`, is kept
+    pub fn keeps(&mut self, chr: &str, pos: u32) -> bool {
+        if let Some(min_distance) = self.min_distance {
+            if self
+                .last_kept
+                .get(chr)
+                .is_some_and(|&last| last.abs_diff(pos) < min_distance)
+            {
+                return false;
+            }
+        }
+        let seen = self.seen;
+        self.seen += 1;
+        if self
+            .every
+            .is_some_and(|every| !seen.is_multiple_of(every as u64))
+        {
+            return false;
+        }
+        if let Some((fraction, rng)) = self.fraction.as_mut() {
+            if rng.next_f64() >= *fraction {
+                return false;
+            }
+        }
+        self.last_kept.insert(chr.to_string(), pos);
+        true
+    }
+}
@@ -0,0 +1,69 @@
+// Variants kept by class: SNPs, indels, or alleles up to a length
+use crate::{ConversionOptions, VcfError};
+use color_eyre::Report;
+
+fn is_base_sequence(allele: &str, bases: &[u8]) -> bool {
+    !allele.is_empty()
+        && allele
+            .bytes()
+            .all(|base| bases.contains(&base.to_ascii_uppercase()))
+}
+
+/// Class restrictions of the options, checked on the alleles of each variant written
+///
+/// A SNP has single A, C, G or T alleles; an indel has base sequences of different lengths, so
+/// symbolic, spanning deletion and monomorphic alleles are neither.
+#[derive(Debug, Clone, Copy)]
+pub struct VariantClassFilter {
+    snps_only: bool,
+    indels_only: bool,
+    max_allele_len: Option<usize>,
+}
+
+impl VariantClassFilter {
+    /// Filter of `options`, `None` when variants of every class are written
+    pub fn load(options: &ConversionOptions) -> Result<Option<Self>, VcfError> {
+        if options.snps_only && options.indels_only {
+            return Err(VcfError::Validation(Report::msg(
+                "Variants are restricted to either SNPs or indels, not both",
+            )));
+        }
+        if !options.snps_only && !options.indels_only && options.max_allele_len.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(VariantClassFilter {
+            snps_only: options.snps_only,
+            indels_only: options.indels_only,
+            max_allele_len: options.max_allele_len.map(|len| len as usize),
+        }))
+    }
+
+    pub fn keeps(&self, alleles: &[String]) -> bool {
+        if self
+            .max_allele_len
+            .is_some_and(|max| alleles.iter().any(|allele| allele.len() > max))
+        {
+            return false;
+        }
+        if self.snps_only {
+            return alleles
+                .iter()
+                .all(|allele| allele.len() == 1 && is_base_sequence(allele, b"ACGT"));
+        }
+        if self.indels_only {
+            return alleles
+                .iter()
+                .all(|allele| is_base_sequence(allele, b"ACGTN"))
+                && alleles
+                    .iter()
+                    .any(|allele| allele.len() != alleles[0].len());
+        }
+        true
+    }
+
+    /// Whether some variant of a record with this reference allele may be kept
+    pub fn may_keep_reference(&self, reference: &str) -> bool {
+        !(self.snps_only && reference.len() != 1)
+            && self.max_allele_len.is_none_or(|max| reference.len() <= max)
+    }
+}
@@ -0,0 +1,96 @@
+// Variants converted or left out by ID, from extract and exclude lists
+use crate::pipeline::Variant;
+use crate::{ConversionOptions, VcfError};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+// IDs of a list, with the chr:pos of those written as chr:pos:ref:alt
+#[derive(Debug, Clone, Default)]
+struct IdList {
+    ids: HashSet<String>,
+    positions: HashSet<String>,
+}
+
+impl IdList {
+    // First column of each line, as PLINK reads --extract files, missing `.` IDs matching nothing
+    fn read(path: &str) -> Result<Self, VcfError> {
+        let reader = BufReader::new(File::open(path)?);
+        let mut list = IdList::default();
+        for line in reader.lines() {
+            let line = line?;
+            let Some(id) = line.split_whitespace().next().filter(|&id| id != ".") else {
+                continue;
+            };
+            let fields: Vec<&str> = id.split(':').collect();
+            if fields.len() >= 4 {
+                list.positions
+                    .insert(format!("{}:{}", fields[0], fields[1]));
+            }
+            list.ids.insert(id.to_string());
+        }
+        Ok(list)
+    }
+
+    fn matches(&self, variant: &Variant) -> bool {
+        self.ids.contains(&variant.input_id) || self.ids.contains(&variant.data.variants_id)
+    }
+
+    // Whether some variant of the record on a vcf line may be listed, from CHROM, POS and ID
+    fn may_match_record(&self, line: &str) -> bool {
+        let mut fields = line.split('\t');
+        let (Some(chr), Some(pos), Some(id)) = (fields.next(), fields.next(), fields.next()) else {
+            return false;
+        };
+        self.ids.contains(id) || self.positions.contains(&format!("{}:{}", chr, pos))
+    }
+}
+
+/// Variants kept by the extract and exclude lists of the options
+///
+/// A variant is listed when its input ID or its chr:pos:ref:alt identifier is, with the contig
+/// named as in the input. With both lists, variants of the extract list are kept unless excluded.
+#[derive(Debug, Clone)]
+pub struct VariantIdFilter {
+    extract: Option<IdList>,
+    exclude: Option<IdList>,
+}
+
+impl VariantIdFilter {
+    /// Filter of `options`, `None` when no list is given
+    pub fn load(options: &ConversionOptions) -> Result<Option<Self>, VcfError> {
+        if options.extract.is_none() && options.exclude.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(VariantIdFilter {
+            extract: options.extract.as_deref().map(IdList::read).transpose()?,
+            exclude: options.exclude.as_deref().map(IdList::read).transpose()?,
+        }))
+    }
+
+    pub fn keeps(&self, variant: &Variant) -> bool {
+        self.extract
+            .as_ref()
+            .is_none_or(|list| list.matches(variant))
+            && !self
+                .exclude
+                .as_ref()
+                .is_some_and(|list| list.matches(variant))
+    }
+
+    /// Whether a record may be kept without parsing it: false when no variant of it is extracted
+    pub fn may_keep_record(&self, line: &str) -> bool {
+        self.extract
+            .as_ref()
+            .is_none_or(|list| list.may_match_record(line))
+    }
+
+    /// Whether the variants kept of a record are all those it gives, no list naming any of them
+    pub fn keeps_whole_record(&self, line: &str) -> bool {
+        self.extract.is_none()
+            && !self
+                .exclude
+                .as_ref()
+                .is_some_and(|list| list.may_match_record(line))
+    }
+}
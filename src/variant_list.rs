@@ -0,0 +1,63 @@
+// Sidecar listing the variants written, in PLINK .bim or .pvar columns
+use crate::pipeline::sink::VariantSink;
+use crate::pipeline::Variant;
+use crate::VcfError;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+/// One row per variant written, with the ids given to split multiallelic records
+///
+/// A path ending in `.pvar` gets PLINK 2 columns, any other the headerless .bim columns, whose
+/// first allele is the alt. Alts of records kept whole are joined with commas.
+pub struct VariantListWriter {
+    writer: BufWriter<File>,
+    pvar: bool,
+}
+
+impl VariantListWriter {
+    pub fn create(path: &str) -> Result<Self, VcfError> {
+        Ok(VariantListWriter {
+            writer: BufWriter::new(File::create(path)?),
+            pvar: path.ends_with(".pvar"),
+        })
+    }
+}
+
+impl VariantSink for VariantListWriter {
+    fn write_header(&mut self, _samples: &[String], _variant_num: u32) -> Result<(), VcfError> {
+        if self.pvar {
+            writeln!(self.writer, "#CHROM\tPOS\tID\tREF\tALT")?;
+        }
+        Ok(())
+    }
+
+    fn write_variant(&mut self, variant: &Variant) -> Result<(), VcfError> {
+        let variant_data = &variant.data;
+        let reference = &variant_data.alleles[0];
+        let alts = variant_data.alleles[1..].join(",");
+        if self.pvar {
+            writeln!(
+                self.writer,
+                "{}\t{}\t{}\t{}\t{}",
+                variant_data.chr, variant_data.pos, variant_data.variants_id, reference, alts
+            )?;
+        } else {
+            // no genetic position, written as 0
+            writeln!(
+                self.writer,
+                "{}\t{}\t0\t{}\t{}\t{}",
+                variant_data.chr, variant_data.variants_id, variant_data.pos, alts, reference
+            )?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<u64, VcfError> {
+        self.writer.flush()?;
+        Ok(self.writer.get_ref().metadata()?.len())
+    }
+
+    fn single_pass(&self) -> bool {
+        true
+    }
+}
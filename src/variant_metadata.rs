@@ -0,0 +1,137 @@
+// Table describing each variant written, to trace what splitting and renaming did to the input
+use crate::dosage::allele_count;
+use crate::pipeline::sink::VariantSink;
+use crate::pipeline::Variant;
+use crate::{sample_probabilities, VcfError};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+const COLUMNS: [&str; 9] = [
+    "chr",
+    "pos",
+    "input_id",
+    "variant_id",
+    "ref",
+    "alt",
+    "alt_frequency",
+    "missing_rate",
+    "line",
+];
+
+/// One row per variant written, as a tsv or, for a path ending in `.json`, a json array
+///
+/// The alt frequency is NA for variants with more than one alt, and `line` is the line of the
+/// record in the input, counting header lines.
+pub struct VariantMetadataWriter {
+    writer: BufWriter<File>,
+    json: bool,
+    header_lines: u32,
+    variants_written: u64,
+}
+
+impl VariantMetadataWriter {
+    pub fn create(path: &str, header_lines: u32) -> Result<Self, VcfError> {
+        Ok(VariantMetadataWriter {
+            writer: BufWriter::new(File::create(path)?),
+            json: path.ends_with(".json"),
+            header_lines,
+            variants_written: 0,
+        })
+    }
+}
+
+// Quote a string for json, escaping quotes, backslashes and control characters
+pub(crate) fn json_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+impl VariantSink for VariantMetadataWriter {
+    fn write_header(&mut self, _samples: &[String], _variant_num: u32) -> Result<(), VcfError> {
+        if self.json {
+            write!(self.writer, "[")?;
+        } else {
+            writeln!(self.writer, "{}", COLUMNS.join("\t"))?;
+        }
+        Ok(())
+    }
+
+    fn write_variant(&mut self, variant: &Variant) -> Result<(), VcfError> {
+        let variant_data = &variant.data;
+        let probabilities = sample_probabilities(&variant_data.data_block);
+        let missing = probabilities
+            .iter()
+            .filter(|(ploidy_m, _)| ploidy_m >> 7 == 1)
+            .count();
+        let missing_rate = match probabilities.len() {
+            0 => None,
+            samples => Some((missing as f64 / samples as f64 * 1e6).round() / 1e6),
+        };
+        let alt_frequency = match variant_data.alleles.len() {
+            2 => match allele_count(variant_data) {
+                (_, 0) => None,
+                (count, number) => Some((count / number as f64 * 1e6).round() / 1e6),
+            },
+            _ => None,
+        };
+        let line = self.header_lines as u64 + variant.record_num as u64;
+        let alts = variant_data.alleles[1..].join(",");
+        if self.json {
+            let number = |value: Option<f64>| value.map_or("null".to_string(), |v| v.to_string());
+            let separator = if self.variants_written == 0 { "" } else { "," };
+            write!(
+                self.writer,
+                "{}\n  {{\"chr\": {}, \"pos\": {}, \"input_id\": {}, \"variant_id\": {}, \"ref\": {}, \"alt\": {}, \"alt_frequency\": {}, \"missing_rate\": {}, \"line\": {}}}",
+                separator,
+                json_string(&variant_data.chr),
+                variant_data.pos,
+                json_string(&variant.input_id),
+                json_string(&variant_data.variants_id),
+                json_string(&variant_data.alleles[0]),
+                json_string(&alts),
+                number(alt_frequency),
+                number(missing_rate),
+                line
+            )?;
+        } else {
+            let number = |value: Option<f64>| value.map_or("NA".to_string(), |v| v.to_string());
+            writeln!(
+                self.writer,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                variant_data.chr,
+                variant_data.pos,
+                variant.input_id,
+                variant_data.variants_id,
+                variant_data.alleles[0],
+                alts,
+                number(alt_frequency),
+                number(missing_rate),
+                line
+            )?;
+        }
+        self.variants_written += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<u64, VcfError> {
+        if self.json {
+            writeln!(self.writer, "\n]")?;
+        }
+        self.writer.flush()?;
+        Ok(self.writer.get_ref().metadata()?.len())
+    }
+
+    fn single_pass(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,231 @@
+// Parsed vcf meta-information lines (VCF 4.3, section 1.4) and the sample line
+use crate::{snippet, VcfError};
+use color_eyre::Report;
+use nom::branch::alt;
+use nom::bytes::complete::{escaped_transform, is_not, tag, take_while1};
+use nom::character::complete::{char, none_of};
+use nom::combinator::{map, opt, rest, value};
+use nom::multi::separated_list0;
+use nom::sequence::{delimited, preceded, separated_pair};
+use nom::IResult;
+use std::borrow::Cow;
+use std::io::BufRead;
+
+// Fixed columns of the #CHROM line, before the sample columns
+const FIXED_COLUMNS: usize = 9;
+
+/// A structured meta-information line like `##FORMAT=<ID=GT,Number=1,...>`
+#[derive(Debug, Clone, PartialEq)]
+pub struct StructuredLine {
+    pub key: String,
+    pub fields: Vec<(String, String)>,
+}
+
+impl StructuredLine {
+    pub fn get(&self, field: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(key, _)| key == field)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn id(&self) -> Option<&str> {
+        self.get("ID")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContigDefinition {
+    pub id: String,
+    pub length: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldDefinition {
+    pub id: String,
+    pub number: String,
+    pub field_type: String,
+    pub description: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VcfHeader {
+    pub file_format: Option<String>,
+    pub contigs: Vec<ContigDefinition>,
+    pub infos: Vec<FieldDefinition>,
+    pub formats: Vec<FieldDefinition>,
+    pub filters: Vec<StructuredLine>,
+    /// Other structured lines (ALT, SAMPLE, PEDIGREE, ...)
+    pub structured: Vec<StructuredLine>,
+    /// Unstructured `##key=value` lines
+    pub unstructured: Vec<(String, String)>,
+    pub samples: Vec<String>,
+    /// Lines read up to and including the #CHROM line
+    pub header_lines: u32,
+}
+
+impl VcfHeader {
+    pub fn contig(&self, id: &str) -> Option<&ContigDefinition> {
+        self.contigs.iter().find(|contig| contig.id == id)
+    }
+
+    pub fn format(&self, id: &str) -> Option<&FieldDefinition> {
+        self.formats.iter().find(|format| format.id == id)
+    }
+
+    pub fn info(&self, id: &str) -> Option<&FieldDefinition> {
+        self.infos.iter().find(|info| info.id == id)
+    }
+
+    fn add_meta_line(&mut self, line: &str) -> Result<(), VcfError> {
+        let (key, value) = parse_meta_line(line)
+            .map_err(|_| {
+                header_error(format!(
+                    "Invalid header line {}: {}",
+                    self.header_lines,
+                    snippet(line)
+                ))
+            })?
+            .1;
+        if !value.starts_with('<') {
+            if key == "fileformat" {
+                self.file_format = Some(value.to_string());
+            }
+            self.unstructured
+                .push((key.to_string(), percent_decode(value).to_string()));
+            return Ok(());
+        }
+        let fields = parse_structured_fields(value)
+            .map_err(|_| {
+                header_error(format!(
+                    "Invalid structured header line {}: {}",
+                    self.header_lines,
+                    snippet(line)
+                ))
+            })?
+            .1;
+        let structured = StructuredLine {
+            key: key.to_string(),
+            fields,
+        };
+        match key {
+            "contig" => self.contigs.push(ContigDefinition {
+                id: required_id(&structured, line)?,
+                length: structured.get("length").and_then(|l| l.parse().ok()),
+            }),
+            "INFO" => self.infos.push(field_definition(&structured, line)?),
+            "FORMAT" => self.formats.push(field_definition(&structured, line)?),
+            "FILTER" => self.filters.push(structured),
+            _ => self.structured.push(structured),
+        }
+        Ok(())
+    }
+}
+
+/// Read meta-information lines and the sample line, leaving the reader at the first record
+pub fn parse_vcf_header(reader: &mut impl BufRead) -> Result<VcfHeader, VcfError> {
+    let mut header = VcfHeader::default();
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(header_error(
+                "Reached end of file before the #CHROM header line".to_string(),
+            ));
+        }
+        header.header_lines += 1;
+        let trimmed_line = line.trim_end_matches(['\n', '\r']);
+        // trailing separators would otherwise add empty sample names
+        let trimmed_line = if trimmed_line.starts_with("#CHROM") {
+            trimmed_line.trim_end_matches(['\t', ' '])
+        } else {
+            trimmed_line
+        };
+        if trimmed_line.starts_with("##") {
+            header.add_meta_line(trimmed_line)?;
+        } else if trimmed_line.starts_with('#') {
+            header.samples = trimmed_line
+                .split('\t')
+                .skip(FIXED_COLUMNS)
+                .map(|sample| sample.to_string())
+                .collect();
+            return Ok(header);
+        }
+    }
+}
+
+/// Decode the percent-encoded characters allowed by VCF 4.3 (`%3A`, `%3B`, `%2C`, ...)
+pub fn percent_decode(input: &str) -> Cow<'_, str> {
+    if !input.contains('%') {
+        return Cow::Borrowed(input);
+    }
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex_value = bytes
+            .get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match hex_value {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    Cow::Owned(String::from_utf8_lossy(&decoded).to_string())
+}
+
+fn parse_meta_line(input: &str) -> IResult<&str, (&str, &str)> {
+    preceded(tag("##"), separated_pair(is_not("="), char('='), rest))(input)
+}
+
+fn parse_quoted(input: &str) -> IResult<&str, String> {
+    let escaped_string = escaped_transform(
+        none_of("\\\""),
+        '\\',
+        alt((value("\\", tag("\\")), value("\"", tag("\"")))),
+    );
+    delimited(
+        char('"'),
+        map(opt(escaped_string), |s| s.unwrap_or_default()),
+        char('"'),
+    )(input)
+}
+
+fn parse_structured_fields(input: &str) -> IResult<&str, Vec<(String, String)>> {
+    let key = take_while1(|c: char| c.is_alphanumeric() || c == '_' || c == '.');
+    let unquoted = map(is_not(",>"), |s: &str| percent_decode(s).to_string());
+    let pair = separated_pair(
+        map(key, |s: &str| s.to_string()),
+        char('='),
+        alt((parse_quoted, unquoted)),
+    );
+    delimited(char('<'), separated_list0(char(','), pair), char('>'))(input)
+}
+
+fn required_id(structured: &StructuredLine, line: &str) -> Result<String, VcfError> {
+    structured
+        .id()
+        .map(|id| id.to_string())
+        .ok_or_else(|| header_error(format!("Missing ID in header line: {}", line)))
+}
+
+fn field_definition(structured: &StructuredLine, line: &str) -> Result<FieldDefinition, VcfError> {
+    Ok(FieldDefinition {
+        id: required_id(structured, line)?,
+        number: structured.get("Number").unwrap_or(".").to_string(),
+        field_type: structured.get("Type").unwrap_or("String").to_string(),
+        description: structured.get("Description").unwrap_or("").to_string(),
+    })
+}
+
+fn header_error(message: String) -> VcfError {
+    VcfError::Nom(Report::msg(message))
+}
@@ -0,0 +1,177 @@
+// Verification of a written bgen: a sample of its variants decoded and compared with those converted
+use crate::bgen_file::{BgenProbabilities, BgenVariant, BgenVariantReader};
+use crate::layout1::diploid_genotype_probabilities;
+use crate::pipeline::sink::VariantSink;
+use crate::pipeline::Variant;
+use crate::provenance::SeededRng;
+use crate::VcfError;
+use bgen_reader::bgen::variant_data::VariantData;
+use color_eyre::Report;
+use tracing::info;
+
+/// Keeps a random sample of the variants written to the bgen at `path`, then decodes them from
+/// the finished file and fails on the first one differing from the converted variant
+///
+/// Added after the bgen output, whose `finish` moves the complete file to `path` first. The sample
+/// is drawn by reservoir sampling, so it is the same for the same seed.
+pub struct VerifySink {
+    path: String,
+    sample_size: usize,
+    rng: SeededRng,
+    variants_seen: u64,
+    // variants kept, with their index in the bgen
+    sampled: Vec<(u64, VariantData)>,
+}
+
+impl VerifySink {
+    pub fn new(path: &str, sample_size: u32, seed: Option<u64>) -> Self {
+        VerifySink {
+            path: path.to_string(),
+            sample_size: sample_size as usize,
+            rng: SeededRng::new(seed.unwrap_or_default()),
+            variants_seen: 0,
+            sampled: vec![],
+        }
+    }
+}
+
+// Largest difference between two probabilities stored with `bits` and `other_bits`, from rounding
+fn rounding_tolerance(bits: u8, other_bits: u8) -> f64 {
+    0.5 / ((1u64 << bits.min(other_bits)) - 1) as f64 + 1e-9
+}
+
+// What differs between the converted variant and the one read back, if anything
+fn difference(expected: &VariantData, written: &BgenVariant) -> Option<String> {
+    if (&written.chr, written.pos, &written.alleles)
+        != (&expected.chr, expected.pos, &expected.alleles)
+    {
+        return Some(format!(
+            "written as {}:{}:{}",
+            written.chr,
+            written.pos,
+            written.alleles.join(":")
+        ));
+    }
+    if (&written.variants_id, &written.rsid) != (&expected.variants_id, &expected.rsid) {
+        return Some(format!(
+            "written with IDs {} and {}",
+            written.variants_id, written.rsid
+        ));
+    }
+    match &written.probabilities {
+        BgenProbabilities::Layout1(probabilities) => {
+            let converted = diploid_genotype_probabilities(&expected.data_block);
+            if probabilities.len() != converted.len() {
+                return Some(format!("written with {} samples", probabilities.len()));
+            }
+            // layout 1 stores 1 as 32768
+            let tolerance = 0.5 / 32768.0 + 1e-9;
+            let sample_i = probabilities
+                .iter()
+                .zip(&converted)
+                .position(|pair| match pair {
+                    (Some(written), Some(converted)) => written
+                        .iter()
+                        .zip(converted)
+                        .any(|(w, c)| (w - c).abs() > tolerance),
+                    (written, converted) => written.is_some() != converted.is_some(),
+                })?;
+            Some(format!("sample {} written differently", sample_i + 1))
+        }
+        BgenProbabilities::Layout2(data_block) => {
+            let converted = &expected.data_block;
+            if data_block.ploidy_missingness != converted.ploidy_missingness {
+                return Some("ploidy or missingness written differently".to_string());
+            }
+            if data_block.phased != converted.phased {
+                return Some(format!("written with phased {}", data_block.phased));
+            }
+            if data_block.probabilities.len() != converted.probabilities.len() {
+                return Some(format!(
+                    "written with {} probabilities instead of {}",
+                    data_block.probabilities.len(),
+                    converted.probabilities.len()
+                ));
+            }
+            let tolerance = rounding_tolerance(data_block.bits_storage, converted.bits_storage);
+            let max = |bits: u8| ((1u64 << bits) - 1) as f64;
+            let (written_max, converted_max) =
+                (max(data_block.bits_storage), max(converted.bits_storage));
+            data_block
+                .probabilities
+                .iter()
+                .zip(&converted.probabilities)
+                .any(|(&w, &c)| {
+                    (w as f64 / written_max - c as f64 / converted_max).abs() > tolerance
+                })
+                .then(|| "probabilities written differently".to_string())
+        }
+    }
+}
+
+impl VariantSink for VerifySink {
+    fn write_header(&mut self, _samples: &[String], _variant_num: u32) -> Result<(), VcfError> {
+        Ok(())
+    }
+
+    fn write_variant(&mut self, variant: &Variant) -> Result<(), VcfError> {
+        let index = self.variants_seen;
+        self.variants_seen += 1;
+        if self.sampled.len() < self.sample_size {
+            self.sampled.push((index, variant.data.clone()));
+        } else {
+            let replaced = (self.rng.next_u64() % self.variants_seen) as usize;
+            if replaced < self.sample_size {
+                self.sampled[replaced] = (index, variant.data.clone());
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<u64, VcfError> {
+        let mut reader = BgenVariantReader::open(&self.path)?;
+        let variant_num = reader.summary().variant_num as u64;
+        if variant_num != self.variants_seen {
+            return Err(VcfError::Validation(Report::msg(format!(
+                "{} declares {} variants but {} were converted",
+                self.path, variant_num, self.variants_seen
+            ))));
+        }
+        self.sampled.sort_by_key(|(index, _)| *index);
+        let mut next_index = 0;
+        for (index, expected) in &self.sampled {
+            while next_index < *index {
+                reader.skip_variant()?;
+                next_index += 1;
+            }
+            let Some(written) = reader.next_variant()? else {
+                return Err(VcfError::Validation(Report::msg(format!(
+                    "{} ends before variant {}",
+                    self.path,
+                    index + 1
+                ))));
+            };
+            next_index += 1;
+            if let Some(difference) = difference(expected, &written) {
+                return Err(VcfError::Validation(Report::msg(format!(
+                    "Verification failed: variant {} of {} ({}) was {}",
+                    index + 1,
+                    self.path,
+                    expected.variants_id,
+                    difference
+                ))));
+            }
+        }
+        info!(
+            "Verified {} of {} variants of {}",
+            self.sampled.len(),
+            variant_num,
+            self.path
+        );
+        Ok(0)
+    }
+
+    fn single_pass(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,3075 @@
+extern crate vcf_to_bgen;
+use flate2::read::{MultiGzDecoder, ZlibDecoder};
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::Read;
+use vcf_to_bgen::bgen_file::{read_bgen_summary, read_layout1_variants};
+use vcf_to_bgen::converter::Converter;
+use vcf_to_bgen::layout1::diploid_genotype_probabilities;
+use vcf_to_bgen::output_template::input_stem;
+use vcf_to_bgen::pipeline::sink::{sample_variants, VariantSink};
+use vcf_to_bgen::pipeline::source::{VariantSource, VcfSource};
+use vcf_to_bgen::pipeline::transform::{FillMissingTransform, HardCallTransform, VariantTransform};
+use vcf_to_bgen::pipeline::Variant;
+use vcf_to_bgen::provenance::SeededRng;
+use vcf_to_bgen::sample_file::{check_pair, read_sample_file};
+use vcf_to_bgen::sample_names::deduplicated_samples;
+use vcf_to_bgen::verify::VerifySink;
+use vcf_to_bgen::{
+    build_pipeline, convert_to_bgen, count_variants, num_bits_for_error, open_vcf, scan_variants,
+    BlockCompression, ContigStyle, ConversionOptions, DuplicatePolicy, FillMissing, GenomeBuild,
+    GenotypeField, GenotypePrior, HalfCallPolicy, MissingPolicy, MonomorphicPolicy,
+    MultiallelicMode, OutputFormat, SampleDedupPolicy, ShardMode, SpanningDeletionPolicy,
+    SymbolicAllelePolicy, VcfError,
+};
+
+fn temp_path(name: &str) -> String {
+    std::env::temp_dir()
+        .join(name)
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
+#[test]
+fn conversion_metrics_textfile() {
+    let input = "data/gvcf_ref_blocks.vcf.gz";
+    let output = temp_path("conversion_metrics_textfile.bgen");
+    let (variant_num, number_geno_line) = count_variants(input).unwrap();
+    let options = ConversionOptions::default();
+    let metrics = convert_to_bgen(input, &output, variant_num, number_geno_line, &options).unwrap();
+    assert_eq!(metrics.records_read, 2);
+    assert_eq!(metrics.variants_processed, 1);
+    assert_eq!(metrics.records_skipped, 1);
+    assert_eq!(metrics.bytes_written, fs::metadata(&output).unwrap().len());
+    let metrics_file = temp_path("conversion_metrics_textfile.prom");
+    metrics.write_textfile(&metrics_file).unwrap();
+    let content = fs::read_to_string(&metrics_file).unwrap();
+    assert!(content.contains("vcf_to_bgen_variants_processed_total 1\n"));
+    assert!(content.contains("vcf_to_bgen_phase_duration_seconds{phase=\"convert\"}"));
+}
+
+#[test]
+fn plan_without_converting() {
+    let output = temp_path("plan_without_converting.bgen");
+    let _ = fs::remove_file(&output);
+    let converter = Converter::new("data/multiallelic_1_var_3_alt_allele.vcf.gz", &output)
+        .num_bits(16)
+        .regroup_chromosomes(true);
+    let plan = converter.plan().unwrap();
+    assert_eq!(plan.samples, 10);
+    assert_eq!(plan.estimated_records, 1);
+    assert_eq!(plan.estimated_variants, 3);
+    assert_eq!(plan.outputs, vec![output.clone()]);
+    assert!(plan
+        .filters
+        .contains(&"records regrouped by chromosome".to_string()));
+    assert!(plan.estimated_output_bytes > 0);
+    assert!(!std::path::Path::new(&output).exists());
+    let metrics = converter.run().unwrap();
+    assert_eq!(metrics.variants_processed, 3);
+}
+
+#[test]
+fn index_region_requires_htslib() {
+    let options = ConversionOptions {
+        fetch_region: Some("22:10516000-10517000".to_string()),
+        ..Default::default()
+    };
+    assert!(matches!(
+        open_vcf("data/1_var_10_ind.vcf.gz", &options),
+        Err(VcfError::Validation(_))
+    ));
+}
+
+#[cfg(not(feature = "htslib"))]
+#[test]
+fn htslib_backend_requires_feature() {
+    let options = ConversionOptions {
+        backend: vcf_to_bgen::ReadBackend::Htslib,
+        ..Default::default()
+    };
+    assert!(matches!(
+        open_vcf("data/1_var_10_ind.vcf.gz", &options),
+        Err(VcfError::Validation(_))
+    ));
+}
+
+#[test]
+fn extra_output_from_same_pass() {
+    let output = temp_path("extra_output_16_bits.bgen");
+    let extra_output = temp_path("extra_output_8_bits.bgen");
+    let metrics = Converter::new("data/100_vars_chr22_HG.vcf.gz", &output)
+        .num_bits(16)
+        .extra_output(&extra_output, 8)
+        .run()
+        .unwrap();
+    assert_eq!(metrics.variants_processed, 100);
+    let written = fs::metadata(&output).unwrap().len() + fs::metadata(&extra_output).unwrap().len();
+    assert_eq!(metrics.bytes_written, written);
+
+    // bgen stores 1 to 32 bits per probability
+    let result = Converter::new("data/100_vars_chr22_HG.vcf.gz", &output)
+        .extra_output(&extra_output, 64)
+        .run();
+    assert!(matches!(result, Err(VcfError::Validation(_))));
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_vcf_to_bgen"))
+        .args([
+            "-i",
+            "data/100_vars_chr22_HG.vcf.gz",
+            "-o",
+            &output,
+            "--quiet",
+        ])
+        .args(["--extra-output", &format!("{}:64", extra_output)])
+        .output()
+        .unwrap()
+        .status;
+    assert_eq!(status.code(), Some(2));
+}
+
+#[test]
+fn final_line_without_newline() {
+    let output = temp_path("final_line_without_newline.bgen");
+    let metrics = Converter::new("data/no_trailing_newline.vcf.gz", &output)
+        .run()
+        .unwrap();
+    assert_eq!(metrics.records_read, 2);
+    assert_eq!(metrics.variants_processed, 2);
+}
+
+#[test]
+fn truncated_last_record_is_an_error() {
+    let output = temp_path("truncated_last_record.bgen");
+    let result = Converter::new("data/truncated_last_record.vcf.gz", &output).run();
+    assert!(matches!(result, Err(VcfError::Nom(_))));
+}
+
+#[test]
+fn malformed_records_are_located() {
+    let output = temp_path("malformed_records.bgen");
+    for (input, message) in [
+        (
+            "data/truncated_last_record.vcf.gz",
+            "Line 5 at 22:200 is malformed: ends after column 11 (sample S2), 1 of the 3 samples of the header missing",
+        ),
+        (
+            "data/malformed_pos.vcf.gz",
+            "Line 6 at 22:2O0 is malformed: column 2 (POS) is not a position: '2O0'",
+        ),
+        (
+            "data/empty_sample.vcf.gz",
+            "Line 6 at 22:200 is malformed: column 11 (sample S2) is empty",
+        ),
+    ] {
+        let result = Converter::new(input, &output).run();
+        assert!(
+            matches!(&result, Err(VcfError::Nom(report)) if report.to_string() == message),
+            "{:?}",
+            result.err()
+        );
+    }
+}
+
+#[test]
+fn truncated_compressed_stream_is_an_error() {
+    assert!(matches!(
+        count_variants("data/truncated_stream.vcf.gz"),
+        Err(VcfError::Nom(_))
+    ));
+}
+
+#[test]
+fn export_dosage_matrix() {
+    let output = temp_path("export_dosage_matrix.bgen");
+    let matrix = temp_path("export_dosage_matrix.tsv.gz");
+    Converter::new("data/1_var_10_ind_with_missing.vcf.gz", &output)
+        .dosage_matrix(&matrix)
+        .run()
+        .unwrap();
+    let mut content = String::new();
+    MultiGzDecoder::new(File::open(&matrix).unwrap())
+        .read_to_string(&mut content)
+        .unwrap();
+    let lines: Vec<&str> = content.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].starts_with("variant\tHG00096\tHG00097"));
+    assert_eq!(lines[1], "22:10516173:A:G\tNA\t0\tNA\tNA\t0\t0\t0\t0\t0\t0");
+}
+
+#[test]
+fn dosage_matrix_variant_guard() {
+    let output = temp_path("dosage_matrix_variant_guard.bgen");
+    let options = ConversionOptions {
+        dosage_matrix: Some(temp_path("dosage_matrix_variant_guard.tsv.gz")),
+        dosage_matrix_max_variants: 10,
+        ..Default::default()
+    };
+    let result = Converter::new("data/100_vars_chr22_HG.vcf.gz", &output)
+        .options(options)
+        .run();
+    assert!(matches!(result, Err(VcfError::Validation(_))));
+}
+
+#[test]
+fn sites_only_requires_flag() {
+    let input = "data/sites_only.vcf.gz";
+    let output = temp_path("sites_only_requires_flag.bgen");
+    let result = Converter::new(input, &output).run();
+    assert!(matches!(result, Err(VcfError::Validation(_))));
+    let options = ConversionOptions {
+        allow_sites_only: true,
+        ..Default::default()
+    };
+    let metrics = Converter::new(input, &output)
+        .options(options)
+        .run()
+        .unwrap();
+    assert_eq!(metrics.variants_processed, 3);
+}
+
+// Drops every variant of one chromosome
+struct DropChromosome(&'static str);
+
+impl VariantTransform for DropChromosome {
+    fn name(&self) -> &str {
+        "drop_chromosome"
+    }
+
+    fn apply(&mut self, variant: Variant) -> Result<Option<Variant>, VcfError> {
+        Ok(Some(variant).filter(|variant| variant.data.chr != self.0))
+    }
+}
+
+#[test]
+fn pipeline_transform_checks_declared_variants() {
+    let input = "data/100_vars_chr22_HG.vcf.gz";
+    let options = ConversionOptions::default();
+    let (variant_num, number_geno_line) = count_variants(input).unwrap();
+    let output = temp_path("pipeline_transform_kept.bgen");
+    let mut pipeline = build_pipeline(input, &output, number_geno_line, &options)
+        .unwrap()
+        .transform(Box::new(DropChromosome("1")));
+    let metrics = pipeline.run(variant_num, number_geno_line).unwrap();
+    assert_eq!(metrics.variants_processed, variant_num as u64);
+
+    let output = temp_path("pipeline_transform_dropped.bgen");
+    let mut pipeline = build_pipeline(input, &output, number_geno_line, &options)
+        .unwrap()
+        .transform(Box::new(DropChromosome("22")));
+    assert!(matches!(
+        pipeline.run(variant_num, number_geno_line),
+        Err(VcfError::Bgen(_))
+    ));
+}
+
+#[test]
+fn allele_counts_from_written_genotypes() {
+    let output = temp_path("allele_counts.bgen");
+    let allele_counts = temp_path("allele_counts.tsv");
+    Converter::new("data/genotype_probabilities.vcf.gz", &output)
+        .allele_counts(&allele_counts)
+        .run()
+        .unwrap();
+    let content = fs::read_to_string(&allele_counts).unwrap();
+    let lines: Vec<&str> = content.lines().collect();
+    assert_eq!(lines[0], "variant\tchr\tpos\tref\talt\tAC\tAN\tAF");
+    // the third sample is missing, the second has an expected count of 1 from GP
+    assert_eq!(lines[1], "22:100:A:G\t22\t100\tA\tG\t1.2\t4\t0.3");
+    assert_eq!(lines[2], "22:200:A:G\t22\t200\tA\tG\t0.6\t6\t0.1");
+    assert_eq!(lines[3], "22:200:A:T\t22\t200\tA\tT\t0.8\t6\t0.133333");
+}
+
+#[test]
+fn dosage_field_probabilities() {
+    let output = temp_path("dosage_field.bgen");
+    let matrix = temp_path("dosage_field.tsv.gz");
+    Converter::new("data/dosages.vcf.gz", &output)
+        .dosage_field("DS")
+        .dosage_matrix(&matrix)
+        .run()
+        .unwrap();
+    let mut content = String::new();
+    MultiGzDecoder::new(File::open(&matrix).unwrap())
+        .read_to_string(&mut content)
+        .unwrap();
+    let lines: Vec<&str> = content.lines().collect();
+    // fractional dosages are kept, a missing dosage falls back to GT
+    assert_eq!(lines[1], "22:100:A:G\t0.2\t1.6\t1");
+    assert_eq!(lines[2], "22:200:A:G\t1\t0.2\t0");
+    assert_eq!(lines[3], "22:200:A:T\t0\t1.6\t0");
+}
+
+fn dosage_matrix_rows(input: &str, name: &str, prior: GenotypePrior) -> Vec<String> {
+    let output = temp_path(&format!("{}.bgen", name));
+    let matrix = temp_path(&format!("{}.tsv.gz", name));
+    Converter::new(input, &output)
+        .genotype_likelihoods(prior)
+        .dosage_matrix(&matrix)
+        .run()
+        .unwrap();
+    let mut content = String::new();
+    MultiGzDecoder::new(File::open(&matrix).unwrap())
+        .read_to_string(&mut content)
+        .unwrap();
+    content.lines().skip(1).map(|l| l.to_string()).collect()
+}
+
+#[test]
+fn genotype_likelihood_posteriors() {
+    let input = "data/genotype_likelihoods.vcf.gz";
+    let rows = dosage_matrix_rows(input, "likelihoods_flat", GenotypePrior::Flat);
+    // missing or malformed likelihoods fall back to GT, GP takes precedence
+    assert_eq!(
+        rows,
+        [
+            "22:100:A:G\t1\t1\t2",
+            "22:200:C:T\t1\t2\t0",
+            "22:300:G:C\t0.498\t1\t0"
+        ]
+    );
+    let rows = dosage_matrix_rows(input, "likelihoods_af", GenotypePrior::AlleleFrequency);
+    // without INFO/AF the prior is flat
+    assert_eq!(
+        rows,
+        [
+            "22:100:A:G\t0.1961\t1\t2",
+            "22:200:C:T\t1\t2\t0",
+            "22:300:G:C\t0.498\t1\t0"
+        ]
+    );
+}
+
+#[test]
+fn contig_style_conversion() {
+    assert_eq!(ContigStyle::Ucsc.rename("MT"), "chrM");
+    assert_eq!(ContigStyle::Ensembl.rename("chrX"), "X");
+    assert_eq!(ContigStyle::detect(["chr1", "2"]), None);
+    let output = temp_path("contig_style.bgen");
+    let allele_counts = temp_path("contig_style.tsv");
+    Converter::new("data/1_var_10_ind.vcf.gz", &output)
+        .target_style(ContigStyle::Ucsc)
+        .allele_counts(&allele_counts)
+        .run()
+        .unwrap();
+    let content = fs::read_to_string(&allele_counts).unwrap();
+    assert!(content
+        .lines()
+        .nth(1)
+        .unwrap()
+        .starts_with("chr22:10516173:A:G\tchr22\t10516173"));
+}
+
+#[test]
+fn seeded_rng_is_reproducible() {
+    let mut rng = SeededRng::new(42);
+    assert_eq!(
+        [rng.next_u64(), rng.next_u64(), rng.next_u64()],
+        [
+            13679457532755275413,
+            2949826092126892291,
+            5139283748462763858
+        ]
+    );
+    let mut rng = SeededRng::new(7);
+    assert!((0..1000)
+        .map(|_| rng.next_f64())
+        .all(|x| (0.0..1.0).contains(&x)));
+}
+
+#[test]
+fn manifest_records_seed() {
+    let output = temp_path("manifest_records_seed.bgen");
+    let manifest = temp_path("manifest_records_seed.tsv");
+    Converter::new("data/1_var_10_ind.vcf.gz", &output)
+        .seed(42)
+        .manifest(&manifest)
+        .run()
+        .unwrap();
+    let content = fs::read_to_string(&manifest).unwrap();
+    assert!(content.contains(&format!("output\t{}\n", output)));
+    assert!(content.contains("seed\t42\n"));
+    assert!(content.contains("variant_order\tinput order\n"));
+    assert!(content.contains("variants_written\t1\n"));
+}
+
+#[test]
+fn ploidy_map_sets_haploid_regions() {
+    let input = "data/ploidy_map.vcf.gz";
+    let options = ConversionOptions {
+        ploidy_map: Some("data/ploidy_map.txt".to_string()),
+        ..Default::default()
+    };
+    let (_, number_geno_line) = count_variants(input).unwrap();
+    let mut source = VcfSource::open(input, &options, number_geno_line).unwrap();
+    let mut blocks = vec![];
+    while let Some(variants) = source.next_record().unwrap() {
+        blocks.extend(variants.into_iter().map(|variant| variant.data.data_block));
+    }
+    // outside the mapped region, samples keep the ploidy of their GT
+    assert_eq!(blocks[1].ploidy_missingness, vec![2, 2, 2]);
+    // S1 and S2 are haploid: a homozygous call is kept, a heterozygous one is missing
+    assert_eq!(blocks[2].ploidy_missingness, vec![1, 129, 2]);
+    assert_eq!(blocks[2].probabilities, vec![0, 255, 0, 255]);
+    assert_eq!((blocks[2].minimum_ploidy, blocks[2].maximum_ploidy), (1, 2));
+    // the rows of removed samples are dropped, the others follow their sample
+    fs::write(temp_path("ploidy_map_remove.txt"), "S1\n").unwrap();
+    let options = ConversionOptions {
+        ploidy_map: Some("data/ploidy_map.txt".to_string()),
+        remove_samples: Some(temp_path("ploidy_map_remove.txt")),
+        ..Default::default()
+    };
+    let mut source = VcfSource::open(input, &options, number_geno_line).unwrap();
+    let mut blocks = vec![];
+    while let Some(variants) = source.next_record().unwrap() {
+        blocks.extend(variants.into_iter().map(|variant| variant.data.data_block));
+    }
+    assert_eq!(blocks[2].ploidy_missingness, vec![129, 2]);
+    // unknown samples are rejected
+    fs::write(temp_path("ploidy_map_unknown.txt"), "S9 X 1 10 1\n").unwrap();
+    let options = ConversionOptions {
+        ploidy_map: Some(temp_path("ploidy_map_unknown.txt")),
+        ..Default::default()
+    };
+    assert!(matches!(
+        VcfSource::open(input, &options, number_geno_line),
+        Err(VcfError::Validation(_))
+    ));
+}
+
+#[test]
+fn per_sample_bgens() {
+    let input = "data/polyploid.vcf.gz";
+    let bounded_dir = temp_path("per_sample_bounded");
+    let open_dir = temp_path("per_sample_open");
+    // a single open file forces the other samples through the buffered appends
+    Converter::new(input, &temp_path("per_sample_bounded.bgen"))
+        .per_sample_dir(&bounded_dir, 1)
+        .run()
+        .unwrap();
+    Converter::new(input, &temp_path("per_sample_open.bgen"))
+        .per_sample_dir(&open_dir, 16)
+        .run()
+        .unwrap();
+    for sample in ["S1", "S2", "S3", "S4"] {
+        let bounded_path = format!("{}/{}.bgen", bounded_dir, sample);
+        let summary = read_bgen_summary(&bounded_path).unwrap();
+        assert_eq!(summary.sample_num, 1);
+        assert_eq!(summary.variant_num, 2);
+        assert_eq!(summary.samples, Some(vec![sample.to_string()]));
+        assert_eq!(
+            fs::read(&bounded_path).unwrap(),
+            fs::read(format!("{}/{}.bgen", open_dir, sample)).unwrap()
+        );
+    }
+    // the files are compressed as asked and spooled to the temporary directory until complete
+    let zstd_dir = temp_path("per_sample_zstd");
+    let tmp_dir = temp_path("per_sample_spool");
+    fs::create_dir_all(&tmp_dir).unwrap();
+    Converter::new(input, &temp_path("per_sample_zstd.bgen"))
+        .per_sample_dir(&zstd_dir, 1)
+        .compression(BlockCompression::Zstd)
+        .tmp_dir(&tmp_dir)
+        .run()
+        .unwrap();
+    for sample in ["S1", "S2", "S3", "S4"] {
+        let summary = read_bgen_summary(&format!("{}/{}.bgen", zstd_dir, sample)).unwrap();
+        assert_eq!((summary.compression, summary.variant_num), (2, 2));
+    }
+    assert_eq!(fs::read_dir(&tmp_dir).unwrap().count(), 0);
+    // the single-sample block keeps the ploidy and probabilities of that sample
+    let (_, number_geno_line) = count_variants(input).unwrap();
+    let options = ConversionOptions::default();
+    let mut source = VcfSource::open(input, &options, number_geno_line).unwrap();
+    source.next_record().unwrap();
+    let variants = source.next_record().unwrap().unwrap();
+    let variant_data = &sample_variants(&variants[0].data)[3];
+    assert_eq!(variant_data.number_individuals, Some(1));
+    assert_eq!(variant_data.data_block.ploidy_missingness, vec![4]);
+    assert_eq!(variant_data.data_block.probabilities, vec![0, 0, 0, 0]);
+    assert_eq!(
+        (
+            variant_data.data_block.minimum_ploidy,
+            variant_data.data_block.maximum_ploidy
+        ),
+        (4, 4)
+    );
+}
+
+#[test]
+fn sex_file_haploid_male_chrx() {
+    let input = "data/ploidy_map.vcf.gz";
+    let options = ConversionOptions {
+        sex_file: Some("data/sex.txt".to_string()),
+        ..Default::default()
+    };
+    let (_, number_geno_line) = count_variants(input).unwrap();
+    let mut source = VcfSource::open(input, &options, number_geno_line).unwrap();
+    let mut blocks = vec![];
+    while let Some(variants) = source.next_record().unwrap() {
+        blocks.extend(variants.into_iter().map(|variant| variant.data.data_block));
+    }
+    // autosomes are untouched
+    assert_eq!(blocks[0].ploidy_missingness, vec![2, 2, 2]);
+    // on chrX, the het call of the male S2 is missing
+    for data_block in &blocks[1..] {
+        assert_eq!(data_block.ploidy_missingness, vec![1, 129, 2]);
+    }
+    fs::write(temp_path("sex_invalid.txt"), "S1\tunknown\n").unwrap();
+    let options = ConversionOptions {
+        sex_file: Some(temp_path("sex_invalid.txt")),
+        ..Default::default()
+    };
+    assert!(matches!(
+        VcfSource::open(input, &options, number_geno_line),
+        Err(VcfError::Validation(_))
+    ));
+}
+
+#[test]
+fn sex_file_keeps_pseudo_autosomal_regions_diploid() {
+    let input = "data/par.vcf.gz";
+    let (_, number_geno_line) = count_variants(input).unwrap();
+    let ploidy_by_build = |genome_build| {
+        let options = ConversionOptions {
+            sex_file: Some("data/sex.txt".to_string()),
+            genome_build,
+            ..Default::default()
+        };
+        let mut source = VcfSource::open(input, &options, number_geno_line).unwrap();
+        let mut ploidy = vec![];
+        while let Some(variants) = source.next_record().unwrap() {
+            ploidy.extend(
+                variants
+                    .into_iter()
+                    .map(|variant| variant.data.data_block.ploidy_missingness),
+            );
+        }
+        ploidy
+    };
+    // X:20000 and X:2750000 are in PAR1 of GRCh38 only
+    assert_eq!(
+        ploidy_by_build(GenomeBuild::Grch38),
+        vec![vec![2, 2, 2], vec![2, 2, 2], vec![1, 129, 2]]
+    );
+    assert_eq!(
+        ploidy_by_build(GenomeBuild::Grch37),
+        vec![vec![1, 129, 2], vec![1, 129, 2], vec![1, 129, 2]]
+    );
+}
+
+#[test]
+fn concordance_with_truth_set() {
+    let report = temp_path("concordance_report.tsv");
+    Converter::new("data/imputed.vcf.gz", &temp_path("concordance.bgen"))
+        .truth_comparison("data/truth.vcf.gz", &report)
+        .run()
+        .unwrap();
+    let report = fs::read_to_string(report).unwrap();
+    let rows: Vec<&str> = report.lines().collect();
+    assert_eq!(rows[0], "maf_bin\tvariants\tgenotypes\tconcordance\tr2");
+    // only S2, S3 and S4 are in both files, 1:300 is not in the truth set
+    assert_eq!(rows[1], "0-0.001\t1\t3\t1\tNA");
+    assert_eq!(rows[2], "0.001-0.005\t0\t0\tNA\tNA");
+    assert_eq!(rows[7], "0.2-0.5\t1\t3\t0.666667\t0.75");
+}
+
+#[test]
+fn missing_and_half_call_policies() {
+    let input = "data/half_calls.vcf.gz";
+    let (_, number_geno_line) = count_variants(input).unwrap();
+    let first_block = |missing_policy, half_call_policy| {
+        let options = ConversionOptions {
+            missing_policy,
+            half_call_policy,
+            ..Default::default()
+        };
+        let mut source = VcfSource::open(input, &options, number_geno_line).unwrap();
+        let variants = source.next_record().unwrap().unwrap();
+        variants[0].data.data_block.clone()
+    };
+    // genotypes: ./.  .  ./1  1|.
+    let data_block = first_block(MissingPolicy::Missing, HalfCallPolicy::Missing);
+    assert_eq!(data_block.ploidy_missingness, vec![130, 130, 130, 130]);
+    let data_block = first_block(MissingPolicy::Ref, HalfCallPolicy::Missing);
+    assert_eq!(data_block.ploidy_missingness, vec![2, 2, 130, 130]);
+    assert_eq!(data_block.probabilities[..4], [255, 0, 255, 0]);
+    let data_block = first_block(MissingPolicy::Missing, HalfCallPolicy::Haploid);
+    assert_eq!(data_block.ploidy_missingness, vec![130, 130, 1, 1]);
+    assert_eq!(data_block.probabilities[4..], [0, 0]);
+    assert_eq!(
+        (data_block.minimum_ploidy, data_block.maximum_ploidy),
+        (1, 2)
+    );
+    let data_block = first_block(MissingPolicy::Missing, HalfCallPolicy::Ref);
+    assert_eq!(data_block.ploidy_missingness, vec![130, 130, 2, 2]);
+    assert_eq!(data_block.probabilities[4..], [0, 255, 0, 255]);
+}
+
+#[test]
+fn phased_output_follows_genotype_separators() {
+    let options = ConversionOptions::default();
+    let scan = scan_variants("data/phased.vcf.gz", &options).unwrap();
+    assert_eq!((scan.variant_num, scan.number_geno_line), (2, 2));
+    assert!(scan.all_phased && !scan.has_genotype_probabilities);
+    assert!(
+        !scan_variants("data/polyploid.vcf.gz", &options)
+            .unwrap()
+            .all_phased
+    );
+    let scan = scan_variants("data/tetraploid.vcf.gz", &options).unwrap();
+    assert!(!scan.all_phased && scan.has_genotype_probabilities);
+    let manifest_options = |converter: Converter| {
+        let manifest = temp_path("phased_output.manifest");
+        converter.manifest(&manifest).run().unwrap();
+        let manifest = fs::read_to_string(manifest).unwrap();
+        manifest.lines().last().unwrap().to_string()
+    };
+    let output = temp_path("phased_output.bgen");
+    let written = manifest_options(Converter::new("data/phased.vcf.gz", &output));
+    assert!(written.contains("phased: true"));
+    let written = manifest_options(Converter::new("data/phased.vcf.gz", &output).phased(false));
+    assert!(written.contains("phased: false"));
+}
+
+#[test]
+fn phased_flag_set_per_variant() {
+    let input = "data/mixed_phasing.vcf.gz";
+    let (_, number_geno_line) = count_variants(input).unwrap();
+    let phased_flags = |options: &ConversionOptions| {
+        let mut source = VcfSource::open(input, options, number_geno_line).unwrap();
+        let mut flags = vec![];
+        while let Some(variants) = source.next_record().unwrap() {
+            flags.extend(
+                variants
+                    .iter()
+                    .map(|variant| variant.data.data_block.phased),
+            );
+        }
+        flags
+    };
+    assert_eq!(
+        phased_flags(&ConversionOptions::default()),
+        vec![true, false, false]
+    );
+    let options = ConversionOptions {
+        auto_phase: false,
+        ..Default::default()
+    };
+    assert_eq!(phased_flags(&options), vec![false, false, false]);
+}
+
+#[test]
+fn keep_multiallelic_records() {
+    let input = "data/multiallelic_keep.vcf.gz";
+    let options = ConversionOptions {
+        multiallelic: MultiallelicMode::Keep,
+        ..Default::default()
+    };
+    let scan = scan_variants(input, &options).unwrap();
+    assert_eq!(scan.variant_num, 3);
+    let mut source = VcfSource::open(input, &options, scan.number_geno_line).unwrap();
+    let mut variants = vec![];
+    while let Some(record_variants) = source.next_record().unwrap() {
+        variants.extend(record_variants.into_iter().map(|variant| variant.data));
+    }
+    assert_eq!(variants.len(), 3);
+    let unphased = &variants[0];
+    assert_eq!(unphased.variants_id, "1:100:A:G,T");
+    assert_eq!(unphased.alleles, vec!["A", "G", "T"]);
+    assert_eq!(unphased.data_block.number_alleles, 3);
+    assert_eq!(unphased.data_block.ploidy_missingness, vec![2, 2, 130, 2]);
+    // every genotype but 2/2, in vcf order; GP replaces the GT of S2
+    assert_eq!(
+        unphased.data_block.probabilities,
+        vec![0, 0, 0, 255, 0, 0, 128, 127, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+    );
+    assert_eq!(variants[1].data_block.number_alleles, 2);
+    // every allele but the last, for each haplotype
+    let phased = &variants[2].data_block;
+    assert!(phased.phased);
+    assert_eq!(
+        phased.probabilities,
+        vec![255, 0, 0, 0, 0, 255, 255, 0, 0, 0, 0, 0, 0, 0, 0, 255]
+    );
+    // dosages are only defined for biallelic variants
+    let options = ConversionOptions {
+        dosage_matrix: Some(temp_path("keep_multiallelic_dosages.tsv.gz")),
+        ..options
+    };
+    assert!(matches!(
+        build_pipeline(
+            input,
+            &temp_path("keep_multiallelic.bgen"),
+            scan.number_geno_line,
+            &options
+        ),
+        Err(VcfError::Validation(_))
+    ));
+}
+
+#[test]
+fn spanning_deletion_alleles() {
+    let input = "data/spanning_deletion.vcf.gz";
+    // '*' never becomes a variant, even when it is the only alt
+    let scan = scan_variants(input, &ConversionOptions::default()).unwrap();
+    assert_eq!(scan.variant_num, 1);
+    let metrics = Converter::new(input, &temp_path("spanning_deletion.bgen"))
+        .run()
+        .unwrap();
+    assert_eq!(
+        (metrics.variants_processed, metrics.records_skipped),
+        (1, 1)
+    );
+    let first_block = |spanning_deletion| {
+        let options = ConversionOptions {
+            spanning_deletion,
+            ..Default::default()
+        };
+        let mut source = VcfSource::open(input, &options, scan.number_geno_line).unwrap();
+        let variants = source.next_record().unwrap().unwrap();
+        assert_eq!(variants.len(), 1);
+        variants[0].data.data_block.clone()
+    };
+    let data_block = first_block(SpanningDeletionPolicy::Missing);
+    assert_eq!(data_block.ploidy_missingness, vec![2, 130, 130, 2]);
+    let data_block = first_block(SpanningDeletionPolicy::Drop);
+    assert_eq!(data_block.ploidy_missingness, vec![2, 2, 2, 2]);
+    assert_eq!(
+        data_block.probabilities,
+        vec![0, 255, 0, 255, 255, 0, 255, 0]
+    );
+}
+
+#[test]
+fn symbolic_alleles() {
+    let input = "data/symbolic_alleles.vcf.gz";
+    let report = temp_path("symbolic_alleles.tsv");
+    let options = ConversionOptions {
+        skipped_report: Some(report.clone()),
+        ..Default::default()
+    };
+    let scan = scan_variants(input, &options).unwrap();
+    assert_eq!(scan.variant_num, 2);
+    let mut source = VcfSource::open(input, &options, scan.number_geno_line).unwrap();
+    let mut alleles = vec![];
+    while let Some(variants) = source.next_record().unwrap() {
+        alleles.extend(variants.into_iter().map(|v| v.data.alleles.join(",")));
+    }
+    source.finish().unwrap();
+    assert_eq!(alleles, vec!["A,G", "T,G"]);
+    assert_eq!(
+        fs::read_to_string(&report).unwrap(),
+        "line\tchr\tpos\tref\talt\treason\n\
+         7\t1\t200\tC\t<DEL>\tsymbolic allele\n\
+         8\t1\t400\tT\t<DUP>\tsymbolic allele\n"
+    );
+
+    let options = ConversionOptions {
+        symbolic_alleles: SymbolicAllelePolicy::Encode,
+        ..Default::default()
+    };
+    let scan = scan_variants(input, &options).unwrap();
+    assert_eq!(scan.variant_num, 4);
+    let mut source = VcfSource::open(input, &options, scan.number_geno_line).unwrap();
+    source.next_record().unwrap();
+    let variants = source.next_record().unwrap().unwrap();
+    assert_eq!(variants[0].data.alleles, vec!["C", "<DEL>"]);
+
+    let options = ConversionOptions {
+        symbolic_alleles: SymbolicAllelePolicy::Error,
+        ..Default::default()
+    };
+    let mut source = VcfSource::open(input, &options, scan.number_geno_line).unwrap();
+    source.next_record().unwrap();
+    assert!(matches!(source.next_record(), Err(VcfError::Validation(_))));
+}
+
+#[test]
+fn breakend_records_skipped() {
+    let input = "data/breakends.vcf.gz";
+    let report = temp_path("breakends.tsv");
+    let scan = scan_variants(input, &ConversionOptions::default()).unwrap();
+    assert_eq!((scan.variant_num, scan.number_geno_line), (3, 5));
+    let metrics = Converter::new(input, &temp_path("breakends.bgen"))
+        .skipped_report(&report)
+        .run()
+        .unwrap();
+    assert_eq!(
+        (
+            metrics.variants_processed,
+            metrics.records_skipped,
+            metrics.breakends_skipped
+        ),
+        (3, 3, 3)
+    );
+    assert_eq!(
+        fs::read_to_string(&report).unwrap(),
+        "line\tchr\tpos\tref\talt\treason\n\
+         5\t2\t321681\tG\tG]17:198982]\tbreakend\n\
+         6\t2\t321682\tT\t]13:123456]T\tbreakend\n\
+         8\t17\t198982\tA\t[2:321681[A\tbreakend\n"
+    );
+}
+
+#[test]
+fn monomorphic_records() {
+    let input = "data/monomorphic.vcf.gz";
+    let report = temp_path("monomorphic.tsv");
+    let metrics = Converter::new(input, &temp_path("monomorphic_skip.bgen"))
+        .skipped_report(&report)
+        .run()
+        .unwrap();
+    assert_eq!(
+        (metrics.variants_processed, metrics.records_skipped),
+        (1, 1)
+    );
+    assert_eq!(
+        fs::read_to_string(&report).unwrap(),
+        "line\tchr\tpos\tref\talt\treason\n5\t1\t200\tC\t.\tmonomorphic\n"
+    );
+
+    let options = ConversionOptions {
+        monomorphic: MonomorphicPolicy::Encode,
+        ..Default::default()
+    };
+    let scan = scan_variants(input, &options).unwrap();
+    assert_eq!(scan.variant_num, 2);
+    let mut source = VcfSource::open(input, &options, scan.number_geno_line).unwrap();
+    source.next_record().unwrap();
+    let variants = source.next_record().unwrap().unwrap();
+    assert_eq!(variants[0].data.alleles, vec!["C", "N"]);
+    assert_eq!(variants[0].data.variants_id, "1:200:C:N");
+    assert_eq!(
+        variants[0].data.data_block.probabilities,
+        vec![255, 0, 255, 0]
+    );
+}
+
+#[test]
+fn min_gq_masks_genotypes() {
+    let input = "data/genotype_quality.vcf.gz";
+    let missingness = |options: &ConversionOptions| {
+        let scan = scan_variants(input, options).unwrap();
+        let mut source = VcfSource::open(input, options, scan.number_geno_line).unwrap();
+        let mut missingness = vec![];
+        let first = source.next_record().unwrap().unwrap();
+        // masked samples store zero probabilities
+        assert_eq!(
+            first[0].data.data_block.probabilities,
+            vec![0, 255, 0, 0, 255, 0, 0, 255]
+        );
+        missingness.push(first[0].data.data_block.ploidy_missingness.clone());
+        while let Some(variants) = source.next_record().unwrap() {
+            for variant in variants {
+                let data_block = &variant.data.data_block;
+                missingness.push(data_block.ploidy_missingness.clone());
+            }
+        }
+        missingness
+    };
+    let options = ConversionOptions {
+        min_gq: Some(20),
+        ..Default::default()
+    };
+    // a missing GQ keeps the genotype
+    assert_eq!(
+        missingness(&options),
+        vec![
+            vec![2, 130, 2, 2],
+            vec![130, 2, 2, 130],
+            // the other alt of S2 makes it missing in the second split
+            vec![130, 130, 2, 130]
+        ]
+    );
+    let options = ConversionOptions {
+        min_gq: Some(20),
+        multiallelic: MultiallelicMode::Keep,
+        ..Default::default()
+    };
+    assert_eq!(
+        missingness(&options),
+        vec![vec![2, 130, 2, 2], vec![130, 2, 2, 130]]
+    );
+}
+
+#[test]
+fn depth_range_masks_genotypes() {
+    let input = "data/genotype_quality.vcf.gz";
+    let options = ConversionOptions {
+        min_dp: Some(10),
+        max_dp: Some(200),
+        ..Default::default()
+    };
+    let scan = scan_variants(input, &options).unwrap();
+    let mut source = VcfSource::open(input, &options, scan.number_geno_line).unwrap();
+    let variants = source.next_record().unwrap().unwrap();
+    // S2 below and S4 above the range, S3 without DP kept
+    assert_eq!(
+        variants[0].data.data_block.ploidy_missingness,
+        vec![2, 130, 2, 130]
+    );
+    let variants = source.next_record().unwrap().unwrap();
+    assert_eq!(
+        variants[1].data.data_block.ploidy_missingness,
+        vec![130, 130, 2, 2]
+    );
+
+    let options = ConversionOptions {
+        min_dp: Some(300),
+        max_dp: Some(200),
+        ..Default::default()
+    };
+    assert!(matches!(
+        VcfSource::open(input, &options, scan.number_geno_line),
+        Err(VcfError::Validation(_))
+    ));
+}
+
+#[test]
+fn records_without_gt() {
+    let input = "data/no_gt.vcf.gz";
+    let probabilities = |options: &ConversionOptions| {
+        let scan = scan_variants(input, options).unwrap();
+        let mut source = VcfSource::open(input, options, scan.number_geno_line).unwrap();
+        let mut probabilities = vec![];
+        while let Some(variants) = source.next_record().unwrap() {
+            probabilities.push(variants[0].data.data_block.probabilities.clone());
+        }
+        probabilities
+    };
+    // GP, then DS, when there is no GT
+    assert_eq!(
+        probabilities(&ConversionOptions::default()),
+        vec![
+            vec![128, 127, 0, 0],
+            vec![0, 255, 0, 255],
+            vec![255, 0, 0, 0]
+        ]
+    );
+    let options = ConversionOptions {
+        gt_priority: vec![GenotypeField::Ds, GenotypeField::Gt],
+        ..Default::default()
+    };
+    assert_eq!(
+        probabilities(&options),
+        vec![
+            vec![128, 127, 0, 0],
+            vec![0, 255, 0, 255],
+            vec![128, 127, 0, 0]
+        ]
+    );
+}
+
+#[test]
+fn truncated_sample_columns() {
+    let input = "data/truncated_samples.vcf.gz";
+    // samples may drop trailing FORMAT fields, which are then missing
+    for options in [
+        ConversionOptions::default(),
+        ConversionOptions {
+            min_gq: Some(20),
+            min_dp: Some(5),
+            genotype_likelihoods: Some(GenotypePrior::Flat),
+            ..Default::default()
+        },
+    ] {
+        let scan = scan_variants(input, &options).unwrap();
+        assert_eq!(scan.variant_num, 2);
+        let mut source = VcfSource::open(input, &options, scan.number_geno_line).unwrap();
+        let variants = source.next_record().unwrap().unwrap();
+        assert_eq!(
+            variants[0].data.data_block.ploidy_missingness,
+            vec![130, 2, 2]
+        );
+        let variants = source.next_record().unwrap().unwrap();
+        assert_eq!(
+            variants[0].data.data_block.ploidy_missingness,
+            vec![2, 130, 2]
+        );
+        assert_eq!(variants[0].data.data_block.probabilities[..2], [255, 0]);
+    }
+}
+
+#[test]
+fn collapse_multiallelic_records() {
+    let input = "data/multiallelic_collapse.vcf.gz";
+    let options = ConversionOptions {
+        multiallelic: MultiallelicMode::Collapse,
+        ..Default::default()
+    };
+    let scan = scan_variants(input, &options).unwrap();
+    assert_eq!(scan.variant_num, 2);
+    let mut source = VcfSource::open(input, &options, scan.number_geno_line).unwrap();
+    let variants = source.next_record().unwrap().unwrap();
+    let variant_data = &variants[0].data;
+    // ATT is the most frequent alt, AT and ATTT become <OTHER>
+    assert_eq!(variant_data.alleles, vec!["A", "ATT", "<OTHER>"]);
+    assert_eq!(variant_data.variants_id, "1:100:A:ATT,<OTHER>");
+    // genotypes in vcf order: 0/0, 0/1, 1/1, 0/2, 1/2 and 2/2 implied
+    assert_eq!(
+        variant_data.data_block.probabilities,
+        vec![
+            0, 0, 0, 255, 0, //
+            0, 0, 255, 0, 0, //
+            0, 0, 0, 0, 255, //
+            255, 0, 0, 0, 0,
+        ]
+    );
+    // with two alts, the record is kept whole
+    let variants = source.next_record().unwrap().unwrap();
+    assert_eq!(variants[0].data.alleles, vec!["C", "T", "G"]);
+}
+
+#[test]
+fn keep_multiallelic_likelihoods() {
+    let input = "data/multiallelic_likelihoods.vcf.gz";
+    let options = ConversionOptions {
+        multiallelic: MultiallelicMode::Keep,
+        genotype_likelihoods: Some(GenotypePrior::Flat),
+        ..Default::default()
+    };
+    let scan = scan_variants(input, &options).unwrap();
+    let mut source = VcfSource::open(input, &options, scan.number_geno_line).unwrap();
+    let variants = source.next_record().unwrap().unwrap();
+    let data_block = &variants[0].data.data_block;
+    // every genotype but the last, in vcf order: 0/0, 0/1, 1/1, 0/2, 1/2
+    assert_eq!(data_block.ploidy_missingness, vec![2, 2, 2]);
+    assert_eq!(
+        data_block.probabilities,
+        vec![
+            254, 0, 0, 0, 0, //
+            0, 0, 0, 0, 255, //
+            43, 43, 43, 43, 43,
+        ]
+    );
+}
+
+#[test]
+fn hard_call_threshold() {
+    let input = "data/no_gt.vcf.gz";
+    let options = ConversionOptions::default();
+    let scan = scan_variants(input, &options).unwrap();
+    let mut source = VcfSource::open(input, &options, scan.number_geno_line).unwrap();
+    let mut transform = HardCallTransform::new(0.9);
+    let mut blocks = vec![];
+    while let Some(variants) = source.next_record().unwrap() {
+        let variant = transform.apply(variants[0].clone()).unwrap().unwrap();
+        let data_block = variant.data.data_block;
+        blocks.push((data_block.ploidy_missingness, data_block.probabilities));
+    }
+    assert_eq!(
+        blocks,
+        vec![
+            // GP 0.5,0.5,0 is too uncertain, 0,0,1 is called
+            (vec![130, 2], vec![0, 0, 0, 0]),
+            // DS of 1 is a heterozygous call
+            (vec![2, 2], vec![0, 255, 0, 255]),
+            (vec![2, 2], vec![255, 0, 0, 0]),
+        ]
+    );
+    let options = ConversionOptions {
+        hard_call_threshold: Some(1.5),
+        ..Default::default()
+    };
+    assert!(matches!(
+        convert_to_bgen(
+            input,
+            &temp_path("hard_call_threshold.bgen"),
+            scan.variant_num,
+            scan.number_geno_line,
+            &options
+        ),
+        Err(VcfError::Validation(_))
+    ));
+}
+
+#[test]
+fn fill_missing_genotypes() {
+    let input = "data/fill_missing.vcf.gz";
+    let options = ConversionOptions::default();
+    let scan = scan_variants(input, &options).unwrap();
+    let blocks = |fill_missing| {
+        let mut source = VcfSource::open(input, &options, scan.number_geno_line).unwrap();
+        let mut transform = FillMissingTransform::new(fill_missing);
+        let mut blocks = vec![];
+        while let Some(variants) = source.next_record().unwrap() {
+            let variant = transform.apply(variants[0].clone()).unwrap().unwrap();
+            let data_block = variant.data.data_block;
+            blocks.push((data_block.ploidy_missingness, data_block.probabilities));
+        }
+        blocks
+    };
+    assert_eq!(
+        blocks(FillMissing::Ref),
+        vec![
+            (vec![2; 4], vec![255, 0, 0, 255, 0, 0, 255, 0]),
+            (vec![2; 4], vec![255, 255, 255, 0, 0, 0, 255, 255]),
+        ]
+    );
+    // half of the called alleles are alt, the missing sample is heterozygous
+    assert_eq!(
+        blocks(FillMissing::MeanDosage),
+        vec![
+            (vec![2; 4], vec![255, 0, 0, 255, 0, 0, 0, 255]),
+            (vec![2; 4], vec![255, 255, 255, 0, 0, 0, 128, 128]),
+        ]
+    );
+    assert_eq!(blocks(FillMissing::None)[0].0, vec![2, 2, 2, 130]);
+}
+
+#[test]
+fn layout1_output() {
+    let output = temp_path("layout1_output.bgen");
+    Converter::new("data/fill_missing.vcf.gz", &output)
+        .layout(1)
+        .run()
+        .unwrap();
+    assert_eq!(read_bgen_summary(&output).unwrap().layout_id, 1);
+    // AA, AB and BB for each sample, zero when missing; phased haplotypes lose their phase
+    let variants = read_layout1_variants(&output).unwrap();
+    let ids: Vec<&str> = variants.iter().map(|v| v.variants_id.as_str()).collect();
+    assert_eq!(ids, vec!["1:100:A:G", "1:200:C:T"]);
+    for variant in &variants {
+        assert_eq!(
+            variant.probabilities,
+            vec![
+                Some([1.0, 0.0, 0.0]),
+                Some([0.0, 1.0, 0.0]),
+                Some([0.0, 0.0, 1.0]),
+                None
+            ]
+        );
+    }
+    let result = Converter::new("data/multiallelic_collapse.vcf.gz", &output)
+        .layout(1)
+        .multiallelic(MultiallelicMode::Keep)
+        .run();
+    assert!(matches!(result, Err(VcfError::Validation(_))));
+}
+
+#[test]
+fn legacy_round_trip() {
+    let input = "data/genotype_probabilities.vcf.gz";
+    let output = temp_path("legacy_round_trip.bgen");
+    let options = ConversionOptions::legacy();
+    Converter::new(input, &output)
+        .options(options.clone())
+        .run()
+        .unwrap();
+    let variants = read_layout1_variants(&output).unwrap();
+    // every split variant comes back with its 16-bit probabilities
+    let scan = scan_variants(input, &options).unwrap();
+    let mut source = VcfSource::open(input, &options, scan.number_geno_line).unwrap();
+    let mut expected = vec![];
+    while let Some(record_variants) = source.next_record().unwrap() {
+        expected.extend(record_variants);
+    }
+    assert_eq!(variants.len(), expected.len());
+    for (variant, expected) in variants.iter().zip(&expected) {
+        assert_eq!(variant.variants_id, expected.data.variants_id);
+        assert_eq!(
+            (&variant.chr, variant.pos),
+            (&expected.data.chr, expected.data.pos)
+        );
+        assert_eq!(variant.alleles, expected.data.alleles);
+        let expected_probabilities = diploid_genotype_probabilities(&expected.data.data_block);
+        for (read, expected) in variant.probabilities.iter().zip(expected_probabilities) {
+            match (read, expected) {
+                (Some(read), Some(expected)) => {
+                    for (read, expected) in read.iter().zip(expected) {
+                        assert!((read - expected).abs() < 1e-4);
+                    }
+                }
+                (read, expected) => assert_eq!(read.is_none(), expected.is_none()),
+            }
+        }
+    }
+    // GP of the first sample, 0.8,0.2,0
+    let first = variants[0].probabilities[0].unwrap();
+    assert!((first[0] - 0.8).abs() < 1e-4 && (first[1] - 0.2).abs() < 1e-4);
+}
+
+#[test]
+fn zstd_compressed_blocks() {
+    let output = temp_path("zstd_compressed_blocks.bgen");
+    Converter::new("data/fill_missing.vcf.gz", &output)
+        .compression(BlockCompression::Zstd)
+        .run()
+        .unwrap();
+    let summary = read_bgen_summary(&output).unwrap();
+    assert_eq!(
+        (summary.compression, summary.layout_id, summary.variant_num),
+        (2, 2, 2)
+    );
+    let bytes = fs::read(&output).unwrap();
+    let u32_at = |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+    // identifiers of 1:100:A:G: id, rsid and chr, position, 2 alleles
+    let block_start = u32_at(0) as usize + 4 + 2 + 9 + 2 + 9 + 2 + 1 + 4 + 2 + (4 + 1) * 2;
+    let block_len = u32_at(block_start) as usize;
+    let uncompressed_len = u32_at(block_start + 4) as usize;
+    let decoded = zstd::bulk::decompress(
+        &bytes[block_start + 8..block_start + 4 + block_len],
+        uncompressed_len,
+    )
+    .unwrap();
+    assert_eq!(
+        decoded,
+        vec![
+            4, 0, 0, 0, 2, 0, 2, 2, // samples, alleles, ploidy range
+            2, 2, 2, 130, 0, 8, // ploidy of each sample, unphased, 8 bits
+            255, 0, 0, 255, 0, 0, 255, 0,
+        ]
+    );
+}
+
+#[test]
+fn uncompressed_blocks() {
+    let output = temp_path("uncompressed_blocks.bgen");
+    Converter::new("data/fill_missing.vcf.gz", &output)
+        .compression(BlockCompression::None)
+        .run()
+        .unwrap();
+    let summary = read_bgen_summary(&output).unwrap();
+    assert_eq!(
+        (summary.compression, summary.layout_id, summary.variant_num),
+        (0, 2, 2)
+    );
+    let bytes = fs::read(&output).unwrap();
+    let u32_at = |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+    // identifiers of 1:100:A:G: id, rsid and chr, position, 2 alleles
+    let block_start = u32_at(0) as usize + 4 + 2 + 9 + 2 + 9 + 2 + 1 + 4 + 2 + (4 + 1) * 2;
+    let block_len = u32_at(block_start) as usize;
+    // raw blocks have no uncompressed length
+    assert_eq!(
+        &bytes[block_start + 4..block_start + 4 + block_len],
+        [
+            4, 0, 0, 0, 2, 0, 2, 2, // samples, alleles, ploidy range
+            2, 2, 2, 130, 0, 8, // ploidy of each sample, unphased, 8 bits
+            255, 0, 0, 255, 0, 0, 255, 0,
+        ]
+    );
+
+    // layout 1 blocks are left raw too
+    let output = temp_path("uncompressed_layout1.bgen");
+    Converter::new("data/fill_missing.vcf.gz", &output)
+        .layout(1)
+        .compression(BlockCompression::None)
+        .run()
+        .unwrap();
+    let summary = read_bgen_summary(&output).unwrap();
+    assert_eq!((summary.compression, summary.layout_id), (0, 1));
+    let variants = read_layout1_variants(&output).unwrap();
+    assert_eq!(variants.len(), 2);
+    assert_eq!(variants[0].probabilities[0], Some([1.0, 0.0, 0.0]));
+    assert_eq!(variants[0].probabilities[3], None);
+}
+
+#[test]
+fn zlib_compression_level() {
+    let mut blocks = vec![];
+    for level in [1, 9] {
+        let output = temp_path(&format!("zlib_compression_level_{}.bgen", level));
+        Converter::new("data/fill_missing.vcf.gz", &output)
+            .compression_level(level)
+            .run()
+            .unwrap();
+        let summary = read_bgen_summary(&output).unwrap();
+        assert_eq!((summary.compression, summary.layout_id), (1, 2));
+        let bytes = fs::read(&output).unwrap();
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        // identifiers of 1:100:A:G: id, rsid and chr, position, 2 alleles
+        let block_start = u32_at(0) as usize + 4 + 2 + 9 + 2 + 9 + 2 + 1 + 4 + 2 + (4 + 1) * 2;
+        let block_len = u32_at(block_start) as usize;
+        let mut decoded = vec![];
+        ZlibDecoder::new(&bytes[block_start + 8..block_start + 4 + block_len])
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded.len(), u32_at(block_start + 4) as usize);
+        blocks.push(decoded);
+    }
+    // both levels hold the same probabilities
+    assert_eq!(blocks[0], blocks[1]);
+    assert_eq!(
+        blocks[0][..14],
+        [4, 0, 0, 0, 2, 0, 2, 2, 2, 2, 2, 130, 0, 8]
+    );
+
+    let output = temp_path("zlib_compression_level_zstd.bgen");
+    let result = Converter::new("data/fill_missing.vcf.gz", &output)
+        .compression(BlockCompression::Zstd)
+        .compression_level(1)
+        .run();
+    assert!(result.is_err());
+}
+
+#[test]
+fn bgi_index() {
+    let output = temp_path("bgi_index.bgen");
+    Converter::new("data/fill_missing.vcf.gz", &output)
+        .write_index(true)
+        .run()
+        .unwrap();
+    let bytes = fs::read(&output).unwrap();
+    let connection = rusqlite::Connection::open(format!("{}.bgi", output)).unwrap();
+    let first: (String, u32, String, u32, String, Option<String>) = connection
+        .query_row(
+            "SELECT chromosome, position, rsid, number_of_alleles, allele1, allele2 FROM Variant \
+             ORDER BY file_start_position",
+            [],
+            |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                    row.get(5)?,
+                ))
+            },
+        )
+        .unwrap();
+    assert_eq!(
+        (&first.0[..], first.1, &first.2[..], first.3),
+        ("1", 100, "1:100:A:G", 2)
+    );
+    assert_eq!((&first.4[..], first.5.as_deref()), ("A", Some("G")));
+    let mut statement = connection
+        .prepare(
+            "SELECT file_start_position, size_in_bytes FROM Variant ORDER BY file_start_position",
+        )
+        .unwrap();
+    let rows: Vec<(u64, u64)> = statement
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(rows.len(), 2);
+    // variant blocks follow the header and each other up to the end of the file
+    let u32_at = |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+    assert_eq!(rows[0].0, u32_at(0) as u64 + 4);
+    assert_eq!(rows[1].0, rows[0].0 + rows[0].1);
+    assert_eq!(rows[1].0 + rows[1].1, bytes.len() as u64);
+
+    let (file_size, first_bytes): (u64, Vec<u8>) = connection
+        .query_row(
+            "SELECT file_size, first_1000_bytes FROM Metadata",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .unwrap();
+    assert_eq!(file_size, bytes.len() as u64);
+    assert_eq!(first_bytes, bytes[..bytes.len().min(1000)]);
+}
+
+#[test]
+fn split_by_chromosome() {
+    let template = temp_path("split_by_chromosome_chr{chr}.bgen");
+    let metrics = Converter::new("data/ploidy_map.vcf.gz", &template)
+        .split_by_chr(true)
+        .run()
+        .unwrap();
+    assert_eq!(metrics.variants_processed, 3);
+    for (chr, variant_num) in [("1", 1), ("X", 2)] {
+        let summary = read_bgen_summary(&template.replace("{chr}", chr)).unwrap();
+        assert_eq!(summary.variant_num, variant_num);
+        assert_eq!(summary.sample_num, 3);
+    }
+
+    // the output must say where the chromosome goes
+    let result = Converter::new(
+        "data/ploidy_map.vcf.gz",
+        &temp_path("split_by_chromosome.bgen"),
+    )
+    .split_by_chr(true)
+    .run();
+    assert!(result.is_err());
+}
+
+#[test]
+fn sharded_outputs() {
+    for (shard_mode, shard_variants) in
+        [(ShardMode::RoundRobin, [2, 1]), (ShardMode::Block, [2, 1])]
+    {
+        let output = temp_path(&format!("sharded_outputs_{:?}.bgen", shard_mode));
+        Converter::new("data/ploidy_map.vcf.gz", &output)
+            .shards(2, shard_mode)
+            .run()
+            .unwrap();
+        for (shard, variant_num) in shard_variants.into_iter().enumerate() {
+            let path = output.replace(".bgen", &format!(".shard{}.bgen", shard));
+            assert_eq!(read_bgen_summary(&path).unwrap().variant_num, variant_num);
+        }
+        let manifest = fs::read_to_string(output.replace(".bgen", ".shards.tsv")).unwrap();
+        let shards: Vec<&str> = manifest
+            .lines()
+            .skip(1)
+            .map(|line| line.split('\t').nth(3).unwrap())
+            .collect();
+        let expected = match shard_mode {
+            ShardMode::RoundRobin => ["0", "1", "0"],
+            ShardMode::Block => ["0", "0", "1"],
+        };
+        assert_eq!(shards, expected);
+        assert!(!fs::exists(output.replace(".bgen", ".shards.tsv.tmp")).unwrap());
+    }
+
+    // the manifest is not overwritten either, even with the shards themselves gone
+    let input = "data/ploidy_map.vcf.gz";
+    let output = temp_path("sharded_outputs_Block.bgen");
+    for shard in 0..2 {
+        fs::remove_file(output.replace(".bgen", &format!(".shard{}.bgen", shard))).unwrap();
+    }
+    let (variant_num, number_geno_line) = count_variants(input).unwrap();
+    let options = ConversionOptions {
+        shards: Some(2),
+        shard_mode: ShardMode::Block,
+        overwrite: false,
+        ..Default::default()
+    };
+    let result = build_pipeline(input, &output, number_geno_line, &options)
+        .and_then(|mut pipeline| pipeline.run(variant_num, number_geno_line));
+    assert!(matches!(result, Err(VcfError::Validation(_))));
+}
+
+#[test]
+fn output_path_placeholders() {
+    assert_eq!(input_stem("data/chr22.vcf.gz"), "chr22");
+    assert_eq!(input_stem("cohort.chr1.bcf"), "cohort.chr1");
+    let input = "data/ploidy_map.vcf.gz";
+
+    let template = temp_path("placeholders_{input_stem}_{shard}.bgen");
+    Converter::new(input, &template)
+        .shards(2, ShardMode::RoundRobin)
+        .run()
+        .unwrap();
+    for (shard, variant_num) in [(0, 2), (1, 1)] {
+        let path = temp_path(&format!("placeholders_ploidy_map_{}.bgen", shard));
+        assert_eq!(read_bgen_summary(&path).unwrap().variant_num, variant_num);
+    }
+    let manifest = fs::read_to_string(temp_path("placeholders_ploidy_map.shards.tsv")).unwrap();
+    assert_eq!(manifest.lines().count(), 1 + 3);
+
+    let template = temp_path("placeholders_{input_stem}_chr{chr}.bgen");
+    Converter::new(input, &template)
+        .split_by_chr(true)
+        .run()
+        .unwrap();
+    let path = temp_path("placeholders_ploidy_map_chrX.bgen");
+    assert_eq!(read_bgen_summary(&path).unwrap().variant_num, 2);
+
+    // placeholders no output replaces, which would otherwise end up in file names
+    for template in ["placeholders_{chr}.bgen", "placeholders_{sample}.bgen"] {
+        let result = Converter::new(input, &temp_path(template)).run();
+        assert!(matches!(result, Err(VcfError::Validation(_))));
+    }
+}
+
+#[test]
+fn no_sample_ids() {
+    let output = temp_path("no_sample_ids.bgen");
+    Converter::new("data/fill_missing.vcf.gz", &output)
+        .no_sample_ids()
+        .run()
+        .unwrap();
+    let summary = read_bgen_summary(&output).unwrap();
+    assert_eq!((summary.sample_num, summary.variant_num), (4, 2));
+    assert!(summary.samples.is_none());
+    // variant blocks start right after the 20-byte header, whose flags say there are no ids
+    let bytes = fs::read(&output).unwrap();
+    assert_eq!(bytes[..4], 20u32.to_le_bytes());
+    let flags = u32::from_le_bytes(bytes[20..24].try_into().unwrap());
+    assert_eq!(flags >> 31, 0);
+}
+
+#[test]
+fn renamed_and_anonymized_samples() {
+    let rename_map = temp_path("renamed_samples.tsv");
+    fs::write(&rename_map, "S1\tP1\nS3\tP3\n").unwrap();
+    let output = temp_path("renamed_samples.bgen");
+    Converter::new("data/fill_missing.vcf.gz", &output)
+        .rename_samples(&rename_map)
+        .run()
+        .unwrap();
+    let samples = read_bgen_summary(&output).unwrap().samples.unwrap();
+    assert_eq!(samples, ["P1", "S2", "P3", "S4"]);
+
+    let reverse_map = temp_path("anonymized_samples.tsv");
+    let output = temp_path("anonymized_samples.bgen");
+    Converter::new("data/fill_missing.vcf.gz", &output)
+        .anonymize(&reverse_map)
+        .run()
+        .unwrap();
+    let samples = read_bgen_summary(&output).unwrap().samples.unwrap();
+    assert_eq!(samples, ["sample_1", "sample_2", "sample_3", "sample_4"]);
+    assert_eq!(
+        fs::read_to_string(&reverse_map).unwrap(),
+        "sample_1\tS1\nsample_2\tS2\nsample_3\tS3\nsample_4\tS4\n"
+    );
+
+    // two samples cannot end up with the same name
+    fs::write(&rename_map, "S1\tS2\n").unwrap();
+    let result = Converter::new("data/fill_missing.vcf.gz", &output)
+        .rename_samples(&rename_map)
+        .run();
+    assert!(matches!(result, Err(VcfError::Validation(_))));
+}
+
+#[test]
+fn pgen_output() {
+    let output = temp_path("pgen_output.pgen");
+    Converter::new("data/fill_missing.vcf.gz", &output)
+        .output_format(OutputFormat::Pgen)
+        .run()
+        .unwrap();
+    let prefix = output.strip_suffix(".pgen").unwrap();
+    let pgen = fs::read(&output).unwrap();
+    assert_eq!(
+        pgen,
+        [
+            0x6c,
+            0x1b,
+            0x02, // magic, fixed-width hard calls
+            2,
+            0,
+            0,
+            0,
+            4,
+            0,
+            0,
+            0,             // variants, samples
+            0b11_10_01_00, // 0/0, 0/1, 1/1 and missing, first sample in the lowest bits
+            0b11_10_01_00,
+        ]
+    );
+    assert_eq!(
+        fs::read_to_string(format!("{}.pvar", prefix)).unwrap(),
+        "#CHROM\tPOS\tID\tREF\tALT\n1\t100\t1:100:A:G\tA\tG\n1\t200\t1:200:C:T\tC\tT\n"
+    );
+    assert_eq!(
+        fs::read_to_string(format!("{}.psam", prefix)).unwrap(),
+        "#IID\nS1\nS2\nS3\nS4\n"
+    );
+}
+
+#[test]
+fn gen_output() {
+    let output = temp_path("gen_output.gen");
+    Converter::new("data/genotype_probabilities.vcf.gz", &output)
+        .output_format(OutputFormat::Gen)
+        .run()
+        .unwrap();
+    let gen = fs::read_to_string(&output).unwrap();
+    let first: Vec<&str> = gen.lines().next().unwrap().split(' ').collect();
+    assert_eq!(
+        [first[0], first[1], first[3], first[4], first[5]],
+        ["22", "22:100:A:G", "100", "A", "G"]
+    );
+    // GP of the first sample, 0.8,0.2,0
+    assert_eq!(first[6..9], ["0.8", "0.2", "0"]);
+    let sample_path = output.replace(".gen", ".sample");
+    let samples = read_sample_file(&sample_path).unwrap();
+    assert_eq!(samples.len(), (first.len() - 6) / 3);
+}
+
+#[test]
+fn variant_list_sidecar() {
+    let input = "data/multiallelic_1_var_3_alt_allele.vcf.gz";
+    let bim = temp_path("variant_list_sidecar.bim");
+    Converter::new(input, &temp_path("variant_list_sidecar.bgen"))
+        .variant_list(&bim)
+        .run()
+        .unwrap();
+    // one row per split variant, alt allele first
+    assert_eq!(
+        fs::read_to_string(&bim).unwrap(),
+        "22\t22:10516173:A:G\t0\t10516173\tG\tA\n\
+         22\t22:10516173:A:C\t0\t10516173\tC\tA\n\
+         22\t22:10516173:A:AC\t0\t10516173\tAC\tA\n"
+    );
+
+    let pvar = temp_path("variant_list_sidecar.pvar");
+    Converter::new(input, &temp_path("variant_list_sidecar_whole.bgen"))
+        .multiallelic(MultiallelicMode::Keep)
+        .variant_list(&pvar)
+        .run()
+        .unwrap();
+    let pvar = fs::read_to_string(&pvar).unwrap();
+    let mut lines = pvar.lines();
+    assert_eq!(lines.next(), Some("#CHROM\tPOS\tID\tREF\tALT"));
+    let fields: Vec<&str> = lines.next().unwrap().split('\t').collect();
+    assert_eq!((fields[3], fields[4]), ("A", "G,C,AC"));
+}
+
+#[test]
+fn output_checksums() {
+    let file_checksums = |path: &str| {
+        let bytes = fs::read(path).unwrap();
+        (
+            format!("{:x}", Md5::digest(&bytes)),
+            format!("{:x}", Sha256::digest(&bytes)),
+        )
+    };
+    let output = temp_path("output_checksums.bgen");
+    Converter::new("data/fill_missing.vcf.gz", &output)
+        .checksums(true)
+        .run()
+        .unwrap();
+    let (md5, sha256) = file_checksums(&output);
+    assert_eq!(
+        fs::read_to_string(format!("{}.md5", output)).unwrap(),
+        format!("{}  output_checksums.bgen\n", md5)
+    );
+    assert_eq!(
+        fs::read_to_string(format!("{}.sha256", output)).unwrap(),
+        format!("{}  output_checksums.bgen\n", sha256)
+    );
+
+    // the header and sample block are the same as without checksums
+    let plain = temp_path("output_checksums_plain.bgen");
+    Converter::new("data/fill_missing.vcf.gz", &plain)
+        .run()
+        .unwrap();
+    let (bytes, plain_bytes) = (fs::read(&output).unwrap(), fs::read(&plain).unwrap());
+    let data_start = u32::from_le_bytes(bytes[..4].try_into().unwrap()) as usize + 4;
+    assert_eq!(bytes[..data_start], plain_bytes[..data_start]);
+
+    // the counting pass hashes the input, so the provenance is in the header hashed while written
+    let with_provenance = temp_path("output_checksums_provenance.bgen");
+    Converter::new("data/fill_missing.vcf.gz", &with_provenance)
+        .checksums(true)
+        .provenance()
+        .run()
+        .unwrap();
+    let input_sha256 = format!(
+        "{:x}",
+        Sha256::digest(fs::read("data/fill_missing.vcf.gz").unwrap())
+    );
+    let scan = scan_variants(
+        "data/fill_missing.vcf.gz",
+        &ConversionOptions {
+            provenance: true,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert_eq!(scan.input_sha256.as_ref(), Some(&input_sha256));
+    let free_data = read_bgen_summary(&with_provenance).unwrap().free_data;
+    assert!(String::from_utf8(free_data)
+        .unwrap()
+        .contains(&format!("input_sha256\t{}\n", input_sha256)));
+    let (md5, _) = file_checksums(&with_provenance);
+    let checksum_line = fs::read_to_string(format!("{}.md5", with_provenance)).unwrap();
+    assert!(checksum_line.starts_with(&md5));
+
+    // files whose header is rewritten once complete are hashed again
+    let template = temp_path("output_checksums_chr{chr}.bgen");
+    Converter::new("data/ploidy_map.vcf.gz", &template)
+        .split_by_chr(true)
+        .checksums(true)
+        .run()
+        .unwrap();
+    for chr in ["1", "X"] {
+        let path = template.replace("{chr}", chr);
+        let (md5, _) = file_checksums(&path);
+        let checksum_line = fs::read_to_string(format!("{}.md5", path)).unwrap();
+        assert!(checksum_line.starts_with(&md5));
+    }
+}
+
+#[test]
+fn failed_conversion_leaves_no_output() {
+    let output = temp_path("failed_conversion.bgen");
+    let _ = fs::remove_file(&output);
+    // the missing genotypes of the first record fail strict phasing once the header is written
+    let result = Converter::new("data/fill_missing.vcf.gz", &output)
+        .reference_panel()
+        .run();
+    assert!(result.is_err());
+    assert!(!fs::exists(&output).unwrap());
+    assert!(!fs::exists(format!("{}.tmp", output)).unwrap());
+
+    Converter::new("data/fill_missing.vcf.gz", &output)
+        .run()
+        .unwrap();
+    assert!(fs::exists(&output).unwrap());
+    assert!(!fs::exists(format!("{}.tmp", output)).unwrap());
+}
+
+// Fails the conversion after the given number of variants, as an interruption would
+struct InterruptAfter(u32);
+
+impl VariantTransform for InterruptAfter {
+    fn name(&self) -> &str {
+        "interrupt_after"
+    }
+
+    fn apply(&mut self, variant: Variant) -> Result<Option<Variant>, VcfError> {
+        if self.0 == 0 {
+            return Err(VcfError::Validation(color_eyre::Report::msg("interrupted")));
+        }
+        self.0 -= 1;
+        Ok(Some(variant))
+    }
+}
+
+#[test]
+fn resume_interrupted_conversion() {
+    let input = "data/100_vars_chr22_HG.vcf.gz";
+    let (variant_num, number_geno_line) = count_variants(input).unwrap();
+    let expected = temp_path("resume_expected.bgen");
+    Converter::new(input, &expected)
+        .checkpoint_every(10)
+        .checksums(true)
+        .run()
+        .unwrap();
+    assert!(!fs::exists(format!("{}.ckpt", expected)).unwrap());
+
+    let output = temp_path("resume_interrupted.bgen");
+    let mut options = ConversionOptions {
+        checkpoint_every: Some(10),
+        checksums: true,
+        ..Default::default()
+    };
+    let mut pipeline = build_pipeline(input, &output, number_geno_line, &options)
+        .unwrap()
+        .transform(Box::new(InterruptAfter(25)));
+    assert!(pipeline.run(variant_num, number_geno_line).is_err());
+    drop(pipeline);
+    assert!(fs::exists(format!("{}.tmp", output)).unwrap());
+    let checkpoint = fs::read_to_string(format!("{}.ckpt", output)).unwrap();
+    assert!(checkpoint.contains("variants_written\t20\n"));
+
+    options.resume = true;
+    let mut pipeline = build_pipeline(input, &output, number_geno_line, &options).unwrap();
+    let metrics = pipeline.run(variant_num, number_geno_line).unwrap();
+    assert_eq!(metrics.variants_processed, variant_num as u64);
+    assert_eq!(fs::read(&output).unwrap(), fs::read(&expected).unwrap());
+    let checksum = |path: &str| {
+        let line = fs::read_to_string(format!("{}.sha256", path)).unwrap();
+        line.split_whitespace().next().unwrap().to_string()
+    };
+    assert_eq!(checksum(&output), checksum(&expected));
+    assert!(!fs::exists(format!("{}.ckpt", output)).unwrap());
+
+    // without a checkpoint there is nothing to resume
+    assert!(Converter::new(input, &output)
+        .checkpoint_every(10)
+        .resume()
+        .run()
+        .is_err());
+}
+
+#[test]
+fn tmp_dir_holds_partial_outputs() {
+    let input = "data/100_vars_chr22_HG.vcf.gz";
+    let (variant_num, number_geno_line) = count_variants(input).unwrap();
+    let tmp_dir = temp_path("tmp_dir_spool");
+    fs::create_dir_all(&tmp_dir).unwrap();
+    let output = temp_path("tmp_dir_output.bgen");
+    let spooled = format!("{}/tmp_dir_output.bgen.tmp", tmp_dir);
+    let mut options = ConversionOptions {
+        tmp_dir: Some(tmp_dir.clone()),
+        checkpoint_every: Some(10),
+        ..Default::default()
+    };
+    let mut pipeline = build_pipeline(input, &output, number_geno_line, &options)
+        .unwrap()
+        .transform(Box::new(InterruptAfter(25)));
+    assert!(pipeline.run(variant_num, number_geno_line).is_err());
+    drop(pipeline);
+    // the partial bgen kept to be resumed and its checkpoint are in the temporary directory, not
+    // next to the output
+    assert!(fs::exists(&spooled).unwrap());
+    assert!(!fs::exists(format!("{}.tmp", output)).unwrap());
+    let checkpoint = format!("{}/tmp_dir_output.bgen.ckpt", tmp_dir);
+    assert!(fs::exists(&checkpoint).unwrap());
+    assert!(!fs::exists(format!("{}.ckpt", output)).unwrap());
+
+    options.resume = true;
+    let mut pipeline = build_pipeline(input, &output, number_geno_line, &options).unwrap();
+    pipeline.run(variant_num, number_geno_line).unwrap();
+    assert_eq!(read_bgen_summary(&output).unwrap().variant_num, variant_num);
+    assert!(!fs::exists(&spooled).unwrap());
+    assert!(!fs::exists(&checkpoint).unwrap());
+
+    // a failed conversion leaves nothing in the temporary directory
+    let result = Converter::new("data/fill_missing.vcf.gz", &output)
+        .reference_panel()
+        .tmp_dir(&tmp_dir)
+        .run();
+    assert!(result.is_err());
+    assert_eq!(fs::read_dir(&tmp_dir).unwrap().count(), 0);
+
+    let result = Converter::new(input, &output)
+        .tmp_dir(&temp_path("tmp_dir_missing"))
+        .run();
+    assert!(matches!(result, Err(VcfError::Validation(_))));
+}
+
+#[test]
+fn adaptive_num_bits() {
+    let convert = |input: &str, name: &str, num_bits: Option<u8>| {
+        let output = temp_path(name);
+        let converter = Converter::new(input, &output).compression(BlockCompression::None);
+        match num_bits {
+            Some(num_bits) => converter.num_bits(num_bits),
+            None => converter.adaptive_num_bits(),
+        }
+        .run()
+        .unwrap();
+        fs::read(output).unwrap()
+    };
+    // hard calls only need one bit
+    let input = "data/100_vars_chr22_HG.vcf.gz";
+    let adaptive = convert(input, "adaptive_hard_calls.bgen", None);
+    assert_eq!(
+        adaptive,
+        convert(input, "adaptive_hard_calls_1.bgen", Some(1))
+    );
+
+    // GP of tenths fit in 4 bits, halves need all 16
+    let input = "data/genotype_probabilities.vcf.gz";
+    let adaptive = convert(input, "adaptive_probabilities.bgen", None).len();
+    assert!(adaptive < convert(input, "adaptive_probabilities_16.bgen", Some(16)).len());
+    assert!(adaptive > convert(input, "adaptive_probabilities_1.bgen", Some(1)).len());
+
+    let output = temp_path("adaptive_layout1.bgen");
+    assert!(Converter::new(input, &output)
+        .adaptive_num_bits()
+        .layout(1)
+        .run()
+        .is_err());
+}
+
+#[test]
+fn num_bits_out_of_range() {
+    let output = temp_path("num_bits_out_of_range.bgen");
+    for num_bits in [0, 33, 64] {
+        let result = Converter::new("data/100_vars_chr22_HG.vcf.gz", &output)
+            .num_bits(num_bits)
+            .run();
+        assert!(matches!(result, Err(VcfError::Validation(report))
+            if report.to_string() == format!("Probabilities are stored with 1 to 32 bits, not {}", num_bits)));
+        // rejected with the other invalid arguments
+        let status = std::process::Command::new(env!("CARGO_BIN_EXE_vcf_to_bgen"))
+            .args([
+                "-i",
+                "data/100_vars_chr22_HG.vcf.gz",
+                "-o",
+                &output,
+                "--quiet",
+            ])
+            .args(["--num-bits", &num_bits.to_string()])
+            .output()
+            .unwrap()
+            .status;
+        assert_eq!(status.code(), Some(2));
+    }
+}
+
+#[test]
+fn num_bits_for_max_prob_error() {
+    assert_eq!(num_bits_for_error(1e-4).unwrap(), 14);
+    assert_eq!(num_bits_for_error(0.5).unwrap(), 2);
+    assert!(num_bits_for_error(1e-12).is_err());
+
+    let output = temp_path("max_prob_error.bgen");
+    let metrics = Converter::new("data/genotype_probabilities.vcf.gz", &output)
+        .max_prob_error(1e-4)
+        .run()
+        .unwrap();
+    let achieved = metrics.max_prob_error.unwrap();
+    assert!(achieved > 0.0 && achieved <= 1e-4, "{}", achieved);
+
+    assert!(
+        Converter::new("data/genotype_probabilities.vcf.gz", &output)
+            .max_prob_error(1e-4)
+            .layout(1)
+            .run()
+            .is_err()
+    );
+}
+
+#[test]
+fn deterministic_outputs_are_byte_identical() {
+    let output = temp_path("deterministic.bgen");
+    let manifest = temp_path("deterministic.manifest.tsv");
+    let convert = || {
+        Converter::new("data/100_vars_chr22_HG.vcf.gz", &output)
+            .deterministic()
+            .write_index(true)
+            .manifest(&manifest)
+            .run()
+            .unwrap();
+        [output.clone(), format!("{}.bgi", output), manifest.clone()]
+            .map(|path| fs::read(path).unwrap())
+    };
+    let first = convert();
+    // index timestamps have a one second resolution
+    std::thread::sleep(std::time::Duration::from_millis(1100));
+    assert_eq!(first, convert());
+}
+
+#[test]
+fn single_pass_conversion() {
+    let input = "data/multiallelic_1_var_3_alt_allele.vcf.gz";
+    let two_passes = temp_path("two_passes.bgen");
+    let single_pass = temp_path("single_pass.bgen");
+    let convert = |output: &str, single: bool| {
+        let converter = Converter::new(input, output)
+            .compression(BlockCompression::None)
+            .variant_list(&format!("{}.pvar", output));
+        match single {
+            true => converter.single_pass(),
+            false => converter,
+        }
+        .run()
+        .unwrap()
+    };
+    let metrics = convert(&single_pass, true);
+    assert_eq!(metrics.phase_durations[0].0, "convert");
+    convert(&two_passes, false);
+    assert_eq!(
+        fs::read(&single_pass).unwrap(),
+        fs::read(&two_passes).unwrap()
+    );
+    let summary = read_bgen_summary(&single_pass).unwrap();
+    assert_eq!(summary.variant_num, metrics.variants_processed as u32);
+
+    // shards are sized from the count, so the input is read twice
+    let metrics = Converter::new(input, &temp_path("single_pass_sharded.bgen"))
+        .single_pass()
+        .shards(2, ShardMode::Block)
+        .run()
+        .unwrap();
+    assert_eq!(metrics.phase_durations[0].0, "count");
+
+    // chromosomes are checked while converting
+    let output = temp_path("single_pass_interleaved.bgen");
+    let result = Converter::new("data/interleaved_chromosomes.vcf.gz", &output)
+        .single_pass()
+        .run();
+    assert!(matches!(result, Err(VcfError::Validation(_))));
+    assert!(!fs::exists(&output).unwrap());
+}
+
+#[test]
+fn variant_metadata_table() {
+    let input = "data/multiallelic_1_var_3_alt_allele.vcf.gz";
+    let tsv = temp_path("variant_metadata.tsv");
+    let json = temp_path("variant_metadata.json");
+    for metadata in [&tsv, &json] {
+        Converter::new(input, &temp_path("variant_metadata.bgen"))
+            .variant_metadata(metadata)
+            .run()
+            .unwrap();
+    }
+    let content = fs::read_to_string(&tsv).unwrap();
+    let lines: Vec<&str> = content.lines().collect();
+    assert_eq!(
+        lines[0],
+        "chr\tpos\tinput_id\tvariant_id\tref\talt\talt_frequency\tmissing_rate\tline"
+    );
+    // each split alt keeps the id and line of its record, samples carrying other alts being missing
+    assert_eq!(
+        lines[1],
+        "22\t10516173\t.\t22:10516173:A:G\tA\tG\t0.166667\t0.4\t21"
+    );
+    assert_eq!(
+        lines[3],
+        "22\t10516173\t.\t22:10516173:A:AC\tA\tAC\t0.166667\t0.4\t21"
+    );
+    assert_eq!(lines.len(), 4);
+
+    let content = fs::read_to_string(&json).unwrap();
+    assert!(content.starts_with("[\n  {\"chr\": \"22\", \"pos\": 10516173, \"input_id\": \".\""));
+    assert!(content.contains("\"alt_frequency\": 0.166667, \"missing_rate\": 0.4, \"line\": 21}"));
+    assert!(content.ends_with("}\n]\n"));
+    assert_eq!(content.matches("},\n").count(), 2);
+}
+
+#[test]
+fn provenance_in_header_free_data() {
+    let input = "data/fill_missing.vcf.gz";
+    let output = temp_path("provenance.bgen");
+    Converter::new(input, &output)
+        .layout(1)
+        .provenance()
+        .deterministic()
+        .run()
+        .unwrap();
+    let summary = read_bgen_summary(&output).unwrap();
+    let provenance = String::from_utf8(summary.free_data).unwrap();
+    let input_sha256 = format!("{:x}", Sha256::digest(fs::read(input).unwrap()));
+    assert_eq!(
+        provenance,
+        format!(
+            "tool\tvcf_to_bgen {}\ncommand\tNA\ninput\t{}\ninput_sha256\t{}\n",
+            env!("CARGO_PKG_VERSION"),
+            input,
+            input_sha256
+        )
+    );
+    // variant blocks follow the longer header
+    let bytes = fs::read(&output).unwrap();
+    let header_size = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+    assert_eq!(header_size as usize, 20 + provenance.len());
+    let ids: Vec<String> = read_layout1_variants(&output)
+        .unwrap()
+        .into_iter()
+        .map(|variant| variant.variants_id)
+        .collect();
+    assert_eq!(ids, ["1:100:A:G", "1:200:C:T"]);
+
+    // hashed as the input is read and written over the header at the end, even when the
+    // conversion stops before the end of the input
+    let single_pass_output = temp_path("provenance_single_pass.bgen");
+    Converter::new(input, &single_pass_output)
+        .layout(1)
+        .provenance()
+        .deterministic()
+        .single_pass()
+        .max_variants(1)
+        .run()
+        .unwrap();
+    let summary = read_bgen_summary(&single_pass_output).unwrap();
+    assert_eq!(String::from_utf8(summary.free_data).unwrap(), provenance);
+    assert_eq!(summary.variant_num, 1);
+}
+
+#[test]
+fn regions_filter_records_and_count() {
+    let output = temp_path("regions.bgen");
+    let variant_list = temp_path("regions.pvar");
+    Converter::new("data/100_vars_chr22_HG.vcf.gz", &output)
+        .regions("22:10530699-10561503,21")
+        .variant_list(&variant_list)
+        .run()
+        .unwrap();
+    // the header count matches the variants written
+    assert_eq!(read_bgen_summary(&output).unwrap().variant_num, 11);
+    let positions: Vec<String> = fs::read_to_string(&variant_list)
+        .unwrap()
+        .lines()
+        .skip(1)
+        .map(|line| line.split('\t').nth(1).unwrap().to_string())
+        .collect();
+    assert_eq!(positions.len(), 11);
+    assert_eq!(positions.first().unwrap(), "10530699");
+    assert_eq!(positions.last().unwrap(), "10561503");
+
+    // whole chromosomes and single positions, in one read
+    let output = temp_path("regions_single_pass.bgen");
+    Converter::new("data/ploidy_map.vcf.gz", &output)
+        .regions("X,1:100")
+        .single_pass()
+        .run()
+        .unwrap();
+    assert_eq!(read_bgen_summary(&output).unwrap().variant_num, 3);
+
+    for regions in ["22:200-100", "22:a-b", ":1-2"] {
+        let result = Converter::new("data/100_vars_chr22_HG.vcf.gz", &output)
+            .regions(regions)
+            .run();
+        assert!(matches!(result, Err(VcfError::Validation(_))));
+    }
+}
+
+#[test]
+fn skipped_report_next_to_output() {
+    let output = temp_path("skipped_report_default.bgen");
+    let result = std::process::Command::new(env!("CARGO_BIN_EXE_vcf_to_bgen"))
+        .args([
+            "-i",
+            "data/100_vars_chr22_HG.vcf.gz",
+            "-o",
+            &output,
+            "--force",
+        ])
+        .args(["--quiet", "--regions", "22:10530699-10561503"])
+        .output()
+        .unwrap();
+    assert!(result.status.success());
+    // records outside the regions are listed too, with their line of the input
+    let report_path = format!("{}.skipped.tsv", output);
+    let report = fs::read_to_string(&report_path).unwrap();
+    let rows: Vec<&str> = report.lines().collect();
+    assert_eq!(rows.len(), 1 + 89);
+    assert_eq!(rows[0], "line\tchr\tpos\tref\talt\treason");
+    assert_eq!(rows[1], "21\t22\t10516173\tA\tG\tregion");
+    assert!(rows[1..].iter().all(|row| row.ends_with("\tregion")));
+
+    fs::remove_file(&report_path).unwrap();
+    let result = std::process::Command::new(env!("CARGO_BIN_EXE_vcf_to_bgen"))
+        .args([
+            "-i",
+            "data/100_vars_chr22_HG.vcf.gz",
+            "-o",
+            &output,
+            "--force",
+        ])
+        .args(["--quiet", "--no-skipped-report"])
+        .output()
+        .unwrap();
+    assert!(result.status.success());
+    assert!(!std::path::Path::new(&report_path).exists());
+}
+
+#[test]
+fn regions_file_bed_intervals() {
+    let bed = temp_path("targets.bed");
+    // 0-based starts: the first interval holds 10530699 to 10530759, overlapping the second
+    fs::write(
+        &bed,
+        "track name=targets\n22\t10530698\t10530754\n22\t10530753\t10530759\textra\n\n21\t0\t100\n22\t10561502\t10561503\n",
+    )
+    .unwrap();
+    let output = temp_path("regions_file.bgen");
+    let variant_list = temp_path("regions_file.pvar");
+    Converter::new("data/100_vars_chr22_HG.vcf.gz", &output)
+        .regions_file(&bed)
+        .variant_list(&variant_list)
+        .run()
+        .unwrap();
+    assert_eq!(read_bgen_summary(&output).unwrap().variant_num, 4);
+    let positions: Vec<String> = fs::read_to_string(&variant_list)
+        .unwrap()
+        .lines()
+        .skip(1)
+        .map(|line| line.split('\t').nth(1).unwrap().to_string())
+        .collect();
+    assert_eq!(positions, ["10530699", "10530754", "10530759", "10561503"]);
+
+    fs::write(&bed, "22\t100\n").unwrap();
+    let result = Converter::new("data/100_vars_chr22_HG.vcf.gz", &output)
+        .regions_file(&bed)
+        .run();
+    assert!(matches!(result, Err(VcfError::Validation(_))));
+    let result = Converter::new("data/100_vars_chr22_HG.vcf.gz", &output)
+        .regions_file(&bed)
+        .regions("22")
+        .run();
+    assert!(matches!(result, Err(VcfError::Validation(_))));
+}
+
+#[test]
+fn keep_and_remove_samples() {
+    let input = "data/fill_missing.vcf.gz";
+    let keep = temp_path("keep_samples.txt");
+    // plain IDs or FID IID rows, written in input order
+    fs::write(&keep, "S4\nfam1 S2\n").unwrap();
+    let output = temp_path("keep_samples.bgen");
+    Converter::new(input, &output)
+        .layout(1)
+        .keep_samples(&keep)
+        .run()
+        .unwrap();
+    let summary = read_bgen_summary(&output).unwrap();
+    assert_eq!(summary.sample_num, 2);
+    assert_eq!(summary.samples.unwrap(), ["S2", "S4"]);
+    for variant in read_layout1_variants(&output).unwrap() {
+        assert_eq!(variant.probabilities, vec![Some([0.0, 1.0, 0.0]), None]);
+    }
+
+    let remove = temp_path("remove_samples.txt");
+    fs::write(&remove, "S1\nnot_in_input\n").unwrap();
+    let output = temp_path("remove_samples.bgen");
+    Converter::new(input, &output)
+        .keep_samples(&keep)
+        .remove_samples(&remove)
+        .run()
+        .unwrap();
+    let samples = read_bgen_summary(&output).unwrap().samples.unwrap();
+    assert_eq!(samples, ["S2", "S4"]);
+    Converter::new(input, &output)
+        .remove_samples(&remove)
+        .run()
+        .unwrap();
+    let samples = read_bgen_summary(&output).unwrap().samples.unwrap();
+    assert_eq!(samples, ["S2", "S3", "S4"]);
+
+    // every sample to keep must be in the input, and some sample must be left
+    let result = Converter::new(input, &output).keep_samples(&remove).run();
+    assert!(matches!(result, Err(VcfError::Validation(_))));
+    fs::write(&remove, "S1\nS2\nS3\nS4\n").unwrap();
+    let result = Converter::new(input, &output).remove_samples(&remove).run();
+    assert!(matches!(result, Err(VcfError::Validation(_))));
+}
+
+#[test]
+fn samples_in_sample_file_order() {
+    let input = "data/fill_missing.vcf.gz";
+    let sample_file = temp_path("sample_order.sample");
+    fs::write(
+        &sample_file,
+        "ID_1 ID_2 missing\n0 0 0\nS4 S4 0\nS1 S1 0\nS3 S3 0\nS2 S2 0\n",
+    )
+    .unwrap();
+    let output = temp_path("sample_order.bgen");
+    Converter::new(input, &output)
+        .layout(1)
+        .sample_order(&sample_file)
+        .run()
+        .unwrap();
+    check_pair(&sample_file, &output).unwrap();
+    for variant in read_layout1_variants(&output).unwrap() {
+        assert_eq!(
+            variant.probabilities,
+            vec![
+                None,
+                Some([1.0, 0.0, 0.0]),
+                Some([0.0, 0.0, 1.0]),
+                Some([0.0, 1.0, 0.0])
+            ]
+        );
+    }
+
+    // every sample converted must be in the .sample file, and every sample of it converted
+    let subset = temp_path("sample_order_subset.sample");
+    fs::write(&subset, "ID_1 ID_2 missing\n0 0 0\nS3 S3 0\nS1 S1 0\n").unwrap();
+    let result = Converter::new(input, &output).sample_order(&subset).run();
+    assert!(matches!(result, Err(VcfError::Validation(_))));
+    let remove = temp_path("sample_order_remove.txt");
+    fs::write(&remove, "S2\nS4\n").unwrap();
+    Converter::new(input, &output)
+        .remove_samples(&remove)
+        .sample_order(&subset)
+        .run()
+        .unwrap();
+    check_pair(&subset, &output).unwrap();
+    let result = Converter::new(input, &output)
+        .remove_samples(&remove)
+        .sample_order(&sample_file)
+        .run();
+    assert!(matches!(result, Err(VcfError::Validation(_))));
+}
+
+#[test]
+fn extract_and_exclude_variant_ids() {
+    let input = "data/variant_ids.vcf.gz";
+    let written_ids = |list: &str, extract: bool, single_pass: bool| {
+        let output = temp_path("variant_ids.bgen");
+        let variant_list = temp_path("variant_ids.pvar");
+        let mut converter = Converter::new(input, &output).variant_list(&variant_list);
+        converter = match extract {
+            true => converter.extract(list),
+            false => converter.exclude(list),
+        };
+        if single_pass {
+            converter = converter.single_pass();
+        }
+        converter.run().unwrap();
+        let ids: Vec<String> = fs::read_to_string(&variant_list)
+            .unwrap()
+            .lines()
+            .skip(1)
+            .map(|line| line.split('\t').nth(2).unwrap().to_string())
+            .collect();
+        // the header declares the variants written
+        assert_eq!(
+            read_bgen_summary(&output).unwrap().variant_num as usize,
+            ids.len()
+        );
+        ids
+    };
+    // input IDs select whole records, chr:pos:ref:alt one alt of a split record
+    let list = temp_path("extract_ids.txt");
+    fs::write(&list, "rs50\n1:200:C:G extra columns\n.\n").unwrap();
+    for single_pass in [false, true] {
+        assert_eq!(
+            written_ids(&list, true, single_pass),
+            ["1:200:C:G", "2:50:T:C"]
+        );
+    }
+    fs::write(&list, "rs200\n1:300:G:A\n").unwrap();
+    assert_eq!(written_ids(&list, false, false), ["1:100:A:G", "2:50:T:C"]);
+    fs::write(&list, "1:200:C:T\n").unwrap();
+    assert_eq!(
+        written_ids(&list, false, false),
+        ["1:100:A:G", "1:200:C:G", "1:300:G:A", "2:50:T:C"]
+    );
+}
+
+#[test]
+fn minor_allele_count_filter() {
+    // expected minor allele counts of 1.2, 0.6 and 0.8
+    let output = temp_path("min_mac.bgen");
+    let skipped = temp_path("min_mac_skipped.tsv");
+    for single_pass in [false, true] {
+        let mut converter = Converter::new("data/genotype_probabilities.vcf.gz", &output)
+            .min_mac(1)
+            .skipped_report(&skipped);
+        if single_pass {
+            converter = converter.single_pass();
+        }
+        converter.run().unwrap();
+        assert_eq!(read_bgen_summary(&output).unwrap().variant_num, 1);
+        assert_eq!(
+            fs::read_to_string(&skipped).unwrap(),
+            "line\tchr\tpos\tref\talt\treason\n6\t22\t200\tA\tG\tmac\n6\t22\t200\tA\tT\tmac\n"
+        );
+    }
+
+    // the counting pass keeps the variants written
+    let input = "data/100_vars_chr22_HG.vcf.gz";
+    let allele_counts = temp_path("min_mac_allele_counts.tsv");
+    Converter::new(input, &output)
+        .allele_counts(&allele_counts)
+        .run()
+        .unwrap();
+    let expected = fs::read_to_string(&allele_counts)
+        .unwrap()
+        .lines()
+        .skip(1)
+        .filter(|line| {
+            let fields: Vec<f64> = line
+                .split('\t')
+                .skip(5)
+                .take(2)
+                .map(|f| f.parse().unwrap())
+                .collect();
+            fields[0].min(fields[1] - fields[0]) >= 5.0
+        })
+        .count();
+    assert!(expected > 0 && expected < 100);
+    Converter::new(input, &output).min_mac(5).run().unwrap();
+    assert_eq!(
+        read_bgen_summary(&output).unwrap().variant_num as usize,
+        expected
+    );
+}
+
+#[test]
+fn pass_only_skips_filtered_records() {
+    let input = "data/filters.vcf.gz";
+    let output = temp_path("pass_only.bgen");
+    let options = ConversionOptions {
+        pass_only: true,
+        ..Default::default()
+    };
+    // PASS and unfiltered records are kept, in the count too
+    let scan = scan_variants(input, &options).unwrap();
+    assert_eq!((scan.variant_num, scan.number_geno_line), (2, 5));
+    let metrics = convert_to_bgen(input, &output, scan.variant_num, 5, &options).unwrap();
+    assert_eq!(metrics.variants_processed, 2);
+    assert_eq!(
+        metrics.filters_skipped,
+        [("LowQual".to_string(), 2), ("q10;s50".to_string(), 1)]
+    );
+    let metrics_file = temp_path("pass_only.prom");
+    metrics.write_textfile(&metrics_file).unwrap();
+    let content = fs::read_to_string(&metrics_file).unwrap();
+    assert!(content.contains("vcf_to_bgen_filter_skipped_total{filter=\"LowQual\"} 2\n"));
+
+    // every record is converted by default
+    Converter::new(input, &output).run().unwrap();
+    assert_eq!(read_bgen_summary(&output).unwrap().variant_num, 5);
+}
+
+#[test]
+fn drop_all_missing_variants() {
+    // both samples of 1:200 C T,G carry G, so the split variant of T is all missing
+    let input = "data/variant_ids.vcf.gz";
+    let output = temp_path("drop_all_missing.bgen");
+    let skipped = temp_path("drop_all_missing_skipped.tsv");
+    for single_pass in [false, true] {
+        let mut converter = Converter::new(input, &output)
+            .drop_all_missing()
+            .skipped_report(&skipped);
+        if single_pass {
+            converter = converter.single_pass();
+        }
+        converter.run().unwrap();
+        assert_eq!(read_bgen_summary(&output).unwrap().variant_num, 4);
+        assert_eq!(
+            fs::read_to_string(&skipped).unwrap(),
+            "line\tchr\tpos\tref\talt\treason\n7\t1\t200\tC\tT\tall missing\n"
+        );
+    }
+
+    Converter::new(input, &output).run().unwrap();
+    assert_eq!(read_bgen_summary(&output).unwrap().variant_num, 5);
+}
+
+#[test]
+fn thin_variants() {
+    let input = "data/100_vars_chr22_HG.vcf.gz";
+    let output = temp_path("thin.bgen");
+    let variant_list = temp_path("thin.pvar");
+    let positions = |configure: fn(Converter) -> Converter| {
+        configure(Converter::new(input, &output).variant_list(&variant_list))
+            .run()
+            .unwrap();
+        let positions: Vec<u32> = fs::read_to_string(&variant_list)
+            .unwrap()
+            .lines()
+            .skip(1)
+            .map(|line| line.split('\t').nth(1).unwrap().parse().unwrap())
+            .collect();
+        // the header count matches the variants written
+        assert_eq!(
+            read_bgen_summary(&output).unwrap().variant_num as usize,
+            positions.len()
+        );
+        positions
+    };
+    let all = positions(|converter| converter);
+    let every_tenth: Vec<u32> = all.iter().step_by(10).copied().collect();
+    assert_eq!(positions(|converter| converter.thin(10)), every_tenth);
+    assert_eq!(
+        positions(|converter| converter.thin(10).single_pass()),
+        every_tenth
+    );
+
+    let mut spaced: Vec<u32> = vec![];
+    for &pos in &all {
+        if spaced.last().is_none_or(|&last| pos - last >= 5000) {
+            spaced.push(pos);
+        }
+    }
+    assert!(spaced.len() < all.len());
+    assert_eq!(positions(|converter| converter.thin_bp(5000)), spaced);
+    // spacing is measured from the variants kept
+    let kept = positions(|converter| converter.thin_bp(5000).thin(2));
+    assert!(kept.windows(2).all(|pair| pair[1] - pair[0] >= 5000));
+    assert!(kept.len() < spaced.len());
+
+    let invalid = Converter::new(input, &output).thin(0).run();
+    assert!(invalid.is_err());
+}
+
+#[test]
+fn sample_variants_with_seed() {
+    let input = "data/100_vars_chr22_HG.vcf.gz";
+    let output = temp_path("sample_variants.bgen");
+    let variant_list = temp_path("sample_variants.pvar");
+    let sampled = |seed: u64, single_pass: bool| {
+        let mut converter = Converter::new(input, &output)
+            .variant_list(&variant_list)
+            .sample_variants(0.2)
+            .seed(seed);
+        if single_pass {
+            converter = converter.single_pass();
+        }
+        converter.run().unwrap();
+        let ids: Vec<String> = fs::read_to_string(&variant_list)
+            .unwrap()
+            .lines()
+            .skip(1)
+            .map(|line| line.split('\t').nth(2).unwrap().to_string())
+            .collect();
+        assert_eq!(
+            read_bgen_summary(&output).unwrap().variant_num as usize,
+            ids.len()
+        );
+        ids
+    };
+    let first = sampled(42, false);
+    assert!(!first.is_empty() && first.len() < 50);
+    assert_eq!(sampled(42, false), first);
+    assert_eq!(sampled(42, true), first);
+    assert_ne!(sampled(7, false), first);
+
+    for fraction in [0.0, 1.5] {
+        let invalid = Converter::new(input, &output)
+            .sample_variants(fraction)
+            .run();
+        assert!(invalid.is_err());
+    }
+}
+
+#[test]
+fn max_variants_stops_early() {
+    let input = "data/100_vars_chr22_HG.vcf.gz";
+    let output = temp_path("max_variants.bgen");
+    let variant_list = temp_path("max_variants.pvar");
+    Converter::new(input, &output)
+        .variant_list(&variant_list)
+        .run()
+        .unwrap();
+    let all = fs::read_to_string(&variant_list).unwrap();
+    for single_pass in [false, true] {
+        let mut converter = Converter::new(input, &output)
+            .variant_list(&variant_list)
+            .max_variants(10);
+        if single_pass {
+            converter = converter.single_pass();
+        }
+        converter.run().unwrap();
+        assert_eq!(read_bgen_summary(&output).unwrap().variant_num, 10);
+        let first: Vec<&str> = all.lines().take(11).collect();
+        assert_eq!(
+            fs::read_to_string(&variant_list).unwrap(),
+            first.join("\n") + "\n"
+        );
+    }
+
+    // the limit falls between the split alts of 1:200 C T,G
+    let input = "data/variant_ids.vcf.gz";
+    for single_pass in [false, true] {
+        let mut converter = Converter::new(input, &output)
+            .variant_list(&variant_list)
+            .max_variants(2);
+        if single_pass {
+            converter = converter.single_pass();
+        }
+        converter.run().unwrap();
+        assert_eq!(read_bgen_summary(&output).unwrap().variant_num, 2);
+        let alts: Vec<String> = fs::read_to_string(&variant_list)
+            .unwrap()
+            .lines()
+            .skip(1)
+            .map(|line| line.split('\t').nth(4).unwrap().to_string())
+            .collect();
+        assert_eq!(alts, ["G", "T"]);
+    }
+}
+
+#[test]
+fn skip_variants_and_start_position() {
+    let input = "data/100_vars_chr22_HG.vcf.gz";
+    let output = temp_path("skip_variants.bgen");
+    let variant_list = temp_path("skip_variants.pvar");
+    let written = |configure: fn(Converter) -> Converter| {
+        configure(Converter::new(input, &output).variant_list(&variant_list))
+            .run()
+            .unwrap();
+        let rows: Vec<String> = fs::read_to_string(&variant_list)
+            .unwrap()
+            .lines()
+            .skip(1)
+            .map(str::to_string)
+            .collect();
+        assert_eq!(
+            read_bgen_summary(&output).unwrap().variant_num as usize,
+            rows.len()
+        );
+        rows
+    };
+    let all = written(|converter| converter);
+    assert_eq!(
+        written(|converter| converter.skip_variants(10).max_variants(10)),
+        all[10..20]
+    );
+    assert_eq!(
+        written(|converter| converter.skip_variants(10).max_variants(10).single_pass()),
+        all[10..20]
+    );
+    // jobs splitting the input cover every variant once
+    let mut jobs = written(|converter| converter.max_variants(60));
+    jobs.extend(written(|converter| converter.skip_variants(60)));
+    assert_eq!(jobs, all);
+
+    // 10527034 is the fourth record
+    assert_eq!(
+        written(|converter| converter.start_position("22:10527034")),
+        all[3..]
+    );
+    assert_eq!(
+        written(|converter| converter.start_position("22:10527034").single_pass()),
+        all[3..]
+    );
+    assert!(written(|converter| converter.start_position("21:1")).is_empty());
+    let invalid = Converter::new(input, &output).start_position("22").run();
+    assert!(invalid.is_err());
+}
+
+#[test]
+fn duplicate_variant_policies() {
+    // 1:100:A:G is repeated by the second record, 1:200:C:G by the split alt of the fourth
+    let input = "data/duplicates.vcf.gz";
+    let output = temp_path("duplicates.bgen");
+    let skipped = temp_path("duplicates_skipped.tsv");
+    for single_pass in [false, true] {
+        let mut converter = Converter::new(input, &output)
+            .dup_policy(DuplicatePolicy::KeepFirst)
+            .skipped_report(&skipped);
+        if single_pass {
+            converter = converter.single_pass();
+        }
+        converter.run().unwrap();
+        assert_eq!(read_bgen_summary(&output).unwrap().variant_num, 5);
+        assert_eq!(
+            fs::read_to_string(&skipped).unwrap(),
+            "line\tchr\tpos\tref\talt\treason\n7\t1\t100\tA\tG\tduplicate\n10\t1\t200\tC\tG\tduplicate\n"
+        );
+    }
+
+    let result = Converter::new(input, &output)
+        .dup_policy(DuplicatePolicy::Error)
+        .run();
+    assert!(matches!(result, Err(VcfError::Validation(report))
+        if report.to_string().contains("Record 2 repeats variant 1:100:A:G")));
+
+    // duplicates are written by default
+    Converter::new(input, &output).run().unwrap();
+    assert_eq!(read_bgen_summary(&output).unwrap().variant_num, 7);
+}
+
+#[test]
+fn repeated_sample_ids() {
+    let input = "data/duplicate_samples.vcf.gz";
+    let output = temp_path("duplicate_samples.bgen");
+    let result = Converter::new(input, &output).run();
+    assert!(matches!(result, Err(VcfError::Validation(_))));
+
+    Converter::new(input, &output)
+        .dedup_samples(SampleDedupPolicy::Suffix)
+        .run()
+        .unwrap();
+    assert_eq!(
+        read_bgen_summary(&output).unwrap().samples.unwrap(),
+        ["S1_1", "S2", "S1_2"]
+    );
+    // a numbered name taken by another sample
+    let samples = ["S1", "S1", "S1_2"].map(String::from);
+    assert!(deduplicated_samples(&samples, SampleDedupPolicy::Suffix).is_err());
+}
+
+#[test]
+fn threads_give_identical_outputs() {
+    let input = "data/100_vars_chr22_HG.vcf.gz";
+    let converted = |threads: u32| {
+        let output = temp_path(&format!("threads_{}.bgen", threads));
+        Converter::new(input, &output)
+            .threads(threads)
+            .deterministic()
+            .run()
+            .unwrap();
+        // blocks compressed by this crate, with their index and checksums
+        let zstd_output = temp_path(&format!("threads_{}_zstd.bgen", threads));
+        Converter::new(input, &zstd_output)
+            .threads(threads)
+            .deterministic()
+            .compression(BlockCompression::Zstd)
+            .write_index(true)
+            .checksums(true)
+            .run()
+            .unwrap();
+        let connection = rusqlite::Connection::open(format!("{}.bgi", zstd_output)).unwrap();
+        let mut statement = connection
+            .prepare("SELECT file_start_position, size_in_bytes FROM Variant ORDER BY file_start_position")
+            .unwrap();
+        let index_rows: Vec<(u64, u64)> = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        (
+            fs::read(&output).unwrap(),
+            fs::read(&zstd_output).unwrap(),
+            index_rows,
+            fs::read_to_string(format!("{}.md5", zstd_output))
+                .unwrap()
+                .split_whitespace()
+                .next()
+                .map(str::to_string),
+        )
+    };
+    let single = converted(1);
+    assert_eq!(converted(4), single);
+    assert_eq!(converted(16), single);
+
+    let output = temp_path("threads_0.bgen");
+    let result = Converter::new(input, &output).threads(0).run();
+    assert!(matches!(result, Err(VcfError::Validation(_))));
+}
+
+#[test]
+fn quiet_and_piped_progress() {
+    let output = temp_path("quiet.bgen");
+    let log_file = temp_path("quiet.log");
+    let run = |quiet: bool| {
+        let mut command = std::process::Command::new(env!("CARGO_BIN_EXE_vcf_to_bgen"));
+        command.args(["-i", "data/100_vars_chr22_HG.vcf.gz", "-o", &output]);
+        command.args(["--log-file", &log_file, "--force"]);
+        if quiet {
+            command.arg("--quiet");
+        }
+        let result = command.output().unwrap();
+        assert!(result.status.success());
+        (
+            String::from_utf8(result.stdout).unwrap(),
+            String::from_utf8(result.stderr).unwrap(),
+        )
+    };
+    // the log file still has every message
+    let (stdout, stderr) = run(true);
+    assert_eq!((stdout.as_str(), stderr.as_str()), ("", ""));
+    let log = fs::read_to_string(&log_file).unwrap();
+    assert!(log.contains("INFO Converting variants to bgen format"));
+    assert!(log.contains("Converting: 100 of 100 records (100%)"));
+
+    // piped stderr gets plain status lines rather than a redrawn bar, and stdout nothing
+    let (stdout, stderr) = run(false);
+    assert_eq!(stdout, "");
+    assert!(!stderr.contains('\x1b'));
+    assert!(stderr.contains("INFO Converting variants to bgen format"));
+    assert!(stderr.contains("Converting: 100 of 100 records (100%)"));
+    // with the throughput, in variants and input megabytes per second
+    let status = stderr
+        .lines()
+        .find(|line| line.contains("Converting: 100 of 100 records"))
+        .unwrap();
+    assert!(status.contains(" variants/s, ") && status.ends_with(" MB/s"));
+    assert!(stderr.contains("Counting: 100 records, "));
+}
+
+#[test]
+fn json_lines_progress() {
+    let output = temp_path("progress_jsonl.bgen");
+    let result = std::process::Command::new(env!("CARGO_BIN_EXE_vcf_to_bgen"))
+        .args([
+            "-i",
+            "data/100_vars_chr22_HG.vcf.gz",
+            "-o",
+            &output,
+            "--force",
+        ])
+        .args(["--quiet", "--progress-format", "jsonl"])
+        .output()
+        .unwrap();
+    assert!(result.status.success());
+    assert!(result.stdout.is_empty());
+    // quiet leaves only the events, one per pass when it takes less than their interval
+    let stderr = String::from_utf8(result.stderr).unwrap();
+    let events: Vec<&str> = stderr.lines().collect();
+    assert_eq!(
+        events,
+        [
+            r#"{"phase":"counting","records_done":100,"variants_done":100,"total":null,"eta_s":0,"finished":true}"#,
+            r#"{"phase":"converting","records_done":100,"variants_done":100,"total":100,"eta_s":0,"finished":true}"#,
+        ]
+    );
+}
+
+#[test]
+fn exit_codes_by_failure_class() {
+    let output = temp_path("exit_codes.bgen");
+    let exit_code = |input: &str, extra: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_vcf_to_bgen"))
+            .args(["-i", input, "-o", &output, "--force", "--quiet"])
+            .args(extra)
+            .output()
+            .unwrap()
+            .status
+            .code()
+    };
+    assert_eq!(exit_code("data/missing.vcf.gz", &[]), Some(3));
+    assert_eq!(exit_code("data/truncated_last_record.vcf.gz", &[]), Some(4));
+    assert_eq!(exit_code("data/sites_only.vcf.gz", &[]), Some(5));
+    assert_eq!(
+        exit_code("data/100_vars_chr22_HG.vcf.gz", &["--no-such-flag"]),
+        Some(2)
+    );
+    assert_eq!(exit_code("data/100_vars_chr22_HG.vcf.gz", &[]), Some(0));
+}
+
+#[test]
+fn variant_class_filters() {
+    let written_alts = |input: &str, configure: fn(Converter) -> Converter| {
+        let output = temp_path("variant_class.bgen");
+        let variant_list = temp_path("variant_class.pvar");
+        configure(Converter::new(input, &output).variant_list(&variant_list))
+            .run()
+            .unwrap();
+        let alts: Vec<String> = fs::read_to_string(&variant_list)
+            .unwrap()
+            .lines()
+            .skip(1)
+            .map(|line| line.split('\t').nth(4).unwrap().to_string())
+            .collect();
+        assert_eq!(
+            read_bgen_summary(&output).unwrap().variant_num as usize,
+            alts.len()
+        );
+        alts
+    };
+    // each alt of a split record is classed on its own
+    let input = "data/multiallelic_1_var_3_alt_allele.vcf.gz";
+    assert_eq!(written_alts(input, |c| c.snps_only()), ["G", "C"]);
+    assert_eq!(written_alts(input, |c| c.indels_only()), ["AC"]);
+    assert_eq!(written_alts(input, |c| c.max_allele_len(1)), ["G", "C"]);
+    let kept_whole = written_alts(input, |c| {
+        c.multiallelic(MultiallelicMode::Keep).indels_only()
+    });
+    assert_eq!(kept_whole, ["G,C,AC"]);
+    assert!(written_alts(input, |c| c
+        .multiallelic(MultiallelicMode::Keep)
+        .snps_only())
+    .is_empty());
+    // symbolic and spanning deletion alleles are neither SNPs nor indels
+    let input = "data/symbolic_alleles.vcf.gz";
+    fn symbolic(converter: Converter) -> Converter {
+        converter.symbolic_alleles(SymbolicAllelePolicy::Encode)
+    }
+    assert_eq!(written_alts(input, |c| symbolic(c).snps_only()), ["G", "G"]);
+    assert!(written_alts(input, |c| symbolic(c).indels_only()).is_empty());
+
+    let input = "data/100_vars_chr22_HG.vcf.gz";
+    let snps = written_alts(input, |c| c.snps_only());
+    let indels = written_alts(input, |c| c.indels_only());
+    assert!(!snps.is_empty() && !indels.is_empty());
+    assert_eq!(snps.len() + indels.len(), written_alts(input, |c| c).len());
+    let result = Converter::new(input, &temp_path("variant_class.bgen"))
+        .snps_only()
+        .indels_only()
+        .run();
+    assert!(matches!(result, Err(VcfError::Validation(_))));
+}
+
+#[test]
+fn info_expression_filter() {
+    let input = "data/info_filter.vcf.gz";
+    let written_ids = |expression: &str, multiallelic: MultiallelicMode| {
+        let output = temp_path("info_filter.bgen");
+        let variant_list = temp_path("info_filter.pvar");
+        Converter::new(input, &output)
+            .info_filter(expression)
+            .multiallelic(multiallelic)
+            .variant_list(&variant_list)
+            .run()
+            .unwrap();
+        let ids: Vec<String> = fs::read_to_string(&variant_list)
+            .unwrap()
+            .lines()
+            .skip(1)
+            .map(|line| line.split('\t').nth(2).unwrap().to_string())
+            .collect();
+        assert_eq!(
+            read_bgen_summary(&output).unwrap().variant_num as usize,
+            ids.len()
+        );
+        ids
+    };
+    // per-alt AF values are compared on each split alt, a missing key failing
+    let split = MultiallelicMode::Split;
+    assert_eq!(
+        written_ids("AF>0.001 && INFO/R2>=0.8", split),
+        ["1:300:G:A", "1:400:T:C"]
+    );
+    assert_eq!(
+        written_ids("R2>=0.99 || TYPED", split),
+        ["1:300:G:A", "1:500:A:T"]
+    );
+    // records kept whole pass when any of their values does
+    assert_eq!(
+        written_ids("AF>0.001 && INFO/R2>=0.8", MultiallelicMode::Keep),
+        ["1:300:G:A", "1:400:T:C,G"]
+    );
+
+    for expression in ["AF>", "AF>common", "&& R2>0.8", "INFO/ >1"] {
+        let result = Converter::new(input, &temp_path("info_filter.bgen"))
+            .info_filter(expression)
+            .run();
+        assert!(matches!(result, Err(VcfError::Validation(_))));
+    }
+}
+
+#[test]
+fn dry_run_writes_nothing() {
+    let input = "data/duplicates.vcf.gz";
+    let output = temp_path("dry_run.bgen");
+    let skipped = temp_path("dry_run_skipped.tsv");
+    let variant_list = temp_path("dry_run_variants.tsv");
+    for path in [&output, &skipped, &variant_list] {
+        let _ = fs::remove_file(path);
+    }
+    let report = Converter::new(input, &output)
+        .dup_policy(DuplicatePolicy::KeepFirst)
+        .skipped_report(&skipped)
+        .variant_list(&variant_list)
+        .dry_run()
+        .unwrap();
+    assert_eq!(report.metrics.variants_processed, 5);
+    assert_eq!(report.samples.len(), 2);
+    assert_eq!(
+        report.outputs,
+        vec![output.clone(), variant_list.clone(), skipped.clone()]
+    );
+    for path in [&output, &skipped, &variant_list] {
+        assert!(!fs::exists(path).unwrap());
+    }
+
+    // checks fail as they would converting
+    let result = Converter::new(input, &output)
+        .dup_policy(DuplicatePolicy::Error)
+        .dry_run();
+    assert!(matches!(result, Err(VcfError::Validation(report))
+        if report.to_string().contains("Record 2 repeats variant 1:100:A:G")));
+    assert!(!fs::exists(&output).unwrap());
+
+    let run = std::process::Command::new(env!("CARGO_BIN_EXE_vcf_to_bgen"))
+        .args(["-i", input, "-o", &output, "--dry-run", "--quiet"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(run.stdout).unwrap();
+    assert!(stdout.starts_with("samples\t2\nrecords\t6\nvariants\t7\n"));
+    assert!(stdout.contains(&format!("output\t{}\n", output)));
+    assert!(!fs::exists(&output).unwrap());
+}
+
+#[test]
+fn summary_json_report() {
+    let input = "data/duplicates.vcf.gz";
+    let output = temp_path("summary.bgen");
+    let summary = temp_path("summary.json");
+    let metrics = Converter::new(input, &output)
+        .dup_policy(DuplicatePolicy::KeepFirst)
+        .summary_json(&summary)
+        .run()
+        .unwrap();
+    assert_eq!(metrics.multiallelics_split, 1);
+    assert_eq!(metrics.variants_skipped, vec![("duplicate".to_string(), 2)]);
+    let json = fs::read_to_string(&summary).unwrap();
+    assert!(json.contains(&format!("  \"outputs\": [\"{}\"],\n", output)));
+    assert!(json.contains(
+        "  \"samples\": 2,\n  \"records_read\": 6,\n  \"records_skipped\": 2,\n  \"variants_written\": 5,\n  \"multiallelics_split\": 1,\n"
+    ));
+    assert!(json.contains("  \"variants_skipped\": {\"duplicate\": 2},\n"));
+    assert!(json.contains(
+        "  \"genotypes_written\": 10,\n  \"genotypes_missing\": 2,\n  \"missing_rate\": 0.2,\n"
+    ));
+    assert!(json.contains("  \"phase_seconds\": {\"count\": "));
+    assert!(json.ends_with("}\n"));
+}
+
+#[test]
+fn annotate_rsid_from_reference() {
+    // 1:300:G:A has no input ID; the reference also lists 1:300 with another ref allele
+    let input = "data/variant_ids.vcf.gz";
+    let output = temp_path("annotate_rsid.bgen");
+    let metrics = Converter::new(input, &output)
+        .layout(1)
+        .annotate_rsid("data/dbsnp.tsv.gz")
+        .run()
+        .unwrap();
+    assert_eq!(metrics.rsids_annotated, 1);
+    let variants = read_layout1_variants(&output).unwrap();
+    let rsids: Vec<&str> = variants.iter().map(|v| v.rsid.as_str()).collect();
+    assert_eq!(rsids, ["rs100", "rs200", "rs200", "rs300", "rs50"]);
+    assert_eq!(variants[3].variants_id, "1:300:G:A");
+
+    // without the reference, identifiers are built from the position
+    Converter::new(input, &output).layout(1).run().unwrap();
+    let variants = read_layout1_variants(&output).unwrap();
+    assert_eq!(variants[0].rsid, "1:100:A:G");
+
+    let malformed = temp_path("annotate_rsid_malformed.tsv");
+    fs::write(&malformed, "1\t300\tG\tA\n").unwrap();
+    let result = Converter::new(input, &output)
+        .annotate_rsid(&malformed)
+        .run();
+    assert!(matches!(result, Err(VcfError::Validation(report))
+        if report.to_string().contains("line 1: expected CHROM POS REF ALT RSID")));
+}
+
+#[test]
+fn refuse_to_overwrite_outputs() {
+    let input = "data/variant_ids.vcf.gz";
+    let output = temp_path("no_overwrite.bgen");
+    let index = format!("{}.bgi", output);
+    for path in [&output, &index] {
+        let _ = fs::remove_file(path);
+    }
+    Converter::new(input, &output)
+        .write_index(true)
+        .no_overwrite()
+        .run()
+        .unwrap();
+
+    // the index alone is enough to refuse, before anything is written
+    fs::remove_file(&output).unwrap();
+    let result = Converter::new(input, &output)
+        .write_index(true)
+        .no_overwrite()
+        .run();
+    assert!(matches!(result, Err(VcfError::Validation(report))
+        if report.to_string() == format!("{} already exists: use --force to overwrite it", index)));
+    assert!(!fs::exists(&output).unwrap());
+
+    let run = |force: bool| {
+        let mut command = std::process::Command::new(env!("CARGO_BIN_EXE_vcf_to_bgen"));
+        command.args(["-i", input, "-o", &output, "--index", "--quiet"]);
+        if force {
+            command.arg("--force");
+        }
+        command.output().unwrap().status.success()
+    };
+    assert!(!run(false));
+    assert!(run(true));
+    assert_eq!(read_bgen_summary(&output).unwrap().variant_num, 5);
+}
+
+#[test]
+fn refuse_to_overwrite_chromosome_outputs() {
+    let template = temp_path("no_overwrite_chr{chr}.bgen");
+    let existing = template.replace("{chr}", "1");
+    fs::write(&existing, "precious\n").unwrap();
+    let result = Converter::new("data/ploidy_map.vcf.gz", &template)
+        .split_by_chr(true)
+        .no_overwrite()
+        .run();
+    assert!(matches!(result, Err(VcfError::Validation(report))
+        if report.to_string() == format!("{} already exists: use --force to overwrite it", existing)));
+    assert_eq!(fs::read_to_string(&existing).unwrap(), "precious\n");
+    assert!(!fs::exists(&template).unwrap());
+}
+
+#[test]
+fn verify_written_variants() {
+    let input = "data/variant_ids.vcf.gz";
+    let output = temp_path("verify.bgen");
+    // every layout and compression of blocks
+    for (layout, compression) in [
+        (2, BlockCompression::Zstd),
+        (2, BlockCompression::None),
+        (1, BlockCompression::Zlib),
+        (1, BlockCompression::None),
+    ] {
+        Converter::new(input, &output)
+            .layout(layout)
+            .compression(compression)
+            .verify(3)
+            .run()
+            .unwrap();
+    }
+    for single_pass in [false, true] {
+        let mut converter = Converter::new(input, &output)
+            .compression(BlockCompression::Zstd)
+            .adaptive_num_bits()
+            .verify(100);
+        if single_pass {
+            converter = converter.single_pass();
+        }
+        converter.run().unwrap();
+    }
+
+    // variants converted otherwise than those written
+    Converter::new(input, &output)
+        .compression(BlockCompression::Zstd)
+        .run()
+        .unwrap();
+    let options = ConversionOptions {
+        phased: true,
+        ..Default::default()
+    };
+    let mut source = VcfSource::open(input, &options, 4).unwrap();
+    let mut sink = VerifySink::new(&output, 2, Some(1));
+    while let Some(variants) = source.next_record().unwrap() {
+        for variant in variants {
+            sink.write_variant(&variant).unwrap();
+        }
+    }
+    assert!(matches!(sink.finish(), Err(VcfError::Validation(report))
+        if report.to_string().contains("was written with phased false")));
+
+    let result = Converter::new(input, &output)
+        .shards(2, ShardMode::Block)
+        .verify(3)
+        .run();
+    assert!(matches!(result, Err(VcfError::Validation(_))));
+}
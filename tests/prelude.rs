@@ -0,0 +1,33 @@
+extern crate vcf_to_bgen;
+use vcf_to_bgen::prelude::*;
+
+// Everything used here comes from the prelude, as in a downstream crate
+struct CountingSink {
+    variants: u32,
+}
+
+impl VariantSink for CountingSink {
+    fn write_header(&mut self, _samples: &[String], _variant_num: u32) -> Result<(), VcfError> {
+        Ok(())
+    }
+
+    fn write_variant(&mut self, _variant: &Variant) -> Result<(), VcfError> {
+        self.variants += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<u64, VcfError> {
+        Ok(0)
+    }
+}
+
+#[test]
+fn prelude_covers_a_custom_pipeline() {
+    let input = "data/polyploid.vcf.gz";
+    let options = ConversionOptions::default();
+    let source = VcfSource::open(input, &options, 2).unwrap();
+    let mut pipeline = Pipeline::new(Box::new(source)).sink(Box::new(CountingSink { variants: 0 }));
+    let metrics: ConversionMetrics = pipeline.run(2, 2).unwrap();
+    assert_eq!(metrics.variants_processed, 2);
+    assert!(!VERSION.is_empty());
+}
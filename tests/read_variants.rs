@@ -1,5 +1,8 @@
 extern crate vcf_to_bgen;
-use vcf_to_bgen::count_variants;
+use vcf_to_bgen::{
+    check_sample_limit, check_variant_limit, count_variants, count_variants_with_options,
+    ConversionOptions, RefBlockMode, VcfError,
+};
 
 #[test]
 fn count_100_variants() {
@@ -16,3 +19,48 @@ fn count_variants_with_multiallelic() {
     assert_eq!(num_geno_line, 1);
     assert_eq!(num_variant, 2);
 }
+
+#[test]
+fn count_variants_gvcf_ref_blocks() {
+    let input = "data/gvcf_ref_blocks.vcf.gz";
+    let (num_variant, num_geno_line) = count_variants(input).unwrap();
+    assert_eq!(num_geno_line, 2);
+    assert_eq!(num_variant, 1);
+    let options = ConversionOptions {
+        ref_blocks: RefBlockMode::Expand,
+        ..Default::default()
+    };
+    let (num_variant, num_geno_line) = count_variants_with_options(input, &options).unwrap();
+    assert_eq!(num_geno_line, 2);
+    assert_eq!(num_variant, 4);
+}
+
+#[test]
+fn count_variants_interleaved_chromosomes() {
+    let input = "data/interleaved_chromosomes.vcf.gz";
+    assert!(matches!(
+        count_variants(input),
+        Err(VcfError::Validation(_))
+    ));
+    let options = ConversionOptions {
+        regroup_chromosomes: true,
+        ..Default::default()
+    };
+    let (num_variant, num_geno_line) = count_variants_with_options(input, &options).unwrap();
+    assert_eq!(num_geno_line, 3);
+    assert_eq!(num_variant, 3);
+}
+
+#[test]
+fn bgen_header_limits() {
+    assert!(check_variant_limit(u32::MAX as u64).is_ok());
+    assert!(matches!(
+        check_variant_limit(u32::MAX as u64 + 1),
+        Err(VcfError::LimitExceeded(_))
+    ));
+    assert!(check_sample_limit(&["S1".to_string(), "S2".to_string()]).is_ok());
+    assert!(matches!(
+        check_sample_limit(&["S".repeat(70_000)]),
+        Err(VcfError::LimitExceeded(_))
+    ));
+}
@@ -0,0 +1,69 @@
+extern crate vcf_to_bgen;
+use std::io::{self, Read};
+use std::time::Duration;
+use vcf_to_bgen::retry::{RetryPolicy, RetryingReader};
+
+// Reads `data` from `pos` in small chunks, failing with EIO after `reads_left` reads, like a stale
+// file handle
+struct FlakyReader {
+    data: Vec<u8>,
+    pos: usize,
+    reads_left: Option<u32>,
+}
+
+impl Read for FlakyReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.reads_left.as_mut() {
+            Some(0) => return Err(io::Error::from_raw_os_error(5)),
+            Some(reads_left) => *reads_left -= 1,
+            None => {}
+        }
+        let bytes_read = buf.len().min(3).min(self.data.len() - self.pos);
+        buf[..bytes_read].copy_from_slice(&self.data[self.pos..self.pos + bytes_read]);
+        self.pos += bytes_read;
+        Ok(bytes_read)
+    }
+}
+
+const NO_WAIT: RetryPolicy = RetryPolicy {
+    retries: 2,
+    backoff: Duration::ZERO,
+};
+
+#[test]
+fn transient_error_is_retried_at_offset() {
+    let data = b"0123456789".to_vec();
+    let reopen_data = data.clone();
+    let reader = FlakyReader {
+        data: data.clone(),
+        pos: 0,
+        reads_left: Some(1),
+    };
+    let mut reader = RetryingReader::with_reopen(
+        reader,
+        NO_WAIT,
+        Box::new(move |offset| {
+            Ok(FlakyReader {
+                data: reopen_data.clone(),
+                pos: offset as usize,
+                reads_left: None,
+            })
+        }),
+    );
+    let mut content = vec![];
+    reader.read_to_end(&mut content).unwrap();
+    assert_eq!(content, data);
+}
+
+#[test]
+fn persistent_error_is_reported() {
+    let reader = FlakyReader {
+        data: b"0123456789".to_vec(),
+        pos: 0,
+        reads_left: Some(0),
+    };
+    let mut reader = RetryingReader::new(reader, NO_WAIT);
+    let mut content = vec![];
+    let error = reader.read_to_end(&mut content).unwrap_err();
+    assert_eq!(error.raw_os_error(), Some(5));
+}
@@ -0,0 +1,52 @@
+extern crate vcf_to_bgen;
+use std::fs;
+use vcf_to_bgen::sample_file::check_pair;
+use vcf_to_bgen::{convert_to_bgen, count_variants, ConversionOptions};
+
+fn convert_10_samples(name: &str) -> String {
+    let input = "data/1_var_10_ind.vcf.gz";
+    let output = std::env::temp_dir().join(name);
+    let output = output.to_str().unwrap().to_string();
+    let (variant_num, number_geno_line) = count_variants(input).unwrap();
+    let options = ConversionOptions::default();
+    convert_to_bgen(input, &output, variant_num, number_geno_line, &options).unwrap();
+    output
+}
+
+fn sample_rows(ids: &[&str]) -> String {
+    ids.iter().map(|id| format!("{} {} 0\n", id, id)).collect()
+}
+
+const SAMPLES: [&str; 10] = [
+    "HG00096", "HG00097", "HG00099", "HG00100", "HG00101", "HG00102", "HG00103", "HG00104",
+    "HG00105", "HG00106",
+];
+
+#[test]
+fn check_pair_consistent() {
+    let bgen = convert_10_samples("check_pair_consistent.bgen");
+    let sample = bgen.replace(".bgen", ".sample");
+    let content = "ID_1 ID_2 missing\n0 0 0\n".to_string() + &sample_rows(&SAMPLES);
+    fs::write(&sample, content).unwrap();
+    assert_eq!(check_pair(&sample, &bgen).unwrap(), 10);
+}
+
+#[test]
+fn check_pair_missing_type_row() {
+    let bgen = convert_10_samples("check_pair_missing_type_row.bgen");
+    let sample = bgen.replace(".bgen", ".sample");
+    let content = "ID_1 ID_2 missing\n".to_string() + &sample_rows(&SAMPLES);
+    fs::write(&sample, content).unwrap();
+    assert!(check_pair(&sample, &bgen).is_err());
+}
+
+#[test]
+fn check_pair_wrong_order() {
+    let bgen = convert_10_samples("check_pair_wrong_order.bgen");
+    let sample = bgen.replace(".bgen", ".sample");
+    let mut samples = SAMPLES;
+    samples.swap(3, 4);
+    let content = "ID_1 ID_2 missing\n0 0 0\n".to_string() + &sample_rows(&samples);
+    fs::write(&sample, content).unwrap();
+    assert!(check_pair(&sample, &bgen).is_err());
+}
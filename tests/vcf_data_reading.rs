@@ -2,7 +2,12 @@ extern crate vcf_to_bgen;
 use flate2::read::MultiGzDecoder;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
-use vcf_to_bgen::{parse_genotype_line, read_vcf_header, split_multiallelic};
+use vcf_to_bgen::{
+    check_phased_genotypes, check_ploidy, expand_reference_block, genotypes_phased,
+    is_reference_block, number_of_genotypes, parse_genotype_line, read_vcf_header,
+    regroup_chromosome_lines, rescale_probabilities, split_multiallelic, vcf_genotype_order,
+    VcfError,
+};
 
 #[test]
 fn read_samples() {
@@ -176,3 +181,308 @@ fn read_one_line_multiallelic_3_alt() {
         [2, 130, 130, 2, 130, 2, 2, 130, 2, 2].to_vec()
     );
 }
+
+#[test]
+fn read_one_line_phased_reference_panel() {
+    let input = "data/100_vars_chr22_HG.vcf.gz";
+    // reads header
+    let mut reader = BufReader::new(MultiGzDecoder::new(File::open(input).unwrap()));
+    let samples = read_vcf_header(&mut reader).unwrap();
+    // read first line
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    let num_bits = 1;
+    let number_individuals = 2548;
+    assert_eq!(number_individuals as usize, samples.len());
+    let mut variant_data = parse_genotype_line(&line, number_individuals, num_bits).unwrap();
+    check_phased_genotypes(&variant_data).unwrap();
+    variant_data.set_phased(true);
+    let vec_variant_data = split_multiallelic(variant_data, number_individuals).unwrap();
+    assert!(vec_variant_data[0].data_block.phased);
+    // sample 13 is 0|1: first haplotype carries the ref allele, second the alt
+    assert_eq!(
+        vec_variant_data[0].data_block.probabilities[24..30],
+        [1, 1, 1, 0, 1, 1].to_vec()
+    );
+}
+
+#[test]
+fn reference_panel_rejects_unphased_genotypes() {
+    let input = "data/complicated_format.vcf.gz";
+    let mut reader = BufReader::new(MultiGzDecoder::new(File::open(input).unwrap()));
+    read_vcf_header(&mut reader).unwrap();
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    let variant_data = parse_genotype_line(&line, 10, 1).unwrap();
+    assert!(matches!(
+        check_phased_genotypes(&variant_data),
+        Err(VcfError::Validation(_))
+    ));
+}
+
+#[test]
+fn read_gvcf_ref_block_and_non_ref() {
+    let input = "data/gvcf_ref_blocks.vcf.gz";
+    let mut reader = BufReader::new(MultiGzDecoder::new(File::open(input).unwrap()));
+    let samples = read_vcf_header(&mut reader).unwrap();
+    let number_individuals = 3;
+    assert_eq!(number_individuals as usize, samples.len());
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    let variant_data = parse_genotype_line(&line, number_individuals, 8).unwrap();
+    assert!(is_reference_block(&variant_data));
+    let vec_variant_data = expand_reference_block(&variant_data, number_individuals).unwrap();
+    assert_eq!(vec_variant_data.len(), 3);
+    assert_eq!(vec_variant_data[0].alleles, vec!["T", "."]);
+    assert_eq!(vec_variant_data[2].alleles, vec!["N", "."]);
+    assert_eq!(vec_variant_data[2].pos, 10516172);
+    assert_eq!(
+        vec_variant_data[2].data_block.probabilities,
+        vec![255, 0, 255, 0, 255, 0]
+    );
+    // <NON_REF> is dropped from variant sites
+    line.clear();
+    reader.read_line(&mut line).unwrap();
+    let variant_data = parse_genotype_line(&line, number_individuals, 8).unwrap();
+    assert!(!is_reference_block(&variant_data));
+    let vec_variant_data = split_multiallelic(variant_data, number_individuals).unwrap();
+    assert_eq!(vec_variant_data.len(), 1);
+    assert_eq!(vec_variant_data[0].alleles, vec!["A", "G"]);
+    assert_eq!(
+        vec_variant_data[0].data_block.ploidy_missingness,
+        vec![2, 130, 2]
+    );
+}
+
+#[test]
+fn regroup_interleaved_chromosomes() {
+    let input = "data/interleaved_chromosomes.vcf.gz";
+    let mut reader = BufReader::new(MultiGzDecoder::new(File::open(input).unwrap()));
+    read_vcf_header(&mut reader).unwrap();
+    let lines = regroup_chromosome_lines(&mut reader, 3).unwrap();
+    let positions: Vec<&str> = lines
+        .iter()
+        .map(|line| line.split('\t').take(2).last().unwrap())
+        .collect();
+    assert!(lines[0].starts_with("1\t") && lines[1].starts_with("1\t"));
+    assert_eq!(positions, vec!["100", "200", "100"]);
+}
+
+#[test]
+fn rescale_probabilities_to_fewer_bits() {
+    let input = "data/multiallelic_1_var.vcf.gz";
+    let mut reader = BufReader::new(MultiGzDecoder::new(File::open(input).unwrap()));
+    read_vcf_header(&mut reader).unwrap();
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    let variant_data = parse_genotype_line(&line, 10, 16).unwrap();
+    let vec_variant_data = split_multiallelic(variant_data, 10).unwrap();
+    let rescaled = rescale_probabilities(&vec_variant_data[0], 8);
+    assert_eq!(rescaled.data_block.bits_storage, 8);
+    assert_eq!(
+        rescaled.data_block.probabilities[0..10],
+        vec![255, 0, 255, 0, 0, 255, 255, 0, 255, 0]
+    );
+}
+
+#[test]
+fn read_crlf_and_trailing_separators() {
+    let input = "data/crlf_trailing_tab.vcf.gz";
+    let mut reader = BufReader::new(MultiGzDecoder::new(File::open(input).unwrap()));
+    let samples = read_vcf_header(&mut reader).unwrap();
+    assert_eq!(samples, vec!["S1", "S2", "S3"]);
+    let number_individuals = 3;
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    let variant_data = parse_genotype_line(&line, number_individuals, 8).unwrap();
+    let vec_variant_data = split_multiallelic(variant_data, number_individuals).unwrap();
+    assert_eq!(
+        vec_variant_data[0].data_block.probabilities,
+        vec![255, 0, 0, 255, 0, 0]
+    );
+    assert_eq!(
+        vec_variant_data[0].data_block.ploidy_missingness,
+        vec![2, 2, 2]
+    );
+    line.clear();
+    reader.read_line(&mut line).unwrap();
+    let variant_data = parse_genotype_line(&line, number_individuals, 8).unwrap();
+    let vec_variant_data = split_multiallelic(variant_data, number_individuals).unwrap();
+    assert_eq!(
+        vec_variant_data[0].data_block.probabilities,
+        vec![0, 255, 0, 0, 255, 0]
+    );
+}
+
+#[test]
+fn read_last_sample_without_newline() {
+    let input = "data/last_sample_no_newline.vcf.gz";
+    let mut reader = BufReader::new(MultiGzDecoder::new(File::open(input).unwrap()));
+    let samples = read_vcf_header(&mut reader).unwrap();
+    let number_individuals = samples.len() as u32;
+    let mut line = String::new();
+    // a single-character missing genotype must not swallow the line ending
+    reader.read_line(&mut line).unwrap();
+    let variant_data = parse_genotype_line(&line, number_individuals, 8).unwrap();
+    let vec_variant_data = split_multiallelic(variant_data, number_individuals).unwrap();
+    assert_eq!(
+        vec_variant_data[0].data_block.ploidy_missingness,
+        vec![2, 2, 130]
+    );
+    // the last sample of the final record is followed by no newline
+    line.clear();
+    reader.read_line(&mut line).unwrap();
+    assert!(!line.ends_with('\n'));
+    let variant_data = parse_genotype_line(&line, number_individuals, 8).unwrap();
+    let vec_variant_data = split_multiallelic(variant_data, number_individuals).unwrap();
+    assert_eq!(
+        vec_variant_data[0].data_block.probabilities,
+        vec![255, 0, 255, 0, 0, 0]
+    );
+    assert_eq!(
+        vec_variant_data[0].data_block.ploidy_missingness,
+        vec![2, 2, 2]
+    );
+}
+
+#[test]
+fn read_genotype_probabilities() {
+    let input = "data/genotype_probabilities.vcf.gz";
+    let mut reader = BufReader::new(MultiGzDecoder::new(File::open(input).unwrap()));
+    let samples = read_vcf_header(&mut reader).unwrap();
+    let number_individuals = samples.len() as u32;
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    let variant_data = parse_genotype_line(&line, number_individuals, 8).unwrap();
+    let vec_variant_data = split_multiallelic(variant_data, number_individuals).unwrap();
+    assert_eq!(
+        vec_variant_data[0].data_block.probabilities[0..4],
+        [204, 51, 51, 153]
+    );
+    // a missing GP falls back to GT
+    assert_eq!(
+        vec_variant_data[0].data_block.ploidy_missingness,
+        vec![2, 2, 130]
+    );
+    // multiallelic GP is summed by copies of each alt allele
+    line.clear();
+    reader.read_line(&mut line).unwrap();
+    let variant_data = parse_genotype_line(&line, number_individuals, 8).unwrap();
+    let vec_variant_data = split_multiallelic(variant_data, number_individuals).unwrap();
+    assert_eq!(
+        vec_variant_data[0].data_block.probabilities,
+        vec![102, 153, 255, 0, 255, 0]
+    );
+    assert_eq!(
+        vec_variant_data[1].data_block.probabilities,
+        vec![51, 204, 255, 0, 255, 0]
+    );
+}
+
+#[test]
+fn read_polyploid_genotypes() {
+    let input = "data/polyploid.vcf.gz";
+    let mut reader = BufReader::new(MultiGzDecoder::new(File::open(input).unwrap()));
+    let samples = read_vcf_header(&mut reader).unwrap();
+    let number_individuals = samples.len() as u32;
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    let variant_data = parse_genotype_line(&line, number_individuals, 8).unwrap();
+    let vec_variant_data = split_multiallelic(variant_data, number_individuals).unwrap();
+    let data_block = &vec_variant_data[0].data_block;
+    // one stored probability per number of alt copies, all alt is implied
+    assert_eq!(
+        data_block.probabilities,
+        vec![0, 0, 255, 0, 0, 0, 0, 255, 0, 0, 0, 0, 255, 0, 0, 0]
+    );
+    assert_eq!(data_block.ploidy_missingness, vec![4, 4, 4, 132]);
+    assert_eq!(
+        (data_block.minimum_ploidy, data_block.maximum_ploidy),
+        (4, 4)
+    );
+    // ploidy may differ between samples of a record
+    line.clear();
+    reader.read_line(&mut line).unwrap();
+    let variant_data = parse_genotype_line(&line, number_individuals, 8).unwrap();
+    let vec_variant_data = split_multiallelic(variant_data, number_individuals).unwrap();
+    let data_block = &vec_variant_data[0].data_block;
+    assert_eq!(data_block.probabilities, vec![255, 0, 0, 255, 0, 0, 0, 0]);
+    assert_eq!(data_block.ploidy_missingness, vec![1, 1, 2, 4]);
+    assert_eq!(
+        (data_block.minimum_ploidy, data_block.maximum_ploidy),
+        (1, 4)
+    );
+}
+
+#[test]
+fn tetraploid_genotype_order() {
+    assert_eq!(number_of_genotypes(2, 3), 6);
+    assert_eq!(number_of_genotypes(4, 2), 5);
+    assert_eq!(number_of_genotypes(4, 3), 15);
+    assert_eq!(
+        vcf_genotype_order(2, 3),
+        vec![
+            vec![0, 0],
+            vec![0, 1],
+            vec![1, 1],
+            vec![0, 2],
+            vec![1, 2],
+            vec![2, 2]
+        ]
+    );
+    let order = vcf_genotype_order(4, 3);
+    assert_eq!(order.len(), 15);
+    assert_eq!(order[4], vec![1, 1, 1, 1]);
+    assert_eq!(order[6], vec![0, 0, 1, 2]);
+}
+
+#[test]
+fn read_tetraploid_probabilities() {
+    let input = "data/tetraploid.vcf.gz";
+    let mut reader = BufReader::new(MultiGzDecoder::new(File::open(input).unwrap()));
+    let samples = read_vcf_header(&mut reader).unwrap();
+    let number_individuals = samples.len() as u32;
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    let variant_data = parse_genotype_line(&line, number_individuals, 8).unwrap();
+    // 0 to 3 alt copies are stored from GP, S2 has no GP and keeps its GT
+    let vec_variant_data = split_multiallelic(variant_data, number_individuals).unwrap();
+    let data_block = &vec_variant_data[0].data_block;
+    assert_eq!(data_block.ploidy_missingness, vec![4, 4]);
+    assert_eq!(data_block.probabilities, vec![0, 26, 204, 25, 0, 0, 0, 255]);
+    // GP of a triallelic tetraploid is summed by number of copies of each alt
+    line.clear();
+    reader.read_line(&mut line).unwrap();
+    let variant_data = parse_genotype_line(&line, number_individuals, 8).unwrap();
+    let vec_variant_data = split_multiallelic(variant_data, number_individuals).unwrap();
+    for variant_data in &vec_variant_data {
+        let data_block = &variant_data.data_block;
+        assert_eq!(data_block.ploidy_missingness, vec![4, 132]);
+        assert_eq!(data_block.probabilities[..4], [128, 127, 0, 0]);
+    }
+    // genotypes above the configured ploidy are rejected
+    let variant_data = parse_genotype_line(&line, number_individuals, 8).unwrap();
+    assert!(check_ploidy(&variant_data, 4).is_ok());
+    assert!(matches!(
+        check_ploidy(&variant_data, 2),
+        Err(VcfError::Validation(_))
+    ));
+}
+
+#[test]
+fn detect_phasing_per_record() {
+    assert!(genotypes_phased(&["0|1", "1", ".|."]));
+    assert!(!genotypes_phased(&["0|1", "0/0"]));
+    assert!(!genotypes_phased(&["0", "1"]));
+    let input = "data/mixed_phasing.vcf.gz";
+    let mut reader = BufReader::new(MultiGzDecoder::new(File::open(input).unwrap()));
+    let samples = read_vcf_header(&mut reader).unwrap();
+    let number_individuals = samples.len() as u32;
+    // phased GTs, one unphased GT, phased GTs with GP
+    for expected in [true, false, false] {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        let variant_data = parse_genotype_line(&line, number_individuals, 8).unwrap();
+        assert_eq!(variant_data.phased_genotypes(), expected);
+    }
+}
@@ -0,0 +1,52 @@
+extern crate vcf_to_bgen;
+use flate2::read::MultiGzDecoder;
+use std::fs::File;
+use std::io::BufReader;
+use vcf_to_bgen::read_vcf_header_full;
+use vcf_to_bgen::vcf_header::{parse_vcf_header, percent_decode};
+
+#[test]
+fn read_structured_header() {
+    let input = "data/100_vars_chr22_HG.vcf.gz";
+    let mut reader = BufReader::new(MultiGzDecoder::new(File::open(input).unwrap()));
+    let header = read_vcf_header_full(&mut reader).unwrap();
+    assert_eq!(header.file_format.as_deref(), Some("VCFv4.3"));
+    assert_eq!(header.samples.len(), 2548);
+    assert!(header.contig("22").is_some());
+    let gt = header.format("GT").unwrap();
+    assert_eq!(gt.number, "1");
+    assert_eq!(gt.field_type, "String");
+    assert_eq!(gt.description, "Phased Genotype");
+    let af = header.info("AF").unwrap();
+    assert_eq!(af.number, "A");
+    assert_eq!(
+        af.description,
+        "Estimated allele frequency in the range (0,1)"
+    );
+}
+
+#[test]
+fn read_contig_length_and_escapes() {
+    let header_text = "##fileformat=VCFv4.3\n\
+        ##contig=<ID=chr1,length=248956422,assembly=GRCh38>\n\
+        ##INFO=<ID=NOTE,Number=1,Type=String,Description=\"A \\\"quoted\\\" note, with comma\">\n\
+        ##source=tool%3Aversion\n\
+        #CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\tsample-1\tsample_2\n";
+    let header = parse_vcf_header(&mut header_text.as_bytes()).unwrap();
+    assert_eq!(header.contig("chr1").unwrap().length, Some(248956422));
+    assert_eq!(
+        header.info("NOTE").unwrap().description,
+        "A \"quoted\" note, with comma"
+    );
+    assert!(header
+        .unstructured
+        .contains(&("source".to_string(), "tool:version".to_string())));
+    assert_eq!(header.samples, vec!["sample-1", "sample_2"]);
+}
+
+#[test]
+fn decode_percent_encoding() {
+    assert_eq!(percent_decode("a%3Bb%3Dc%25d%2Ce"), "a;b=c%d,e");
+    assert_eq!(percent_decode("no_escape"), "no_escape");
+    assert_eq!(percent_decode("bad%zz"), "bad%zz");
+}